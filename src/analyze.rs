@@ -0,0 +1,146 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use error::Error;
+use regex_syntax::{Expr, Repeater};
+
+/// A summary of the features that a regular expression uses, and of how well it compiles (see
+/// `Regex::analyze`).
+///
+/// The flags are only reported if they make a difference: for example, `(?s)a` doesn't count as
+/// letting `.` match a new line, because it doesn't have a `.`.
+#[derive(Debug)]
+pub struct PatternReport {
+    /// Whether there are anchors at the beginning or end of the text (`^` and `$`, or `\A` and
+    /// `\z`).
+    pub text_anchors: bool,
+    /// Whether there are anchors at the beginnings or ends of lines (`^` and `$` with the `m`
+    /// flag).
+    pub line_anchors: bool,
+    /// Whether there are word boundaries (`\b` or `\B`). These are the most common reason for
+    /// big automata.
+    pub word_boundaries: bool,
+    /// The number of character classes, including `.`.
+    pub classes: usize,
+    /// The number of counted repetitions with an upper bound, like `x{2,5}`. Each one makes a
+    /// copy of its sub-expression for every possible count.
+    pub bounded_repeats: usize,
+    /// The number of non-greedy repetitions, like `x*?`.
+    pub lazy_repeats: usize,
+    /// The number of capture groups. They are allowed, but a `Regex` can't report what they
+    /// matched.
+    pub capture_groups: usize,
+    /// Whether any literals are case insensitive (the `i` flag).
+    pub case_insensitive: bool,
+    /// Whether any `.` matches new lines (the `s` flag).
+    pub dot_matches_new_line: bool,
+    /// The error from compiling the regular expression, if it failed (for example, because it
+    /// needed too many states).
+    pub compile_error: Option<Error>,
+    /// The size in bytes of the compiled `Regex` (as given by `Regex::to_bytes`), if it compiled.
+    /// This is a good estimate for the memory that it uses.
+    pub compiled_size: Option<usize>,
+}
+
+// Returns the report for `expr`, without the compilation results.
+pub fn features(expr: &Expr) -> PatternReport {
+    let mut ret = PatternReport {
+        text_anchors: false,
+        line_anchors: false,
+        word_boundaries: false,
+        classes: 0,
+        bounded_repeats: 0,
+        lazy_repeats: 0,
+        capture_groups: 0,
+        case_insensitive: false,
+        dot_matches_new_line: false,
+        compile_error: None,
+        compiled_size: None,
+    };
+    ret.add_expr(expr);
+    ret
+}
+
+impl PatternReport {
+    fn add_expr(&mut self, expr: &Expr) {
+        match *expr {
+            Expr::Empty => {},
+            Expr::Literal { casei, .. } => { self.case_insensitive |= casei; },
+            Expr::AnyChar => {
+                self.classes += 1;
+                self.dot_matches_new_line = true;
+            },
+            Expr::AnyCharNoNL | Expr::Class(_) => { self.classes += 1; },
+            Expr::StartText | Expr::EndText => { self.text_anchors = true; },
+            Expr::StartLine | Expr::EndLine => { self.line_anchors = true; },
+            Expr::WordBoundary | Expr::NotWordBoundary => { self.word_boundaries = true; },
+            Expr::Group { ref e, i, .. } => {
+                if i.is_some() {
+                    self.capture_groups += 1;
+                }
+                self.add_expr(e);
+            },
+            Expr::Repeat { ref e, r, greedy } => {
+                if let Repeater::Range { max: Some(_), .. } = r {
+                    self.bounded_repeats += 1;
+                }
+                if !greedy {
+                    self.lazy_repeats += 1;
+                }
+                self.add_expr(e);
+            },
+            Expr::Concat(ref es) | Expr::Alternate(ref es) => {
+                for e in es {
+                    self.add_expr(e);
+                }
+            },
+        }
+    }
+
+    /// Returns true if the regular expression compiled successfully.
+    pub fn is_compilable(&self) -> bool {
+        self.compile_error.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+    use regex::{Regex, RegexOptions};
+
+    #[test]
+    fn analyze() {
+        let opts = RegexOptions::default();
+        let report = Regex::analyze(r"(?i)^foo(\d{2,4})+?.*\bbar", &opts).unwrap();
+        assert!(report.text_anchors && report.word_boundaries && report.case_insensitive);
+        assert!(!report.line_anchors && !report.dot_matches_new_line);
+        assert_eq!(report.classes, 2);
+        assert_eq!(report.bounded_repeats, 1);
+        assert_eq!(report.lazy_repeats, 1);
+        assert_eq!(report.capture_groups, 1);
+        assert!(report.is_compilable());
+        let size = Regex::new(r"(?i)^foo(\d{2,4})+?.*\bbar").unwrap().to_bytes().len();
+        assert_eq!(report.compiled_size, Some(size));
+
+        let report = Regex::analyze(r"(?ms)^a.$(?:b)", &opts).unwrap();
+        assert!(report.line_anchors && report.dot_matches_new_line);
+        assert!(!report.text_anchors && !report.case_insensitive);
+        assert_eq!(report.capture_groups, 0);
+
+        let small = RegexOptions { max_states: 10, ..RegexOptions::default() };
+        let report = Regex::analyze(r"\w{20}", &small).unwrap();
+        assert!(!report.is_compilable());
+        assert!(match report.compile_error {
+            Some(Error::TooManyStates) | Some(Error::DfaBlowup(_)) => true,
+            _ => false,
+        });
+        assert_eq!(report.compiled_size, None);
+
+        assert!(Regex::analyze("a(", &opts).is_err());
+    }
+}
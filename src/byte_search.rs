@@ -0,0 +1,38 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Searching for single bytes.
+//
+// By default this uses the `memchr` crate, which is fast but links to libc. Without the `memchr`
+// feature (for example, when building for wasm), we fall back to simple loops.
+
+#[cfg(feature = "memchr")]
+pub use memchr::{memchr, memrchr};
+
+#[cfg(not(feature = "memchr"))]
+pub fn memchr(needle: u8, haystack: &[u8]) -> Option<usize> {
+    haystack.iter().position(|&b| b == needle)
+}
+
+#[cfg(not(feature = "memchr"))]
+pub fn memrchr(needle: u8, haystack: &[u8]) -> Option<usize> {
+    haystack.iter().rposition(|&b| b == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search() {
+        assert_eq!(memchr(b'a', b"banana"), Some(1));
+        assert_eq!(memrchr(b'a', b"banana"), Some(5));
+        assert_eq!(memchr(b'x', b"banana"), None);
+        assert_eq!(memrchr(b'x', b""), None);
+    }
+}
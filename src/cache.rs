@@ -0,0 +1,142 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use encode::{Decoder, Encode};
+use regex::{Regex, RegexOptions};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// A directory of compiled `Regex`es, so that programs that use the same regular expressions
+/// every time they run only need to compile them once.
+///
+/// The compiled `Regex`es are stored in files whose names are hashes of the regular expression,
+/// the options, and the version of this crate. The cache is only an optimization, so any problem
+/// with it (for example, a missing directory or a corrupted file) just means that the `Regex` is
+/// compiled from scratch.
+#[derive(Clone, Debug)]
+pub struct ProgramCache {
+    dir: PathBuf,
+}
+
+impl ProgramCache {
+    /// Creates a cache that stores its files in `dir`. The directory is created when it is first
+    /// needed.
+    pub fn new<P: AsRef<Path>>(dir: P) -> ProgramCache {
+        ProgramCache { dir: dir.as_ref().to_path_buf() }
+    }
+
+    /// Returns the `Regex` for `re` with the given options, either from the cache or by compiling
+    /// it (and then adding it to the cache).
+    pub fn get_or_compile(&self, re: &str, options: &RegexOptions) -> ::Result<Regex> {
+        let key = cache_key(re, options);
+        let path = self.dir.join(format!("{:016x}.regex", hash(&key)));
+        if let Some(regex) = read_entry(&path, &key) {
+            return Ok(regex);
+        }
+
+        let regex = try!(Regex::new_with_options(re, options));
+        let _ = self.write_entry(&path, &key, &regex);
+        Ok(regex)
+    }
+
+    // Writes the file for a cache entry. We write to a temporary file first, so that no one ever
+    // sees a partially written entry.
+    fn write_entry(&self, path: &Path, key: &[u8], regex: &Regex) -> ::std::io::Result<()> {
+        let mut contents = Vec::new();
+        key.to_vec().encode(&mut contents);
+        contents.extend(regex.to_bytes());
+
+        try!(fs::create_dir_all(&self.dir));
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut file = try!(File::create(&tmp_path));
+            try!(file.write_all(&contents));
+        }
+        fs::rename(&tmp_path, path)
+    }
+}
+
+// Everything that affects the compiled `Regex`. The options are included using their `Debug`
+// representation, which is fine because it can only change with the crate version.
+fn cache_key(re: &str, options: &RegexOptions) -> Vec<u8> {
+    format!("{}\0{:?}\0{}", env!("CARGO_PKG_VERSION"), options, re).into_bytes()
+}
+
+// FNV-1a. Unlike the hashers in the standard library, this is guaranteed to give the same answer
+// every time.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for &b in bytes {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h
+}
+
+// Reads a cache entry, checking that it is for the right key (in case of hash collisions).
+fn read_entry(path: &Path, key: &[u8]) -> Option<Regex> {
+    let mut contents = Vec::new();
+    match File::open(path) {
+        Ok(mut file) => {
+            if file.read_to_end(&mut contents).is_err() {
+                return None;
+            }
+        },
+        Err(_) => { return None; },
+    }
+
+    let mut input = Decoder::new(&contents);
+    let stored_key: Vec<u8> = try_opt!(Vec::decode(&mut input));
+    if stored_key != key {
+        return None;
+    }
+    Regex::from_bytes(input.remaining()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::{Regex, RegexOptions};
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use super::*;
+
+    #[test]
+    fn get_or_compile() {
+        let dir = env::temp_dir().join("regex_dfa_cache_test");
+        let _ = fs::remove_dir_all(&dir);
+        let cache = ProgramCache::new(&dir);
+        let opts = RegexOptions::default();
+
+        let re = cache.get_or_compile(r"\bfoo[0-9]+", &opts).unwrap();
+        assert_eq!(re.find("a foo12"), Some((2, 7)));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // The second time, the regex comes from the file.
+        let cached = cache.get_or_compile(r"\bfoo[0-9]+", &opts).unwrap();
+        assert_eq!(cached.to_bytes(), re.to_bytes());
+        assert_eq!(cached.find("a foo12"), Some((2, 7)));
+
+        // Different options get different entries.
+        let opts2 = RegexOptions { max_states: 1000, ..RegexOptions::default() };
+        cache.get_or_compile(r"\bfoo[0-9]+", &opts2).unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        // A corrupted entry is replaced.
+        for entry in fs::read_dir(&dir).unwrap() {
+            let mut file = File::create(entry.unwrap().path()).unwrap();
+            file.write_all(b"garbage").unwrap();
+        }
+        let re = cache.get_or_compile(r"\bfoo[0-9]+", &opts).unwrap();
+        assert_eq!(re.find("a foo12"), Some((2, 7)));
+        assert!(Regex::from_bytes(b"garbage").is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,200 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use error::Error;
+use regex_syntax::Expr;
+use std::char;
+use std::cmp::{max, Ordering};
+
+/// A set of chars, for building automata (like `Transducer`s) without going through a regular
+/// expression.
+///
+/// The main point of this is to make Unicode's general categories and scripts available: they
+/// have the same names as in the `\p{...}` syntax for regular expressions.
+///
+/// ```rust
+/// use regex_dfa::{CharSet, Transducer};
+///
+/// let greek = CharSet::from_script("Greek").unwrap();
+/// assert!(greek.contains('λ'));
+///
+/// // A transducer that deletes everything except Greek letters.
+/// let mut t = Transducer::new();
+/// t.add_state(true);
+/// t.add_eps(0, 1, "");
+/// for &(lo, hi) in greek.ranges() {
+///     t.add_copy(1, 1, lo, hi);
+/// }
+/// for &(lo, hi) in greek.negated().ranges() {
+///     t.add_transition(1, 1, lo, hi, "");
+/// }
+/// assert_eq!(t.apply("λόγος = logos"), vec!["λόγος"]);
+/// ```
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct CharSet {
+    // Sorted, non-overlapping and non-adjacent inclusive ranges.
+    ranges: Vec<(char, char)>,
+}
+
+// The char after `c`, skipping the surrogates.
+fn next_char(c: char) -> Option<char> {
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        _ => char::from_u32(c as u32 + 1),
+    }
+}
+
+// The char before `c`, skipping the surrogates.
+fn prev_char(c: char) -> Option<char> {
+    match c {
+        '\u{E000}' => Some('\u{D7FF}'),
+        '\0' => None,
+        _ => char::from_u32(c as u32 - 1),
+    }
+}
+
+impl CharSet {
+    /// Returns the set containing the chars between the first and last elements (inclusive) of
+    /// each pair in `ranges`.
+    pub fn from_ranges(ranges: &[(char, char)]) -> CharSet {
+        let mut sorted: Vec<(char, char)> = ranges.iter().cloned().filter(|r| r.0 <= r.1).collect();
+        sorted.sort();
+
+        let mut ret: Vec<(char, char)> = Vec::with_capacity(sorted.len());
+        for (lo, hi) in sorted {
+            if let Some(last) = ret.last_mut() {
+                if next_char(last.1).map_or(true, |n| lo <= n) {
+                    last.1 = max(last.1, hi);
+                    continue;
+                }
+            }
+            ret.push((lo, hi));
+        }
+        CharSet { ranges: ret }
+    }
+
+    /// Returns the set of chars in the given Unicode general category, like "L" (letters) or "Nd"
+    /// (decimal digits).
+    pub fn from_category(name: &str) -> ::Result<CharSet> {
+        CharSet::from_property(name)
+    }
+
+    /// Returns the set of chars in the given Unicode script, like "Greek" or "Han".
+    pub fn from_script(name: &str) -> ::Result<CharSet> {
+        CharSet::from_property(name)
+    }
+
+    // Looks up a set of chars by the name that `\p{...}` uses.
+    fn from_property(name: &str) -> ::Result<CharSet> {
+        let unknown = || Error::UnknownCharSet(name.to_owned());
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(unknown());
+        }
+        match Expr::parse(&format!("\\p{{{}}}", name)) {
+            Ok(Expr::Class(cc)) => {
+                let ranges: Vec<_> = cc.iter().map(|r| (r.start, r.end)).collect();
+                Ok(CharSet::from_ranges(&ranges))
+            },
+            _ => Err(unknown()),
+        }
+    }
+
+    /// Returns the ranges making up this set, in increasing order.
+    pub fn ranges(&self) -> &[(char, char)] {
+        &self.ranges
+    }
+
+    /// Returns true if `c` belongs to this set.
+    pub fn contains(&self, c: char) -> bool {
+        self.ranges.binary_search_by(|&(lo, hi)| {
+            if hi < c {
+                Ordering::Less
+            } else if lo > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        }).is_ok()
+    }
+
+    /// Returns true if this set has no chars in it.
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Returns the set of chars that are in this set or in `other`.
+    pub fn union(&self, other: &CharSet) -> CharSet {
+        let mut ranges = self.ranges.clone();
+        ranges.extend_from_slice(&other.ranges);
+        CharSet::from_ranges(&ranges)
+    }
+
+    /// Returns the set of chars that are not in this set.
+    pub fn negated(&self) -> CharSet {
+        let mut ret = Vec::new();
+        let mut next = Some('\0');
+        for &(lo, hi) in &self.ranges {
+            if let (Some(start), Some(end)) = (next, prev_char(lo)) {
+                if start <= end {
+                    ret.push((start, end));
+                }
+            }
+            next = next_char(hi);
+        }
+        if let Some(start) = next {
+            ret.push((start, char::MAX));
+        }
+        CharSet { ranges: ret }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::char;
+    use super::CharSet;
+
+    #[test]
+    fn from_ranges() {
+        let set = CharSet::from_ranges(&[('x', 'z'), ('a', 'c'), ('d', 'f'), ('b', 'b'),
+                                         ('q', 'p')]);
+        assert_eq!(set.ranges(), &[('a', 'f'), ('x', 'z')]);
+        assert!(set.contains('e'));
+        assert!(!set.contains('g'));
+        assert!(CharSet::from_ranges(&[]).is_empty());
+
+        // The surrogates don't break up ranges.
+        let set = CharSet::from_ranges(&[('\u{D000}', '\u{D7FF}'), ('\u{E000}', '\u{E00F}')]);
+        assert_eq!(set.ranges(), &[('\u{D000}', '\u{E00F}')]);
+    }
+
+    #[test]
+    fn negated() {
+        let set = CharSet::from_ranges(&[('\0', 'a'), ('c', 'c'), ('\u{E000}', '\u{FFFF}')]);
+        let neg = set.negated();
+        assert_eq!(neg.ranges(), &[('b', 'b'), ('d', '\u{D7FF}'), ('\u{10000}', char::MAX)]);
+        assert_eq!(neg.negated(), set);
+        assert_eq!(set.union(&neg).ranges(), &[('\0', char::MAX)]);
+        assert!(set.union(&neg).negated().is_empty());
+    }
+
+    #[test]
+    fn unicode() {
+        let letters = CharSet::from_category("L").unwrap();
+        assert!(letters.contains('a') && letters.contains('λ') && letters.contains('字'));
+        assert!(!letters.contains('1'));
+        assert!(CharSet::from_category("Nd").unwrap().contains('٣'));
+
+        let greek = CharSet::from_script("Greek").unwrap();
+        assert!(greek.contains('Ω'));
+        assert!(!greek.contains('a'));
+
+        assert!(CharSet::from_script("Klingon").is_err());
+        assert!(CharSet::from_category("L}|\\p{N").is_err());
+        assert!(CharSet::from_category("").is_err());
+    }
+}
@@ -0,0 +1,64 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use dfa::Dfa;
+use look::Look;
+use nfa::Accept;
+use quickcheck::{Arbitrary, Gen};
+use range_map::Range;
+use std::cmp::max;
+
+// The number of distinct bytes that arbitrary `Dfa`s and inputs use. It's kept small so that
+// random inputs have a decent chance of following transitions instead of falling off the
+// automaton immediately.
+const ARB_ALPHABET_SIZE: u8 = 4;
+
+// Generates random, well-formed `Dfa`s without going through the regex parser. The first state
+// is always the (anchored) initial state, and every accepting state has a return value.
+impl Arbitrary for Dfa<u8> {
+    fn arbitrary<G: Gen>(g: &mut G) -> Dfa<u8> {
+        let num_states = g.gen_range(1, max(g.size(), 1) + 1);
+        let mut ret = Dfa::new();
+
+        for _ in 0..num_states {
+            let accept = *g.choose(&[Accept::Never, Accept::AtEoi, Accept::Always]).unwrap();
+            let ret_val = if accept == Accept::Never { None } else { Some(g.gen_range(0, 3)) };
+            ret.add_state(accept, ret_val);
+        }
+        for src in 0..num_states {
+            let mut trans = Vec::new();
+            for b in 0..ARB_ALPHABET_SIZE {
+                if g.gen() {
+                    trans.push((Range::new(b'a' + b, b'a' + b), g.gen_range(0, num_states)));
+                }
+            }
+            ret.set_transitions(src, trans.into_iter().collect());
+        }
+
+        ret.init[Look::Boundary.as_usize()] = Some(0);
+        ret
+    }
+}
+
+/// A random input for the `Dfa`s that `Arbitrary` generates.
+///
+/// The bytes all come from the small alphabet that those `Dfa`s have transitions for, so the
+/// inputs usually get further than the first byte.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DfaInput(pub Vec<u8>);
+
+impl Arbitrary for DfaInput {
+    fn arbitrary<G: Gen>(g: &mut G) -> DfaInput {
+        let bytes: Vec<u8> = Arbitrary::arbitrary(g);
+        DfaInput(bytes.into_iter().map(|b| b'a' + b % ARB_ALPHABET_SIZE).collect())
+    }
+
+    fn shrink(&self) -> Box<Iterator<Item=DfaInput>> {
+        Box::new(self.0.shrink().map(DfaInput))
+    }
+}
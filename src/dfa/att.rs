@@ -0,0 +1,239 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Conversion between `Dfa`s and the AT&T text format used by OpenFST and friends.
+//
+// Every non-empty line of the text is either an arc or a final state. An arc is written
+// `src dst label`, optionally followed by an output label (which must be the same as the input
+// label) and a weight. A final state is written `state`, optionally followed by a weight. Fields
+// are separated by tabs or spaces, and weights are ignored. As usual for this format, the start
+// state is the source of the first arc (or, if there are no arcs, the first final state).
+//
+// State ids are arbitrary non-negative integers, and labels are byte values (0 to 255). Since we
+// only deal with deterministic automata, there are no epsilon transitions; in particular, the
+// label 0 is the byte 0.
+
+use dfa::{Dfa, RetTrait};
+use error::Error;
+use look::Look;
+use nfa::{Accept, StateIdx};
+use range_map::Range;
+use std::collections::HashMap;
+use std::iter;
+
+impl<Ret: RetTrait> Dfa<Ret> {
+    /// Writes this `Dfa` in the AT&T text format (see `from_att_text`).
+    ///
+    /// The `Dfa` is treated as a plain automaton, like in `accepts`: we start from the initial
+    /// state for the beginning of the input, and every state that can accept at the end of the
+    /// input is final. Return values are ignored.
+    pub fn to_att_text(&self) -> String {
+        let mut ret = String::new();
+        let start = match self.init_at_start() {
+            Some(s) => s,
+            None => { return ret; },
+        };
+
+        // If the start state has no transitions then nothing else is reachable, and we can't
+        // write any arcs (because the first arc would determine the start state).
+        if self.transitions(start).ranges_values().next().is_none() {
+            if *self.accept(start) != Accept::Never {
+                ret.push_str("0\n");
+            }
+            return ret;
+        }
+
+        // The start state needs to come first, so it gets renumbered to zero.
+        let order: Vec<StateIdx> = iter::once(start)
+            .chain((0..self.num_states()).filter(|&s| s != start))
+            .collect();
+        let mut new_idx = vec![0; self.num_states()];
+        for (i, &s) in order.iter().enumerate() {
+            new_idx[s] = i;
+        }
+
+        for &s in &order {
+            for &(range, tgt) in self.transitions(s).ranges_values() {
+                for b in (range.start as usize)..(range.end as usize + 1) {
+                    ret.push_str(&format!("{}\t{}\t{}\n", new_idx[s], new_idx[tgt], b));
+                }
+            }
+        }
+        for &s in &order {
+            if *self.accept(s) != Accept::Never {
+                ret.push_str(&format!("{}\n", new_idx[s]));
+            }
+        }
+        ret
+    }
+}
+
+// The states that we have seen so far while parsing.
+struct AttStates {
+    // Maps state ids in the text to state indices.
+    ids: HashMap<usize, StateIdx>,
+    finals: Vec<bool>,
+    // For every state, its arcs together with the line they were defined on.
+    arcs: Vec<Vec<(u8, StateIdx, usize)>>,
+}
+
+impl AttStates {
+    fn new() -> AttStates {
+        AttStates {
+            ids: HashMap::new(),
+            finals: Vec::new(),
+            arcs: Vec::new(),
+        }
+    }
+
+    // Returns the index of the state with the given id, adding a new state if necessary.
+    fn state(&mut self, id: usize) -> StateIdx {
+        let next_idx = self.ids.len();
+        let idx = *self.ids.entry(id).or_insert(next_idx);
+        if idx == self.finals.len() {
+            self.finals.push(false);
+            self.arcs.push(Vec::new());
+        }
+        idx
+    }
+}
+
+impl Dfa<()> {
+    /// Reads a `Dfa` from the AT&T text format.
+    ///
+    /// Every line is either an arc `src dst label [output_label [weight]]` or a final state
+    /// `state [weight]`. Labels are byte values, and weights are ignored. The start state is the
+    /// source of the first arc. The resulting `Dfa` starts at the beginning of the input and
+    /// accepts at the end of the input if it is in a final state (so `accepts` returns true
+    /// exactly for the strings that the automaton accepts).
+    pub fn from_att_text(text: &str) -> ::Result<Dfa<()>> {
+        let mut states = AttStates::new();
+
+        for (line_idx, line) in text.lines().enumerate() {
+            let line_num = line_idx + 1;
+            let err = |msg: &str| Error::AttSyntax(line_num, msg.to_owned());
+            let state_id = |field: &str| {
+                field.parse::<usize>().map_err(|_| err("invalid state id"))
+            };
+            let fields: Vec<&str> = line.split(|c| c == '\t' || c == ' ')
+                .filter(|f| !f.is_empty())
+                .collect();
+            if fields.is_empty() {
+                continue;
+            }
+            if fields.len() > 5 {
+                return Err(err("too many fields"));
+            }
+
+            if fields.len() <= 2 {
+                let st = states.state(try!(state_id(fields[0])));
+                states.finals[st] = true;
+            } else {
+                let src = states.state(try!(state_id(fields[0])));
+                let dst = states.state(try!(state_id(fields[1])));
+                let label = try!(fields[2].parse::<u8>().map_err(|_| err("invalid label")));
+                if fields.len() >= 4 && fields[3] != fields[2] {
+                    return Err(err("output label differs from input label"));
+                }
+                states.arcs[src].push((label, dst, line_num));
+            }
+        }
+
+        let mut ret = Dfa::new();
+        for (st, mut st_arcs) in states.arcs.into_iter().enumerate() {
+            if states.finals[st] {
+                ret.add_state(Accept::AtEoi, Some(()));
+            } else {
+                ret.add_state(Accept::Never, None);
+            }
+
+            st_arcs.sort();
+            let mut trans: Vec<(Range<u8>, StateIdx)> = Vec::new();
+            for (label, dst, line_num) in st_arcs {
+                if let Some(&mut (ref mut range, last_dst)) = trans.last_mut() {
+                    if range.end == label {
+                        if last_dst != dst {
+                            let msg = "nondeterministic arc".to_owned();
+                            return Err(Error::AttSyntax(line_num, msg));
+                        }
+                        continue;
+                    } else if range.end as usize + 1 == label as usize && last_dst == dst {
+                        range.end = label;
+                        continue;
+                    }
+                }
+                trans.push((Range::new(label, label), dst));
+            }
+            ret.set_transitions(st, trans.into_iter().collect());
+        }
+
+        if ret.num_states() > 0 {
+            ret.init[Look::Boundary.as_usize()] = Some(0);
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfa::Dfa;
+    use dfa::tests::make_whole;
+    use error::Error;
+
+    #[test]
+    fn from_att_text() {
+        // Accepts "ab*".
+        let dfa = Dfa::from_att_text("3\t7\t97\n7 7 98 98 0.5\n\n7\t0.0\n").unwrap();
+        assert!(dfa.accepts("a".chars()));
+        assert!(dfa.accepts("abbb".chars()));
+        assert!(!dfa.accepts("".chars()));
+        assert!(!dfa.accepts("ba".chars()));
+        assert_eq!(dfa.validate(), Ok(()));
+
+        let dfa = Dfa::from_att_text("0\n").unwrap();
+        assert!(dfa.accepts("".chars()));
+        assert!(!dfa.accepts("a".chars()));
+
+        let dfa = Dfa::from_att_text("").unwrap();
+        assert!(!dfa.accepts("".chars()));
+    }
+
+    #[test]
+    fn from_att_text_errors() {
+        assert!(matches!(Dfa::from_att_text("0 1 97\n0 2 97\n1\n"), Err(Error::AttSyntax(2, _))));
+        assert!(matches!(Dfa::from_att_text("0 1 a\n"), Err(Error::AttSyntax(1, _))));
+        assert!(matches!(Dfa::from_att_text("0 1 97 98\n"), Err(Error::AttSyntax(1, _))));
+        assert!(matches!(Dfa::from_att_text("0 1 256\n"), Err(Error::AttSyntax(1, _))));
+        assert!(matches!(Dfa::from_att_text("0 -1 97\n"), Err(Error::AttSyntax(1, _))));
+        assert!(matches!(Dfa::from_att_text("0 1 2 2 3 4\n"), Err(Error::AttSyntax(1, _))));
+
+        // Repeating an arc is fine.
+        assert!(Dfa::from_att_text("0 1 97\n0 1 97\n1\n").is_ok());
+    }
+
+    #[test]
+    fn to_att_text() {
+        assert_eq!(Dfa::from_att_text("5 2 97\n2 5 98\n2\n").unwrap().to_att_text(),
+                   "0\t1\t97\n1\t0\t98\n1\n");
+    }
+
+    #[test]
+    fn att_round_trip() {
+        let inputs = ["", "a", "ab", "abab", "b", "é", "aé", "xyz", "ü", "éé"];
+        for re in &["a", "(ab)*", "[a-z]+", "é|ü", "a?é*", ""] {
+            let dfa = make_whole(re);
+            let text = dfa.to_att_text();
+            let back = Dfa::from_att_text(&text).unwrap();
+            for input in &inputs {
+                assert_eq!(dfa.accepts(input.chars()), back.accepts(input.chars()));
+            }
+            let again = Dfa::from_att_text(&back.to_att_text()).unwrap();
+            assert_eq!(back.to_att_text(), again.to_att_text());
+        }
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use dfa::{Dfa, RetTrait};
+use nfa::{Accept, StateIdx};
+use range_map::Range;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Debug, Display, Formatter};
+
+/// The structural differences between two `Dfa`s (see `Dfa::diff`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct DfaDiff<Ret> {
+    /// The places where the two automata accept differently (or return different values).
+    pub accepts: Vec<AcceptDiff<Ret>>,
+    /// The transitions that only one of the automata has.
+    pub transitions: Vec<TransitionDiff>,
+}
+
+/// A pair of aligned states that accept differently.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AcceptDiff<Ret> {
+    /// The shortest input that leads to the two states. It is accepted differently by the two
+    /// automata.
+    pub example: Vec<u8>,
+    /// The state in the first automaton (or `None` if the first automaton has already failed).
+    pub left: Option<StateIdx>,
+    /// The state in the second automaton (or `None` if the second automaton has already failed).
+    pub right: Option<StateIdx>,
+    pub left_accept: Accept,
+    pub right_accept: Accept,
+    pub left_ret: Option<Ret>,
+    pub right_ret: Option<Ret>,
+}
+
+/// A range of bytes on which only one of a pair of aligned states has transitions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransitionDiff {
+    /// The shortest input that leads to the two states.
+    pub example: Vec<u8>,
+    pub left: Option<StateIdx>,
+    pub right: Option<StateIdx>,
+    pub bytes: Range<u8>,
+    /// True if the transitions are in the first automaton, false if they are in the second.
+    pub in_left: bool,
+}
+
+impl<Ret> DfaDiff<Ret> {
+    /// Returns true if no differences were found.
+    pub fn is_empty(&self) -> bool {
+        self.accepts.is_empty() && self.transitions.is_empty()
+    }
+}
+
+impl<Ret: Debug> Display for DfaDiff<Ret> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for d in &self.accepts {
+            try!(writeln!(f, "after {:?}: left accepts {:?} ({:?}), right accepts {:?} ({:?})",
+                          String::from_utf8_lossy(&d.example),
+                          d.left_accept, d.left_ret, d.right_accept, d.right_ret));
+        }
+        for d in &self.transitions {
+            try!(writeln!(f, "after {:?}: only {} has transitions on {:?}",
+                          String::from_utf8_lossy(&d.example),
+                          if d.in_left { "left" } else { "right" },
+                          d.bytes));
+        }
+        Ok(())
+    }
+}
+
+type Pair = (Option<StateIdx>, Option<StateIdx>);
+
+// Returns the acceptance condition and return value of `state`, where a missing state never
+// accepts.
+fn accept_ret<Ret: RetTrait>(dfa: &Dfa<Ret>, state: Option<StateIdx>) -> (Accept, Option<Ret>) {
+    match state {
+        Some(s) => (*dfa.accept(s), dfa.ret(s).cloned()),
+        None => (Accept::Never, None),
+    }
+}
+
+// Aligns the states of `left` and `right` by running them side by side (starting from their
+// initial states for the beginning of the input) and compares each pair of aligned states.
+pub fn diff<Ret: RetTrait>(left: &Dfa<Ret>, right: &Dfa<Ret>) -> DfaDiff<Ret> {
+    let mut ret = DfaDiff { accepts: Vec::new(), transitions: Vec::new() };
+    // The shortest input leading to each pair of states that we have seen.
+    let mut examples: HashMap<Pair, Vec<u8>> = HashMap::new();
+    let mut queue = VecDeque::new();
+    let init = (left.init_at_start(), right.init_at_start());
+    if init != (None, None) {
+        examples.insert(init, Vec::new());
+        queue.push_back(init);
+    }
+
+    while let Some(pair) = queue.pop_front() {
+        let example = examples[&pair].clone();
+        let (left_accept, left_ret) = accept_ret(left, pair.0);
+        let (right_accept, right_ret) = accept_ret(right, pair.1);
+        if left_accept != right_accept || left_ret != right_ret {
+            ret.accepts.push(AcceptDiff {
+                example: example.clone(),
+                left: pair.0,
+                right: pair.1,
+                left_accept: left_accept,
+                right_accept: right_accept,
+                left_ret: left_ret,
+                right_ret: right_ret,
+            });
+        }
+
+        // The current run of bytes on which only one side has a transition.
+        let mut run: Option<(Range<u8>, bool)> = None;
+        for b in 0..256u32 {
+            let b = b as u8;
+            let l = pair.0.and_then(|s| left.transitions(s).get(b).cloned());
+            let r = pair.1.and_then(|s| right.transitions(s).get(b).cloned());
+            let side = match (l, r) {
+                (Some(_), None) => Some(true),
+                (None, Some(_)) => Some(false),
+                _ => None,
+            };
+
+            let extends = match (run, side) {
+                (Some((range, in_left)), Some(s)) =>
+                    in_left == s && range.end as u32 + 1 == b as u32,
+                _ => false,
+            };
+            if extends {
+                run.as_mut().unwrap().0.end = b;
+            } else {
+                if let Some((range, in_left)) = run.take() {
+                    ret.transitions.push(TransitionDiff {
+                        example: example.clone(),
+                        left: pair.0,
+                        right: pair.1,
+                        bytes: range,
+                        in_left: in_left,
+                    });
+                }
+                run = side.map(|s| (Range::new(b, b), s));
+            }
+
+            let next = (l, r);
+            if next != (None, None) && !examples.contains_key(&next) {
+                let mut next_example = example.clone();
+                next_example.push(b);
+                examples.insert(next, next_example);
+                queue.push_back(next);
+            }
+        }
+        if let Some((range, in_left)) = run {
+            ret.transitions.push(TransitionDiff {
+                example: example,
+                left: pair.0,
+                right: pair.1,
+                bytes: range,
+                in_left: in_left,
+            });
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use dfa::tests::make_anchored;
+    use nfa::{Accept, Nfa};
+    use range_map::Range;
+    use std::usize;
+
+    #[test]
+    fn diff() {
+        let left = make_anchored("^ab?c$");
+        let right = make_anchored("^abc?$");
+        let diff = left.diff(&right);
+        let examples: Vec<_> = diff.accepts.iter().map(|d| d.example.clone()).collect();
+        assert_eq!(examples, vec![b"ab".to_vec(), b"ac".to_vec()]);
+        assert!(diff.accepts[1].left_accept != Accept::Never);
+        assert_eq!(diff.accepts[1].right_accept, Accept::Never);
+        assert_eq!(diff.accepts[1].right, None);
+
+        let trans = diff.transitions.iter()
+            .find(|d| d.example == b"a")
+            .unwrap();
+        assert_eq!(trans.bytes, Range::new(b'c', b'c'));
+        assert!(trans.in_left);
+        assert!(format!("{}", diff).contains("after \"a\": only left has transitions on"));
+
+        assert!(left.diff(&left).is_empty());
+        assert_eq!(right.diff(&left).accepts.len(), 2);
+    }
+
+    #[test]
+    fn diff_ignores_numbering() {
+        let dfa = Nfa::from_regex("^(ab|cb)+$").unwrap()
+            .remove_looks()
+            .byte_me(usize::MAX).unwrap()
+            .determinize(usize::MAX).unwrap();
+        let min = dfa.clone().optimize();
+        assert!(dfa.num_states() > min.num_states());
+        assert!(dfa.diff(&min).is_empty());
+    }
+}
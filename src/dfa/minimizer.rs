@@ -7,14 +7,21 @@
 // except according to those terms.
 
 use dfa::{Dfa, RetTrait};
+use error::{Error, TimeoutReport};
 use nfa::{Accept, StateIdx, StateSet};
 use range_map::{RangeMultiMap, RangeSet};
 use refinery::Partition;
-use std::collections::{HashSet, HashMap};
+use regex::Stage;
+use std::collections::HashMap;
+use std::time::Instant;
 
 pub struct Minimizer {
     partition: Partition,
-    distinguishers: HashSet<usize>,
+    // The worklist of parts that still need to be used as splitters. We pop from the end, so that
+    // the next distinguisher can be found in constant time.
+    distinguishers: Vec<usize>,
+    // For every part of the partition, whether it is currently in `distinguishers`.
+    in_worklist: Vec<bool>,
     // The reversed transitions of the dfa.
     rev: Vec<RangeMultiMap<u8, StateIdx>>,
 }
@@ -22,35 +29,57 @@ pub struct Minimizer {
 impl Minimizer {
     // Partition the states according to
     // - when they accept,
-    // - what they return if they do sometimes accept, and
+    // - what they return if they do sometimes accept,
+    // - what they are tagged with, and
     // - what set of bytes do we expect to see next.
+    //
+    // The parts are sorted by their smallest state, so that the result doesn't depend on the order
+    // in which the `HashMap` iterates.
     fn initial_partition<Ret: RetTrait>(dfa: &Dfa<Ret>) -> Vec<Vec<StateIdx>> {
-        let mut part: HashMap<(Accept, Option<&Ret>, RangeSet<u8>), Vec<StateIdx>> = HashMap::new();
+        let mut part: HashMap<(Accept, Option<&Ret>, Option<usize>, RangeSet<u8>), Vec<StateIdx>>
+            = HashMap::new();
         for (idx, st) in dfa.states.iter().enumerate() {
             let chars = st.transitions.to_range_set();
-            part.entry((st.accept, dfa.ret(idx), chars)).or_insert_with(Vec::new).push(idx);
+            part.entry((st.accept, dfa.ret(idx), st.tag, chars))
+                .or_insert_with(Vec::new)
+                .push(idx);
         }
-        part.into_iter().map(|x| x.1).collect()
+        // Each part is already sorted, since we added the states in order.
+        let mut ret: Vec<_> = part.into_iter().map(|x| x.1).collect();
+        ret.sort_by_key(|p| p[0]);
+        ret
     }
 
     // Refine the current partition based on the fact that everything in `splitter` is distinct
     // from everything not in it.
     fn refine(&mut self, splitter: &[StateIdx]) {
         let dists = &mut self.distinguishers;
+        let in_worklist = &mut self.in_worklist;
 
         self.partition.refine_with_callback(splitter, |p, int_idx, diff_idx| {
-            if dists.contains(&int_idx) || p.part(diff_idx).len() < p.part(int_idx).len() {
-                dists.insert(diff_idx);
-            } else {
-                dists.insert(int_idx);
+            if in_worklist.len() < p.num_parts() {
+                in_worklist.resize(p.num_parts(), false);
+            }
+
+            // If the part that got split was already waiting to be processed, both halves need to
+            // be processed. Otherwise, it's enough to process the smaller half.
+            let new_dist =
+                if in_worklist[int_idx] || p.part(diff_idx).len() < p.part(int_idx).len() {
+                    diff_idx
+                } else {
+                    int_idx
+                };
+            if !in_worklist[new_dist] {
+                in_worklist[new_dist] = true;
+                dists.push(new_dist);
             }
         });
     }
 
     fn next_distinguisher(&mut self) -> Option<usize> {
-        let maybe_elt = self.distinguishers.iter().next().cloned();
+        let maybe_elt = self.distinguishers.pop();
         if let Some(elt) = maybe_elt {
-            self.distinguishers.remove(&elt);
+            self.in_worklist[elt] = false;
         }
         maybe_elt
     }
@@ -77,20 +106,34 @@ impl Minimizer {
         sets
     }
 
-    fn compute_partition(&mut self) {
+    // Refines the partition until it can't be refined any more, or until `deadline` passes.
+    fn compute_partition(&mut self, deadline: Option<Instant>) -> ::Result<()> {
+        let mut steps = 0;
         while let Some(dist) = self.next_distinguisher() {
-            let sets = self.get_input_sets(dist);
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    return Err(Error::TimedOut(TimeoutReport {
+                        stage: Stage::Minimize,
+                        dfa_states: self.rev.len(),
+                        steps: steps,
+                    }));
+                }
+            }
 
+            let sets = self.get_input_sets(dist);
             for set in &sets {
                 self.refine(set);
             }
+            steps += sets.len();
         }
+        Ok(())
     }
 
-    pub fn minimize<Ret: RetTrait>(dfa: &Dfa<Ret>) -> Dfa<Ret> {
+    pub fn minimize<Ret: RetTrait>(dfa: &Dfa<Ret>, deadline: Option<Instant>)
+    -> ::Result<Dfa<Ret>> {
         let mut min = Minimizer::new(dfa);
 
-        min.compute_partition();
+        try!(min.compute_partition(deadline));
 
         let mut ret = Dfa::new();
 
@@ -111,7 +154,7 @@ impl Minimizer {
         ret.init = dfa.init.iter()
             .map(|x| x.map(|s: StateIdx| old_state_to_new[s]))
             .collect();
-        ret
+        Ok(ret)
     }
 
     fn new<Ret: RetTrait>(dfa: &Dfa<Ret>) -> Minimizer {
@@ -121,17 +164,19 @@ impl Minimizer {
         // According to Hopcroft's algorithm, we're allowed to leave out one of the distinguishers
         // (at least, as long as it isn't a set of accepting states). Choose the one with the
         // most states to leave out.
-        let mut dists: HashSet<usize> = (0..part.num_parts()).collect();
-        let worst = (0..dists.len())
+        let worst = (0..part.num_parts())
             .filter(|i| dfa.states[part.part(*i)[0]].accept == Accept::Never)
             .max_by_key(|i| part.part(*i).len());
+        let dists: Vec<usize> = (0..part.num_parts()).filter(|i| Some(*i) != worst).collect();
+        let mut in_worklist = vec![true; part.num_parts()];
         if let Some(worst) = worst {
-            dists.remove(&worst);
+            in_worklist[worst] = false;
         }
 
         Minimizer {
             partition: part,
             distinguishers: dists,
+            in_worklist: in_worklist,
             rev: dfa.reversed_transitions(),
         }
     }
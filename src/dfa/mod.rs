@@ -6,25 +6,38 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+#[cfg(any(test, feature = "quickcheck"))]
+mod arbitrary;
+mod att;
+mod diff;
 mod trie;
 mod prefix_searcher;
 mod minimizer;
+#[cfg(feature = "serde")]
+mod serialize;
+mod to_regex;
 
 use dfa::minimizer::Minimizer;
 use dfa::prefix_searcher::PrefixSearcher;
+use error::Error;
 use graph::Graph;
 use look::Look;
 use itertools::Itertools;
 use nfa::{Accept, StateIdx};
-use range_map::{RangeMap, RangeMultiMap};
+use range_map::{Range, RangeMap, RangeMultiMap};
 use refinery::Partition;
-use runner::program::TableInsts;
+use runner::program::{Table, TableInsts};
 use std;
-use std::fmt::{Debug, Formatter};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Debug, Formatter};
 use std::hash::Hash;
 use std::mem;
+use std::time::Instant;
 use std::u32;
 
+#[cfg(any(test, feature = "quickcheck"))]
+pub use dfa::arbitrary::DfaInput;
+pub use dfa::diff::{AcceptDiff, DfaDiff, TransitionDiff};
 pub use dfa::prefix_searcher::PrefixPart;
 
 #[derive(Clone, PartialEq, Debug)]
@@ -32,6 +45,8 @@ pub struct State<Ret> {
     pub transitions: RangeMap<u8, StateIdx>,
     pub accept: Accept,
     pub ret: Option<Ret>,
+    // An opaque tag that the minimizer must respect: states with different tags are never merged.
+    pub tag: Option<usize>,
 }
 
 impl<Ret> State<Ret> {
@@ -40,27 +55,102 @@ impl<Ret> State<Ret> {
             transitions: RangeMap::new(),
             accept: accept,
             ret: ret,
+            tag: None,
         }
     }
 }
 
+/// The bounds on the return values of a `Dfa`. It is implemented for every type that satisfies
+/// them.
 pub trait RetTrait: Clone + Copy + Debug + Eq + Hash {}
 impl<T: Clone + Copy + Debug + Eq + Hash> RetTrait for T {}
 
+/// The ways in which a `Dfa` can fail to be well-formed. See `Dfa::validate`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DfaError {
+    /// The `init` vector doesn't have one entry for each `Look`.
+    WrongInitLength(usize),
+    /// The initial state for the given `Look` doesn't exist.
+    InitOutOfRange(Look, StateIdx),
+    /// There is a transition from the first state to the second, which doesn't exist.
+    TargetOutOfRange(StateIdx, StateIdx),
+    /// The transitions out of the given state are not sorted, or they overlap.
+    UnsortedTransitions(StateIdx),
+    /// The given state has a transition on a range of bytes that is empty (because it ends
+    /// before it starts).
+    EmptyTransition(StateIdx),
+    /// The given state accepts, but it has no return value.
+    MissingRet(StateIdx),
+    /// The given state has transitions to two different states on the given byte. See
+    /// `Dfa::map_alphabet`.
+    ConflictingTransitions(StateIdx, u8),
+}
+
+impl fmt::Display for DfaError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        use self::DfaError::*;
+        match *self {
+            WrongInitLength(len) =>
+                write!(f, "expected {} initial states, found {}", Look::num(), len),
+            InitOutOfRange(look, st) =>
+                write!(f, "initial state {} (for {:?}) is out of range", st, look),
+            TargetOutOfRange(src, tgt) =>
+                write!(f, "state {} has a transition to non-existent state {}", src, tgt),
+            UnsortedTransitions(st) =>
+                write!(f, "state {} has overlapping or unsorted transitions", st),
+            EmptyTransition(st) => write!(f, "state {} has a transition on no bytes", st),
+            MissingRet(st) => write!(f, "state {} accepts but has no return value", st),
+            ConflictingTransitions(st, b) =>
+                write!(f, "state {} has conflicting transitions on byte {}", st, b),
+        }
+    }
+}
+
+/// A deterministic automaton over bytes, whose accepting states have return values of type
+/// `Ret`.
+///
+/// The `Dfa`s that `Regex` runs are built from regular expressions, but a `Dfa` can also be built
+/// directly (with `from_words`, `from_att_text`, or `add_state`, `set_transitions` and
+/// `set_init_state`), combined with others (with `product` and `concat`), and checked with
+/// `validate`.
+///
+/// ```rust
+/// use regex_dfa::Dfa;
+///
+/// let dfa = Dfa::from_words(vec!["car", "cat"]);
+/// assert_eq!(dfa.validate(), Ok(()));
+/// assert!(dfa.accepts("cat".chars()));
+/// assert!(!dfa.accepts("ca".chars()));
+/// assert_eq!(dfa.completions("ca", 10), vec!["car".to_owned(), "cat".to_owned()]);
+/// ```
+///
+/// With the `serde` feature, a `Dfa` can be serialized (for example, to JSON) as a pair
+/// `[states, init]`:
+///
+/// - `states` is a list of states. Each state is a triple `[accept, ret, transitions]`, where
+///   `accept` is one of the strings `"never"`, `"at_eoi"` or `"always"`, `ret` is the value that
+///   the state returns (or `null`), and `transitions` is a list of triples `[first, last, target]`
+///   meaning that the bytes from `first` to `last` (inclusive) lead to the state at index
+///   `target`. The transitions are sorted and don't overlap.
+/// - `init` is a list with one entry for every `Look` (in the order `"full"`, `"word_char"`,
+///   `"not_word_char"`, `"new_line"`, `"boundary"`, `"empty"`), giving the index of the initial
+///   state to use after that kind of char (or `null`).
+///
+/// Deserializing fails if the result isn't well-formed (see `validate`).
 #[derive(Clone, PartialEq)]
 pub struct Dfa<Ret: 'static> {
     states: Vec<State<Ret>>,
 
-    /// This is a vector of length `Look::num()` containing all possible starting positions.
-    ///
-    /// `init[Look::Boundary]` is the starting position if we are at the beginning of the
-    /// input.
-    ///
-    /// `init[Look::Full]` is the default starting position.
-    ///
-    /// All other positions in `init` are only used if we are specifically asked to start
-    /// there; this is mainly useful in the forward-backward engine.
-    pub init: Vec<Option<StateIdx>>,
+    // This is a vector of length `Look::num()` containing all possible starting positions.
+    //
+    // `init[Look::Boundary]` is the starting position if we are at the beginning of the
+    // input.
+    //
+    // `init[Look::Full]` is the default starting position.
+    //
+    // All other positions in `init` are only used if we are specifically asked to start
+    // there; this is mainly useful in the forward-backward engine.
+    init: Vec<Option<StateIdx>>,
 }
 
 impl<Ret: RetTrait> Dfa<Ret> {
@@ -77,23 +167,41 @@ impl<Ret: RetTrait> Dfa<Ret> {
         self.states.len()
     }
 
+    /// Adds a state with no transitions, and returns its index.
+    ///
+    /// `ret` is the value that the state returns when it accepts, so it should only be `None` if
+    /// `accept` is `Accept::Never`.
     pub fn add_state(&mut self, accept: Accept, ret: Option<Ret>) -> StateIdx {
         self.states.push(State::new(accept, ret));
         self.states.len() - 1
     }
 
+    /// Replaces the transitions out of the state `from`. The transitions map ranges of bytes to
+    /// the indices of the states that they lead to.
     pub fn set_transitions(&mut self, from: StateIdx, transitions: RangeMap<u8, StateIdx>) {
         self.states[from].transitions = transitions;
     }
 
+    /// Returns the state to start in after the given kind of char, if there is one.
+    ///
+    /// `Look::Boundary` is for the beginning of the input, and `Look::Full` is for anywhere else;
+    /// the other kinds are only used when a search asks for them.
     pub fn init_state(&self, look: Look) -> Option<StateIdx> {
         self.init[look.as_usize()]
     }
 
+    /// Sets (or, with `None`, removes) the state to start in after the given kind of char.
+    pub fn set_init_state(&mut self, look: Look, state: Option<StateIdx>) {
+        self.init[look.as_usize()] = state;
+    }
+
+    /// Returns the state to start in at the beginning of the input, if there is one.
     pub fn init_at_start(&self) -> Option<StateIdx> {
         self.init_state(Look::Boundary)
     }
 
+    /// Returns the state to start in anywhere other than the beginning of the input, if there is
+    /// one.
     pub fn init_otherwise(&self) -> Option<StateIdx> {
         self.init_state(Look::Full)
     }
@@ -103,6 +211,69 @@ impl<Ret: RetTrait> Dfa<Ret> {
         self.init_otherwise().is_none() && self.init_at_start().is_some()
     }
 
+    /// Checks that this `Dfa` is well-formed.
+    ///
+    /// Automata that come out of `Nfa::determinize` are always well-formed, but this is useful
+    /// for checking automata that were built by hand.
+    pub fn validate(&self) -> Result<(), DfaError> {
+        if self.init.len() != Look::num() {
+            return Err(DfaError::WrongInitLength(self.init.len()));
+        }
+        for &look in Look::all() {
+            if let Some(st) = self.init_state(look) {
+                if st >= self.num_states() {
+                    return Err(DfaError::InitOutOfRange(look, st));
+                }
+            }
+        }
+
+        for (idx, st) in self.states.iter().enumerate() {
+            if st.accept != Accept::Never && st.ret.is_none() {
+                return Err(DfaError::MissingRet(idx));
+            }
+
+            let ranges = st.transitions.ranges_values().collect::<Vec<_>>();
+            if ranges.iter().any(|&&(range, _)| range.start > range.end) {
+                return Err(DfaError::EmptyTransition(idx));
+            }
+            for pair in ranges.windows(2) {
+                if pair[0].0.end >= pair[1].0.start {
+                    return Err(DfaError::UnsortedTransitions(idx));
+                }
+            }
+            for &&(_, tgt) in &ranges {
+                if tgt >= self.num_states() {
+                    return Err(DfaError::TargetOutOfRange(idx, tgt));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if this `Dfa` accepts the whole of the given string.
+    ///
+    /// We start at the initial state for the beginning of the input, feed in the UTF-8 encoding of
+    /// every char, and then check whether the state that we end up in accepts at the end of the
+    /// input. This treats the `Dfa` as a plain automaton: return values (and therefore any
+    /// look-ahead that they record) are ignored.
+    pub fn accepts<I: IntoIterator<Item=char>>(&self, chars: I) -> bool {
+        let mut state = match self.init_at_start() {
+            Some(s) => s,
+            None => { return false; },
+        };
+
+        let mut buf = [0u8; 4];
+        for c in chars {
+            for &b in c.encode_utf8(&mut buf).as_bytes() {
+                match self.transitions(state).get(b) {
+                    Some(&next) => { state = next; },
+                    None => { return false; },
+                }
+            }
+        }
+        *self.accept(state) != Accept::Never
+    }
+
     /// Get transitions from a given state.
     pub fn transitions(&self, state: StateIdx) -> &RangeMap<u8, StateIdx> {
         &self.states[state].transitions
@@ -118,6 +289,27 @@ impl<Ret: RetTrait> Dfa<Ret> {
         self.states[state].ret.as_ref()
     }
 
+    /// Attaches a tag to a state.
+    ///
+    /// Tags don't affect what the `Dfa` matches, but `optimize` never merges two states with
+    /// different tags (or a tagged state with an untagged one), and the tags follow their states
+    /// when they are renumbered. This is useful for keeping track of states that mean something
+    /// (for example, "we are in the middle of a number") through minimization.
+    pub fn tag_state(&mut self, state: StateIdx, tag: usize) {
+        self.states[state].tag = Some(tag);
+    }
+
+    /// Returns a map from every tag to the states that have it, in increasing order.
+    pub fn tag_map(&self) -> HashMap<usize, Vec<StateIdx>> {
+        let mut ret = HashMap::new();
+        for (idx, st) in self.states.iter().enumerate() {
+            if let Some(tag) = st.tag {
+                ret.entry(tag).or_insert_with(Vec::new).push(idx);
+            }
+        }
+        ret
+    }
+
     /// Changes the return value.
     pub fn map_ret<T: RetTrait, F: FnMut(Ret) -> T>(self, mut f: F) -> Dfa<T> {
         let mut ret: Dfa<T> = Dfa::new();
@@ -128,17 +320,267 @@ impl<Ret: RetTrait> Dfa<Ret> {
                 transitions: st.transitions,
                 accept: st.accept,
                 ret: st.ret.map(&mut f),
+                tag: st.tag,
             };
             ret.states.push(new_st);
         }
         ret
     }
 
+    /// Changes the bytes that the transitions are labelled with.
+    ///
+    /// Every transition on the byte `b` becomes a transition on `f(b)`, or it is removed if
+    /// `f(b)` is `None`. The states, initial states and return values are unchanged.
+    ///
+    /// If `f` maps two bytes to the same byte and some state has transitions on them to two
+    /// different states, the result would not be deterministic; in that case, we return
+    /// `DfaError::ConflictingTransitions`.
+    pub fn map_alphabet<F: FnMut(u8) -> Option<u8>>(&self, mut f: F)
+    -> Result<Dfa<Ret>, DfaError> {
+        let byte_map: Vec<Option<u8>> = (0..256u32).map(|b| f(b as u8)).collect();
+        let mut ret = self.clone();
+
+        for (idx, st) in ret.states.iter_mut().enumerate() {
+            let mut targets: Vec<Option<StateIdx>> = vec![None; 256];
+            for (b, &tgt) in st.transitions.keys_values() {
+                if let Some(new_b) = byte_map[b as usize] {
+                    let old = targets[new_b as usize];
+                    if old.is_some() && old != Some(tgt) {
+                        return Err(DfaError::ConflictingTransitions(idx, new_b));
+                    }
+                    targets[new_b as usize] = Some(tgt);
+                }
+            }
+
+            st.transitions = transitions_from_targets(&targets);
+        }
+        Ok(ret)
+    }
+
+    /// Returns the product of this `Dfa` and `other`.
+    ///
+    /// Each state of the product corresponds to a pair of states, one from each `Dfa`; either of
+    /// them may be missing, if that `Dfa` has already failed to match. Whether a product state
+    /// accepts is decided by `combine`, which is given the `Accept`s of the two states (with
+    /// `Accept::Never` standing in for a missing state). For example, taking the minimum gives the
+    /// intersection of the two `Dfa`s and taking the maximum gives their union. An accepting
+    /// product state returns the return values of both of its states.
+    pub fn product<T, F>(&self, other: &Dfa<T>, combine: F) -> Dfa<(Option<Ret>, Option<T>)>
+    where T: RetTrait, F: Fn(&Accept, &Accept) -> Accept {
+        let mut prod = Dfa::new();
+        // The pairs of states making up the product states, and a map back to the product states.
+        let mut pairs: Vec<(Option<StateIdx>, Option<StateIdx>)> = Vec::new();
+        let mut ids = HashMap::new();
+
+        let mut init = Vec::with_capacity(self.init.len());
+        for (&a, &b) in self.init.iter().zip(other.init.iter()) {
+            if a.is_some() || b.is_some() {
+                let next_id = pairs.len();
+                let id = *ids.entry((a, b)).or_insert(next_id);
+                if id == next_id {
+                    pairs.push((a, b));
+                }
+                init.push(Some(id));
+            } else {
+                init.push(None);
+            }
+        }
+
+        let mut idx = 0;
+        while idx < pairs.len() {
+            let (a, b) = pairs[idx];
+            let accept = combine(&a.map_or(Accept::Never, |s| self.states[s].accept),
+                                 &b.map_or(Accept::Never, |s| other.states[s].accept));
+            let ret = if accept != Accept::Never {
+                Some((a.and_then(|s| self.states[s].ret), b.and_then(|s| other.states[s].ret)))
+            } else {
+                None
+            };
+            prod.add_state(accept, ret);
+
+            let mut targets: Vec<Option<StateIdx>> = vec![None; 256];
+            for byte in 0..256 {
+                let next = (a.and_then(|s| self.states[s].transitions.get(byte as u8).cloned()),
+                            b.and_then(|s| other.states[s].transitions.get(byte as u8).cloned()));
+                if next.0.is_some() || next.1.is_some() {
+                    let next_id = pairs.len();
+                    let id = *ids.entry(next).or_insert(next_id);
+                    if id == next_id {
+                        pairs.push(next);
+                    }
+                    targets[byte] = Some(id);
+                }
+            }
+            prod.set_transitions(idx, transitions_from_targets(&targets));
+            idx += 1;
+        }
+
+        prod.init = init;
+        prod
+    }
+
+    /// Returns a `Dfa` that matches a match of this `Dfa` followed immediately by a match of
+    /// `other`, and returns the return values of `other`.
+    ///
+    /// Wherever this `Dfa` accepts, `other` starts in its initial state for the beginning of the
+    /// input, as if its input began there. A state that only accepts at the end of the input (as
+    /// in `a$`) can't be followed by anything, so it only leads to a match if `other` matches the
+    /// empty string. Like `accepts`, this treats both `Dfa`s as plain automata, without looking
+    /// at any look-ahead recorded in their return values.
+    ///
+    /// Each state of the result corresponds to a state of this `Dfa` and a set of states of
+    /// `other`, so there can be many of them; this fails with `Error::TooManyStates` if there
+    /// would be more than `max_states`.
+    pub fn concat<T: RetTrait>(&self, other: &Dfa<T>, max_states: usize) -> ::Result<Dfa<T>> {
+        let other_init = other.init_at_start();
+        // Adds the initial state of `other` if `a` accepts, and puts the pair in a canonical form
+        // (or returns `None` if neither `Dfa` can match from here).
+        let normalize = |a: Option<StateIdx>, mut bs: Vec<StateIdx>| {
+            if let (Some(a), Some(b)) = (a, other_init) {
+                if self.states[a].accept == Accept::Always {
+                    bs.push(b);
+                }
+            }
+            bs.sort();
+            bs.dedup();
+            if a.is_none() && bs.is_empty() { None } else { Some((a, bs)) }
+        };
+
+        let mut cat = Dfa::new();
+        let mut pairs: Vec<(Option<StateIdx>, Vec<StateIdx>)> = Vec::new();
+        let mut ids = HashMap::new();
+
+        let mut init = Vec::with_capacity(self.init.len());
+        for &a in &self.init {
+            init.push(normalize(a, Vec::new()).map(|pair| {
+                let next_id = pairs.len();
+                let id = *ids.entry(pair.clone()).or_insert(next_id);
+                if id == next_id {
+                    pairs.push(pair);
+                }
+                id
+            }));
+        }
+
+        let mut idx = 0;
+        while idx < pairs.len() {
+            if pairs.len() > max_states {
+                return Err(Error::TooManyStates);
+            }
+
+            let (a, bs) = pairs[idx].clone();
+            let mut accept = Accept::Never;
+            let mut ret = None;
+            for &b in &bs {
+                if other.states[b].accept > accept {
+                    accept = other.states[b].accept;
+                    ret = other.states[b].ret;
+                }
+            }
+            if let (Some(a), Some(b)) = (a, other_init) {
+                let at_eoi = self.states[a].accept == Accept::AtEoi;
+                let empty_match = other.states[b].accept != Accept::Never;
+                if at_eoi && empty_match && accept == Accept::Never {
+                    accept = Accept::AtEoi;
+                    ret = other.states[b].ret;
+                }
+            }
+            cat.add_state(accept, ret);
+
+            let mut targets: Vec<Option<StateIdx>> = vec![None; 256];
+            for byte in 0..256 {
+                let next_a = a.and_then(|s| self.states[s].transitions.get(byte as u8).cloned());
+                let next_bs = bs.iter()
+                    .filter_map(|&s| other.states[s].transitions.get(byte as u8).cloned())
+                    .collect();
+                if let Some(next) = normalize(next_a, next_bs) {
+                    let next_id = pairs.len();
+                    let id = *ids.entry(next.clone()).or_insert(next_id);
+                    if id == next_id {
+                        pairs.push(next);
+                    }
+                    targets[byte] = Some(id);
+                }
+            }
+            cat.set_transitions(idx, transitions_from_targets(&targets));
+            idx += 1;
+        }
+
+        cat.init = init;
+        Ok(cat)
+    }
+
     /// Returns an equivalent DFA with a minimal number of states.
     ///
-    /// Uses Hopcroft's algorithm.
+    /// Uses Hopcroft's algorithm. Since the minimal DFA is unique, this already shares common
+    /// suffixes as much as possible (two states are merged whenever everything that can follow
+    /// them is the same), so there is nothing to be gained from also minimizing the reversed
+    /// automaton (as in Brzozowski's algorithm).
     fn minimize(&self) -> Dfa<Ret> {
-        Minimizer::minimize(self)
+        Minimizer::minimize(self, None).expect("BUG: minimizing without a deadline can't time out")
+    }
+
+    // Returns a vector with `true` for every state from which it is possible to reach an accepting
+    // state.
+    fn live_states(&self) -> Vec<bool> {
+        let rev = self.reversed_transitions();
+        let mut live = vec![false; self.num_states()];
+        let mut stack: Vec<StateIdx> = (0..self.num_states())
+            .filter(|&s| self.states[s].accept != Accept::Never)
+            .collect();
+        for &s in &stack {
+            live[s] = true;
+        }
+        while let Some(s) = stack.pop() {
+            for &(_, src) in rev[s].ranges_values() {
+                if !live[src] {
+                    live[src] = true;
+                    stack.push(src);
+                }
+            }
+        }
+        live
+    }
+
+    /// Removes all the states that can't be reached from an initial state, and all the states
+    /// that can't reach an accepting state.
+    ///
+    /// Transitions into removed states are deleted, so instead of wandering forever through
+    /// "sink" states that can never accept, the automaton fails as soon as a match becomes
+    /// impossible. If an initial state is removed, that initial state is set to `None`. The
+    /// remaining states keep their relative order.
+    ///
+    /// This doesn't change the language of the automaton. `minimize` does this (and more)
+    /// anyway, but this is much cheaper and it is useful on its own for automata that were built
+    /// by hand.
+    pub fn trim(&mut self) {
+        let n = self.num_states();
+        let mut keep = self.live_states();
+        let mut reachable = vec![false; n];
+        for s in self.dfs_order(self.init.iter().filter_map(|x| *x)) {
+            reachable[s] = true;
+        }
+
+        let mut state_map: Vec<Option<StateIdx>> = vec![None; n];
+        let mut num_kept = 0;
+        for s in 0..n {
+            keep[s] = keep[s] && reachable[s];
+            if keep[s] {
+                state_map[s] = Some(num_kept);
+                num_kept += 1;
+            }
+        }
+
+        let old_states = mem::replace(&mut self.states, Vec::with_capacity(num_kept));
+        for (idx, mut st) in old_states.into_iter().enumerate() {
+            if keep[idx] {
+                st.transitions.retain_values(|t| keep[*t]);
+                st.transitions.map_values(|t| state_map[*t].unwrap());
+                self.states.push(st);
+            }
+        }
+        let init: Vec<_> = self.init.iter().map(|x| x.and_then(|s| state_map[s])).collect();
+        self.init = init;
     }
 
     /// Returns the transitions of this automaton, reversed.
@@ -154,15 +596,272 @@ impl<Ret: RetTrait> Dfa<Ret> {
         ret
     }
 
-    /// Returns a set of strings that match the beginning of this `Dfa`.
+    /// Returns the bytes that can begin an input accepted by this `Dfa`, starting from the initial
+    /// state for the beginning of the input. The bytes are returned in increasing order.
+    ///
+    /// If the initial state accepts (i.e. if this `Dfa` accepts the empty input), every byte is
+    /// returned.
+    pub fn start_bytes(&self) -> Vec<u8> {
+        self.start_bytes_after(Look::Boundary)
+    }
+
+    /// Like `start_bytes`, but for the matches that begin in the initial state for `look`.
+    pub fn start_bytes_after(&self, look: Look) -> Vec<u8> {
+        match self.init_state(look) {
+            None => Vec::new(),
+            Some(init) => if self.states[init].accept != Accept::Never {
+                (0..256u32).map(|b| b as u8).collect()
+            } else {
+                self.transitions(init).keys_values().map(|x| x.0).collect()
+            },
+        }
+    }
+
+    /// Returns the length (in bytes) of the shortest input that this `Dfa` accepts, starting from
+    /// the initial state for the beginning of the input.
+    ///
+    /// Returns `None` if this `Dfa` doesn't accept anything.
+    pub fn min_match_len(&self) -> Option<usize> {
+        let init = match self.init_at_start() {
+            Some(s) => s,
+            None => { return None; },
+        };
+
+        // A breadth-first search finds the accepting states in order of their distance from init.
+        let mut dist = vec![None; self.num_states()];
+        let mut queue = VecDeque::new();
+        dist[init] = Some(0);
+        queue.push_back(init);
+        while let Some(s) = queue.pop_front() {
+            let d = dist[s].unwrap();
+            if self.states[s].accept != Accept::Never {
+                return Some(d);
+            }
+            for &(_, t) in self.transitions(s).ranges_values() {
+                if dist[t].is_none() {
+                    dist[t] = Some(d + 1);
+                    queue.push_back(t);
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the length (in bytes) of the longest input that this `Dfa` accepts, starting from
+    /// the initial state for the beginning of the input.
+    ///
+    /// Returns `None` if this `Dfa` doesn't accept anything (just like `min_match_len`), and
+    /// `Some(None)` if there is no bound on the length of accepted inputs.
+    pub fn max_match_len(&self) -> Option<Option<usize>> {
+        let init = match self.init_at_start() {
+            Some(s) => s,
+            None => { return None; },
+        };
+        let n = self.num_states();
+
+        // The only states that matter are the ones that we can reach from `init` and that can
+        // reach an accepting state. Call those states "useful".
+        let mut useful = self.live_states();
+        let mut reachable = vec![false; n];
+        for s in self.dfs_order(Some(init).into_iter()) {
+            reachable[s] = true;
+        }
+        for (u, &r) in useful.iter_mut().zip(&reachable) {
+            *u = *u && r;
+        }
+        if !useful[init] {
+            return None;
+        }
+
+        // Visit the useful states in topological order, finding the longest path to each one. If
+        // we can't visit them all, there's a cycle and so there are arbitrarily long matches.
+        let mut in_degree = vec![0; n];
+        for s in (0..n).filter(|&s| useful[s]) {
+            for &(_, t) in self.transitions(s).ranges_values() {
+                if useful[t] {
+                    in_degree[t] += 1;
+                }
+            }
+        }
+        let mut longest = vec![0; n];
+        let mut ready = if in_degree[init] == 0 { vec![init] } else { Vec::new() };
+        let mut num_visited = 0;
+        let mut ret = 0;
+        while let Some(s) = ready.pop() {
+            num_visited += 1;
+            if self.states[s].accept != Accept::Never {
+                ret = std::cmp::max(ret, longest[s]);
+            }
+            for &(_, t) in self.transitions(s).ranges_values() {
+                if useful[t] {
+                    longest[t] = std::cmp::max(longest[t], longest[s] + 1);
+                    in_degree[t] -= 1;
+                    if in_degree[t] == 0 {
+                        ready.push(t);
+                    }
+                }
+            }
+        }
+
+        if num_visited == useful.iter().filter(|&&u| u).count() {
+            Some(Some(ret))
+        } else {
+            Some(None)
+        }
+    }
+
+    /// Finds the cheapest way to split `input` into pieces that are each accepted by this `Dfa`.
+    ///
+    /// Every piece is run from the initial state for the beginning of the input, and its cost is
+    /// `cost(ret)`, where `ret` is the return value of the state that accepted it. Since the
+    /// minimizer never merges states with different return values, the costs are preserved by
+    /// `optimize`. A state that only accepts at the end of the input can only end the last piece,
+    /// and empty pieces are never used.
+    ///
+    /// Returns the total cost, together with the end offsets of the pieces, or `None` if `input`
+    /// can't be split up at all. If there are several cheapest ways to split `input`, we prefer
+    /// ones whose pieces start earlier.
+    pub fn min_cost_match<F: Fn(&Ret) -> u64>(&self, input: &[u8], cost: F)
+    -> Option<(u64, Vec<usize>)> {
+        let init = match self.init_at_start() {
+            Some(s) => s,
+            None => { return None; },
+        };
+
+        // `best[i]` is the cost of the cheapest way to split up `input[..i]`, together with the
+        // start of the last piece.
+        let mut best: Vec<Option<(u64, usize)>> = vec![None; input.len() + 1];
+        best[0] = Some((0, 0));
+        for start in 0..input.len() {
+            let start_cost = match best[start] {
+                Some((c, _)) => c,
+                None => { continue; },
+            };
+
+            let mut state = init;
+            for (i, &b) in input[start..].iter().enumerate() {
+                state = match self.transitions(state).get(b) {
+                    Some(&next) => next,
+                    None => { break; },
+                };
+
+                let end = start + i + 1;
+                let accepts = match *self.accept(state) {
+                    Accept::Always => true,
+                    Accept::AtEoi => end == input.len(),
+                    Accept::Never => false,
+                };
+                if accepts {
+                    let c = start_cost.saturating_add(cost(self.ret(state).unwrap()));
+                    if best[end].map_or(true, |(old, _)| c < old) {
+                        best[end] = Some((c, start));
+                    }
+                }
+            }
+        }
+
+        let total = match best[input.len()] {
+            Some((c, _)) => c,
+            None => { return None; },
+        };
+        let mut ends = Vec::new();
+        let mut end = input.len();
+        while end > 0 {
+            ends.push(end);
+            end = best[end].unwrap().1;
+        }
+        ends.reverse();
+        Some((total, ends))
+    }
+
+    /// Returns (at most `limit` of) the words that start with `prefix` and are accepted by this
+    /// `Dfa`, in lexicographic order.
+    ///
+    /// This is meant for autocompletion with automata that accept finitely many words (like the
+    /// ones from `from_words`). If there are infinitely many completions then there might not be
+    /// a lexicographically first one, so we only return the ones that don't visit any state twice
+    /// after reading `prefix`. Completions that aren't valid UTF-8 are skipped.
+    pub fn completions(&self, prefix: &str, limit: usize) -> Vec<String> {
+        let mut ret = Vec::new();
+        let mut state = match self.init_at_start() {
+            Some(s) => s,
+            None => { return ret; },
+        };
+        for b in prefix.bytes() {
+            state = match self.transitions(state).get(b) {
+                Some(&next) => next,
+                None => { return ret; },
+            };
+        }
+
+        let live = self.live_states();
+        let mut word = prefix.as_bytes().to_vec();
+        let mut on_path = vec![false; self.num_states()];
+        self.completions_from(state, &live, &mut on_path, &mut word, limit, &mut ret);
+        ret
+    }
+
+    // Adds the completions of `word` to `ret`, assuming that `word` leads to `state`.
+    fn completions_from(&self, state: StateIdx, live: &[bool], on_path: &mut [bool],
+                        word: &mut Vec<u8>, limit: usize, ret: &mut Vec<String>) {
+        if ret.len() >= limit || !live[state] || on_path[state] {
+            return;
+        }
+        if self.states[state].accept != Accept::Never {
+            if let Ok(s) = String::from_utf8(word.clone()) {
+                ret.push(s);
+            }
+        }
+
+        on_path[state] = true;
+        for &(range, tgt) in self.transitions(state).ranges_values() {
+            for b in (range.start as u32)..(range.end as u32 + 1) {
+                word.push(b as u8);
+                self.completions_from(tgt, live, on_path, word, limit, ret);
+                word.pop();
+            }
+        }
+        on_path[state] = false;
+    }
+
+    /// Compares this `Dfa` with `other`, for example to review how a change to a regular
+    /// expression changed its automaton.
     ///
-    /// If the set is non-empty, every match of this `Dfa` is guaranteed to start with one of these
-    /// strings.
-    pub fn prefix_strings(&self) -> Vec<PrefixPart> {
+    /// The states of the two automata are aligned by running them side by side on every input,
+    /// starting from their initial states for the beginning of the input. Every aligned pair that
+    /// accepts differently gives a (shortest) input that the two automata treat differently, and
+    /// we also report the transitions that exist in only one of each aligned pair. The state
+    /// numbering doesn't matter, so a `Dfa` never differs from its optimized version. (However,
+    /// transitions into states that can never accept do count as differences.)
+    pub fn diff(&self, other: &Dfa<Ret>) -> DfaDiff<Ret> {
+        diff::diff(self, other)
+    }
+
+    /// Converts this `Dfa` back into a regular expression, or returns `None` if it accepts nothing.
+    ///
+    /// The regular expression matches (when anchored at both ends) exactly the inputs that
+    /// `accepts` would accept; return values are ignored. Since a `Dfa` works on bytes, each char
+    /// in the result stands for a single byte: anything other than an ASCII letter or digit is
+    /// written as `\xNN`. For automata that only ever see ASCII, this is the usual meaning. It is
+    /// produced by state elimination, so it can be much longer than the original expression.
+    pub fn to_regex(&self) -> Option<String> {
+        to_regex::to_regex(self)
+    }
+
+    // Returns a set of strings that match the beginning of this `Dfa`.
+    //
+    // If the set is non-empty, every match of this `Dfa` is guaranteed to start with one of these
+    // strings.
+    pub(crate) fn prefix_strings(&self) -> Vec<PrefixPart> {
         // It might seem silly to look for prefixes starting at the anchored state, but it's useful
         // for forward-backward matching. In cases where the regex is honestly anchored, we won't
         // ask to make a prefix anyway.
-        if let Some(state) = self.init_state(Look::Boundary) {
+        self.prefix_strings_after(Look::Boundary)
+    }
+
+    // Like `prefix_strings`, but for the matches that begin in the initial state for `look`.
+    pub(crate) fn prefix_strings_after(&self, look: Look) -> Vec<PrefixPart> {
+        if let Some(state) = self.init_state(look) {
             PrefixSearcher::extract(self, state)
         } else {
             Vec::new()
@@ -175,6 +874,40 @@ impl<Ret: RetTrait> Dfa<Ret> {
     }
     */
 
+    /// Returns the coarsest partition of the bytes into ranges such that every state treats all
+    /// the bytes in a range in the same way.
+    ///
+    /// The ranges are returned in increasing order, and together they cover every byte.
+    pub fn alphabet_partition(&self) -> Vec<Range<u8>> {
+        // cuts[b] is true if some state treats b differently from b - 1.
+        let mut cuts = vec![false; 256];
+        let mut targets = vec![None; 256];
+        for st in &self.states {
+            for t in &mut targets {
+                *t = None;
+            }
+            for (b, &tgt) in st.transitions.keys_values() {
+                targets[b as usize] = Some(tgt);
+            }
+            for b in 1..256 {
+                if targets[b] != targets[b - 1] {
+                    cuts[b] = true;
+                }
+            }
+        }
+
+        let mut ret = Vec::new();
+        let mut start = 0;
+        for b in 1..256 {
+            if cuts[b] {
+                ret.push(Range::new(start as u8, (b - 1) as u8));
+                start = b;
+            }
+        }
+        ret.push(Range::new(start as u8, 255));
+        ret
+    }
+
     // Finds the bytes that are treated equivalently by this Dfa.
     //
     // Returns a Vec of length 256 such that vec[i] == vec[j] when i and j are two equivalent
@@ -205,8 +938,10 @@ impl<Ret: RetTrait> Dfa<Ret> {
         (ret, 32 - size.leading_zeros())
     }
 
-    /// Compiles this `Dfa` into instructions for execution.
-    pub fn compile(&self) -> TableInsts<Ret> {
+    // Compiles this `Dfa` into instructions for execution.
+    //
+    // If there are at most 255 states, the transition table stores them as `u8`s.
+    pub(crate) fn compile(&self) -> TableInsts<Ret> {
         let (byte_class, log_num_classes) = self.byte_equivalence_classes();
 
         let mut table = vec![u32::MAX; self.num_states() << log_num_classes];
@@ -224,13 +959,16 @@ impl<Ret: RetTrait> Dfa<Ret> {
             }
         }
 
-        TableInsts {
+        let mut ret = TableInsts {
             log_num_classes: log_num_classes,
             byte_class: byte_class,
             accept: accept,
             accept_at_eoi: accept_at_eoi,
-            table: table,
-        }
+            table: Table::from_targets(table, self.num_states()),
+            loops: Vec::new(),
+        };
+        ret.find_loops();
+        ret
     }
 
     /// Finds an equivalent DFA with the minimal number of states.
@@ -240,21 +978,28 @@ impl<Ret: RetTrait> Dfa<Ret> {
         ret
     }
 
-    /// Deletes any transitions that return to the initial state.
-    ///
-    /// This results in a new Dfa with the following properties:
-    /// - if the original Dfa has a match then the new Dfa also has a match that ends in the same
-    ///   position (and vice versa), and
-    /// - the new Dfa doesn't need to backtrack to find matches: if it fails then it can be
-    ///   restarted at the same position it failed in.
-    ///
-    /// The reason for this method is that it makes prefixes more effective: where the original Dfa
-    /// would just loop back to the start state, the new Dfa will signal a failure. Then we can use
-    /// a `Prefix` to scan ahead for a good place to resume matching.
-    ///
-    /// # Panics
-    /// - if `self` is not anchored.
-    pub fn cut_loop_to_init(mut self) -> Dfa<Ret> {
+    // Like `optimize`, but gives up with `Error::TimedOut` if minimization is still running when
+    // `deadline` passes.
+    pub(crate) fn optimize_until(self, deadline: Option<Instant>) -> ::Result<Dfa<Ret>> {
+        let mut ret = try!(Minimizer::minimize(&self, deadline));
+        ret.sort_states();
+        Ok(ret)
+    }
+
+    // Deletes any transitions that return to the initial state.
+    //
+    // This results in a new Dfa with the following properties:
+    // - if the original Dfa has a match then the new Dfa also has a match that ends in the same
+    //   position (and vice versa), and
+    // - the new Dfa doesn't need to backtrack to find matches: if it fails then it can be
+    //   restarted at the same position it failed in.
+    //
+    // The reason for this method is that it makes prefixes more effective: where the original Dfa
+    // would just loop back to the start state, the new Dfa will signal a failure. Then we can use
+    // a `Prefix` to scan ahead for a good place to resume matching.
+    //
+    // Panics if `self` is not anchored.
+    pub(crate) fn cut_loop_to_init(mut self) -> Dfa<Ret> {
         if !self.is_anchored() {
             panic!("only anchored Dfas can be cut");
         }
@@ -278,8 +1023,8 @@ impl<Ret: RetTrait> Dfa<Ret> {
     /// Sorts states in depth-first alphabetical order.
     ///
     /// This has the following advantages:
-    /// - the construction of a `Dfa` becomes deterministic: without sorting, the states aren't in
-    ///   deterministic order because `minimize` using hashing.
+    /// - the state numbering is canonical: two `Dfa`s that are the same up to renaming their
+    ///   states will be identical after sorting.
     /// - better locality: after sorting, many transitions just go straight to the next state.
     /// - we prune unreachable states.
     fn sort_states(&mut self) {
@@ -387,6 +1132,109 @@ impl<Ret: RetTrait> Dfa<Ret> {
     */
 }
 
+impl Dfa<(Look, u8)> {
+    /// Returns the minimal anchored `Dfa` that accepts exactly the given words.
+    ///
+    /// This is much faster than compiling a regular expression that is an alternation of all the
+    /// words: we sort the words and then build the minimal automaton incrementally, using the
+    /// algorithm of Daciuk, Mihov, Watson and Watson. At every point, all of the states except
+    /// the ones on the path of the last word we added are already minimized, and we keep those in
+    /// a register so that new words can share their suffixes with old ones.
+    pub fn from_words<'a, I: IntoIterator<Item=&'a str>>(words: I) -> Dfa<(Look, u8)> {
+        // While we're building the automaton, each state is a flag saying whether it accepts and
+        // a list of transitions (sorted by byte). The first state is the initial one.
+        type Node = (bool, Vec<(u8, usize)>);
+
+        // Minimizes the states in `path` after the first `len` bytes, by replacing them with
+        // equivalent states from `register` (or adding them to `register`, if there aren't any).
+        fn minimize_path(nodes: &mut Vec<Node>, register: &mut HashMap<Node, usize>,
+                         path: &mut Vec<usize>, len: usize) {
+            while path.len() > len + 1 {
+                let child = path.pop().unwrap();
+                let parent = *path.last().unwrap();
+                let existing = register.get(&nodes[child]).cloned();
+                match existing {
+                    Some(idx) => { nodes[parent].1.last_mut().unwrap().1 = idx; },
+                    None => { register.insert(nodes[child].clone(), child); },
+                }
+            }
+        }
+
+        let mut words: Vec<&str> = words.into_iter().collect();
+        words.sort();
+        words.dedup();
+
+        let mut nodes: Vec<Node> = vec![(false, Vec::new())];
+        let mut register = HashMap::new();
+        // The states that we visit when reading the last word that we added.
+        let mut path = vec![0];
+        let mut prev: &[u8] = &[];
+        for word in words {
+            let word = word.as_bytes();
+            let common = word.iter().zip(prev).take_while(|&(a, b)| a == b).count();
+            minimize_path(&mut nodes, &mut register, &mut path, common);
+
+            for &b in &word[common..] {
+                let next = nodes.len();
+                nodes.push((false, Vec::new()));
+                nodes[*path.last().unwrap()].1.push((b, next));
+                path.push(next);
+            }
+            nodes[*path.last().unwrap()].0 = true;
+            prev = word;
+        }
+        minimize_path(&mut nodes, &mut register, &mut path, 0);
+
+        // Some of the nodes were replaced by equivalent ones, so we only keep the reachable ones.
+        let mut ret = Dfa::new();
+        let mut old_to_new = vec![None; nodes.len()];
+        let mut queue = VecDeque::new();
+        let mut num_kept = 1;
+        old_to_new[0] = Some(0);
+        queue.push_back(0);
+        while let Some(old) = queue.pop_front() {
+            let accept = if nodes[old].0 { Accept::Always } else { Accept::Never };
+            let ret_val = if nodes[old].0 { Some((Look::Full, 0)) } else { None };
+            ret.add_state(accept, ret_val);
+            for &(_, tgt) in &nodes[old].1 {
+                if old_to_new[tgt].is_none() {
+                    old_to_new[tgt] = Some(num_kept);
+                    num_kept += 1;
+                    queue.push_back(tgt);
+                }
+            }
+        }
+        for (old, node) in nodes.iter().enumerate() {
+            if let Some(new) = old_to_new[old] {
+                let mut targets = vec![None; 256];
+                for &(b, tgt) in &node.1 {
+                    targets[b as usize] = old_to_new[tgt];
+                }
+                ret.set_transitions(new, transitions_from_targets(&targets));
+            }
+        }
+        ret.init[Look::Boundary.as_usize()] = Some(0);
+        ret
+    }
+}
+
+// Turns a vector of length 256 (giving the target state for every byte) into transitions.
+pub fn transitions_from_targets(targets: &[Option<StateIdx>]) -> RangeMap<u8, StateIdx> {
+    let mut trans: Vec<(Range<u8>, StateIdx)> = Vec::new();
+    for (b, &tgt) in targets.iter().enumerate() {
+        if let Some(tgt) = tgt {
+            if let Some(last) = trans.last_mut() {
+                if last.1 == tgt && last.0.end as usize + 1 == b {
+                    last.0.end = b as u8;
+                    continue;
+                }
+            }
+            trans.push((Range::new(b as u8, b as u8), tgt));
+        }
+    }
+    trans.into_iter().collect()
+}
+
 impl<Ret: Debug> Debug for Dfa<Ret> {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         try!(f.write_fmt(format_args!("Dfa ({} states):\n", self.states.len())));
@@ -424,7 +1272,9 @@ pub mod tests {
     use itertools::Itertools;
     use look::Look;
     use nfa::{Accept, Nfa, StateIdx};
+    use quickcheck::quickcheck;
     use range_map::{Range, RangeMap};
+    use std::cmp::max;
     use std::usize;
 
     // Creates a non-backtracking dfa from a regex string.
@@ -455,6 +1305,17 @@ pub mod tests {
             .optimize()
     }
 
+    // Creates a dfa that accepts exactly the inputs that `re` matches in their entirety.
+    pub fn make_whole(re: &str) -> Dfa<(Look, u8)> {
+        // regex_syntax rejects empty groups, so the empty regex needs its own case.
+        let re = if re.is_empty() { "^$".to_owned() } else { format!("^(?:{})$", re) };
+        Nfa::from_regex(&re).unwrap()
+            .remove_looks()
+            .byte_me(usize::MAX).unwrap()
+            .determinize_longest(usize::MAX).unwrap()
+            .optimize()
+    }
+
     pub fn trans_dfa_anchored(size: usize, trans: &[(StateIdx, StateIdx, Range<u8>)])
     -> Dfa<(Look, u8)> {
         let mut ret = Dfa::new();
@@ -470,6 +1331,80 @@ pub mod tests {
         ret
     }
 
+    // Runs `dfa` over the whole input, starting from the anchored initial state. Returns the value
+    // that the `Dfa` returns if it accepts at the end of the input.
+    fn run_to_end(dfa: &Dfa<u8>, input: &[u8]) -> Option<u8> {
+        let mut state = match dfa.init_at_start() {
+            Some(s) => s,
+            None => { return None; },
+        };
+        for &b in input {
+            match dfa.transitions(state).get(b) {
+                Some(&next) => { state = next; },
+                None => { return None; },
+            }
+        }
+
+        if *dfa.accept(state) != Accept::Never {
+            dfa.ret(state).cloned()
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn arbitrary_is_well_formed() {
+        fn prop(dfa: Dfa<u8>) -> bool {
+            dfa.validate().is_ok() && dfa.optimize().validate().is_ok()
+        }
+        quickcheck(prop as fn(_) -> _);
+    }
+
+    #[test]
+    fn optimize_preserves_language() {
+        fn prop(dfa: Dfa<u8>, input: DfaInput) -> bool {
+            let input = input.0;
+            run_to_end(&dfa, &input) == run_to_end(&dfa.clone().optimize(), &input)
+        }
+        quickcheck(prop as fn(_, _) -> _);
+    }
+
+    #[test]
+    fn compile_preserves_language() {
+        fn prop(dfa: Dfa<u8>, input: DfaInput) -> bool {
+            let input = input.0;
+            let prog = dfa.compile();
+            let prog_ret = match prog.find_from(&input, 0, dfa.init_at_start().unwrap()) {
+                Ok((end, ret)) if end == input.len() => Some(ret),
+                _ => None,
+            };
+            run_to_end(&dfa, &input) == prog_ret
+        }
+        quickcheck(prop as fn(_, _) -> _);
+    }
+
+    // `compile` produces a lookup table rather than a list of instructions, so there is no room
+    // for peephole optimizations like fusing literals or removing trivial branches. The one that
+    // does apply (dropping unreachable states) is taken care of by `optimize`; check that.
+    #[test]
+    fn compile_has_no_unreachable_states() {
+        for re in &["abc|abd", "a+b*c", r"\bfoo(bar)?\b", "(?m)^x$"] {
+            let dfa = make_dfa(re).unwrap();
+            let insts = dfa.compile().disassemble();
+            assert_eq!(insts.len(), dfa.num_states());
+
+            let mut reachable = vec![false; insts.len()];
+            let mut stack: Vec<StateIdx> = dfa.init.iter().filter_map(|x| *x).collect();
+            while let Some(st) = stack.pop() {
+                if !reachable[st] {
+                    reachable[st] = true;
+                    stack.extend(insts[st].transitions.iter().map(|t| t.2));
+                }
+            }
+            assert!(reachable.iter().all(|&r| r));
+        }
+    }
+
     #[test]
     fn test_anchored_dfa_simple() {
         let dfa = make_anchored("a");
@@ -498,6 +1433,120 @@ pub mod tests {
         assert_eq!(dfa, tgt);
     }
 
+    #[test]
+    fn accepts() {
+        let dfa = make_anchored("a+b");
+        assert!(dfa.accepts("ab".chars()));
+        assert!(dfa.accepts("aaab".chars()));
+        assert!(!dfa.accepts("".chars()));
+        assert!(!dfa.accepts("aaa".chars()));
+        assert!(!dfa.accepts("abb".chars()));
+
+        let dfa = make_anchored("(é|ü)+$");
+        assert!(dfa.accepts("éüé".chars()));
+        assert!(dfa.accepts(vec!['ü']));
+        assert!(!dfa.accepts("eu".chars()));
+
+        assert!(!Dfa::<u8>::new().accepts("".chars()));
+    }
+
+    #[test]
+    fn match_len() {
+        let len = |re: &str| {
+            let dfa = make_whole(re);
+            (dfa.min_match_len(), dfa.max_match_len())
+        };
+        assert_eq!(len("abc"), (Some(3), Some(Some(3))));
+        assert_eq!(len("a(bc|d)"), (Some(2), Some(Some(3))));
+        assert_eq!(len("[0-9]{2,4}"), (Some(2), Some(Some(4))));
+        assert_eq!(len("é"), (Some(2), Some(Some(2))));
+        assert_eq!(len("ab*"), (Some(1), Some(None)));
+        assert_eq!(len("(ab)+c"), (Some(3), Some(None)));
+        assert_eq!(len("a?"), (Some(0), Some(Some(1))));
+
+        let empty: Dfa<u8> = Dfa::new();
+        assert_eq!((empty.min_match_len(), empty.max_match_len()), (None, None));
+    }
+
+    #[test]
+    fn min_cost_match() {
+        // A vocabulary of "a", "ab", "abc", "b" and "c", with the cost of each word as the
+        // return value.
+        let mut dfa: Dfa<u64> = Dfa::new();
+        let accepts = [(Accept::Never, None), (Accept::Always, Some(5)),
+                       (Accept::Always, Some(3)), (Accept::Always, Some(20)),
+                       (Accept::Always, Some(5)), (Accept::AtEoi, Some(1))];
+        for &(accept, ret) in &accepts {
+            dfa.add_state(accept, ret);
+        }
+        dfa.set_transitions(0, vec![(Range::new(b'a', b'a'), 1), (Range::new(b'b', b'b'), 4),
+                                    (Range::new(b'c', b'c'), 5)].into_iter().collect());
+        dfa.set_transitions(1, vec![(Range::new(b'b', b'b'), 2)].into_iter().collect());
+        dfa.set_transitions(2, vec![(Range::new(b'c', b'c'), 3)].into_iter().collect());
+        dfa.init[Look::Boundary.as_usize()] = Some(0);
+
+        let cost = |d: &Dfa<u64>, s: &str| d.min_cost_match(s.as_bytes(), |&c| c);
+        for d in &[dfa.clone(), dfa.clone().optimize()] {
+            assert_eq!(cost(d, "abc"), Some((4, vec![2, 3])));
+            assert_eq!(cost(d, "abab"), Some((6, vec![2, 4])));
+            assert_eq!(cost(d, "aab"), Some((8, vec![1, 3])));
+            assert_eq!(cost(d, ""), Some((0, vec![])));
+            // "c" only accepts at the end of the input.
+            assert_eq!(cost(d, "cab"), None);
+            assert_eq!(cost(d, "abd"), None);
+        }
+    }
+
+    #[test]
+    fn from_words() {
+        let words = ["tops", "tap", "top", "taps", "tap"];
+        let dfa = Dfa::from_words(words.iter().cloned());
+        for w in &words {
+            assert!(dfa.accepts(w.chars()), "should accept {:?}", w);
+        }
+        for w in &["", "t", "ta", "tip", "tapss", "stop"] {
+            assert!(!dfa.accepts(w.chars()), "shouldn't accept {:?}", w);
+        }
+        // The "a" and "o" transitions lead to the same state.
+        assert_eq!(dfa.num_states(), 5);
+        assert!(dfa.is_anchored());
+        assert_eq!(dfa.validate(), Ok(()));
+
+        let dfa = Dfa::from_words(vec!["", "é", "a", "ab", "b"]);
+        assert!(dfa.accepts("".chars()));
+        assert!(dfa.accepts("é".chars()));
+        assert!(!dfa.accepts("ba".chars()));
+        assert_eq!(dfa.num_states(), dfa.clone().optimize().num_states());
+        assert_eq!(Dfa::from_words(Vec::new()).num_states(), 1);
+    }
+
+    #[test]
+    fn completions() {
+        let dfa = Dfa::from_words(vec!["car", "cart", "care", "cat", "dog", "ça"]);
+        assert_eq!(dfa.completions("car", 10), vec!["car", "care", "cart"]);
+        assert_eq!(dfa.completions("ca", 2), vec!["car", "care"]);
+        assert_eq!(dfa.completions("", 10), vec!["car", "care", "cart", "cat", "dog", "ça"]);
+        assert_eq!(dfa.completions("ç", 10), vec!["ça"]);
+        assert!(dfa.completions("cab", 10).is_empty());
+        assert!(dfa.completions("c", 0).is_empty());
+
+        // There are infinitely many completions, but we don't go around the loop.
+        let dfa = make_anchored("^ab*c$");
+        assert_eq!(dfa.completions("a", 10), vec!["ac"]);
+        assert_eq!(dfa.completions("abb", 10), vec!["abbc"]);
+    }
+
+    #[test]
+    fn from_words_matches_regex() {
+        let words = ["abc", "abd", "bcd", "abcd", "xbcd", "xbc", "a"];
+        let dfa = Dfa::from_words(words.iter().cloned());
+        let re = make_anchored(&format!("^({})$", words.join("|")));
+        for w in &["abc", "abd", "bcd", "abcd", "xbcd", "xbc", "a", "", "ab", "bc", "xb", "abcde"] {
+            assert_eq!(dfa.accepts(w.chars()), re.accepts(w.chars()), "{:?}", w);
+        }
+        assert!(dfa.num_states() <= re.num_states());
+    }
+
     #[test]
     fn test_anchored_dfa_literal_prefix() {
         let dfa = make_anchored("abc[A-z]");
@@ -505,6 +1554,86 @@ pub mod tests {
         assert_eq!(pref, vec!["abc".as_bytes()]);
     }
 
+    #[test]
+    fn alphabet_partition() {
+        let dfa = make_anchored("[a-c]x|d");
+        assert_eq!(dfa.alphabet_partition(),
+                   vec![Range::new(0, b'a' - 1), Range::new(b'a', b'c'), Range::new(b'd', b'd'),
+                        Range::new(b'e', b'w'), Range::new(b'x', b'x'), Range::new(b'y', 255)]);
+
+        let empty: Dfa<u8> = Dfa::new();
+        assert_eq!(empty.alphabet_partition(), vec![Range::new(0, 255)]);
+    }
+
+    #[test]
+    fn map_alphabet() {
+        fn lowercase(b: u8) -> Option<u8> {
+            Some(if b'A' <= b && b <= b'Z' { b - b'A' + b'a' } else { b })
+        }
+
+        let upper = make_anchored("[A-C]X+");
+        let lower = upper.map_alphabet(lowercase).unwrap();
+        assert!(lower.accepts("bxx".chars()));
+        assert!(!lower.accepts("BXX".chars()));
+        assert!(lower.validate().is_ok());
+
+        let dropped = upper.map_alphabet(|b| if b == b'B' { None } else { Some(b) }).unwrap();
+        assert!(dropped.accepts("AX".chars()));
+        assert!(!dropped.accepts("BX".chars()));
+
+        let dfa = make_anchored("ab|Ac");
+        assert_eq!(dfa.map_alphabet(lowercase),
+                   Err(DfaError::ConflictingTransitions(0, b'a')));
+    }
+
+    #[test]
+    fn product() {
+        use std::cmp::min;
+
+        let a = make_anchored("a+");
+        let b = make_anchored("[ab]a");
+
+        let both = a.product(&b, |x, y| min(*x, *y));
+        assert!(both.validate().is_ok());
+        assert!(both.accepts("aa".chars()));
+        assert!(!both.accepts("a".chars()));
+        assert!(!both.accepts("ba".chars()));
+
+        let either = a.product(&b, |x, y| max(*x, *y));
+        assert!(either.accepts("a".chars()));
+        assert!(either.accepts("ba".chars()));
+        assert!(either.accepts("aaa".chars()));
+        assert!(!either.accepts("b".chars()));
+
+        let a_not_b = a.product(&b, |x, y| if *y == Accept::Never { *x } else { Accept::Never });
+        assert!(a_not_b.accepts("a".chars()));
+        assert!(a_not_b.accepts("aaa".chars()));
+        assert!(!a_not_b.accepts("aa".chars()));
+    }
+
+    #[test]
+    fn concat() {
+        let a = make_anchored("ab?");
+        let b = make_anchored("b+c");
+        let ab = a.concat(&b, usize::MAX).unwrap();
+        assert!(ab.validate().is_ok());
+        assert!(ab.accepts("abc".chars()));
+        assert!(ab.accepts("abbc".chars()));
+        assert!(ab.accepts("abbbc".chars()));
+        assert!(!ab.accepts("ac".chars()));
+        assert!(!ab.accepts("abb".chars()));
+        assert!(!ab.accepts("abcabc".chars()));
+        assert!(a.concat(&b, 1).is_err());
+
+        // Nothing can follow a state that only accepts at the end of the input, except for an
+        // empty match.
+        let a = make_anchored("a$");
+        assert!(!a.concat(&b, usize::MAX).unwrap().accepts("abc".chars()));
+        let ax = a.concat(&make_anchored("x*"), usize::MAX).unwrap();
+        assert!(ax.accepts("a".chars()));
+        assert!(!ax.accepts("ax".chars()));
+    }
+
     #[test]
     fn test_minimize() {
         let auto = make_dfa("a*?b*?").unwrap();
@@ -515,14 +1644,149 @@ pub mod tests {
         assert_eq!(auto.states.len(), 2);
 
         let mut auto = make_dfa("[cgt]gggtaaa|tttaccc[acg]").unwrap();
-        // Since `minimize` is non-deterministic (involving random hashes), run this a bunch of
-        // times.
         for _ in 0..100 {
             auto = auto.optimize();
             assert_eq!(auto.states.len(), 16);
         }
     }
 
+    #[test]
+    fn tags_survive_minimization() {
+        let mut dfa = Nfa::from_regex("^(ax|bx)$").unwrap()
+            .remove_looks()
+            .byte_me(usize::MAX).unwrap()
+            .determinize(usize::MAX).unwrap();
+        let init = dfa.init_at_start().unwrap();
+        let after_a = *dfa.transitions(init).get(b'a').unwrap();
+        let after_b = *dfa.transitions(init).get(b'b').unwrap();
+        assert!(after_a != after_b);
+
+        // Without tags, the states after "a" and after "b" are merged.
+        let untagged = dfa.clone().optimize().num_states();
+        dfa.tag_state(after_a, 1);
+        dfa.tag_state(after_b, 2);
+        let min = dfa.optimize();
+        assert_eq!(min.num_states(), untagged + 1);
+        assert!(min.accepts("ax".chars()));
+
+        let tags = min.tag_map();
+        assert_eq!(tags.len(), 2);
+        let init = min.init_at_start().unwrap();
+        assert_eq!(tags[&1], vec![*min.transitions(init).get(b'a').unwrap()]);
+        assert_eq!(tags[&2], vec![*min.transitions(init).get(b'b').unwrap()]);
+        assert!(tags.values().all(|states| !states.contains(&init)));
+    }
+
+    #[test]
+    fn optimize_until() {
+        use error::Error;
+        use regex::Stage;
+        use std::time::{Duration, Instant};
+
+        let dfa = make_dfa("[cgt]gggtaaa|tttaccc[acg]").unwrap();
+        match dfa.clone().optimize_until(Some(Instant::now())) {
+            Err(Error::TimedOut(report)) => {
+                assert_eq!(report.stage, Stage::Minimize);
+                assert_eq!(report.dfa_states, dfa.num_states());
+                assert_eq!(report.steps, 0);
+            },
+            x => panic!("expected a timeout, got {:?}", x),
+        }
+
+        let later = Instant::now() + Duration::from_secs(3600);
+        assert_eq!(dfa.clone().optimize_until(Some(later)).unwrap(), dfa.clone().optimize());
+        assert_eq!(dfa.clone().optimize_until(None).unwrap(), dfa.optimize());
+    }
+
+    #[test]
+    fn test_minimize_deterministic() {
+        // Every `HashMap` gets its own random keys, so if the state numbering depended on the
+        // iteration order then we would expect to see some differences here.
+        let dfa = make_dfa("[cgt]gggtaaa|tttaccc[acg]|(ab|cd)*xyz").unwrap();
+        let min = dfa.minimize();
+        for _ in 0..20 {
+            assert_eq!(dfa.minimize(), min);
+        }
+    }
+
+    #[test]
+    fn trim() {
+        // State 1 is unreachable, and state 3 is a sink that never accepts.
+        let mut dfa = trans_dfa_anchored(5, &[(0, 2, Range::new(b'a', b'a')),
+                                              (0, 3, Range::new(b'b', b'b')),
+                                              (1, 4, Range::new(b'a', b'a')),
+                                              (2, 4, Range::new(b'c', b'c')),
+                                              (3, 3, Range::new(b'a', b'z'))]);
+        dfa.init[Look::Boundary.as_usize()] = Some(0);
+        dfa.states[4].accept = Accept::Always;
+        dfa.states[4].ret = Some((Look::Full, 0));
+        dfa.trim();
+
+        let mut target = trans_dfa_anchored(3, &[(0, 1, Range::new(b'a', b'a')),
+                                                 (1, 2, Range::new(b'c', b'c'))]);
+        target.init[Look::Boundary.as_usize()] = Some(0);
+        target.states[2].accept = Accept::Always;
+        target.states[2].ret = Some((Look::Full, 0));
+        assert_eq!(dfa, target);
+
+        // If nothing accepts, everything goes.
+        let mut dfa = trans_dfa_anchored(2, &[(0, 1, Range::new(b'a', b'a'))]);
+        dfa.init[Look::Boundary.as_usize()] = Some(0);
+        dfa.trim();
+        assert_eq!(dfa.num_states(), 0);
+        assert_eq!(dfa.init_at_start(), None);
+    }
+
+    #[test]
+    fn trim_preserves_language() {
+        fn prop(dfa: Dfa<u8>, input: DfaInput) -> bool {
+            let input = input.0;
+            let mut trimmed = dfa.clone();
+            trimmed.trim();
+            trimmed.validate().is_ok() && run_to_end(&dfa, &input) == run_to_end(&trimmed, &input)
+        }
+        quickcheck(prop as fn(_, _) -> _);
+    }
+
+    #[test]
+    fn test_minimize_shares_suffixes() {
+        // Without sharing suffixes, "xyz" would need to appear twice.
+        let dfa = make_anchored("(ab|cd)xyz");
+        assert_eq!(dfa.num_states(), 7);
+
+        let dfa = make_anchored("(foo|bar|baz)ing");
+        let shared = make_anchored("(foo|ba[rz])ing");
+        assert_eq!(dfa.num_states(), shared.num_states());
+    }
+
+    #[test]
+    fn test_minimize_large() {
+        // A long cycle of accepting states, all of which are equivalent.
+        let n = 20000;
+        let mut dfa = Dfa::new();
+        for _ in 0..n {
+            dfa.add_state(Accept::Always, Some(0u8));
+        }
+        for i in 0..n {
+            let next = (i + 1) % n;
+            dfa.set_transitions(i, Some((Range::new(b'a', b'b'), next)).into_iter().collect());
+        }
+        dfa.init[Look::Boundary.as_usize()] = Some(0);
+        assert_eq!(dfa.minimize().num_states(), 1);
+
+        // A long chain that can't be collapsed at all.
+        let mut dfa = Dfa::new();
+        for i in 0..n {
+            dfa.add_state(if i + 1 == n { Accept::Always } else { Accept::Never },
+                          if i + 1 == n { Some(0u8) } else { None });
+        }
+        for i in 0..(n - 1) {
+            dfa.set_transitions(i, Some((Range::new(b'a', b'a'), i + 1)).into_iter().collect());
+        }
+        dfa.init[Look::Boundary.as_usize()] = Some(0);
+        assert_eq!(dfa.minimize().num_states(), n);
+    }
+
    #[test]
     fn test_class_normalized() {
         let mut re = make_dfa("[abcdw]").unwrap();
@@ -544,6 +1808,30 @@ pub mod tests {
         assert!(make_dfa_bounded(r"test1\b\Btest2", 100).unwrap().states.is_empty());
     }
 
+    #[test]
+    fn validate() {
+        assert_eq!(make_dfa("a+b|c").unwrap().validate(), Ok(()));
+        assert_eq!(make_anchored(r"\bx\b").validate(), Ok(()));
+
+        let mut dfa = trans_dfa_anchored(2, &[(0, 2, Range::new(b'a', b'a'))]);
+        assert_eq!(dfa.validate(), Err(DfaError::TargetOutOfRange(0, 2)));
+
+        dfa = trans_dfa_anchored(2, &[(0, 1, Range::new(b'a', b'a'))]);
+        dfa.init[Look::Full.as_usize()] = Some(5);
+        assert_eq!(dfa.validate(), Err(DfaError::InitOutOfRange(Look::Full, 5)));
+
+        dfa = trans_dfa_anchored(2, &[(0, 1, Range::new(b'a', b'a'))]);
+        dfa.states[1].accept = Accept::Always;
+        assert_eq!(dfa.validate(), Err(DfaError::MissingRet(1)));
+
+        dfa.init.pop();
+        assert_eq!(dfa.validate(), Err(DfaError::WrongInitLength(Look::num() - 1)));
+
+        // `Range`'s fields are public, so nothing stops a range from ending before it starts.
+        dfa = trans_dfa_anchored(2, &[(0, 1, Range { start: b'b', end: b'a' })]);
+        assert_eq!(dfa.validate(), Err(DfaError::EmptyTransition(0)));
+    }
+
     #[test]
     fn test_syntax_error() {
         assert!(make_dfa_bounded("(abc", 10).is_err());
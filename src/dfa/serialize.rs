@@ -0,0 +1,100 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Serialization for `Dfa`s (enabled by the `serde` feature). The schema is described in the
+// documentation of `Dfa`. Deserializing checks that the result is well-formed (see
+// `Dfa::validate`).
+
+use dfa::{Dfa, RetTrait};
+use nfa::{Accept, StateIdx};
+use range_map::Range;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+type StateRepr<Ret> = (Accept, Option<Ret>, Vec<(u8, u8, StateIdx)>);
+
+impl<Ret: RetTrait + Serialize> Serialize for Dfa<Ret> {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> Result<(), S::Error> {
+        let states: Vec<StateRepr<Ret>> = self.states.iter()
+            .map(|st| {
+                let trans = st.transitions.ranges_values()
+                    .map(|&(range, tgt)| (range.start, range.end, tgt))
+                    .collect();
+                (st.accept, st.ret, trans)
+            })
+            .collect();
+        (states, &self.init).serialize(serializer)
+    }
+}
+
+impl<Ret: RetTrait + Deserialize> Deserialize for Dfa<Ret> {
+    fn deserialize<D: Deserializer>(deserializer: &mut D) -> Result<Dfa<Ret>, D::Error> {
+        let (states, init): (Vec<StateRepr<Ret>>, Vec<Option<StateIdx>>) =
+            try!(Deserialize::deserialize(deserializer));
+
+        let mut ret = Dfa::new();
+        for (accept, st_ret, trans) in states {
+            let idx = ret.add_state(accept, st_ret);
+            let trans = trans.into_iter()
+                .map(|(first, last, tgt)| (Range::new(first, last), tgt))
+                .collect();
+            ret.set_transitions(idx, trans);
+        }
+        ret.init = init;
+
+        match ret.validate() {
+            Ok(()) => Ok(ret),
+            Err(e) => Err(de::Error::custom(format!("invalid DFA: {}", e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfa::Dfa;
+    use dfa::tests::make_dfa;
+    use look::Look;
+    use nfa::Accept;
+    use range_map::Range;
+    use serde_json;
+
+    #[test]
+    fn round_trip() {
+        for re in &["a+b|c", r"\bx\b", "(?m)^é$", ""] {
+            let dfa = make_dfa(re).unwrap();
+            let json = serde_json::to_string(&dfa).unwrap();
+            let back: Dfa<(Look, u8)> = serde_json::from_str(&json).unwrap();
+            assert_eq!(dfa, back);
+        }
+    }
+
+    #[test]
+    fn schema() {
+        let mut dfa = Dfa::new();
+        dfa.add_state(Accept::Never, None);
+        dfa.add_state(Accept::Always, Some(3u8));
+        dfa.set_transitions(0, vec![(Range::new(b'a', b'b'), 1)].into_iter().collect());
+        dfa.init[Look::Boundary.as_usize()] = Some(0);
+
+        let json = r#"[[["never",null,[[97,98,1]]],["always",3,[]]],[null,null,null,null,0,null]]"#;
+        assert_eq!(serde_json::to_string(&dfa).unwrap(), json);
+    }
+
+    #[test]
+    fn invalid() {
+        // The transition goes to a state that doesn't exist.
+        let json = r#"[[["never",null,[[97,97,1]]]],[0,null,null,null,0,null]]"#;
+        assert!(serde_json::from_str::<Dfa<u8>>(json).is_err());
+
+        // There are too few initial states.
+        let json = r#"[[["always",3,[]]],[0]]"#;
+        assert!(serde_json::from_str::<Dfa<u8>>(json).is_err());
+
+        let json = r#"[[["always",3,[]]],[0,null,null,null,0,null]]"#;
+        assert!(serde_json::from_str::<Dfa<u8>>(json).is_ok());
+    }
+}
@@ -0,0 +1,285 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Converts a `Dfa` into a regular expression by state elimination: we add a new initial and a new
+// final state, label every edge with a regular expression, and then remove the original states
+// one by one, replacing each path i -> k -> j by an edge labelled (i, k)(k, k)*(k, j).
+
+use dfa::{Dfa, RetTrait};
+use nfa::Accept;
+use range_map::Range;
+
+// A regular expression over bytes. There is no variant for the empty language: edges that don't
+// exist are `None` instead.
+#[derive(Clone, Debug, PartialEq)]
+enum Re {
+    // Matches the empty string.
+    Empty,
+    // Matches any one byte in the (sorted, non-overlapping) ranges.
+    Bytes(Vec<Range<u8>>),
+    Concat(Vec<Re>),
+    Alt(Vec<Re>),
+    Star(Box<Re>),
+}
+
+fn concat(a: Re, b: Re) -> Re {
+    match (a, b) {
+        (Re::Empty, x) | (x, Re::Empty) => x,
+        (Re::Concat(mut xs), Re::Concat(ys)) => {
+            xs.extend(ys);
+            Re::Concat(xs)
+        },
+        (Re::Concat(mut xs), y) => {
+            xs.push(y);
+            Re::Concat(xs)
+        },
+        (x, Re::Concat(mut ys)) => {
+            ys.insert(0, x);
+            Re::Concat(ys)
+        },
+        (x, y) => Re::Concat(vec![x, y]),
+    }
+}
+
+fn alt(a: Re, b: Re) -> Re {
+    let mut alts = match a {
+        Re::Alt(xs) => xs,
+        x => vec![x],
+    };
+    let new_alts = match b {
+        Re::Alt(ys) => ys,
+        y => vec![y],
+    };
+
+    for y in new_alts {
+        if alts.contains(&y) {
+            continue;
+        }
+        // Alternatives that are just sets of bytes get merged into a single set.
+        if let Re::Bytes(ref y_ranges) = y {
+            let merged = alts.iter_mut().any(|x| {
+                if let Re::Bytes(ref mut x_ranges) = *x {
+                    *x_ranges = union_ranges(x_ranges, y_ranges);
+                    true
+                } else {
+                    false
+                }
+            });
+            if merged {
+                continue;
+            }
+        }
+        alts.push(y);
+    }
+
+    if alts.len() == 1 {
+        alts.pop().unwrap()
+    } else {
+        Re::Alt(alts)
+    }
+}
+
+fn star(a: Re) -> Re {
+    match a {
+        Re::Empty => Re::Empty,
+        Re::Star(x) => Re::Star(x),
+        x => Re::Star(Box::new(x)),
+    }
+}
+
+fn union_ranges(a: &[Range<u8>], b: &[Range<u8>]) -> Vec<Range<u8>> {
+    let mut all: Vec<Range<u8>> = a.iter().chain(b.iter()).cloned().collect();
+    all.sort_by_key(|r| (r.start, r.end));
+
+    let mut ret: Vec<Range<u8>> = Vec::with_capacity(all.len());
+    for r in all {
+        if let Some(last) = ret.last_mut() {
+            if r.start as u32 <= last.end as u32 + 1 {
+                if r.end > last.end {
+                    last.end = r.end;
+                }
+                continue;
+            }
+        }
+        ret.push(r);
+    }
+    ret
+}
+
+// Writes a byte so that it stands for itself, both inside and outside a character class.
+fn push_byte(b: u8, out: &mut String) {
+    if (b as char).is_alphanumeric() && b < 0x80 {
+        out.push(b as char);
+    } else {
+        out.push_str(&format!("\\x{:02X}", b));
+    }
+}
+
+impl Re {
+    fn write(&self, out: &mut String) {
+        match *self {
+            Re::Empty => {},
+            Re::Bytes(ref ranges) => {
+                if ranges.len() == 1 && ranges[0].start == ranges[0].end {
+                    push_byte(ranges[0].start, out);
+                } else {
+                    out.push('[');
+                    for r in ranges {
+                        push_byte(r.start, out);
+                        if r.end > r.start {
+                            out.push('-');
+                            push_byte(r.end, out);
+                        }
+                    }
+                    out.push(']');
+                }
+            },
+            Re::Concat(ref xs) => {
+                for x in xs {
+                    if let Re::Alt(_) = *x {
+                        out.push_str("(?:");
+                        x.write(out);
+                        out.push(')');
+                    } else {
+                        x.write(out);
+                    }
+                }
+            },
+            Re::Alt(ref xs) => {
+                let optional = xs.contains(&Re::Empty);
+                if optional {
+                    out.push_str("(?:");
+                }
+                let mut first = true;
+                for x in xs.iter().filter(|x| **x != Re::Empty) {
+                    if !first {
+                        out.push('|');
+                    }
+                    first = false;
+                    x.write(out);
+                }
+                if optional {
+                    out.push_str(")?");
+                }
+            },
+            Re::Star(ref x) => {
+                if let Re::Bytes(_) = **x {
+                    x.write(out);
+                } else {
+                    out.push_str("(?:");
+                    x.write(out);
+                    out.push(')');
+                }
+                out.push('*');
+            },
+        }
+    }
+}
+
+pub fn to_regex<Ret: RetTrait>(dfa: &Dfa<Ret>) -> Option<String> {
+    let init = try_opt!(dfa.init_at_start());
+    let n = dfa.num_states();
+    // The new initial state is `n`, and the new final state is `n + 1`.
+    let start = n;
+    let end = n + 1;
+    let mut edges: Vec<Vec<Option<Re>>> = vec![vec![None; n + 2]; n + 2];
+
+    edges[start][init] = Some(Re::Empty);
+    for st in 0..n {
+        if *dfa.accept(st) != Accept::Never {
+            edges[st][end] = Some(Re::Empty);
+        }
+        for &(range, tgt) in dfa.transitions(st).ranges_values() {
+            let new = match edges[st][tgt].take() {
+                Some(Re::Bytes(ranges)) => union_ranges(&ranges, &[range]),
+                _ => vec![range],
+            };
+            edges[st][tgt] = Some(Re::Bytes(new));
+        }
+    }
+
+    // Eliminate the states one at a time, always choosing the one that creates the fewest new
+    // edges.
+    let mut remaining: Vec<usize> = (0..n).collect();
+    while !remaining.is_empty() {
+        let cost = |k: usize| {
+            let ins = (0..(n + 2)).filter(|&i| i != k && edges[i][k].is_some()).count();
+            let outs = (0..(n + 2)).filter(|&j| j != k && edges[k][j].is_some()).count();
+            ins * outs
+        };
+        let pos = (0..remaining.len()).min_by_key(|&p| cost(remaining[p])).unwrap();
+        let k = remaining.swap_remove(pos);
+
+        let loop_re = edges[k][k].take().map(star).unwrap_or(Re::Empty);
+        let ins: Vec<(usize, Re)> = (0..(n + 2))
+            .filter_map(|i| edges[i][k].take().map(|re| (i, re)))
+            .collect();
+        let outs: Vec<(usize, Re)> = (0..(n + 2))
+            .filter_map(|j| edges[k][j].take().map(|re| (j, re)))
+            .collect();
+        for &(i, ref in_re) in &ins {
+            for &(j, ref out_re) in &outs {
+                let path = concat(concat(in_re.clone(), loop_re.clone()), out_re.clone());
+                let new = match edges[i][j].take() {
+                    Some(old) => alt(old, path),
+                    None => path,
+                };
+                edges[i][j] = Some(new);
+            }
+        }
+    }
+
+    edges[start][end].take().map(|re| {
+        let mut ret = String::new();
+        re.write(&mut ret);
+        ret
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use dfa::Dfa;
+    use dfa::tests::{make_anchored, make_whole};
+    use look::Look;
+
+    fn round_trip(re: &str, inputs: &[&str]) {
+        let dfa = make_whole(re);
+        let out = dfa.to_regex().unwrap();
+        let back = make_whole(&out);
+        for input in inputs {
+            assert_eq!(dfa.accepts(input.chars()), back.accepts(input.chars()),
+                       "{:?} became {:?}, which disagrees on {:?}", re, out, input);
+        }
+    }
+
+    #[test]
+    fn to_regex() {
+        round_trip("abc", &["abc", "ab", "abcd", ""]);
+        round_trip("a|b|c", &["a", "b", "c", "d", "ab", ""]);
+        round_trip("(ab)*c", &["c", "abc", "ababc", "abac", "ab", ""]);
+        round_trip("a?b+", &["b", "ab", "abbb", "a", "aab", ""]);
+        round_trip("[0-9]+(\\.[0-9]+)?", &["1", "12.5", "12.", ".5", "1.2.3", ""]);
+        round_trip("(a|b)*abb", &["abb", "aabb", "babb", "ab", "abba", "bbabb"]);
+        round_trip("a*", &["", "a", "aaa", "b"]);
+
+        assert_eq!(make_anchored("^abc$").to_regex(), Some("abc".to_owned()));
+        assert_eq!(make_anchored("^$").to_regex(), Some("".to_owned()));
+        assert_eq!(make_anchored("^[a-c]$").to_regex(), Some("[a-c]".to_owned()));
+        // Anything that isn't ASCII alphanumeric is escaped, and each char stands for a byte.
+        assert_eq!(make_anchored("^é\\.$").to_regex(), Some("\\xC3\\xA9\\x2E".to_owned()));
+    }
+
+    #[test]
+    fn to_regex_empty() {
+        let mut dfa: Dfa<u8> = Dfa::new();
+        assert_eq!(dfa.to_regex(), None);
+        dfa.add_state(::nfa::Accept::Never, None);
+        dfa.init[Look::Boundary.as_usize()] = Some(0);
+        assert_eq!(dfa.to_regex(), None);
+    }
+}
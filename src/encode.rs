@@ -0,0 +1,248 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// A compact binary format for compiled regexes (see `Regex::to_bytes`).
+//
+// Integers are stored in little-endian order, with `usize`s stored as 64-bit integers. A `Vec` is
+// stored as its length followed by its elements, and an `Option` is stored as a byte saying
+// whether it is `Some`, followed by the value (if there is one). Decoding never panics: if the
+// input is malformed, it just returns `None`.
+
+use look::{Look, WordChars};
+
+// Like `try!`, but for `Option`s.
+macro_rules! try_opt {
+    ($e:expr) => {
+        match $e {
+            Some(x) => x,
+            None => { return None; },
+        }
+    }
+}
+
+pub trait Encode: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+    fn decode(input: &mut Decoder) -> Option<Self>;
+}
+
+pub struct Decoder<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(input: &'a [u8]) -> Decoder<'a> {
+        Decoder { input: input }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    // Returns the input that hasn't been consumed yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.input
+    }
+
+    // Consumes the next `len` bytes of input.
+    pub fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len <= self.input.len() {
+            let (ret, rest) = self.input.split_at(len);
+            self.input = rest;
+            Some(ret)
+        } else {
+            None
+        }
+    }
+
+    fn uint(&mut self, len: usize) -> Option<u64> {
+        self.take(len).map(|bytes| bytes.iter().rev().fold(0, |acc, &b| (acc << 8) | b as u64))
+    }
+}
+
+fn encode_uint(x: u64, len: usize, out: &mut Vec<u8>) {
+    for i in 0..len {
+        out.push((x >> (8 * i)) as u8);
+    }
+}
+
+impl Encode for u8 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+
+    fn decode(input: &mut Decoder) -> Option<u8> {
+        input.take(1).map(|b| b[0])
+    }
+}
+
+impl Encode for u32 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_uint(*self as u64, 4, out);
+    }
+
+    fn decode(input: &mut Decoder) -> Option<u32> {
+        input.uint(4).map(|x| x as u32)
+    }
+}
+
+impl Encode for usize {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_uint(*self as u64, 8, out);
+    }
+
+    fn decode(input: &mut Decoder) -> Option<usize> {
+        input.uint(8).and_then(|x| if x as usize as u64 == x { Some(x as usize) } else { None })
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(if *self { 1 } else { 0 });
+    }
+
+    fn decode(input: &mut Decoder) -> Option<bool> {
+        match u8::decode(input) {
+            Some(0) => Some(false),
+            Some(1) => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl Encode for Look {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(self.as_usize() as u8);
+    }
+
+    fn decode(input: &mut Decoder) -> Option<Look> {
+        u8::decode(input).and_then(|x| Look::all().get(x as usize).cloned())
+    }
+}
+
+impl Encode for WordChars {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.ranges().encode(out);
+    }
+
+    fn decode(input: &mut Decoder) -> Option<WordChars> {
+        match try_opt!(Option::<Vec<(u32, u32)>>::decode(input)) {
+            None => Some(WordChars::unicode()),
+            Some(ref ranges) if ranges.iter().all(|&(lo, hi)| lo <= hi) => {
+                Some(WordChars::from_ranges(ranges))
+            },
+            Some(_) => None,
+        }
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            Some(ref x) => {
+                out.push(1);
+                x.encode(out);
+            },
+            None => { out.push(0); },
+        }
+    }
+
+    fn decode(input: &mut Decoder) -> Option<Option<T>> {
+        match u8::decode(input) {
+            Some(0) => Some(None),
+            Some(1) => T::decode(input).map(Some),
+            _ => None,
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.len().encode(out);
+        for x in self {
+            x.encode(out);
+        }
+    }
+
+    fn decode(input: &mut Decoder) -> Option<Vec<T>> {
+        // We don't trust `len` enough to allocate space for it up front.
+        let len = try_opt!(usize::decode(input));
+        let mut ret = Vec::new();
+        for _ in 0..len {
+            ret.push(try_opt!(T::decode(input)));
+        }
+        Some(ret)
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+
+    fn decode(input: &mut Decoder) -> Option<(A, B)> {
+        let a = try_opt!(A::decode(input));
+        let b = try_opt!(B::decode(input));
+        Some((a, b))
+    }
+}
+
+impl<A: Encode, B: Encode, C: Encode> Encode for (A, B, C) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+        self.2.encode(out);
+    }
+
+    fn decode(input: &mut Decoder) -> Option<(A, B, C)> {
+        let a = try_opt!(A::decode(input));
+        let b = try_opt!(B::decode(input));
+        let c = try_opt!(C::decode(input));
+        Some((a, b, c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use look::{Look, WordChars};
+    use super::*;
+
+    fn round_trip<T: Encode + PartialEq + ::std::fmt::Debug>(x: T) {
+        let mut buf = Vec::new();
+        x.encode(&mut buf);
+        let mut input = Decoder::new(&buf);
+        assert_eq!(T::decode(&mut input), Some(x));
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(0xABu8);
+        round_trip(0xDEADBEEFu32);
+        round_trip(123456789usize);
+        round_trip(vec![Some((Look::NewLine, 3u8)), None, Some((Look::Boundary, 0))]);
+        round_trip((7usize, Look::WordChar, 2u8));
+        round_trip(vec![true, false]);
+        round_trip(WordChars::unicode());
+        round_trip(WordChars::from_ranges(&[(0x61, 0x7A), (0x3B1, 0x3C9)]));
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(u32::decode(&mut Decoder::new(&[1, 2, 3])), None);
+        assert_eq!(bool::decode(&mut Decoder::new(&[2])), None);
+        assert_eq!(Look::decode(&mut Decoder::new(&[6])), None);
+        // A word-char range that ends before it starts.
+        let mut buf = Vec::new();
+        Some(vec![(5u32, 4u32)]).encode(&mut buf);
+        assert_eq!(WordChars::decode(&mut Decoder::new(&buf)), None);
+        assert_eq!(Option::<u8>::decode(&mut Decoder::new(&[1])), None);
+        // A huge length with nothing after it.
+        assert_eq!(Vec::<u8>::decode(&mut Decoder::new(&[0xFF; 8])), None);
+    }
+}
@@ -6,15 +6,49 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use regex::Stage;
 use regex_syntax;
 use std::error;
 use std::fmt;
 
+/// Some statistics about a failed attempt to turn an NFA into a DFA.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlowupReport {
+    /// The number of states in the NFA.
+    pub nfa_states: usize,
+    /// The number of DFA states that were created before we gave up.
+    pub dfa_states: usize,
+    /// The size of the largest set of NFA states that corresponds to a single DFA state.
+    pub largest_state_set: usize,
+}
+
+/// How far we got with compiling a regex before running out of time. See
+/// `RegexOptions::time_limit`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeoutReport {
+    /// The stage that was running when the time ran out.
+    pub stage: Stage,
+    /// The number of states in the DFA that was being built (or minimized).
+    pub dfa_states: usize,
+    /// How much of the stage was finished: the number of DFA states whose transitions were
+    /// computed, or the number of times that the minimizer refined its partition.
+    pub steps: usize,
+}
+
 #[derive(Debug)]
 pub enum Error {
     RegexSyntax(regex_syntax::Error),
     TooManyStates,
+    DfaBlowup(BlowupReport),
     InvalidEngine(&'static str),
+    AttSyntax(usize, String),
+    InvalidProgram,
+    Stopped(Stage),
+    SignatureSyntax(usize, String),
+    UnknownCharSet(String),
+    TimedOut(TimeoutReport),
+    GlobSyntax(usize, String),
+    LikeSyntax(usize, String),
 }
 
 use error::Error::*;
@@ -23,7 +57,21 @@ impl fmt::Display for Error {
         match *self {
             RegexSyntax(ref e) => write!(f, "Regex syntax error: {}", e),
             TooManyStates => write!(f, "State overflow"),
+            DfaBlowup(ref r) =>
+                write!(f, "State overflow: gave up after creating {} DFA states from {} NFA states \
+                           (the largest set of NFA states had size {})",
+                       r.dfa_states, r.nfa_states, r.largest_state_set),
             InvalidEngine(s) => write!(f, "Invalid engine: {}", s),
+            AttSyntax(line, ref s) => write!(f, "AT&T syntax error on line {}: {}", line, s),
+            InvalidProgram => write!(f, "Invalid compiled program"),
+            Stopped(stage) => write!(f, "Stopped after the {:?} stage", stage),
+            SignatureSyntax(idx, ref s) => write!(f, "Syntax error in signature {}: {}", idx, s),
+            UnknownCharSet(ref name) => write!(f, "Unknown Unicode class \"{}\"", name),
+            TimedOut(ref r) =>
+                write!(f, "Timed out in the {:?} stage after {} steps (with {} DFA states)",
+                       r.stage, r.steps, r.dfa_states),
+            GlobSyntax(pos, ref s) => write!(f, "Glob syntax error at byte {}: {}", pos, s),
+            LikeSyntax(pos, ref s) => write!(f, "LIKE syntax error at byte {}: {}", pos, s),
         }
     }
 }
@@ -33,7 +81,16 @@ impl error::Error for Error {
         match *self {
             RegexSyntax(ref e) => e.description(),
             TooManyStates => "This NFA required too many states to represent as a DFA.",
+            DfaBlowup(_) => "This NFA required too many states to represent as a DFA.",
             InvalidEngine(_) => "The regex was not compatible with the requested engine.",
+            AttSyntax(..) => "The automaton was not in valid AT&T text format.",
+            InvalidProgram => "The bytes did not encode a compiled regex from this version.",
+            Stopped(_) => "The compiler was asked to stop before finishing.",
+            SignatureSyntax(..) => "The signature was not valid hex with wildcards.",
+            UnknownCharSet(_) => "There is no Unicode category or script with that name.",
+            TimedOut(_) => "Compiling the regex took longer than the time limit.",
+            GlobSyntax(..) => "The glob pattern was not valid.",
+            LikeSyntax(..) => "The LIKE pattern was not valid.",
         }
     }
 }
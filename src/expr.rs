@@ -0,0 +1,394 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Rewriting of regular expressions, before they are turned into automata.
+//!
+//! There are two kinds of rewrites in this module. The first kind (`simplify`) doesn't change
+//! the meaning of the regular expression, but it makes the automata that we build from it
+//! smaller. The second kind are "degradations": they make the language of the regular expression
+//! bigger, in exchange for making its automaton smaller. They are used by `Regex::new_adaptive`
+//! when a regex would otherwise need too many states.
+
+use range_map::{Range, RangeSet};
+use regex_syntax::{CharClass, ClassRange, Expr, Repeater};
+use std::char;
+use std::cmp::min;
+
+/// Controls how `Regex::new_adaptive` simplifies a regex that needs too many states.
+///
+/// The simplifications are tried in order (first coarsening character classes, then capping
+/// repetitions), and each one is only applied if the regex still needs too many states.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdaptivePolicy {
+    /// The maximum number of states that the regex may use.
+    pub max_states: usize,
+    /// If this is `Some(n)`, character classes that contain non-ASCII characters and that consist
+    /// of more than `n` ranges are replaced by their ASCII part, together with all non-ASCII
+    /// characters. For example, a unicode-aware `\w` becomes `[0-9A-Z_a-z\x{80}-\x{10FFFF}]`.
+    pub max_class_ranges: Option<usize>,
+    /// If this is `Some(n)`, counted repetitions like `x{a,b}` with `a` or `b` bigger than `n`
+    /// are replaced by `x{min(a, n),}`.
+    pub max_repeat: Option<u32>,
+}
+
+impl Default for AdaptivePolicy {
+    fn default() -> AdaptivePolicy {
+        AdaptivePolicy {
+            max_states: 10000,
+            max_class_ranges: Some(8),
+            max_repeat: Some(16),
+        }
+    }
+}
+
+/// A simplification that was applied by `Regex::new_adaptive`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Degradation {
+    /// This many character classes were replaced by coarser ones.
+    CoarsenedClasses(usize),
+    /// This many counted repetitions were replaced by unbounded ones.
+    CappedRepetitions(usize),
+}
+
+// Rewrites `expr` from the top down. Whenever `f` returns `Some(e)`, the current sub-expression
+// is replaced by `e` (and `e` itself is not rewritten any further).
+fn rewrite<F>(expr: &Expr, f: &mut F) -> Expr where F: FnMut(&Expr) -> Option<Expr> {
+    use regex_syntax::Expr::*;
+
+    if let Some(e) = f(expr) {
+        return e;
+    }
+
+    match *expr {
+        Concat(ref es) => Concat(es.iter().map(|e| rewrite(e, f)).collect()),
+        Alternate(ref es) => Alternate(es.iter().map(|e| rewrite(e, f)).collect()),
+        Repeat { ref e, r, greedy } =>
+            Repeat { e: Box::new(rewrite(e, f)), r: r, greedy: greedy },
+        Group { ref e, i, ref name } =>
+            Group { e: Box::new(rewrite(e, f)), i: i, name: name.clone() },
+        ref e => e.clone(),
+    }
+}
+
+// If `expr` begins with a literal, returns the literal, whether it is case insensitive, and the
+// rest of `expr`.
+//
+// Empty literals don't count.
+fn leading_literal(expr: &Expr) -> Option<(&[char], bool, &[Expr])> {
+    let ret = match *expr {
+        Expr::Literal { ref chars, casei } => Some((&chars[..], casei, &[][..])),
+        Expr::Concat(ref es) => match es.first() {
+            Some(&Expr::Literal { ref chars, casei }) => Some((&chars[..], casei, &es[1..])),
+            _ => None,
+        },
+        _ => None,
+    };
+    ret.and_then(|x| if x.0.is_empty() { None } else { Some(x) })
+}
+
+// Makes a concatenation of `es`, flattening nested concatenations, dropping empty expressions,
+// and fusing adjacent literals.
+fn concat(es: Vec<Expr>) -> Expr {
+    fn flatten(es: Vec<Expr>, out: &mut Vec<Expr>) {
+        for e in es {
+            match e {
+                Expr::Concat(inner) => flatten(inner, out),
+                e => out.push(e),
+            }
+        }
+    }
+
+    let mut flat = Vec::with_capacity(es.len());
+    flatten(es, &mut flat);
+
+    let mut ret: Vec<Expr> = Vec::with_capacity(flat.len());
+    for e in flat {
+        match e {
+            Expr::Empty => {},
+            Expr::Literal { ref chars, .. } if chars.is_empty() => {},
+            Expr::Literal { chars, casei } => {
+                if let Some(&mut Expr::Literal { chars: ref mut last, casei: last_casei }) =
+                        ret.last_mut() {
+                    if last_casei == casei {
+                        last.extend(chars);
+                        continue;
+                    }
+                }
+                ret.push(Expr::Literal { chars: chars, casei: casei });
+            },
+            e => ret.push(e),
+        }
+    }
+
+    match ret.len() {
+        0 => Expr::Empty,
+        1 => ret.pop().unwrap(),
+        _ => Expr::Concat(ret),
+    }
+}
+
+// Makes an alternation of `es`, flattening nested alternations and factoring out literal
+// prefixes that are shared by adjacent alternatives.
+//
+// We only factor adjacent alternatives because the order of alternatives determines the match
+// priority: `foo|bar|fob` can't become `fo(o|b)|bar`, because then `fob` would take priority
+// over `bar`.
+fn alternate(es: Vec<Expr>) -> Expr {
+    let mut flat: Vec<Expr> = Vec::with_capacity(es.len());
+    for e in es {
+        match e {
+            Expr::Alternate(inner) => flat.extend(inner),
+            e => flat.push(e),
+        }
+    }
+
+    let first_char = |e: &Expr| leading_literal(e).map(|(chars, casei, _)| (chars[0], casei));
+    let mut ret: Vec<Expr> = Vec::with_capacity(flat.len());
+    let mut i = 0;
+    while i < flat.len() {
+        let first = first_char(&flat[i]);
+        let mut j = i + 1;
+        while first.is_some() && j < flat.len() && first_char(&flat[j]) == first {
+            j += 1;
+        }
+
+        if j - i > 1 {
+            // The unwraps are fine, because everything in flat[i..j] has a leading literal.
+            let (lit, casei, _) = leading_literal(&flat[i]).unwrap();
+            let prefix_len = flat[(i + 1)..j].iter()
+                .map(|e| {
+                    let other = leading_literal(e).unwrap().0;
+                    lit.iter().zip(other.iter()).take_while(|&(a, b)| a == b).count()
+                })
+                .min()
+                .unwrap();
+            let prefix = lit[..prefix_len].to_vec();
+
+            let rests: Vec<Expr> = flat[i..j].iter()
+                .map(|e| {
+                    let (chars, casei, rest) = leading_literal(e).unwrap();
+                    let mut parts = vec![
+                        Expr::Literal { chars: chars[prefix_len..].to_vec(), casei: casei }
+                    ];
+                    parts.extend(rest.iter().cloned());
+                    concat(parts)
+                })
+                .collect();
+            ret.push(concat(vec![Expr::Literal { chars: prefix, casei: casei }, alternate(rests)]));
+        } else {
+            ret.push(flat[i].clone());
+        }
+        i = j;
+    }
+
+    if ret.len() == 1 {
+        ret.pop().unwrap()
+    } else {
+        Expr::Alternate(ret)
+    }
+}
+
+/// Simplifies a regular expression without changing what (or how) it matches.
+///
+/// This removes groups (since we don't support captures, they don't mean anything), flattens
+/// nested concatenations and alternations, fuses adjacent literals, and factors out common
+/// prefixes of adjacent alternatives (so that `foobar|foobaz` becomes `fooba(r|z)`).
+pub fn simplify(expr: &Expr) -> Expr {
+    use regex_syntax::Expr::*;
+
+    match *expr {
+        Group { ref e, .. } => simplify(e),
+        Concat(ref es) => concat(es.iter().map(simplify).collect()),
+        Alternate(ref es) => alternate(es.iter().map(simplify).collect()),
+        Repeat { ref e, r, greedy } => Repeat { e: Box::new(simplify(e)), r: r, greedy: greedy },
+        ref e => e.clone(),
+    }
+}
+
+/// Replaces big, non-ASCII character classes by coarser ones.
+///
+/// Returns the new expression, and the number of classes that were replaced.
+pub fn coarsen_classes(expr: &Expr, max_ranges: usize) -> (Expr, usize) {
+    let mut count = 0;
+    let ret = rewrite(expr, &mut |x| {
+        if let Expr::Class(ref cc) = *x {
+            let non_ascii = cc.iter().any(|r| r.end > '\x7F');
+            if non_ascii && cc.iter().count() > max_ranges {
+                let mut ranges: Vec<ClassRange> = cc.iter()
+                    .filter(|r| r.start <= '\x7F')
+                    .map(|r| ClassRange { start: r.start, end: min(r.end, '\x7F') })
+                    .collect();
+                ranges.push(ClassRange { start: '\u{80}', end: char::MAX });
+                count += 1;
+                return Some(Expr::Class(CharClass::new(ranges)));
+            }
+        }
+        None
+    });
+    (ret, count)
+}
+
+/// Replaces counted repetitions that have big counts by unbounded ones.
+///
+/// Returns the new expression, and the number of repetitions that were replaced.
+pub fn cap_repetitions(expr: &Expr, cap: u32) -> (Expr, usize) {
+    let mut count = 0;
+    let ret = rewrite(expr, &mut |x| {
+        if let Expr::Repeat { ref e, r: Repeater::Range { min: lo, max: hi }, greedy } = *x {
+            if lo > cap || hi.map_or(false, |hi| hi > cap) {
+                count += 1;
+                let (inner, inner_count) = cap_repetitions(e, cap);
+                count += inner_count;
+                return Some(Expr::Repeat {
+                    e: Box::new(inner),
+                    r: Repeater::Range { min: min(lo, cap), max: None },
+                    greedy: greedy,
+                });
+            }
+        }
+        None
+    });
+    (ret, count)
+}
+
+/// If `expr` only matches strings of some fixed length, and the chars that are allowed at each
+/// position don't depend on the chars at other positions, returns the sets of allowed chars. For
+/// example, `AC[GT].` gives four sets. Returns `None` for anything more complicated, such as an
+/// alternation or an anchor.
+pub fn fixed_positions(expr: &Expr) -> Option<Vec<RangeSet<u32>>> {
+    let mut ret = Vec::new();
+    if add_positions(expr, &mut ret) {
+        Some(ret)
+    } else {
+        None
+    }
+}
+
+// Appends the sets of allowed chars for `expr` to `out`, returning false if `expr` doesn't have
+// fixed positions (and then `out` contains junk).
+fn add_positions(expr: &Expr, out: &mut Vec<RangeSet<u32>>) -> bool {
+    fn class_to_set(cc: &CharClass) -> RangeSet<u32> {
+        cc.iter().map(|r| Range::new(r.start as u32, r.end as u32)).collect()
+    }
+
+    match *expr {
+        Expr::Empty => true,
+        Expr::Literal { ref chars, casei } => {
+            for &ch in chars {
+                let cc = CharClass::new(vec![ClassRange { start: ch, end: ch }]);
+                out.push(class_to_set(&if casei { cc.case_fold() } else { cc }));
+            }
+            true
+        },
+        Expr::Class(ref cc) => {
+            out.push(class_to_set(cc));
+            true
+        },
+        Expr::AnyChar => {
+            out.push(RangeSet::full());
+            true
+        },
+        Expr::AnyCharNoNL => {
+            out.push(RangeSet::except(b"\n\r".into_iter().map(|b| *b as u32)));
+            true
+        },
+        Expr::Group { ref e, .. } => add_positions(e, out),
+        Expr::Concat(ref es) => es.iter().all(|e| add_positions(e, out)),
+        Expr::Repeat { ref e, r: Repeater::Range { min: lo, max: Some(hi) }, .. } if lo == hi =>
+            (0..lo).all(|_| add_positions(e, out)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex_syntax::Expr;
+
+    fn parse(re: &str) -> Expr {
+        Expr::parse(re).unwrap()
+    }
+
+    #[test]
+    fn fixed_positions() {
+        let pos = super::fixed_positions(&parse("A(?:C[GT]){2}.(?i)x")).unwrap();
+        assert_eq!(pos.len(), 7);
+        assert_eq!(pos[0], RangeSet::single('A' as u32));
+        assert_eq!(pos[2], pos[4]);
+        assert!(pos[5].contains('A' as u32) && !pos[5].contains('\n' as u32));
+        assert!(pos[6].contains('x' as u32) && pos[6].contains('X' as u32));
+
+        assert_eq!(super::fixed_positions(&parse("")), Some(vec![]));
+        assert_eq!(super::fixed_positions(&parse("a|b")), None);
+        assert_eq!(super::fixed_positions(&parse("ab?")), None);
+        assert_eq!(super::fixed_positions(&parse("^ab")), None);
+    }
+
+    #[test]
+    fn simplify() {
+        fn simp(re: &str, target: &str) {
+            assert_eq!(super::simplify(&parse(re)), super::simplify(&parse(target)));
+        }
+
+        fn lit(s: &str) -> Expr {
+            Expr::Literal { chars: s.chars().collect(), casei: false }
+        }
+
+        simp("foo(?:bar|baz|bat)", "fooba(r|z|t)");
+        simp("foobar|foobaz|foobat", "fooba(r|z|t)");
+        simp("a(?:b(?:c))d", "abcd");
+        simp("(a|(b|c))|d", "a|b|c|d");
+
+        // Only adjacent alternatives get factored.
+        assert_eq!(super::simplify(&parse("foo|bar|fob")),
+                   Expr::Alternate(vec![lit("foo"), lit("bar"), lit("fob")]));
+        // Alternatives that are used up by the common prefix become empty.
+        assert_eq!(super::simplify(&parse("ab|a|ac")),
+                   Expr::Concat(vec![
+                       lit("a"),
+                       Expr::Alternate(vec![lit("b"), Expr::Empty, lit("c")]),
+                   ]));
+        // Case-insensitive literals aren't fused with case-sensitive ones.
+        match super::simplify(&parse("a(?i:b)c")) {
+            Expr::Concat(ref es) => assert_eq!(es.len(), 3),
+            ref e => panic!("expected a concatenation, found {:?}", e),
+        }
+
+        assert_eq!(super::simplify(&parse("foobar|foobaz")),
+                   Expr::Concat(vec![
+                       Expr::Literal { chars: "fooba".chars().collect(), casei: false },
+                       Expr::Alternate(vec![
+                           Expr::Literal { chars: vec!['r'], casei: false },
+                           Expr::Literal { chars: vec!['z'], casei: false },
+                       ]),
+                   ]));
+    }
+
+    #[test]
+    fn coarsen() {
+        let (e, count) = coarsen_classes(&parse(r"\w+"), 8);
+        assert_eq!(count, 1);
+        assert_eq!(e, parse(r"[0-9A-Z_a-z\x{80}-\x{10FFFF}]+"));
+
+        // ASCII-only classes and small classes are left alone.
+        let (e, count) = coarsen_classes(&parse("[a-z][0-9]|[éè]"), 1);
+        assert_eq!(count, 0);
+        assert_eq!(e, parse("[a-z][0-9]|[éè]"));
+    }
+
+    #[test]
+    fn cap() {
+        let (e, count) = cap_repetitions(&parse("a{2,100}b{3}(c{50})*"), 10);
+        assert_eq!(count, 2);
+        assert_eq!(e, parse("a{2,}b{3}(c{10,})*"));
+
+        let (e, count) = cap_repetitions(&parse("(a{20}){30}"), 10);
+        assert_eq!(count, 2);
+        assert_eq!(e, parse("(a{10,}){10,}"));
+    }
+}
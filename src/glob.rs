@@ -0,0 +1,314 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use charset::CharSet;
+use error::Error;
+use regex_syntax::{CharClass, ClassRange, Expr, Repeater};
+use std;
+
+/// Options for compiling a glob pattern. See `Regex::from_glob`.
+///
+/// The syntax is the usual one for shell globs:
+///
+/// - `?` matches any single char,
+/// - `*` matches any sequence of chars,
+/// - `[abc]` and `[a-z]` match any of the chars in the brackets, and `[!abc]` (or `[^abc]`)
+///   matches any char that isn't in the brackets. A `]` right after the opening bracket is part
+///   of the set, as is a `-` at the beginning or the end.
+/// - `**` matches any sequence of path components (see `recursive`),
+/// - `\` makes the char after it match literally, unless `\` is the separator.
+///
+/// The glob must match the whole string.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GlobOptions {
+    /// The char that separates path components. The default is `/`.
+    pub separator: char,
+    /// If true (the default), `?`, `*`, and the bracketed sets never match the separator, so
+    /// `*.rs` matches `lib.rs` but not `src/lib.rs`.
+    pub literal_separator: bool,
+    /// If true (the default), `**` as a whole path component matches any number of components:
+    /// `src/**/*.rs` matches `src/lib.rs` and `src/runner/mod.rs`, and a trailing `/**` matches
+    /// everything below a directory. Anywhere else, `**` is the same as `*`.
+    pub recursive: bool,
+    /// Whether to ignore case. The default is false.
+    pub case_insensitive: bool,
+    /// The maximum number of states that the compiled `Regex` may have.
+    pub max_states: usize,
+}
+
+impl Default for GlobOptions {
+    fn default() -> GlobOptions {
+        GlobOptions {
+            separator: '/',
+            literal_separator: true,
+            recursive: true,
+            case_insensitive: false,
+            max_states: std::usize::MAX,
+        }
+    }
+}
+
+fn class(set: &CharSet) -> Expr {
+    let ranges = set.ranges().iter().map(|&(lo, hi)| ClassRange { start: lo, end: hi }).collect();
+    Expr::Class(CharClass::new(ranges))
+}
+
+fn any_char() -> Expr {
+    class(&CharSet::from_ranges(&[('\0', std::char::MAX)]))
+}
+
+fn star(e: Expr) -> Expr {
+    Expr::Repeat { e: Box::new(e), r: Repeater::ZeroOrMore, greedy: true }
+}
+
+struct Translator<'a> {
+    options: &'a GlobOptions,
+    chars: Vec<(usize, char)>,
+    pos: usize,
+    out: Vec<Expr>,
+    lit: Vec<char>,
+}
+
+impl<'a> Translator<'a> {
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).map(|&(_, c)| c)
+    }
+
+    fn push(&mut self, e: Expr) {
+        if !self.lit.is_empty() {
+            let chars = std::mem::replace(&mut self.lit, Vec::new());
+            self.out.push(Expr::Literal { chars: chars, casei: self.options.case_insensitive });
+        }
+        self.out.push(e);
+    }
+
+    // A single char other than the separator (or any char at all, if the separator isn't
+    // special).
+    fn not_separator(&self, set: CharSet) -> CharSet {
+        if self.options.literal_separator {
+            let sep = self.options.separator;
+            set.negated().union(&CharSet::from_ranges(&[(sep, sep)])).negated()
+        } else {
+            set
+        }
+    }
+
+    fn translate(mut self) -> ::Result<Expr> {
+        self.out.push(Expr::StartText);
+        while let Some(c) = self.peek(0) {
+            match c {
+                '?' => {
+                    let set = self.not_separator(CharSet::from_ranges(&[('\0', std::char::MAX)]));
+                    self.push(class(&set));
+                    self.pos += 1;
+                },
+                '*' => self.star(),
+                '[' => { try!(self.bracket()); },
+                '\\' if self.options.separator != '\\' => {
+                    match self.peek(1) {
+                        Some(next) => self.lit.push(next),
+                        None => {
+                            let pos = self.chars[self.pos].0;
+                            return Err(Error::GlobSyntax(pos, "trailing backslash".to_owned()));
+                        },
+                    }
+                    self.pos += 2;
+                },
+                _ => {
+                    self.lit.push(c);
+                    self.pos += 1;
+                },
+            }
+        }
+        self.push(Expr::EndText);
+        Ok(Expr::Concat(self.out))
+    }
+
+    fn star(&mut self) {
+        let sep = self.options.separator;
+        let start = self.pos;
+        while self.peek(0) == Some('*') {
+            self.pos += 1;
+        }
+
+        let component_start = start == 0 || self.chars[start - 1].1 == sep;
+        let component_end = self.peek(0).map_or(true, |c| c == sep);
+        if self.options.recursive && self.pos - start >= 2 && component_start && component_end {
+            if self.peek(0).is_none() {
+                // A trailing `**` matches everything that's left.
+                self.push(star(any_char()));
+            } else {
+                // `**/` matches any number of whole components, including none.
+                self.pos += 1;
+                let components = Expr::Concat(vec![
+                    star(any_char()),
+                    Expr::Literal { chars: vec![sep], casei: false },
+                ]);
+                self.push(Expr::Repeat {
+                    e: Box::new(components),
+                    r: Repeater::ZeroOrOne,
+                    greedy: true,
+                });
+            }
+        } else {
+            let set = self.not_separator(CharSet::from_ranges(&[('\0', std::char::MAX)]));
+            self.push(star(class(&set)));
+        }
+    }
+
+    fn bracket(&mut self) -> ::Result<()> {
+        let open = self.chars[self.pos].0;
+        self.pos += 1;
+        let negated = match self.peek(0) {
+            Some('!') | Some('^') => { self.pos += 1; true },
+            _ => false,
+        };
+
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let lo = match self.peek(0) {
+                None => {
+                    return Err(Error::GlobSyntax(open, "unclosed character class".to_owned()));
+                },
+                Some(']') if !first => break,
+                Some(c) => c,
+            };
+            first = false;
+            match (self.peek(1), self.peek(2)) {
+                (Some('-'), Some(hi)) if hi != ']' => {
+                    if hi < lo {
+                        let pos = self.chars[self.pos].0;
+                        let msg = format!("invalid range {}-{}", lo, hi);
+                        return Err(Error::GlobSyntax(pos, msg));
+                    }
+                    ranges.push(ClassRange { start: lo, end: hi });
+                    self.pos += 3;
+                },
+                _ => {
+                    ranges.push(ClassRange { start: lo, end: lo });
+                    self.pos += 1;
+                },
+            }
+        }
+        // Skip the closing bracket.
+        self.pos += 1;
+
+        let mut cc = CharClass::new(ranges);
+        if self.options.case_insensitive {
+            cc = cc.case_fold();
+        }
+        let ranges: Vec<(char, char)> = cc.iter().map(|r| (r.start, r.end)).collect();
+        let set = CharSet::from_ranges(&ranges);
+        let set = self.not_separator(if negated { set.negated() } else { set });
+        self.push(class(&set));
+        Ok(())
+    }
+}
+
+/// Translates a glob into a regular expression that matches the same strings.
+pub fn to_expr(glob: &str, options: &GlobOptions) -> ::Result<Expr> {
+    Translator {
+        options: options,
+        chars: glob.char_indices().collect(),
+        pos: 0,
+        out: Vec::new(),
+        lit: Vec::new(),
+    }.translate()
+}
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+    use regex::Regex;
+    use super::GlobOptions;
+
+    fn glob_matches(glob: &str, options: &GlobOptions, s: &str) -> bool {
+        Regex::from_glob(glob, options).unwrap().is_match(s)
+    }
+
+    #[test]
+    fn glob() {
+        let opts = GlobOptions::default();
+        let cases = [
+            ("*.rs", "lib.rs", true),
+            ("*.rs", "src/lib.rs", false),
+            ("*.rs", "lib.rs.orig", false),
+            ("src/*.rs", "src/lib.rs", true),
+            ("src/*", "src/", true),
+            ("?.txt", "a.txt", true),
+            ("?.txt", "ab.txt", false),
+            ("a?b", "a/b", false),
+            ("[abc].txt", "b.txt", true),
+            ("[abc].txt", "d.txt", false),
+            ("[!abc].txt", "d.txt", true),
+            ("[^abc].txt", "a.txt", false),
+            ("[!abc]", "/", false),
+            ("[a-z]x", "qx", true),
+            ("[a-z]x", "Qx", false),
+            ("[]]", "]", true),
+            ("[a-]", "-", true),
+            ("\\*", "*", true),
+            ("\\*", "a", false),
+            ("src/**/*.rs", "src/lib.rs", true),
+            ("src/**/*.rs", "src/runner/mod.rs", true),
+            ("src/**/*.rs", "src/a/b/c.rs", true),
+            ("src/**/*.rs", "lib.rs", false),
+            ("**/*.rs", "lib.rs", true),
+            ("**/*.rs", "a/b/lib.rs", true),
+            ("src/**", "src/a/b", true),
+            ("src/**", "tests/a", false),
+            ("a**b", "axxb", true),
+            ("a**b", "a/b", false),
+            ("λ*", "λόγος", true),
+        ];
+        for &(glob, s, expected) in &cases {
+            assert_eq!(glob_matches(glob, &opts, s), expected, "{} on {}", glob, s);
+        }
+    }
+
+    #[test]
+    fn options() {
+        let opts = GlobOptions { literal_separator: false, ..GlobOptions::default() };
+        assert!(glob_matches("*.rs", &opts, "src/lib.rs"));
+        assert!(glob_matches("a?b", &opts, "a/b"));
+
+        let opts = GlobOptions { recursive: false, ..GlobOptions::default() };
+        assert!(glob_matches("src/**/*.rs", &opts, "src/runner/mod.rs"));
+        assert!(!glob_matches("src/**/*.rs", &opts, "src/lib.rs"));
+        assert!(!glob_matches("src/**/*.rs", &opts, "src/a/b/c.rs"));
+
+        let opts = GlobOptions { separator: '\\', ..GlobOptions::default() };
+        assert!(glob_matches("src/*.rs", &opts, "src/lib.rs"));
+        assert!(glob_matches("*.rs", &opts, "src/lib.rs"));
+        assert!(glob_matches("src\\**\\*.rs", &opts, "src\\runner\\mod.rs"));
+        assert!(!glob_matches("*.rs", &opts, "src\\lib.rs"));
+
+        let opts = GlobOptions { case_insensitive: true, ..GlobOptions::default() };
+        assert!(glob_matches("*.RS", &opts, "lib.rs"));
+        assert!(glob_matches("[a-z].txt", &opts, "Q.TXT"));
+        assert!(!glob_matches("[!a-z].txt", &opts, "Q.txt"));
+    }
+
+    #[test]
+    fn syntax_error() {
+        let opts = GlobOptions::default();
+        match Regex::from_glob("ab[cd", &opts) {
+            Err(Error::GlobSyntax(2, _)) => {},
+            x => panic!("expected a syntax error, got {:?}", x),
+        }
+        match Regex::from_glob("ab\\", &opts) {
+            Err(Error::GlobSyntax(2, _)) => {},
+            x => panic!("expected a syntax error, got {:?}", x),
+        }
+        match Regex::from_glob("[z-a]", &opts) {
+            Err(Error::GlobSyntax(1, _)) => {},
+            x => panic!("expected a syntax error, got {:?}", x),
+        }
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/// Input that might be stored in several pieces.
+///
+/// Text editors keep their buffers in ropes or piece tables, and network code receives its input
+/// in packets; copying all of that into one contiguous buffer just to search it is wasteful. The
+/// matchers that only need one forward pass over the input (`StreamDetector::is_match_in` and
+/// `LongestMatcher::longest_match_in`) accept any `Haystack`, and see its chunks as though they
+/// were joined together. Chunks may split UTF-8 sequences, and they may be empty.
+///
+/// ```rust
+/// use regex_dfa::StreamDetector;
+///
+/// let det = StreamDetector::new(r"\bfoo\b").unwrap();
+/// let rope: &[&str] = &["a fo", "o b"];
+/// assert!(det.is_match_in(rope));
+/// assert!(det.is_match_in("a foo b"));
+/// assert!(!det.is_match_in(&b"a food"[..]));
+/// ```
+pub trait Haystack {
+    /// Calls `f` on each chunk of the input in order, until `f` returns false or the input runs
+    /// out.
+    fn for_each_chunk(&self, f: &mut FnMut(&[u8]) -> bool);
+}
+
+impl Haystack for str {
+    fn for_each_chunk(&self, f: &mut FnMut(&[u8]) -> bool) {
+        f(self.as_bytes());
+    }
+}
+
+impl Haystack for [u8] {
+    fn for_each_chunk(&self, f: &mut FnMut(&[u8]) -> bool) {
+        f(self);
+    }
+}
+
+impl<'a> Haystack for [&'a str] {
+    fn for_each_chunk(&self, f: &mut FnMut(&[u8]) -> bool) {
+        for chunk in self {
+            if !f(chunk.as_bytes()) {
+                break;
+            }
+        }
+    }
+}
+
+impl<'a> Haystack for [&'a [u8]] {
+    fn for_each_chunk(&self, f: &mut FnMut(&[u8]) -> bool) {
+        for chunk in self {
+            if !f(chunk) {
+                break;
+            }
+        }
+    }
+}
+
+impl<'a, H: Haystack + ?Sized> Haystack for &'a H {
+    fn for_each_chunk(&self, f: &mut FnMut(&[u8]) -> bool) {
+        (**self).for_each_chunk(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Haystack;
+
+    fn collect<H: Haystack + ?Sized>(h: &H, max_chunks: usize) -> Vec<u8> {
+        let mut ret = Vec::new();
+        let mut count = 0;
+        h.for_each_chunk(&mut |chunk: &[u8]| {
+            ret.extend_from_slice(chunk);
+            count += 1;
+            count < max_chunks
+        });
+        ret
+    }
+
+    #[test]
+    fn chunks() {
+        assert_eq!(collect("abc", 10), b"abc");
+        assert_eq!(collect(&b"abc"[..], 10), b"abc");
+        let pieces: &[&str] = &["a", "", "bc", "d"];
+        assert_eq!(collect(pieces, 10), b"abcd");
+        assert_eq!(collect(&pieces, 10), b"abcd");
+        assert_eq!(collect(pieces, 3), b"abc");
+        let pieces: &[&[u8]] = &[b"\xce", b"\xb1"];
+        assert_eq!(collect(pieces, 10), "α".as_bytes());
+    }
+}
@@ -21,6 +21,28 @@ assert_eq!(re.find("My birthday is 1986-08-22!"), Some((15, 25)));
 The most useful function in this crate is `Regex::find`, which looks for the first substring of the
 given string that match the language of the DFA.
 
+# Match semantics
+
+Matches follow the same "leftmost-first" rules as Perl and the `regex` crate: `find` returns the
+match that starts earliest, and among the matches starting there, it prefers the one that a
+backtracking engine would find first. In particular, earlier alternatives take priority over later
+ones, even if they give shorter matches.
+
+```rust
+use regex_dfa::Regex;
+assert_eq!(Regex::new("sam|samwise").unwrap().find("samwise"), Some((0, 3)));
+assert_eq!(Regex::new("samwise|sam").unwrap().find("samwise"), Some((0, 7)));
+assert_eq!(Regex::new("a+?").unwrap().find("aaa"), Some((0, 1)));
+```
+
+These priorities are resolved while the DFA is being built (and so they cost nothing at match
+time), which also means that the DFA can't tell you which of the alternatives matched.
+
+Other engines have a fast path for "one-pass" regexes, where there is never more than one way to
+continue a match. Here, every regex gets that treatment: the DFA is in exactly one state after
+each byte, and it never backtracks or tracks several threads, so there's no special case for
+one-pass regexes (or any need to detect them).
+
 # Comparison to the `regex` crate
 
 Compared to rust's standard `regex` crate, the main feature of `regex_dfa` is that `regex_dfa`
@@ -59,7 +81,7 @@ compiler plugin story stabilizes a bit.
 */
 
 #![cfg_attr(test, feature(test))]
-#[cfg(test)]
+#[cfg(any(test, feature = "quickcheck"))]
 extern crate quickcheck;
 
 #[cfg(test)]
@@ -72,9 +94,24 @@ extern crate rand;
 #[cfg(test)]
 extern crate test;
 
+#[cfg(feature = "memmap")]
+extern crate memmap;
+
+#[cfg(feature = "serde")]
+extern crate serde;
+
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+
 extern crate itertools;
+#[cfg(feature = "memchr")]
 extern crate memchr;
 extern crate num_traits;
+#[cfg(feature = "rayon")]
+extern crate rayon;
 extern crate range_map;
 extern crate refinery;
 extern crate regex_syntax;
@@ -83,16 +120,67 @@ extern crate utf8_ranges;
 #[macro_use]
 extern crate lazy_static;
 
+#[macro_use]
+mod encode;
+mod analyze;
+mod byte_search;
+mod cache;
+mod charset;
 mod dfa;
 mod error;
+mod expr;
+mod glob;
+mod haystack;
+mod line_index;
 mod look;
+mod longest;
+mod like;
+mod lstar;
 mod graph;
 mod nfa;
+mod pattern_set;
 mod regex;
 mod runner;
+mod searcher;
+mod signature;
+mod static_tables;
+mod transducer;
 mod unicode;
-
-pub use error::Error;
-pub use regex::Regex;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+pub use analyze::PatternReport;
+pub use cache::ProgramCache;
+pub use charset::CharSet;
+pub use dfa::{AcceptDiff, Dfa, DfaDiff, DfaError, RetTrait, TransitionDiff};
+#[cfg(feature = "quickcheck")]
+pub use dfa::DfaInput;
+pub use error::{BlowupReport, Error, TimeoutReport};
+pub use expr::{AdaptivePolicy, Degradation};
+pub use glob::GlobOptions;
+pub use haystack::Haystack;
+pub use line_index::LineIndex;
+pub use look::Look;
+pub use longest::{LongestMatcher, LongestMatcherSet};
+pub use like::LikeOptions;
+pub use lstar::LearnedDfa;
+pub use nfa::{Accept, DeterminizeOptions, DeterminizeStats, ExploreOrder, InvalidUtf8};
+pub use pattern_set::PatternSet;
+#[cfg(feature = "memmap")]
+pub use regex::FileMatches;
+#[cfg(feature = "profile")]
+pub use regex::Profile;
+pub use regex::{ByteSet, CharMatches, Compiler, Coverage, Detector, DetectorStream, EmptyMatches,
+                GrepLines, IntoMatches, Literals, MatchCache, MatchContext, MatchEnd, Matches,
+                MultiCompiler, NoMatch, RecordMatches, Regex, RegexOptions, SingleByteRegex, Stage,
+                StreamDetector, Utf16Regex};
+pub use runner::forward_backward::PrefilterOptions;
+pub use runner::program::{Disassembly, InstInfo, TraceEvent};
+pub use searcher::Searcher;
+pub use signature::{SignatureHits, SignatureScanner};
+pub use static_tables::StaticTables;
+pub use transducer::Transducer;
+#[cfg(feature = "wasm")]
+pub use wasm::WasmRegex;
 pub type Result<T> = ::std::result::Result<T, Error>;
 
@@ -0,0 +1,142 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use error::Error;
+use regex_syntax::{CharClass, ClassRange, Expr, Repeater};
+use std;
+
+/// Options for compiling an SQL `LIKE` pattern. See `Regex::from_like`.
+///
+/// In a `LIKE` pattern, `%` matches any sequence of chars, `_` matches any single char, and the
+/// escape char makes the char after it match literally (so with the default escape char, `\%`
+/// matches a percent sign). Every other char matches itself. As in SQL, the pattern must match
+/// the whole string.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LikeOptions {
+    /// The escape char, as in `LIKE 'a!%' ESCAPE '!'`. The default is `\`; `None` turns escaping
+    /// off.
+    pub escape: Option<char>,
+    /// If true, letters match regardless of case, as in `ILIKE`. The default is false.
+    pub case_insensitive: bool,
+    /// The maximum number of states that the compiled `Regex` may have.
+    pub max_states: usize,
+}
+
+impl Default for LikeOptions {
+    fn default() -> LikeOptions {
+        LikeOptions {
+            escape: Some('\\'),
+            case_insensitive: false,
+            max_states: std::usize::MAX,
+        }
+    }
+}
+
+/// Translates a `LIKE` pattern into a regular expression that matches the same strings.
+pub fn to_expr(pattern: &str, options: &LikeOptions) -> ::Result<Expr> {
+    let all = ClassRange { start: '\0', end: std::char::MAX };
+    let any = || Expr::Class(CharClass::new(vec![all]));
+    let casei = options.case_insensitive;
+    let mut out = vec![Expr::StartText];
+    let mut lit = Vec::new();
+    let flush = |lit: &mut Vec<char>, out: &mut Vec<Expr>| {
+        if !lit.is_empty() {
+            out.push(Expr::Literal { chars: std::mem::replace(lit, Vec::new()), casei: casei });
+        }
+    };
+
+    let mut chars = pattern.char_indices();
+    while let Some((pos, c)) = chars.next() {
+        if Some(c) == options.escape {
+            match chars.next() {
+                Some((_, next)) => lit.push(next),
+                None => {
+                    let msg = "the pattern ends with the escape char".to_owned();
+                    return Err(Error::LikeSyntax(pos, msg));
+                },
+            }
+        } else if c == '%' {
+            flush(&mut lit, &mut out);
+            out.push(Expr::Repeat { e: Box::new(any()), r: Repeater::ZeroOrMore, greedy: true });
+        } else if c == '_' {
+            flush(&mut lit, &mut out);
+            out.push(any());
+        } else {
+            lit.push(c);
+        }
+    }
+    flush(&mut lit, &mut out);
+    out.push(Expr::EndText);
+    Ok(Expr::Concat(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+    use regex::Regex;
+    use super::LikeOptions;
+
+    fn like(pattern: &str, options: &LikeOptions, s: &str) -> bool {
+        Regex::from_like(pattern, options).unwrap().is_match(s)
+    }
+
+    #[test]
+    fn like_patterns() {
+        let opts = LikeOptions::default();
+        let cases = [
+            ("abc", "abc", true),
+            ("abc", "abcd", false),
+            ("abc", "ABC", false),
+            ("a%", "a", true),
+            ("a%", "abc", true),
+            ("a%", "ba", false),
+            ("%b%", "abc", true),
+            ("%b%", "ac", false),
+            ("%", "", true),
+            ("%", "a\nb", true),
+            ("a_c", "abc", true),
+            ("a_c", "ac", false),
+            ("a_c", "a\nc", true),
+            ("_", "λ", true),
+            ("__", "λ", false),
+            ("100\\%", "100%", true),
+            ("100\\%", "1000", false),
+            ("a\\_c", "a_c", true),
+            ("a\\_c", "abc", false),
+            ("a\\\\", "a\\", true),
+            ("a.*", "a.*", true),
+            ("a.*", "abc", false),
+        ];
+        for &(pattern, s, expected) in &cases {
+            assert_eq!(like(pattern, &opts, s), expected, "{} on {}", pattern, s);
+        }
+    }
+
+    #[test]
+    fn options() {
+        let opts = LikeOptions { case_insensitive: true, ..LikeOptions::default() };
+        assert!(like("abc%", &opts, "ABCD"));
+        assert!(like("_όγο%", &opts, "ΛΌΓΟΣ"));
+
+        let opts = LikeOptions { escape: Some('!'), ..LikeOptions::default() };
+        assert!(like("a!%", &opts, "a%"));
+        assert!(!like("a!%", &opts, "ab"));
+        assert!(like("a\\%", &opts, "a\\b"));
+
+        let opts = LikeOptions { escape: None, ..LikeOptions::default() };
+        assert!(like("a\\", &opts, "a\\"));
+    }
+
+    #[test]
+    fn syntax_error() {
+        match Regex::from_like("ab\\", &LikeOptions::default()) {
+            Err(Error::LikeSyntax(2, _)) => {},
+            x => panic!("expected a syntax error, got {:?}", x),
+        }
+    }
+}
@@ -0,0 +1,273 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use longest::LongestMatcher;
+use std::ops::Range;
+
+/// Splits a document into tokens, and keeps the tokens up to date as lines are edited.
+///
+/// At every position, the token is the longest match of a `LongestMatcher` (if there is no
+/// non-empty match, one char is skipped). Tokens can span lines, as with block comments. After
+/// an edit, only the tokens whose scan could have seen the edited text are redone, and scanning
+/// stops as soon as it gets back in step with the old tokens. This is the usual way that editors
+/// keep their syntax highlighting up to date.
+///
+/// ```rust
+/// use regex_dfa::{LineIndex, LongestMatcher};
+///
+/// let m = LongestMatcher::new(r"/\*([^*]|\*[^/])*\*/|[a-z]+").unwrap();
+/// let mut index = LineIndex::new(&m, "a /* b\nc */ d\ne");
+/// assert_eq!(index.line_tokens(0), &[(0, 1), (2, 11)]);
+/// assert_eq!(index.line_tokens(1), &[(12, 13)]);
+///
+/// index.replace_line(0, "a b");
+/// assert_eq!(index.text(), "a b\nc */ d\ne");
+/// assert_eq!(index.line_tokens(1), &[(4, 5), (9, 10)]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct LineIndex<'a> {
+    matcher: &'a LongestMatcher,
+    text: String,
+    // The byte offset of the start of each line.
+    line_starts: Vec<usize>,
+    // Every place where we looked for a token, in order. Together they cover the whole text.
+    scans: Vec<Scan>,
+    // The tokens, which are the `Scan`s that found something.
+    tokens: Vec<(usize, usize)>,
+}
+
+// The result of looking for a token at one position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Scan {
+    start: usize,
+    // The end of the token, if there is one.
+    end: Option<usize>,
+    // The automaton looked at everything before this position (and it is `text.len() + 1` if it
+    // looked at the end of the text). Edits after this position don't affect the scan.
+    extent: usize,
+}
+
+impl Scan {
+    // The position of the next scan.
+    fn next(&self, text: &str) -> usize {
+        match self.end {
+            Some(end) => end,
+            None => self.start + text[self.start..].chars().next().map_or(1, |c| c.len_utf8()),
+        }
+    }
+
+    fn shifted(&self, removed: usize, added: usize) -> Scan {
+        Scan {
+            start: self.start - removed + added,
+            end: self.end.map(|e| e - removed + added),
+            extent: self.extent - removed + added,
+        }
+    }
+}
+
+// Looks for a token at `pos`, driving the automaton one char at a time so that we know how far
+// it looked.
+fn scan(m: &LongestMatcher, text: &str, pos: usize) -> Scan {
+    // Even if nothing can start here, we need the char at `pos` to know how far to skip.
+    let mut ret = Scan { start: pos, end: None, extent: pos + 1 };
+    let mut state = match m.start_state(text[..pos].chars().next_back()) {
+        Some(s) => s,
+        None => { return ret; },
+    };
+
+    let mut cur = pos;
+    let mut chars = text[pos..].chars();
+    loop {
+        let next = chars.next();
+        ret.extent = next.map_or(text.len() + 1, |c| cur + c.len_utf8());
+        if cur > pos && m.accept_status(state, next) {
+            ret.end = Some(cur);
+        }
+        match next.and_then(|c| m.next_state(state, c).map(|s| (c, s))) {
+            Some((c, s)) => {
+                state = s;
+                cur += c.len_utf8();
+            },
+            None => { return ret; },
+        }
+    }
+}
+
+impl<'a> LineIndex<'a> {
+    /// Splits `text` into tokens using `matcher`.
+    pub fn new(matcher: &'a LongestMatcher, text: &str) -> LineIndex<'a> {
+        let mut ret = LineIndex {
+            matcher: matcher,
+            text: text.to_owned(),
+            line_starts: Vec::new(),
+            scans: Vec::new(),
+            tokens: Vec::new(),
+        };
+        ret.scans = ret.scan_from(0, text.len(), 0, 0, &[]);
+        ret.update_lines_and_tokens();
+        ret
+    }
+
+    /// The current text of the document.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The number of lines in the document. (A document that ends with a newline has an empty
+    /// last line.)
+    pub fn num_lines(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// All of the tokens, as byte ranges `(start, end)`.
+    pub fn tokens(&self) -> &[(usize, usize)] {
+        &self.tokens
+    }
+
+    /// The tokens that start on the given line (numbered from zero).
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no such line.
+    pub fn line_tokens(&self, line: usize) -> &[(usize, usize)] {
+        let start = self.line_starts[line];
+        let end = self.line_starts.get(line + 1).cloned().unwrap_or(self.text.len() + 1);
+        let first = self.tokens.iter().position(|t| t.0 >= start).unwrap_or(self.tokens.len());
+        let len = self.tokens[first..].iter().take_while(|t| t.0 < end).count();
+        &self.tokens[first..(first + len)]
+    }
+
+    /// Replaces the contents of a line (not including its newline) with `new`, and updates the
+    /// tokens. `new` may contain newlines of its own.
+    ///
+    /// Returns the range of the new text in which the tokens were redone; everything outside it
+    /// is the same as before (apart from being shifted).
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no such line.
+    pub fn replace_line(&mut self, line: usize, new: &str) -> Range<usize> {
+        let start = self.line_starts[line];
+        let old_end = self.text[start..].find('\n').map_or(self.text.len(), |i| start + i);
+        let removed = old_end - start;
+        self.text = format!("{}{}{}", &self.text[..start], new, &self.text[old_end..]);
+
+        // The first scan that could have seen the edit. Every scan before it stays the same.
+        let first = self.scans.iter().position(|s| s.extent > start).unwrap_or(self.scans.len());
+        let resume = self.scans.get(first).map_or(start, |s| s.start);
+
+        let old_scans = self.scans.split_off(first);
+        let new_scans = self.scan_from(resume, start + new.len(), new.len(), removed, &old_scans);
+        let redone_end = new_scans.last().map_or(resume, |s| s.next(&self.text));
+
+        // `scan_from` stopped at a scan that we already had, so the rest can be reused.
+        let resumed_at = redone_end - new.len() + removed;
+        let rest = old_scans.iter()
+            .skip_while(|s| s.start < resumed_at)
+            .map(|s| s.shifted(removed, new.len()));
+        self.scans.extend(new_scans.iter().cloned().chain(rest));
+        self.update_lines_and_tokens();
+        resume..redone_end
+    }
+
+    // Scans the text from `pos` until the end, or until it gets back in step with the `old`
+    // scans. The last edit replaced `removed` bytes with `added` bytes, ending at `edit_end` (in
+    // the new text). Once we are past the end of the edit, the text (and the char before it)
+    // is the same as before, so if one of the old scans started at the corresponding position
+    // then everything from there on would come out the same.
+    fn scan_from(&self, mut pos: usize, edit_end: usize, added: usize, removed: usize,
+                 old: &[Scan])
+    -> Vec<Scan> {
+        let mut ret = Vec::new();
+        while pos < self.text.len() {
+            if pos > edit_end {
+                let old_pos = pos - added + removed;
+                if old.binary_search_by(|s| s.start.cmp(&old_pos)).is_ok() {
+                    break;
+                }
+            }
+            let s = scan(self.matcher, &self.text, pos);
+            pos = s.next(&self.text);
+            ret.push(s);
+        }
+        ret
+    }
+
+    fn update_lines_and_tokens(&mut self) {
+        self.line_starts = Some(0).into_iter()
+            .chain(self.text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        self.tokens = self.scans.iter()
+            .filter_map(|s| s.end.map(|e| (s.start, e)))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use longest::LongestMatcher;
+    use super::LineIndex;
+
+    // Checks that editing gives the same tokens as starting from scratch.
+    fn check_edits(re: &str, text: &str, edits: &[(usize, &str)]) {
+        let m = LongestMatcher::new(re).unwrap();
+        let mut index = LineIndex::new(&m, text);
+        for &(line, new) in edits {
+            let redone = index.replace_line(line, new);
+            let fresh = LineIndex::new(&m, index.text());
+            assert_eq!(index.tokens(), fresh.tokens());
+            assert_eq!(index.scans, fresh.scans);
+            assert_eq!(index.num_lines(), fresh.num_lines());
+            assert!(redone.end <= index.text().len());
+        }
+    }
+
+    #[test]
+    fn tokens() {
+        let m = LongestMatcher::new(r"[a-z]+|[0-9]+").unwrap();
+        let index = LineIndex::new(&m, "ab 12\n\ncd!");
+        assert_eq!(index.tokens(), &[(0, 2), (3, 5), (7, 9)]);
+        assert_eq!(index.num_lines(), 3);
+        assert_eq!(index.line_tokens(0), &[(0, 2), (3, 5)]);
+        assert!(index.line_tokens(1).is_empty());
+        assert_eq!(index.line_tokens(2), &[(7, 9)]);
+
+        let index = LineIndex::new(&m, "");
+        assert_eq!(index.num_lines(), 1);
+        assert!(index.line_tokens(0).is_empty());
+    }
+
+    #[test]
+    fn edits_stay_local() {
+        let m = LongestMatcher::new(r"[a-z]+").unwrap();
+        let mut index = LineIndex::new(&m, "aa\nbb\ncc\ndd");
+        assert_eq!(index.replace_line(1, "b b"), 3..7);
+        assert_eq!(index.tokens(), &[(0, 2), (3, 4), (5, 6), (7, 9), (10, 12)]);
+    }
+
+    #[test]
+    fn edits() {
+        let comments = r"/\*([^*]|\*[^/])*\*/|[a-z]+|\s+";
+        check_edits(comments, "a /* b\nc */ d\ne", &[
+            (0, "a b"), (0, "a /* b"), (1, "c"), (2, "*/ f"), (0, ""), (2, "x\ny\nz"), (4, "/*"),
+        ]);
+
+        // Tokens that depend on what comes before and after them.
+        check_edits(r"\bfoo\b|(?m)^bar$|[a-z]+|[0-9]+", "foo\nbar\nxfoo\nbar1", &[
+            (1, "bar"), (2, "foo"), (3, "bar"), (0, "fo"), (0, "foo bar"), (3, ""), (3, "é"),
+        ]);
+
+        // Tokens where the automaton looks far ahead, and then falls back to a short match.
+        check_edits(r"a|a[^z]*z", "aaa\naaa\naaa", &[
+            (2, "aaz"), (2, "aaa"), (0, "a"), (1, "z"), (1, ""), (0, "éa"),
+        ]);
+
+        // Appending and deleting at the end of the text.
+        check_edits(r"[a-z]+", "ab\n", &[(1, "cd"), (1, ""), (0, ""), (0, "x\n")]);
+    }
+}
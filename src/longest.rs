@@ -0,0 +1,580 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use dfa::Dfa;
+use error::Error;
+use expr;
+use haystack::Haystack;
+use look::Look;
+use nfa::{Accept, Nfa};
+use regex_syntax::Expr;
+use runner::program::{TableInsts, TraceEvent};
+use std;
+use std::cmp::max;
+
+/// Finds the longest match of a regular expression that starts at a given position.
+///
+/// A `Regex` follows the leftmost-first rules (see the crate documentation), so it might stop
+/// before the longest match. Tokenizers usually want the longest match instead (the "maximal
+/// munch" rule), and they already know where the match has to start. A `LongestMatcher` is
+/// compiled for exactly that: it ignores match priorities, and it only tries one position.
+///
+/// ```rust
+/// use regex_dfa::{LongestMatcher, Regex};
+///
+/// let re = "if|[a-z]+";
+/// assert_eq!(Regex::new(re).unwrap().find("iffy"), Some((0, 2)));
+/// assert_eq!(LongestMatcher::new(re).unwrap().longest_match_at("iffy", 0), Some(4));
+/// ```
+#[derive(Clone, Debug)]
+pub struct LongestMatcher {
+    prog: TableInsts<(Look, u8)>,
+    // The initial state of `prog` for each kind of preceding char, indexed by `Look::as_usize`.
+    init: Vec<Option<usize>>,
+}
+
+impl LongestMatcher {
+    /// Creates a new `LongestMatcher` from a regular expression string.
+    pub fn new(re: &str) -> ::Result<LongestMatcher> {
+        LongestMatcher::new_bounded(re, std::usize::MAX)
+    }
+
+    /// Like `new`, but fails if the `LongestMatcher` would require too many states.
+    pub fn new_bounded(re: &str, max_states: usize) -> ::Result<LongestMatcher> {
+        LongestMatcher::from_expr(&try!(Expr::parse(re)), max_states)
+    }
+
+    fn from_expr(expr: &Expr, max_states: usize) -> ::Result<LongestMatcher> {
+        let dfa = try!(LongestMatcher::make_dfa(expr, max_states));
+        Ok(LongestMatcher {
+            prog: dfa.compile(),
+            init: Look::all().iter().map(|&look| dfa.init_state(look)).collect(),
+        })
+    }
+
+    // Makes a `Dfa` that finds the longest match of `expr` starting at the beginning of its input.
+    fn make_dfa(expr: &Expr, max_states: usize) -> ::Result<Dfa<(Look, u8)>> {
+        let expr = expr::simplify(expr);
+        let mut nfa = try!(Nfa::from_expr(&expr).remove_looks().byte_me(max_states));
+        nfa.reduce();
+
+        // Like the look-behind engine, we have one initial state for each kind of char that can
+        // come before the match.
+        let looks = [Look::Boundary, Look::NewLine, Look::WordChar, Look::NotWordChar];
+        let nfa = nfa.anchor_after_looks(&looks);
+        Ok(try!(nfa.determinize_longest(max_states)).optimize())
+    }
+
+    /// Returns the end of the longest match that starts exactly at `at`, if there is one.
+    ///
+    /// The assertions see all of `s`, so `\b` at `at` looks at the char before it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `at` is not a `char` boundary of `s`.
+    pub fn longest_match_at(&self, s: &str, at: usize) -> Option<usize> {
+        assert!(s.is_char_boundary(at));
+        if self.prog.is_empty() {
+            return None;
+        }
+        let input = s.as_bytes();
+        let state = try_opt!(self.init[Look::before(input, at).as_usize()]);
+        self.prog.find_from(input, at, state)
+            .ok()
+            .map(|(end, (_, bytes))| end - bytes as usize)
+    }
+
+    /// Like `longest_match_at`, but reads the input from an iterator instead of a `&str`.
+    ///
+    /// This is for text that isn't stored in one piece, like the ropes and piece tables of text
+    /// editors: `chars` yields each char of the input together with its byte position (as
+    /// `str::char_indices` does), starting with the char at `at`, and `prev` is the char before
+    /// `at`. The iterator can be infinite, since it is only read until the automaton can't
+    /// continue. A match that reaches the end of the input ends at the position of the last char
+    /// plus its length in UTF-8.
+    ///
+    /// ```rust
+    /// use regex_dfa::LongestMatcher;
+    ///
+    /// let m = LongestMatcher::new(r"\w+\b").unwrap();
+    /// let chunks = ["hel", "", "lo wor", "ld"];
+    /// let mut offset = 0;
+    /// let chars = chunks.iter().flat_map(|chunk| {
+    ///     let start = offset;
+    ///     offset += chunk.len();
+    ///     chunk.char_indices().map(move |(i, c)| (start + i, c))
+    /// });
+    /// assert_eq!(m.longest_match_iter(None, 0, chars), Some(5));
+    /// ```
+    pub fn longest_match_iter<I>(&self, prev: Option<char>, at: usize, chars: I) -> Option<usize>
+    where I: IntoIterator<Item=(usize, char)> {
+        let mut state = try_opt!(self.start_state(prev));
+        let mut end = at;
+        let mut ret = None;
+        for (pos, ch) in chars {
+            if self.accept_status(state, Some(ch)) {
+                ret = Some(pos);
+            }
+            state = match self.next_state(state, ch) {
+                Some(next) => next,
+                None => { return ret; },
+            };
+            end = pos + ch.len_utf8();
+        }
+        if self.accept_status(state, None) {
+            ret = Some(end);
+        }
+        ret
+    }
+
+    /// Returns the end of the longest match at the start of `input`, which can be stored in
+    /// several pieces (see `Haystack`). The match begins after `prev` (or at the start of the
+    /// input, if `prev` is `None`), and its end is measured from the start of `input`.
+    ///
+    /// ```rust
+    /// use regex_dfa::LongestMatcher;
+    ///
+    /// let m = LongestMatcher::new(r"\b\w+\b").unwrap();
+    /// let pieces: &[&str] = &["hel", "lo wor", "ld"];
+    /// assert_eq!(m.longest_match_in(None, pieces), Some(5));
+    /// assert_eq!(m.longest_match_in(Some('x'), pieces), None);
+    /// ```
+    pub fn longest_match_in<H: Haystack + ?Sized>(&self, prev: Option<char>, input: &H)
+    -> Option<usize> {
+        let state = try_opt!(self.start_state(prev));
+        self.prog.find_in_chunks(input, state).map(|(end, (_, bytes))| end - bytes as usize)
+    }
+
+    /// Returns the state to start in, if the match begins after `prev` (or at the start of the
+    /// input, if `prev` is `None`). Returns `None` if nothing can match there.
+    ///
+    /// Together with `next_state` and `accept_status`, this lets you drive the automaton yourself,
+    /// one char at a time. States are plain numbers, so they can be stored and resumed later: a
+    /// syntax highlighter, for example, can remember the state at the end of each line, and then
+    /// only rerun the lines that change.
+    ///
+    /// ```rust
+    /// use regex_dfa::LongestMatcher;
+    ///
+    /// let m = LongestMatcher::new(r"/\*[^*]*\*/").unwrap();
+    /// let mut state = m.start_state(None).unwrap();
+    /// for c in "/* multi-line".chars() {
+    ///     state = m.next_state(state, c).unwrap();
+    /// }
+    /// // ...store `state` at the end of the line, and pick it up again later.
+    /// for c in "comment */".chars() {
+    ///     state = m.next_state(state, c).unwrap();
+    /// }
+    /// assert!(m.accept_status(state, None));
+    /// ```
+    pub fn start_state(&self, prev: Option<char>) -> Option<usize> {
+        if self.prog.is_empty() {
+            return None;
+        }
+        self.init[Look::after_char(prev).as_usize()]
+    }
+
+    /// Returns the state after reading `ch` in `state`, or `None` if the automaton can't continue
+    /// (and so there won't be any more matches).
+    pub fn next_state(&self, state: usize, ch: char) -> Option<usize> {
+        let mut buf = [0u8; 4];
+        let mut state = state;
+        for &b in ch.encode_utf8(&mut buf).as_bytes() {
+            state = try_opt!(self.prog.next_state(state, b));
+        }
+        Some(state)
+    }
+
+    /// Returns true if a match ends in `state`, given that the next char is `next` (or that this
+    /// is the end of the input, if `next` is `None`). The next char matters for regexes that look
+    /// ahead, like `foo\b` or `(?m)foo$`.
+    pub fn accept_status(&self, state: usize, next: Option<char>) -> bool {
+        let ends_here = |ret: Option<(Look, u8)>, look_ahead: usize| {
+            ret.map_or(false, |(_, bytes)| bytes as usize == look_ahead)
+        };
+        if ends_here(self.prog.accept[state], 0) {
+            return true;
+        }
+
+        match next {
+            None => ends_here(self.prog.accept_at_eoi[state], 0),
+            Some(ch) => {
+                // If the match needs look-ahead, the program only accepts after it has seen some
+                // of the next char.
+                let mut buf = [0u8; 4];
+                let mut st = state;
+                for (i, &b) in ch.encode_utf8(&mut buf).as_bytes().iter().enumerate() {
+                    st = match self.prog.next_state(st, b) {
+                        Some(next_st) => next_st,
+                        None => { return false; },
+                    };
+                    if ends_here(self.prog.accept[st], i + 1) {
+                        return true;
+                    }
+                }
+                false
+            },
+        }
+    }
+
+    /// Returns the ends of the shortest and the longest matches that start exactly at `at`, if
+    /// there are any.
+    ///
+    /// Both ends come from the same pass over the input, so this is cheaper than asking for them
+    /// separately (for example, to show both the lazy and the greedy version of a match).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `at` is not a `char` boundary of `s`.
+    ///
+    /// ```rust
+    /// use regex_dfa::LongestMatcher;
+    ///
+    /// let m = LongestMatcher::new("<.+>").unwrap();
+    /// assert_eq!(m.match_ends_at("<a><b>", 0), Some((3, 6)));
+    /// ```
+    pub fn match_ends_at(&self, s: &str, at: usize) -> Option<(usize, usize)> {
+        assert!(s.is_char_boundary(at));
+        if self.prog.is_empty() {
+            return None;
+        }
+        let input = s.as_bytes();
+        let state = try_opt!(self.init[Look::before(input, at).as_usize()]);
+        let ((short, (_, short_bytes)), (long, (_, long_bytes))) =
+            try_opt!(self.prog.first_and_last_accept_from(input, at, state));
+        Some((short - short_bytes as usize, long - long_bytes as usize))
+    }
+}
+
+/// Finds which of several regular expressions has the longest match at a given position.
+///
+/// This is the question that a scanner asks at every token: the token is the longest match of
+/// any of the rules, and if several rules match the same text then the first of them wins.
+///
+/// ```rust
+/// use regex_dfa::LongestMatcherSet;
+///
+/// let rules = LongestMatcherSet::new(&["if", "[a-z]+", "[0-9]+", "=|=="]).unwrap();
+/// assert_eq!(rules.longest_match_at("if x", 0), Some((0, 2)));
+/// assert_eq!(rules.longest_match_at("iffy", 0), Some((1, 4)));
+/// assert_eq!(rules.longest_match_at("x==1", 1), Some((3, 3)));
+/// assert_eq!(rules.longest_match_at("x==1", 3), Some((2, 4)));
+/// ```
+#[derive(Clone, Debug)]
+pub struct LongestMatcherSet {
+    // Finds the longest match of any of the patterns. Each accepting state returns the pattern
+    // and the number of bytes of look-ahead for the match that ends there: the first for matches
+    // that end before another char, and the second for matches that end at the end of the input.
+    prog: TableInsts<SetRet>,
+    // The initial state of `prog` for each kind of preceding char, indexed by `Look::as_usize`.
+    init: Vec<Option<usize>>,
+}
+
+// A pattern and a number of bytes of look-ahead, for matches that end before another char and
+// for matches that end at the end of the input.
+type SetRet = (Option<(usize, u8)>, Option<(usize, u8)>);
+
+impl LongestMatcherSet {
+    /// Compiles a list of regular expressions. The patterns are identified by their index in
+    /// `res`.
+    pub fn new<S: AsRef<str>>(res: &[S]) -> ::Result<LongestMatcherSet> {
+        LongestMatcherSet::new_bounded(res, std::usize::MAX)
+    }
+
+    /// Like `new`, but fails if any of the automata would require too many states.
+    pub fn new_bounded<S: AsRef<str>>(res: &[S], max_states: usize)
+    -> ::Result<LongestMatcherSet> {
+        let mut set: Option<Dfa<SetRet>> = None;
+        for (idx, re) in res.iter().enumerate() {
+            let expr = try!(Expr::parse(re.as_ref()));
+            let dfa = try!(LongestMatcher::make_dfa(&expr, max_states));
+            let dfa = LongestMatcherSet::pattern_dfa(&dfa, idx);
+
+            // As in `PatternSet`, the states of the product keep track of every pattern at once.
+            // The patterns in `set` come first, so they win ties.
+            set = Some(match set {
+                None => dfa,
+                Some(set) => set.product(&dfa, |a, b| max(*a, *b))
+                    .map_ret(|(a, b)| {
+                        let (a_any, a_eoi) = a.unwrap_or((None, None));
+                        let (b_any, b_eoi) = b.unwrap_or((None, None));
+                        (longer(a_any, b_any), longer(a_eoi, b_eoi))
+                    })
+                    .optimize(),
+            });
+            if set.as_ref().map_or(0, |s| s.num_states()) > max_states {
+                return Err(Error::TooManyStates);
+            }
+        }
+
+        let set = set.unwrap_or_else(Dfa::new);
+        Ok(LongestMatcherSet {
+            prog: set.compile(),
+            init: Look::all().iter().map(|&look| set.init_state(look)).collect(),
+        })
+    }
+
+    // Converts the return values of a `Dfa` made by `LongestMatcher::make_dfa` to the ones for
+    // pattern `idx`. A state that only accepts at the end of the input doesn't end a match before
+    // another char.
+    fn pattern_dfa(dfa: &Dfa<(Look, u8)>, idx: usize) -> Dfa<SetRet> {
+        let mut ret = Dfa::new();
+        for st in 0..dfa.num_states() {
+            let accept = *dfa.accept(st);
+            let end = dfa.ret(st).map(|&(_, bytes)| (idx, bytes));
+            let any = if accept == Accept::Always { end } else { None };
+            ret.add_state(accept, if accept == Accept::Never { None } else { Some((any, end)) });
+            ret.set_transitions(st, dfa.transitions(st).clone());
+        }
+        for &look in Look::all() {
+            ret.set_init_state(look, dfa.init_state(look));
+        }
+        ret
+    }
+
+    /// Returns the pattern with the longest match that starts exactly at `at`, and the end of
+    /// that match. If several patterns have matches of that length, the first one wins.
+    ///
+    /// The patterns are compiled into one automaton that keeps track of all of them at once, so
+    /// this only takes one pass over the input.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `at` is not a `char` boundary of `s`.
+    pub fn longest_match_at(&self, s: &str, at: usize) -> Option<(usize, usize)> {
+        assert!(s.is_char_boundary(at));
+        if self.prog.is_empty() {
+            return None;
+        }
+        let input = s.as_bytes();
+        let state = try_opt!(self.init[Look::before(input, at).as_usize()]);
+
+        // The program only remembers the last position where it accepted, but a match that needed
+        // look-ahead can end at the same place as an earlier one (which might be for an earlier
+        // pattern), so we look at every position where it accepts.
+        let prog = &self.prog;
+        let mut best: Option<(usize, usize)> = None;
+        let _ = prog.find_from_with("", input, at, state, &mut |ev| {
+            if let TraceEvent::Accept { pos, state, .. } = ev {
+                let ret = if pos == input.len() {
+                    prog.accept_at_eoi[state].and_then(|r| r.1)
+                } else {
+                    prog.accept[state].and_then(|r| r.0)
+                };
+                if let Some((idx, bytes)) = ret {
+                    let end = pos - bytes as usize;
+                    if best.map_or(true, |(i, e)| end > e || (end == e && idx < i)) {
+                        best = Some((idx, end));
+                    }
+                }
+            }
+        });
+        best
+    }
+}
+
+// Returns the pattern whose match ends later (because it needed less look-ahead), or `a` if they
+// end at the same place.
+fn longer(a: Option<(usize, u8)>, b: Option<(usize, u8)>) -> Option<(usize, u8)> {
+    match (a, b) {
+        (Some(a), Some(b)) => if b.1 < a.1 { Some(b) } else { Some(a) },
+        (a, b) => a.or(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LongestMatcher;
+
+    fn longest(re: &str, s: &str, at: usize) -> Option<usize> {
+        LongestMatcher::new(re).unwrap().longest_match_at(s, at)
+    }
+
+    #[test]
+    fn longest_match_at() {
+        assert_eq!(longest("a|ab|abc", "xabcd", 1), Some(4));
+        assert_eq!(longest("abc|ab|a", "xabcd", 1), Some(4));
+        assert_eq!(longest("a+?", "aaab", 0), Some(3));
+        assert_eq!(longest("a+", "baaa", 0), None);
+        assert_eq!(longest("a*", "baaa", 0), Some(0));
+        assert_eq!(longest("a+", "baaa", 2), Some(4));
+        assert_eq!(longest("[a-z]+|[0-9]+", "ab12", 2), Some(4));
+        assert_eq!(longest("é+", "aééb", 1), Some(5));
+        assert_eq!(longest("[^a]", "", 0), None);
+    }
+
+    #[test]
+    fn longest_match_at_looks() {
+        assert_eq!(longest(r"\bfoo\b", "a foo", 2), Some(5));
+        assert_eq!(longest(r"\bfoo\b", "afoo", 1), None);
+        assert_eq!(longest(r"\bfoo\b", "a foox", 2), None);
+        assert_eq!(longest(r"(?m)^ab$", "x\nab\ny", 2), Some(4));
+        assert_eq!(longest(r"(?m)^ab$", "xab\ny", 1), None);
+        assert_eq!(longest(r"ab$", "xab", 1), Some(3));
+        assert_eq!(longest(r"ab\b|abc", "abc d", 0), Some(3));
+    }
+
+    #[test]
+    fn match_ends_at() {
+        let ends = |re: &str, s: &str, at: usize| {
+            LongestMatcher::new(re).unwrap().match_ends_at(s, at)
+        };
+        assert_eq!(ends("a+", "baaa", 1), Some((2, 4)));
+        assert_eq!(ends("a+", "baaa", 0), None);
+        assert_eq!(ends("a*", "baaa", 0), Some((0, 0)));
+        assert_eq!(ends("a|abc", "xabcd", 1), Some((2, 4)));
+        assert_eq!(ends("é+", "éé", 0), Some((2, 4)));
+        assert_eq!(ends(r"a+\b", "aa a", 0), Some((2, 2)));
+        assert_eq!(ends(r"(?m)a+$", "aa\n", 0), Some((2, 2)));
+        assert_eq!(ends("a+$", "aa", 0), Some((2, 2)));
+        assert_eq!(ends(r"[a-z]+\b", "ab cd", 0), Some((2, 2)));
+    }
+
+    #[test]
+    fn step_by_char() {
+        let m = LongestMatcher::new(r"\bfoo\b").unwrap();
+        assert_eq!(m.start_state(Some('x')), None);
+        let run = |prev: Option<char>, s: &str| {
+            s.chars().fold(m.start_state(prev), |st, c| st.and_then(|st| m.next_state(st, c)))
+        };
+        let st = run(Some(' '), "foo").unwrap();
+        assert!(m.accept_status(st, None));
+        assert!(m.accept_status(st, Some(' ')));
+        assert!(!m.accept_status(st, Some('é')));
+        assert!(!m.accept_status(st, Some('x')));
+        assert!(!m.accept_status(run(None, "fo").unwrap(), None));
+        assert_eq!(run(None, "fox"), None);
+
+        let m = LongestMatcher::new(r"(?m)^ab$").unwrap();
+        assert_eq!(m.start_state(Some('x')), None);
+        let st = m.start_state(Some('\n')).and_then(|st| m.next_state(st, 'a'))
+            .and_then(|st| m.next_state(st, 'b')).unwrap();
+        assert!(m.accept_status(st, Some('\n')));
+        assert!(m.accept_status(st, None));
+        assert!(!m.accept_status(st, Some('c')));
+
+        let m = LongestMatcher::new("é+").unwrap();
+        let st = m.next_state(m.start_state(None).unwrap(), 'é').unwrap();
+        assert!(m.accept_status(st, None));
+        assert!(m.accept_status(st, Some('x')));
+        assert_eq!(m.next_state(st, 'e'), None);
+    }
+
+    #[test]
+    fn longest_match_iter() {
+        // Splits `s` into chunks of `size` bytes (or a little more, to keep chars together).
+        fn chunks(s: &str, size: usize) -> Vec<&str> {
+            use std::cmp;
+
+            let mut ret = Vec::new();
+            let mut start = 0;
+            while start < s.len() {
+                let mut end = cmp::min(start + size, s.len());
+                while !s.is_char_boundary(end) {
+                    end += 1;
+                }
+                ret.push(&s[start..end]);
+                start = end;
+            }
+            ret
+        }
+
+        let text = "if iffy == 12;\n  éa_1 bb";
+        for re in &["if|[a-z]+", r"\w+\b", r"(?m)[0-9]+$|;$", r"\s*", r"\bb+", "x"] {
+            let m = LongestMatcher::new(re).unwrap();
+            for (at, _) in text.char_indices().chain(Some((text.len(), ' '))) {
+                let expected = m.longest_match_at(text, at);
+                let prev = text[..at].chars().next_back();
+                for size in 1..4 {
+                    let pieces = chunks(&text[at..], size);
+                    let mut offset = at;
+                    let chars = pieces.iter().flat_map(|piece| {
+                        let start = offset;
+                        offset += piece.len();
+                        piece.char_indices().map(move |(i, c)| (start + i, c))
+                    });
+                    assert_eq!(m.longest_match_iter(prev, at, chars), expected,
+                               "{} at {}", re, at);
+                }
+            }
+        }
+
+        // Chunks can split chars.
+        let m = LongestMatcher::new(r"\w+\b").unwrap();
+        let pieces: &[&[u8]] = &[b"\xce", b"\xb1\xce", b"\xb2 x"];
+        assert_eq!(m.longest_match_in(None, pieces), Some(4));
+        assert_eq!(m.longest_match_in(None, "αβ x"), m.longest_match_at("αβ x", 0));
+
+        // The input can go on forever.
+        let m = LongestMatcher::new("a+b").unwrap();
+        let chars = (0..).map(|i| (i, if i < 5 { 'a' } else { 'c' }));
+        assert_eq!(m.longest_match_iter(None, 0, chars), None);
+        let chars = (0..).map(|i| (i, if i < 5 { 'a' } else { 'b' }));
+        assert_eq!(m.longest_match_iter(None, 0, chars), Some(6));
+    }
+
+    #[test]
+    fn set() {
+        use super::LongestMatcherSet;
+
+        let rules = LongestMatcherSet::new(&["if", "[a-z]+", r"\bin\b", "[0-9]+", r"\s+"]).unwrap();
+        assert_eq!(rules.longest_match_at("if", 0), Some((0, 2)));
+        assert_eq!(rules.longest_match_at("ifs", 0), Some((1, 3)));
+        assert_eq!(rules.longest_match_at("in x", 0), Some((1, 2)));
+        assert_eq!(rules.longest_match_at("12ab", 0), Some((3, 2)));
+        assert_eq!(rules.longest_match_at("12 ab", 2), Some((4, 3)));
+        assert_eq!(rules.longest_match_at("12+", 2), None);
+        assert_eq!(rules.longest_match_at("12", 2), None);
+
+        // A pattern that only matches in context.
+        let rules = LongestMatcherSet::new(&[r"(?m)^#[a-z]+", "#"]).unwrap();
+        assert_eq!(rules.longest_match_at("#ab", 0), Some((0, 3)));
+        assert_eq!(rules.longest_match_at("x#ab", 1), Some((1, 2)));
+
+        let empty = LongestMatcherSet::new::<&str>(&[]).unwrap();
+        assert_eq!(empty.longest_match_at("abc", 0), None);
+        let rules = LongestMatcherSet::new(&["", "a+"]).unwrap();
+        assert_eq!(rules.longest_match_at("bc", 0), Some((0, 0)));
+        assert_eq!(rules.longest_match_at("aab", 0), Some((1, 2)));
+
+        // A pattern that only matches at the end of the input.
+        let rules = LongestMatcherSet::new(&["ab$", "[a-z]+"]).unwrap();
+        assert_eq!(rules.longest_match_at("ab", 0), Some((0, 2)));
+        assert_eq!(rules.longest_match_at("ab c", 0), Some((1, 2)));
+
+        // The same answers as running each pattern on its own.
+        let res = ["if", "[a-z]+", r"\bin\b", r"[a-z]+\b", r"(?m)\w+$", "[0-9]+", "i.", ""];
+        let rules = LongestMatcherSet::new(&res).unwrap();
+        let each = res.iter().map(|re| LongestMatcher::new(re).unwrap()).collect::<Vec<_>>();
+        let text = "if in inx\ni9 12 x";
+        for (at, _) in text.char_indices().chain(Some((text.len(), ' '))) {
+            let expected = each.iter().enumerate()
+                .filter_map(|(idx, m)| m.longest_match_at(text, at).map(|end| (idx, end)))
+                .fold(None, |best: Option<(usize, usize)>, (idx, end)| match best {
+                    Some((_, best_end)) if best_end >= end => best,
+                    _ => Some((idx, end)),
+                });
+            assert_eq!(rules.longest_match_at(text, at), expected, "at {}", at);
+        }
+    }
+
+    // A tokenizer that splits on whitespace, and otherwise takes the longest token.
+    #[test]
+    fn tokenize() {
+        let m = LongestMatcher::new(r"if|else|[a-z]+|[0-9]+|==|=|\s+").unwrap();
+        let s = "if iffy == 12 else";
+        let mut pos = 0;
+        let mut tokens = Vec::new();
+        while pos < s.len() {
+            let end = m.longest_match_at(s, pos).unwrap();
+            tokens.push(&s[pos..end]);
+            pos = end;
+        }
+        assert_eq!(tokens, vec!["if", " ", "iffy", " ", "==", " ", "12", " ", "else"]);
+    }
+}
@@ -9,9 +9,26 @@
 #![allow(dead_code)]
 
 use range_map::{Range, RangeSet};
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::cmp::Ordering;
+use std::str;
 use unicode::PERLW;
 
+/// The kind of char just before or just after a position in the input, as far as `\b`, `\B`,
+/// `(?m)^` and `(?m)$` are concerned. `Dfa::init` has an initial state for each of them.
+///
+// A `Look` describes the char just before or just after a position, for the purpose of checking
+// zero-width assertions like `\b` and `(?m)^`. Each one stands for a set of chars (see `as_set`),
+// possibly together with the end of the input, and they form a lattice under `intersection`.
+//
+// The set of `Look`s is closed: every compiled program has one initial state for each of them
+// (so `num()` is baked into `Dfa::init` and into the serialized formats), and the engines pick
+// among those initial states using `before`. What is *not* fixed is the set of chars that
+// `WordChar` stands for: a regex can be compiled with its own word chars (see `WordChars`), in
+// which case the compiler uses `WordChars::set` instead of `as_set`, and the engines carry the
+// `WordChars` along with the program so that they can classify positions with
+// `WordChars::before`.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Ord)]
 pub enum Look {
     Full,
@@ -85,7 +102,7 @@ impl Look {
         ALL.iter().cloned().filter(|x| *self <= *x).collect()
     }
 
-    pub fn as_set(&self) -> &RangeSet<u32> {
+    pub fn as_set(&self) -> &'static RangeSet<u32> {
         use self::Look::*;
 
         match *self {
@@ -133,9 +150,144 @@ impl Look {
 
     pub fn num() -> usize { 6 }
 
+    /// Returns the most specific `Look` (other than `Full`) that matches the char ending just
+    /// before position `pos` of `input`. Invalid UTF-8 counts as a non-word char.
+    pub fn before(input: &[u8], pos: usize) -> Look {
+        WordChars::unicode().before(input, pos)
+    }
+
+    /// Returns the most specific `Look` (other than `Full`) that matches `prev`, where `None`
+    /// means the start of the input.
+    pub fn after_char(prev: Option<char>) -> Look {
+        match prev {
+            None => Look::Boundary,
+            Some('\n') => Look::NewLine,
+            Some(c) if is_word_char(c) => Look::WordChar,
+            Some(_) => Look::NotWordChar,
+        }
+    }
+
     pub fn all() -> &'static [Look] {
         &ALL
     }
+
+    /// A name for this `Look`, used when serializing.
+    pub fn name(&self) -> &'static str {
+        use self::Look::*;
+
+        match *self {
+            Full => "full",
+            WordChar => "word_char",
+            NotWordChar => "not_word_char",
+            NewLine => "new_line",
+            Boundary => "boundary",
+            Empty => "empty",
+        }
+    }
+}
+
+/// The chars that `\b` and `\B` treat as word chars (that is, the chars that `Look::WordChar`
+/// stands for).
+///
+/// By default these are the chars that match `\w`, but they can be chosen when compiling a regex
+/// (see `RegexOptions::word_chars`). `\n` is never a word char, because `Look::NewLine` has to
+/// be a subset of `Look::NotWordChar`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WordChars {
+    // The word chars and the non-word chars, or `None` for the chars that match `\w` (whose sets
+    // are cached in `Look::as_set`).
+    custom: Option<(RangeSet<u32>, RangeSet<u32>)>,
+}
+
+impl WordChars {
+    /// The chars that match `\w`.
+    pub fn unicode() -> WordChars {
+        WordChars { custom: None }
+    }
+
+    /// The chars in `set`, apart from `\n`.
+    pub fn from_ranges(set: &[(u32, u32)]) -> WordChars {
+        let word = set.iter()
+            .map(|&(lo, hi)| Range::new(lo, hi))
+            .collect::<RangeSet<u32>>()
+            .intersection(&NEW_LINE.negated());
+        let not_word = word.negated();
+        WordChars { custom: Some((word, not_word)) }
+    }
+
+    pub fn is_unicode(&self) -> bool {
+        self.custom.is_none()
+    }
+
+    /// The ranges of word chars, or `None` for the chars that match `\w`.
+    pub fn ranges(&self) -> Option<Vec<(u32, u32)>> {
+        self.custom.as_ref().map(|c| c.0.ranges().map(|r| (r.start, r.end)).collect())
+    }
+
+    /// The set of chars that `look` stands for, with these word chars.
+    pub fn set(&self, look: Look) -> &RangeSet<u32> {
+        match (look, &self.custom) {
+            (Look::WordChar, &Some((ref word, _))) => word,
+            (Look::NotWordChar, &Some((_, ref not_word))) => not_word,
+            _ => look.as_set(),
+        }
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        match self.custom {
+            None => is_word_char(c),
+            Some((ref word, _)) => word.contains(c as u32),
+        }
+    }
+
+    /// Like `Look::before`, but with these word chars.
+    pub fn before(&self, input: &[u8], pos: usize) -> Look {
+        if pos == 0 {
+            return Look::Boundary;
+        } else if input[pos - 1] == b'\n' {
+            return Look::NewLine;
+        }
+
+        // Find the beginning of the last char (which is at most 4 bytes long).
+        let start = (pos.saturating_sub(4)..pos).rev()
+            .find(|&i| input[i] & 0xC0 != 0x80)
+            .unwrap_or(pos - 1);
+        match str::from_utf8(&input[start..pos]).ok().and_then(|s| s.chars().next()) {
+            Some(c) if self.contains(c) => Look::WordChar,
+            _ => Look::NotWordChar,
+        }
+    }
+}
+
+/// Returns true if `c` is a word char (that is, if it matches `\w`).
+pub fn is_word_char(c: char) -> bool {
+    PERLW.binary_search_by(|&(start, end)| {
+        if end < c {
+            Ordering::Less
+        } else if start > c {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        }
+    }).is_ok()
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Look {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> Result<(), S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Deserialize for Look {
+    fn deserialize<D: Deserializer>(deserializer: &mut D) -> Result<Look, D::Error> {
+        let name: String = try!(Deserialize::deserialize(deserializer));
+        match ALL.iter().find(|look| look.name() == name) {
+            Some(&look) => Ok(look),
+            None => Err(de::Error::custom(format!("unknown look \"{}\"", name))),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -151,6 +303,39 @@ mod tests {
         }
     }
 
+    #[test]
+    fn before() {
+        let input = "a\né ,ßx".as_bytes();
+        assert_eq!(Look::before(input, 0), Look::Boundary);
+        assert_eq!(Look::before(input, 1), Look::WordChar);
+        assert_eq!(Look::before(input, 2), Look::NewLine);
+        // In the middle of 'é'.
+        assert_eq!(Look::before(input, 3), Look::NotWordChar);
+        assert_eq!(Look::before(input, 4), Look::WordChar);
+        assert_eq!(Look::before(input, 5), Look::NotWordChar);
+        assert_eq!(Look::before(input, 6), Look::NotWordChar);
+        assert_eq!(Look::before(input, 8), Look::WordChar);
+        assert_eq!(Look::before(b"a\xff", 2), Look::NotWordChar);
+    }
+
+    #[test]
+    fn custom_word_chars() {
+        let words = WordChars::from_ranges(&[(',' as u32, ',' as u32), (0, 0x10FFFF)]);
+        assert!(!words.contains('\n'));
+        assert!(words.set(Look::NotWordChar).contains('\n' as u32));
+        assert!(!words.set(Look::NotWordChar).contains(',' as u32));
+        assert_eq!(words.set(Look::NewLine), Look::NewLine.as_set());
+
+        let words = WordChars::from_ranges(&[('a' as u32, 'z' as u32), ('é' as u32, 'é' as u32)]);
+        let input = "a\né ,ßx".as_bytes();
+        assert_eq!(words.before(input, 0), Look::Boundary);
+        assert_eq!(words.before(input, 1), Look::WordChar);
+        assert_eq!(words.before(input, 2), Look::NewLine);
+        assert_eq!(words.before(input, 4), Look::WordChar);
+        assert_eq!(words.before(input, 6), Look::NotWordChar);
+        assert_eq!(words.before(input, 8), Look::NotWordChar);
+    }
+
     #[test]
     fn intersection_commutes() {
         fn prop(a: Look, b: Look) -> bool {
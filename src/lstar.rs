@@ -0,0 +1,271 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Angluin's L* algorithm, in the variant of Maler and Pnueli: instead of adding the prefixes of a
+// counterexample to the rows of the observation table, we add its suffixes to the columns. This
+// keeps the rows belonging to states distinct, so the table never needs to be made consistent.
+
+use dfa::{self, Dfa};
+use look::Look;
+use nfa::Accept;
+use std::collections::HashMap;
+
+/// A deterministic automaton that was learned from a black box by asking it questions.
+///
+/// The learner only needs two kinds of questions answered: a *membership* query asks whether a
+/// single input belongs to the language, and an *equivalence* query asks whether the current
+/// guess is right (and if not, for an input on which it is wrong). The result is the smallest
+/// automaton that accepts the language.
+///
+/// ```rust
+/// use regex_dfa::LearnedDfa;
+///
+/// // The inputs with an even number of 'a's.
+/// let even = |s: &[u8]| s.iter().filter(|&&b| b == b'a').count() % 2 == 0;
+///
+/// // Here, we answer equivalence queries by trying all the short inputs.
+/// let check = |guess: &LearnedDfa| {
+///     let mut inputs: Vec<Vec<u8>> = vec![vec![]];
+///     for i in 0..63 {
+///         for &b in b"ab" {
+///             let mut longer = inputs[i].clone();
+///             longer.push(b);
+///             inputs.push(longer);
+///         }
+///     }
+///     inputs.into_iter().find(|s| guess.accepts(s) != even(s))
+/// };
+///
+/// let learned = LearnedDfa::learn(b"ab", &even, check);
+/// assert_eq!(learned.num_states(), 2);
+/// assert!(learned.accepts(b"abab"));
+/// assert!(!learned.accepts(b"bab"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct LearnedDfa {
+    dfa: Dfa<()>,
+}
+
+// The observation table. The rows are indexed by `prefixes` (each of which corresponds to a
+// state of the hypothesis), and the columns by `suffixes`.
+struct Table<'a, M: FnMut(&[u8]) -> bool> {
+    alphabet: &'a [u8],
+    membership: M,
+    // The answers to all the membership queries that we have asked.
+    answers: HashMap<Vec<u8>, bool>,
+    prefixes: Vec<Vec<u8>>,
+    suffixes: Vec<Vec<u8>>,
+}
+
+impl<'a, M: FnMut(&[u8]) -> bool> Table<'a, M> {
+    fn member(&mut self, input: Vec<u8>) -> bool {
+        if let Some(&ans) = self.answers.get(&input) {
+            return ans;
+        }
+        let ans = (self.membership)(&input);
+        self.answers.insert(input, ans);
+        ans
+    }
+
+    fn row(&mut self, prefix: &[u8]) -> Vec<bool> {
+        let suffixes = self.suffixes.clone();
+        suffixes.iter()
+            .map(|suffix| {
+                let mut input = prefix.to_vec();
+                input.extend_from_slice(suffix);
+                self.member(input)
+            })
+            .collect()
+    }
+
+    // Adds prefixes until every row of the form prefix + byte also belongs to some prefix. Then
+    // returns the hypothesis that this table describes.
+    fn close(&mut self) -> Dfa<()> {
+        let mut rows: Vec<Vec<bool>> = Vec::new();
+        for i in 0..self.prefixes.len() {
+            let prefix = self.prefixes[i].clone();
+            rows.push(self.row(&prefix));
+        }
+
+        // The targets of the transitions out of each prefix, indexed by byte.
+        let mut trans: Vec<Vec<Option<usize>>> = Vec::new();
+        let mut i = 0;
+        while i < self.prefixes.len() {
+            let mut targets = vec![None; 256];
+            for &b in self.alphabet {
+                let mut next = self.prefixes[i].clone();
+                next.push(b);
+                let row = self.row(&next);
+                let tgt = match rows.iter().position(|r| *r == row) {
+                    Some(j) => j,
+                    None => {
+                        self.prefixes.push(next);
+                        rows.push(row);
+                        rows.len() - 1
+                    },
+                };
+                targets[b as usize] = Some(tgt);
+            }
+            trans.push(targets);
+            i += 1;
+        }
+
+        let mut ret = Dfa::new();
+        for (i, targets) in trans.iter().enumerate() {
+            // The first suffix is always the empty one.
+            let accept = if rows[i][0] { Accept::AtEoi } else { Accept::Never };
+            ret.add_state(accept, None);
+            ret.set_transitions(i, dfa::transitions_from_targets(targets));
+        }
+        ret.set_init_state(Look::Boundary, Some(0));
+        ret.set_init_state(Look::Full, Some(0));
+        ret
+    }
+}
+
+impl LearnedDfa {
+    /// Learns the language over `alphabet` that `membership` describes.
+    ///
+    /// `membership` returns true if its input belongs to the language. `equivalence` receives the
+    /// current guess, and should return `None` if it is correct, or an input that the guess gets
+    /// wrong. Every membership query is only asked once. As long as the language is regular, this
+    /// finishes after at most one equivalence query per state of the result.
+    ///
+    /// Bytes that aren't in `alphabet` are never asked about, and the learned automaton rejects
+    /// every input containing them. In particular, the counterexamples returned by `equivalence`
+    /// should only contain bytes in `alphabet`.
+    pub fn learn<M, E>(alphabet: &[u8], membership: M, mut equivalence: E) -> LearnedDfa
+    where M: FnMut(&[u8]) -> bool, E: FnMut(&LearnedDfa) -> Option<Vec<u8>> {
+        let mut table = Table {
+            alphabet: alphabet,
+            membership: membership,
+            answers: HashMap::new(),
+            prefixes: vec![vec![]],
+            suffixes: vec![vec![]],
+        };
+
+        loop {
+            let guess = LearnedDfa { dfa: table.close() };
+            match equivalence(&guess) {
+                None => { return guess; },
+                Some(counterexample) => {
+                    for i in 0..counterexample.len() {
+                        let suffix = counterexample[i..].to_vec();
+                        if !table.suffixes.contains(&suffix) {
+                            table.suffixes.push(suffix);
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    /// Returns true if this automaton accepts `input`.
+    pub fn accepts(&self, input: &[u8]) -> bool {
+        let mut state = 0;
+        for &b in input {
+            match self.dfa.transitions(state).get(b) {
+                Some(&next) => { state = next; },
+                None => { return false; },
+            }
+        }
+        *self.dfa.accept(state) != Accept::Never
+    }
+
+    /// Returns the number of states (including the state that rejects everything, if there is
+    /// one).
+    pub fn num_states(&self) -> usize {
+        self.dfa.num_states()
+    }
+
+    /// Returns a regular expression for the learned language, or `None` if the language is empty.
+    ///
+    /// Each char in the regular expression stands for one byte, and anything other than an ASCII
+    /// letter or digit is written as `\xNN`.
+    pub fn to_regex(&self) -> Option<String> {
+        self.dfa.to_regex()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfa::tests::make_whole;
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+    use super::LearnedDfa;
+
+    // All inputs over `alphabet` with length at most `max_len`.
+    fn inputs(alphabet: &[u8], max_len: usize) -> Vec<Vec<u8>> {
+        let mut ret = vec![vec![]];
+        let mut last = vec![vec![]];
+        for _ in 0..max_len {
+            let mut next = Vec::new();
+            for s in &last {
+                for &b in alphabet {
+                    let mut t: Vec<u8> = s.clone();
+                    t.push(b);
+                    next.push(t);
+                }
+            }
+            ret.extend(next.iter().cloned());
+            last = next;
+        }
+        ret
+    }
+
+    fn learn_regex(re: &str, alphabet: &[u8]) -> LearnedDfa {
+        let target = make_whole(re);
+        let member = |s: &[u8]| target.accepts(String::from_utf8(s.to_vec()).unwrap().chars());
+        let all = inputs(alphabet, 7);
+        let learned = LearnedDfa::learn(alphabet, &member, |guess: &LearnedDfa| {
+            all.iter().find(|s| guess.accepts(s) != member(s)).cloned()
+        });
+        for s in &all {
+            assert_eq!(learned.accepts(s), member(s));
+        }
+        learned
+    }
+
+    #[test]
+    fn learn() {
+        // Every state of the minimal automaton, including the one that rejects everything.
+        assert_eq!(learn_regex("(ab)*", b"ab").num_states(), 3);
+        assert_eq!(learn_regex("(a|b)*abb", b"ab").num_states(), 4);
+        assert_eq!(learn_regex("a*b*c", b"abc").num_states(), 4);
+        assert_eq!(learn_regex("a?", b"a").num_states(), 3);
+
+        // Bytes outside the alphabet are rejected.
+        assert!(!learn_regex("[ab]*", b"ab").accepts(b"abc"));
+    }
+
+    #[test]
+    fn queries_are_cached() {
+        let asked = RefCell::new(HashSet::new());
+        let learned = LearnedDfa::learn(b"ab", |s: &[u8]| {
+            assert!(asked.borrow_mut().insert(s.to_vec()), "asked about {:?} twice", s);
+            s.len() % 3 == 0
+        }, |guess: &LearnedDfa| {
+            inputs(b"ab", 6).into_iter().find(|s| guess.accepts(s) != (s.len() % 3 == 0))
+        });
+        assert_eq!(learned.num_states(), 3);
+        assert!(learned.accepts(b"abb"));
+        assert!(!learned.accepts(b"abba"));
+    }
+
+    #[test]
+    fn to_regex() {
+        let learned = learn_regex("a(ba)*", b"ab");
+        let re = learned.to_regex().unwrap();
+        let back = make_whole(&re);
+        assert!(back.accepts("ababa".chars()));
+        assert!(!back.accepts("abab".chars()));
+
+        let empty = LearnedDfa::learn(b"a", |_: &[u8]| false, |_: &LearnedDfa| None);
+        assert_eq!(empty.to_regex(), None);
+    }
+}
@@ -130,7 +130,7 @@
 //! non-consuming transition leads to an accepting state, it means that the source of that
 //! transition should become a conditionally accepting state.
 
-use look::Look;
+use look::{Look, WordChars};
 use nfa::{Accept, HasLooks, LookPair, Nfa, NoLooks, StateIdx};
 use std::cmp::max;
 use std::collections::HashSet;
@@ -165,17 +165,22 @@ impl Nfa<u32, HasLooks> {
     /// Creates a new Nfa from a regex string.
     pub fn from_regex(re: &str) -> ::Result<Nfa<u32, HasLooks>> {
         let expr = try!(Expr::parse(re));
+        Ok(Nfa::from_expr(&expr))
+    }
+
+    /// Creates a new Nfa from a parsed regex.
+    pub fn from_expr(expr: &Expr) -> Nfa<u32, HasLooks> {
         let mut ret = Nfa::new();
 
         ret.add_state(Accept::Never);
-        ret.add_expr(&expr);
+        ret.add_expr(expr);
         ret.add_eps(0, 1);
 
         let len = ret.num_states();
         ret.states[len - 1].accept = Accept::Always;
 
         ret.check_invariants();
-        Ok(ret)
+        ret
     }
 
     /// Adds a non-input consuming transition between states `source` and `target`.
@@ -192,7 +197,17 @@ impl Nfa<u32, HasLooks> {
     }
 
     /// Removes all look transitions, converting this Nfa into an `Nfa<u32, NoLooks>`.
-    pub fn remove_looks(mut self) -> Nfa<u32, NoLooks> {
+    ///
+    /// This is the only place where we compute closures over non-consuming transitions, and we
+    /// compute each one only once: the closure of a state's (unique) target gets folded into its
+    /// consuming transitions. In particular, determinization works on automata without any
+    /// non-consuming transitions, so it never needs to compute closures.
+    pub fn remove_looks(self) -> Nfa<u32, NoLooks> {
+        self.remove_looks_with(&WordChars::unicode())
+    }
+
+    /// Like `remove_looks`, but `\b` and `\B` use `words` as the word chars.
+    pub fn remove_looks_with(mut self, words: &WordChars) -> Nfa<u32, NoLooks> {
         if self.states.is_empty() {
             return Nfa::with_capacity(0);
         }
@@ -207,8 +222,8 @@ impl Nfa<u32, HasLooks> {
                 let consuming = self.states[src_idx].consuming.clone();
                 for look in self.closure(src_idx + 1) {
                     // Add transitions into the look target.
-                    let new_idx = self.add_look_state(look);
-                    let filtered_consuming = consuming.intersection(look.behind.as_set());
+                    let new_idx = self.add_look_state(look, words);
+                    let filtered_consuming = consuming.intersection(words.set(look.behind));
                     for &(range, _) in filtered_consuming.ranges_values() {
                         self.add_transition(src_idx, new_idx, range);
                     }
@@ -225,7 +240,7 @@ impl Nfa<u32, HasLooks> {
         // Add the new initial states: everything that was immediately reachable from state 0 is now
         // an initial state.
         for look in self.closure(0) {
-            let new_idx = self.add_look_state(look);
+            let new_idx = self.add_look_state(look, words);
             self.init.push((look.behind, new_idx));
             if new_idx >= old_len {
                 new_states.push((new_idx, look.ahead, look.target_state));
@@ -234,7 +249,7 @@ impl Nfa<u32, HasLooks> {
 
         // Now add transitions out of the new states.
         for (src_idx, look, tgt_idx) in new_states {
-            let out_consuming = self.states[tgt_idx].consuming.intersection(look.as_set());
+            let out_consuming = self.states[tgt_idx].consuming.intersection(words.set(look));
             for &(range, tgt) in out_consuming.ranges_values() {
                 self.states[src_idx].consuming.insert(range, tgt);
             }
@@ -254,7 +269,7 @@ impl Nfa<u32, HasLooks> {
     // only if the LookPair needs to look ahead.
     //
     // Returns the index of the new state.
-    fn add_look_state(&mut self, look: LookPair) -> StateIdx {
+    fn add_look_state(&mut self, look: LookPair, words: &WordChars) -> StateIdx {
         if look.ahead.is_full() {
             look.target_state
         } else {
@@ -270,9 +285,9 @@ impl Nfa<u32, HasLooks> {
 
             // If the target state of the look is accepting, add a new look-ahead accepting state.
             if self.states[tgt_idx].accept == Accept::Always
-                    && !look.ahead.as_set().is_empty() {
+                    && !words.set(look.ahead).is_empty() {
                 let acc_idx = self.add_look_ahead_state(look.ahead, 1, new_idx);
-                for range in look.ahead.as_set().ranges() {
+                for range in words.set(look.ahead).ranges() {
                     self.add_transition(new_idx, acc_idx, range);
                 }
             }
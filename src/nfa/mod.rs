@@ -14,6 +14,12 @@ use std::marker::PhantomData;
 
 mod has_looks;
 mod no_looks;
+#[cfg(feature = "serde")]
+mod serialize;
+mod utf16;
+
+pub use nfa::no_looks::{DeterminizeOptions, DeterminizeStats, ExploreOrder, InvalidUtf8,
+                        Utf8Cache};
 
 // TODO: it would be nice to make StateIdx a new type instead of a type alias. The problem is that
 // we need to be able to index Vecs with it, and we can't impl<T> Index<StateIdx> for Vec<T>
@@ -54,6 +60,12 @@ impl LookPair {
 /// The enum for determining whether a state is accepting. Classical NFAs would only allow `Never`
 /// and `Always` here, but we also allow `AtEoi`, which means that the state should accept if and
 /// only if we've reached the end of the input.
+///
+/// The variants are ordered from least to most accepting, so a set of states accepts (for
+/// example, when determinizing) according to the `max` of their `Accept`s. Acceptance that depends
+/// on the next char is not expressed here: those states are `Always`, with the condition on the
+/// next char recorded separately as a `Look`. So new kinds of anchors don't need new variants,
+/// only new `Look`s.
 #[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Accept {
     Never,
@@ -123,6 +135,7 @@ struct State<Tok> {
 /// - First, create an `Nfa<u32, HasLooks>` using `from_regex`.
 /// - Call `nfa.remove_looks()` to turn the `Nfa<u32, HasLooks>` to an `Nfa<u32, NoLooks>`.
 /// - Call `nfa.byte_me()` to turn the `Nfa<u32, NoLooks>` into an `Nfa<u8, NoLooks>`.
+///   (Or call `nfa.utf16_me()` to get an `Nfa<u8, NoLooks>` that reads UTF-16 instead of UTF-8.)
 /// - Call one of the `nfa.determinize_*()` methods to make a `Dfa`.
 ///
 /// There are also some operations modifying `Nfa<u8, NoLooks>` that can be called between the last
@@ -270,6 +283,29 @@ impl<Tok: Debug + PrimInt, L: Lookability> Nfa<Tok, L> {
         self.init.iter().all(|pair| pair.0 == Look::Boundary)
     }
 
+    /// Returns true if every match of this Nfa ends at the end of the input.
+    pub fn is_end_anchored(&self) -> bool {
+        self.states.iter().all(|s| s.accept != Accept::Always)
+    }
+
+    /// Returns true if every match of this Nfa begins either at the beginning of the input or
+    /// just after a `'\n'`.
+    pub fn is_line_anchored(&self) -> bool {
+        self.init.iter().all(|pair| pair.0 == Look::Boundary || pair.0 == Look::NewLine)
+    }
+
+    /// Returns true if some match of this Nfa needs to look at the char before it (for example,
+    /// because it starts with `\b`).
+    pub fn has_look_behind(&self) -> bool {
+        self.init.iter().any(|pair| pair.0 != Look::Boundary && pair.0 != Look::Full)
+    }
+
+    /// Returns true if this Nfa needs to look past the end of a match in order to decide whether
+    /// it has matched.
+    pub fn has_look_ahead(&self) -> bool {
+        self.states.iter().any(|s| s.accept_tokens > 0)
+    }
+
     /// Returns true if this Nfa never matches anything.
     pub fn is_empty(&self) -> bool {
         self.states.is_empty()
@@ -7,18 +7,21 @@
 // except according to those terms.
 
 use dfa::Dfa;
-use error::Error;
+use error::{BlowupReport, Error, TimeoutReport};
 use itertools::Itertools;
-use look::Look;
+use look::{Look, WordChars};
 use nfa::{Accept, Nfa, NoLooks, State, StateIdx, StateSet};
 use num_traits::PrimInt;
-use range_map::{Range, RangeMap, RangeMultiMap};
+use range_map::{Range, RangeMap, RangeMultiMap, RangeSet};
+use regex::Stage;
 use std::{char, u8, usize};
-use std::cmp::max;
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::cmp::{max, min};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fmt::Debug;
 use std::marker::PhantomData;
 use std::mem::swap;
+use std::time::Instant;
 use utf8_ranges::{Utf8Range, Utf8Sequence, Utf8Sequences};
 
 // This provides a more compact way of representing UTF-8 sequences.
@@ -34,6 +37,26 @@ struct MergedUtf8Sequences {
     pub last_byte: Vec<Utf8Range>,
 }
 
+/// The UTF-8 encodings of sets of chars, for sharing between several calls to `byte_me_cached`.
+///
+/// Big classes (like `\w`, or the Unicode categories) take a while to encode, and they tend to
+/// appear over and over again in the same set of regular expressions.
+pub struct Utf8Cache {
+    seqs: HashMap<Vec<Range<u32>>, Vec<MergedUtf8Sequences>>,
+}
+
+impl Utf8Cache {
+    pub fn new() -> Utf8Cache {
+        Utf8Cache { seqs: HashMap::new() }
+    }
+}
+
+impl Default for Utf8Cache {
+    fn default() -> Utf8Cache {
+        Utf8Cache::new()
+    }
+}
+
 // Returns this range as a pair of chars, or none if this is an empty range.
 fn to_char_pair(r: Range<u32>) -> Option<(char, char)> {
     // Round up self.start to the nearest legal codepoint.
@@ -108,8 +131,8 @@ impl MergedUtf8Sequences {
     }
 }
 
-// Creates a byte-based Dfa that matches all the chars in `look.as_set()`.
-fn make_char_dfa(look: Look) -> Dfa<(Look, u8)> {
+// Creates a byte-based Dfa that matches all the chars in `set` (which is what `look` stands for).
+fn make_char_dfa(look: Look, set: &RangeSet<u32>) -> Dfa<(Look, u8)> {
     let mut nfa: Nfa<u32, NoLooks> = Nfa::with_capacity(2);
     nfa.add_state(Accept::Never);
     nfa.add_look_ahead_state(look, 1, 0);
@@ -117,7 +140,7 @@ fn make_char_dfa(look: Look) -> Dfa<(Look, u8)> {
     nfa.init.push((Look::Full, 0));
     nfa.init.push((Look::Boundary, 0));
     nfa.states[0].consuming
-        = RangeMultiMap::from_vec(look.as_set().ranges().map(|x| (x, 1)).collect());
+        = RangeMultiMap::from_vec(set.ranges().map(|x| (x, 1)).collect());
 
     // These unwraps are OK because the only failures are caused by having too many states.
     nfa.byte_me(usize::MAX).unwrap()
@@ -125,15 +148,16 @@ fn make_char_dfa(look: Look) -> Dfa<(Look, u8)> {
         .optimize()
 }
 
-// Creates a byte-based Dfa that matches backwards all the chars in `look.as_set()`.
-fn make_rev_char_dfa(look: Look) -> Dfa<(Look, u8)> {
+// Creates a byte-based Dfa that matches backwards all the chars in `set` (which is what `look`
+// stands for).
+fn make_rev_char_dfa(look: Look, set: &RangeSet<u32>) -> Dfa<(Look, u8)> {
     let mut nfa: Nfa<u8, NoLooks> = Nfa::with_capacity(0); // TODO: better capacity
     nfa.add_state(Accept::Never);
     nfa.init.push((Look::Full, 0));
     nfa.init.push((Look::Boundary, 0));
 
     // This is more-or-less C&P from add_utf8_sequence.
-    for seq in MergedUtf8Sequences::from_ranges(look.as_set().ranges()) {
+    for seq in MergedUtf8Sequences::from_ranges(set.ranges()) {
         let mut last_state = nfa.add_state(Accept::Never);
 
         for range in &seq.last_byte {
@@ -160,10 +184,33 @@ fn make_rev_char_dfa(look: Look) -> Dfa<(Look, u8)> {
 // We cache optimized Dfas for the expensive looks. See `Nfa<u8, NoLooks>::add_min_utf8_sequences`
 // for an explanation.
 lazy_static! {
-    static ref WORD_CHAR_DFA: Dfa<(Look, u8)> = make_char_dfa(Look::WordChar);
-    static ref NOT_WORD_CHAR_DFA: Dfa<(Look, u8)> = make_char_dfa(Look::NotWordChar);
-    static ref REV_WORD_CHAR_DFA: Dfa<(Look, u8)> = make_rev_char_dfa(Look::WordChar);
-    static ref REV_NOT_WORD_CHAR_DFA: Dfa<(Look, u8)> = make_rev_char_dfa(Look::NotWordChar);
+    static ref WORD_CHAR_DFA: Dfa<(Look, u8)> =
+        make_char_dfa(Look::WordChar, Look::WordChar.as_set());
+    static ref NOT_WORD_CHAR_DFA: Dfa<(Look, u8)> =
+        make_char_dfa(Look::NotWordChar, Look::NotWordChar.as_set());
+    static ref REV_WORD_CHAR_DFA: Dfa<(Look, u8)> =
+        make_rev_char_dfa(Look::WordChar, Look::WordChar.as_set());
+    static ref REV_NOT_WORD_CHAR_DFA: Dfa<(Look, u8)> =
+        make_rev_char_dfa(Look::NotWordChar, Look::NotWordChar.as_set());
+}
+
+// The Dfa that matches the chars that `look` (either `WordChar` or `NotWordChar`) stands for. The
+// usual word chars have cached Dfas, but other ones need to be built from scratch.
+fn char_dfa(look: Look, words: &WordChars) -> Cow<'static, Dfa<(Look, u8)>> {
+    match (look, words.is_unicode()) {
+        (Look::WordChar, true) => Cow::Borrowed(&*WORD_CHAR_DFA),
+        (Look::NotWordChar, true) => Cow::Borrowed(&*NOT_WORD_CHAR_DFA),
+        _ => Cow::Owned(make_char_dfa(look, words.set(look))),
+    }
+}
+
+// Like `char_dfa`, but the Dfa matches backwards.
+fn rev_char_dfa(look: Look, words: &WordChars) -> Cow<'static, Dfa<(Look, u8)>> {
+    match (look, words.is_unicode()) {
+        (Look::WordChar, true) => Cow::Borrowed(&*REV_WORD_CHAR_DFA),
+        (Look::NotWordChar, true) => Cow::Borrowed(&*REV_NOT_WORD_CHAR_DFA),
+        _ => Cow::Owned(make_rev_char_dfa(look, words.set(look))),
+    }
 }
 
 impl<Tok: Debug + PrimInt> Nfa<Tok, NoLooks> {
@@ -244,9 +291,10 @@ impl<Tok: Debug + PrimInt> Nfa<Tok, NoLooks> {
 }
 
 impl Nfa<u32, NoLooks> {
-    /// Converts this `Nfa` into one that consumes the input byte-by-byte.
-    pub fn byte_me(self, max_states: usize) -> ::Result<Nfa<u8, NoLooks>> {
-        let mut ret = Nfa::<u8, NoLooks> {
+    /// Returns an `Nfa<u8, NoLooks>` with the same states (and initial states) as this one, but
+    /// without any transitions.
+    pub fn without_transitions(&self) -> Nfa<u8, NoLooks> {
+        Nfa::<u8, NoLooks> {
             states: self.states.iter().map(|s| State {
                 accept: s.accept,
                 accept_look: s.accept_look,
@@ -255,26 +303,211 @@ impl Nfa<u32, NoLooks> {
                 consuming: RangeMultiMap::new(),
                 looking: Vec::new(),
             }).collect(),
-            init: self.init,
+            init: self.init.clone(),
             phantom: PhantomData,
-        };
+        }
+    }
+
+    /// Converts this `Nfa` into one that consumes the input byte-by-byte.
+    ///
+    /// Invalid UTF-8 is never matched; see `byte_me_with`.
+    pub fn byte_me(self, max_states: usize) -> ::Result<Nfa<u8, NoLooks>> {
+        self.byte_me_with(max_states, InvalidUtf8::NeverMatch)
+    }
+
+    /// Converts this `Nfa` into one that consumes the input byte-by-byte, treating invalid UTF-8
+    /// as specified by `invalid`.
+    pub fn byte_me_with(self, max_states: usize, invalid: InvalidUtf8)
+    -> ::Result<Nfa<u8, NoLooks>> {
+        self.byte_me_cached(max_states, invalid, &mut Utf8Cache::new())
+    }
+
+    /// Like `byte_me_with`, but reuses (and adds to) the UTF-8 encodings in `cache`.
+    pub fn byte_me_cached(self, max_states: usize, invalid: InvalidUtf8, cache: &mut Utf8Cache)
+    -> ::Result<Nfa<u8, NoLooks>> {
+        let mut ret = self.without_transitions();
+        // The transitions that can consume U+FFFD.
+        let mut replacements = Vec::new();
 
         for (i, state) in self.states.into_iter().enumerate() {
             // Group transitions by the target state, and add them in batches. Most of the time, we
             // can merge a bunch of Utf8Sequences before adding them, which saves a bunch of
             // states.
             for (tgt, transitions) in state.consuming.ranges_values().group_by(|x| x.1) {
-                try!(ret.add_utf8_sequences(i, transitions.into_iter().map(|x| x.0), tgt, max_states));
+                let ranges = transitions.into_iter().map(|x| x.0).collect::<Vec<_>>();
+                if ranges.iter().any(|r| r.start <= 0xFFFD && 0xFFFD <= r.end) {
+                    replacements.push((i, tgt));
+                }
+                try!(ret.add_utf8_sequences(i, ranges, tgt, max_states, cache));
             }
         }
+
+        if invalid == InvalidUtf8::Replace {
+            replacements.sort();
+            replacements.dedup();
+            try!(ret.add_replacements(&replacements, max_states));
+        }
         Ok(ret)
     }
+
+    /// Converts this `Nfa` into one that consumes input in a single-byte encoding, in which the
+    /// byte `b` stands for the char `mapping[b]`.
+    ///
+    /// As with `utf16_me`, this `Nfa` must not have any look-ahead.
+    pub fn single_byte_me(self, mapping: &[char; 256]) -> Nfa<u8, NoLooks> {
+        debug_assert!(!self.has_look_ahead());
+
+        let mut ret = self.without_transitions();
+        for (i, state) in self.states.into_iter().enumerate() {
+            for &(range, tgt) in state.consuming.ranges_values() {
+                let in_range = |b: usize| range.start <= mapping[b] as u32
+                    && mapping[b] as u32 <= range.end;
+
+                // Add a transition for every run of consecutive bytes that map into `range`.
+                let mut b = 0;
+                while b < 256 {
+                    if in_range(b) {
+                        let start = b;
+                        while b + 1 < 256 && in_range(b + 1) {
+                            b += 1;
+                        }
+                        ret.add_transition(i, tgt, Range::new(start as u8, b as u8));
+                    }
+                    b += 1;
+                }
+            }
+        }
+        ret
+    }
+
+    /// Returns an `Nfa` that matches every string within edit distance `k` of a string that this
+    /// `Nfa` matches. The edit distance between two strings is the number of chars that need to
+    /// be inserted, deleted, or substituted in order to turn one into the other.
+    ///
+    /// The new `Nfa` has `k + 1` copies of this one, where the copy number `i` keeps track of the
+    /// states we could be in after making `i` edits. This `Nfa` must not have any look-ahead or
+    /// look-behind.
+    pub fn within_distance(&self, k: usize) -> Nfa<u32, NoLooks> {
+        debug_assert!(!self.has_look_ahead() && !self.has_look_behind());
+
+        let n = self.states.len();
+        let idx = |st: StateIdx, edits: usize| edits * n + st;
+        let mut ret = Nfa::with_capacity(n * (k + 1));
+        for edits in 0..(k + 1) {
+            for st in 0..n {
+                let closure = self.deletion_closure(st, edits, k);
+                let accept = closure.iter().map(|&(s, _)| self.states[s].accept).max();
+                let new_idx = ret.add_state(accept.unwrap_or(Accept::Never));
+                debug_assert!(new_idx == idx(st, edits));
+
+                // Exact matches get priority over edits.
+                for &(s, e) in &closure {
+                    for &(range, tgt) in self.states[s].consuming.ranges_values() {
+                        ret.add_transition(new_idx, idx(tgt, e), range);
+                    }
+                }
+                for &(s, e) in closure.iter().filter(|&&(_, e)| e < k) {
+                    // Substitutions.
+                    for &(_, tgt) in self.states[s].consuming.ranges_values() {
+                        ret.add_transition(new_idx, idx(tgt, e + 1), Range::full());
+                    }
+                    // Insertions.
+                    ret.add_transition(new_idx, idx(s, e + 1), Range::full());
+                }
+            }
+        }
+
+        // The initial states are in the first copy, so they keep their indices.
+        ret.init = self.init.clone();
+        ret.trim_unreachable();
+        ret
+    }
+
+    /// Returns an `Nfa` that matches every string with one char for each element of `positions`,
+    /// where all but at most `k` of the chars belong to the corresponding set. In other words, it
+    /// matches everything within Hamming distance `k` of the strings described by `positions`.
+    ///
+    /// This is much smaller than an alternation of all the ways to choose the mismatches: there
+    /// is one state for every position and number of mismatches so far.
+    pub fn with_mismatches(positions: &[RangeSet<u32>], k: usize) -> Nfa<u32, NoLooks> {
+        let len = positions.len();
+        let idx = |pos: usize, mismatches: usize| mismatches * (len + 1) + pos;
+        let mut ret = Nfa::with_capacity((len + 1) * (k + 1));
+        for _ in 0..(k + 1) {
+            for pos in 0..(len + 1) {
+                ret.add_state(if pos == len { Accept::Always } else { Accept::Never });
+            }
+        }
+
+        for mismatches in 0..(k + 1) {
+            for (pos, set) in positions.iter().enumerate() {
+                let src = idx(pos, mismatches);
+                for range in set.ranges() {
+                    ret.add_transition(src, idx(pos + 1, mismatches), range);
+                }
+                if mismatches < k {
+                    for range in set.negated().ranges() {
+                        ret.add_transition(src, idx(pos + 1, mismatches + 1), range);
+                    }
+                }
+            }
+        }
+        ret.init.push((Look::Full, idx(0, 0)));
+        ret.trim_unreachable();
+        ret
+    }
+
+    // Returns the states that we can reach from state `st` (after making `edits` edits) by
+    // deleting chars, together with the number of edits made after deleting them. Deleting a char
+    // means following a transition without consuming any input.
+    //
+    // Each state appears at most once, with the smallest possible number of edits.
+    fn deletion_closure(&self, st: StateIdx, edits: usize, k: usize) -> Vec<(StateIdx, usize)> {
+        let mut seen = vec![false; self.states.len()];
+        let mut ret = vec![(st, edits)];
+        seen[st] = true;
+
+        // Since `ret` is ordered by the number of edits, the first time we see a state is the
+        // cheapest way to get to it.
+        let mut i = 0;
+        while i < ret.len() {
+            let (s, e) = ret[i];
+            if e < k {
+                for &(_, tgt) in self.states[s].consuming.ranges_values() {
+                    if !seen[tgt] {
+                        seen[tgt] = true;
+                        ret.push((tgt, e + 1));
+                    }
+                }
+            }
+            i += 1;
+        }
+        ret
+    }
 }
 
 impl Nfa<u8, NoLooks> {
     /// Converts this `Nfa` into a `Dfa`.
     pub fn determinize(&self, max_states: usize) -> ::Result<Dfa<(Look, u8)>> {
-        Determinizer::determinize(self, max_states, MatchChoice::TransitionOrder, self.init.clone())
+        self.determinize_with_stats(max_states).map(|x| x.0)
+    }
+
+    /// Converts this `Nfa` into a `Dfa`, and also returns some statistics about the conversion.
+    pub fn determinize_with_stats(&self, max_states: usize)
+    -> ::Result<(Dfa<(Look, u8)>, DeterminizeStats)> {
+        let options = DeterminizeOptions { max_states: max_states, ..Default::default() };
+        Determinizer::determinize(self, &options, MatchChoice::TransitionOrder, self.init.clone())
+    }
+
+    /// Converts this `Nfa` into a `Dfa`, using the given options, and also returns some statistics
+    /// about the conversion.
+    ///
+    /// The options only affect the order of the states in the resulting `Dfa` (and, if there are
+    /// too many states or too little time, how far we get before giving up); the `Dfa`s are all
+    /// equivalent.
+    pub fn determinize_with_options(&self, options: &DeterminizeOptions)
+    -> ::Result<(Dfa<(Look, u8)>, DeterminizeStats)> {
+        Determinizer::determinize(self, options, MatchChoice::TransitionOrder, self.init.clone())
     }
 
     /// Converts this `Nfa` into a `Dfa`.
@@ -283,7 +516,15 @@ impl Nfa<u8, NoLooks> {
     /// possible endpoints for a match then the returned `Dfa` is only guaranteed to match the
     /// longest one.
     pub fn determinize_longest(&self, max_states: usize) -> ::Result<Dfa<(Look, u8)>> {
-        Determinizer::determinize(self, max_states, MatchChoice::LongestMatch, self.init.clone())
+        let options = DeterminizeOptions { max_states: max_states, ..Default::default() };
+        self.determinize_longest_with_options(&options).map(|x| x.0)
+    }
+
+    /// Like `determinize_longest`, but using the given options (and also returning statistics, as
+    /// `determinize_with_options` does).
+    pub fn determinize_longest_with_options(&self, options: &DeterminizeOptions)
+    -> ::Result<(Dfa<(Look, u8)>, DeterminizeStats)> {
+        Determinizer::determinize(self, options, MatchChoice::LongestMatch, self.init.clone())
     }
 
     /// Returns the reversal of this `Nfa`.
@@ -293,6 +534,12 @@ impl Nfa<u8, NoLooks> {
     ///
     /// Note that this loses information about match priorities.
     pub fn reverse(&self, max_states: usize) -> ::Result<Nfa<u8, NoLooks>> {
+        self.reverse_with(max_states, &WordChars::unicode())
+    }
+
+    /// Like `reverse`, but for an `Nfa` whose `\b` and `\B` use `words` as the word chars.
+    pub fn reverse_with(&self, max_states: usize, words: &WordChars)
+    -> ::Result<Nfa<u8, NoLooks>> {
         let mut ret = self.reversed_simple();
 
         // Turn our initial states into ret's accepting states.
@@ -316,15 +563,13 @@ impl Nfa<u8, NoLooks> {
                     // It would make more sense to put this outside the loop, but having it inside
                     // prevents a deadlock: constructing REV_*_DFA ends up calling reverse(), but
                     // with no look-ahead so it never gets inside this loop.
-                    let dfa: &Dfa<_> = if look == Look::WordChar {
-                        &REV_WORD_CHAR_DFA
-                    } else {
+                    let dfa = rev_char_dfa(look, words);
+                    if look == Look::NotWordChar {
                         ret.states[i].accept = max(ret.states[i].accept, Accept::AtEoi);
                         ret.states[i].accept_look = max(ret.states[i].accept_look, Look::Boundary);
-                        &REV_NOT_WORD_CHAR_DFA
-                    };
+                    }
                     let accept_state = ret.add_look_ahead_state(look, 1, i);
-                    try!(ret.add_min_utf8_sequences(i, dfa, accept_state, max_states));
+                    try!(ret.add_min_utf8_sequences(i, &dfa, accept_state, max_states));
                 },
                 Look::Empty => {
                     panic!("Empty cannot be an init look");
@@ -342,6 +587,86 @@ impl Nfa<u8, NoLooks> {
         Ok(ret)
     }
 
+    /// Shrinks this `Nfa` without changing what it matches (or the match priorities).
+    ///
+    /// We remove states that are useless, and then merge states that are equivalent (see
+    /// `merge_equivalent_states`). Since the `Dfa` states are sets of `Nfa` states, this can make
+    /// determinization quite a bit cheaper for regexes with large alternations.
+    pub fn reduce(&mut self) {
+        self.trim_unreachable();
+        self.merge_equivalent_states();
+    }
+
+    /// Merges states that are bisimilar: they accept in the same way, and they have the same
+    /// transitions (in the same order) to bisimilar states.
+    ///
+    /// If two such states are both active, the lower priority one can never contribute a match
+    /// that the higher priority one doesn't, so the merged state has the same match priorities.
+    pub fn merge_equivalent_states(&mut self) {
+        if self.states.is_empty() {
+            return;
+        }
+
+        // Refine the partition into classes until it stops changing. We number the classes in
+        // order of their first state, so the result is deterministic.
+        let mut class = vec![0; self.states.len()];
+        let mut num_classes = 1;
+        loop {
+            let mut sigs = HashMap::new();
+            let mut new_class = Vec::with_capacity(self.states.len());
+            for (idx, st) in self.states.iter().enumerate() {
+                let trans = st.consuming.ranges_values()
+                    .map(|&(range, tgt)| (range.start, range.end, class[tgt]))
+                    .collect::<Vec<_>>();
+                let sig = (class[idx],
+                           st.accept,
+                           st.accept_look,
+                           st.accept_tokens,
+                           class[st.accept_state],
+                           trans);
+                let next_class = sigs.len();
+                new_class.push(*sigs.entry(sig).or_insert(next_class));
+            }
+
+            class = new_class;
+            if sigs.len() == num_classes {
+                break;
+            }
+            num_classes = sigs.len();
+        }
+        if num_classes == self.states.len() {
+            return;
+        }
+
+        let mut old_states = Vec::new();
+        swap(&mut self.states, &mut old_states);
+        for (idx, st) in old_states.into_iter().enumerate() {
+            if class[idx] == self.states.len() {
+                self.states.push(st);
+            }
+        }
+        self.map_states(|s| Some(class[s]));
+
+        // Merging can produce duplicate transitions and initial states. Only the first copy of
+        // each one matters.
+        for st in &mut self.states {
+            let mut trans = Vec::with_capacity(st.consuming.num_ranges());
+            for &pair in st.consuming.ranges_values() {
+                if !trans.contains(&pair) {
+                    trans.push(pair);
+                }
+            }
+            st.consuming = RangeMultiMap::from_vec(trans);
+        }
+        let mut init = Vec::with_capacity(self.init.len());
+        for &pair in &self.init {
+            if !init.contains(&pair) {
+                init.push(pair);
+            }
+        }
+        self.init = init;
+    }
+
     /// Can we accept immediately if the beginning of the input matches `look`?
     fn init_accept(&self, look: Look) -> Accept {
         let set = self.init.iter()
@@ -355,7 +680,75 @@ impl Nfa<u8, NoLooks> {
     ///
     /// The result is actually a little bit different, because `.` matches a whole code point,
     /// whereas the `^.*` that we add works at the byte level.
-    pub fn anchor(mut self, max_states: usize) -> ::Result<Nfa<u8, NoLooks>> {
+    pub fn anchor(self, max_states: usize) -> ::Result<Nfa<u8, NoLooks>> {
+        self.anchor_with_stride(max_states, 1, &[], &WordChars::unicode())
+    }
+
+    /// Like `anchor`, but the result can also start searching in the middle of the input.
+    ///
+    /// Besides the initial state for the beginning of the input, there is one for each kind of
+    /// char that can come before the place where the search starts (see `Look::before`). Each of
+    /// them can start a match right away (if the `Nfa` allows it after that char), or carry on
+    /// searching.
+    ///
+    /// `words` are the word chars that `\b` and `\B` used when this `Nfa` was made.
+    pub fn anchor_resumable(self, max_states: usize, words: &WordChars)
+    -> ::Result<Nfa<u8, NoLooks>> {
+        let looks = [Look::WordChar, Look::NotWordChar, Look::NewLine];
+        self.anchor_with_stride(max_states, 1, &looks, words)
+    }
+
+    /// Like `anchor`, but for an `Nfa` that was made by `utf16_me`.
+    ///
+    /// The `^.*` that we add consumes two bytes at a time, so that a match can only start at the
+    /// beginning of a code unit.
+    pub fn anchor_utf16(self, max_states: usize) -> ::Result<Nfa<u8, NoLooks>> {
+        self.anchor_with_stride(max_states, 2, &[], &WordChars::unicode())
+    }
+
+    /// Returns an anchored `Nfa` that matches the same things as this one does, at a position
+    /// where the char before the start of the input matches `look`.
+    pub fn anchor_after(&self, look: Look) -> Nfa<u8, NoLooks> {
+        let mut ret = self.anchor_after_looks(&[look]);
+        for pair in &mut ret.init {
+            pair.0 = Look::Boundary;
+        }
+        ret
+    }
+
+    /// Returns an `Nfa` that only matches at the start of the input, but with one set of initial
+    /// states for each of `looks`: the initial states for `look` are the ones that this `Nfa`
+    /// would start in if the char before the input matched `look`.
+    pub fn anchor_after_looks(&self, looks: &[Look]) -> Nfa<u8, NoLooks> {
+        let mut ret = self.clone();
+        ret.init = looks.iter()
+            .flat_map(|&look| self.init.iter()
+                .filter(move |pair| look <= pair.0)
+                .map(move |pair| (look, pair.1)))
+            .collect();
+        ret
+    }
+
+    // Adds a path from `source` to `target` that consumes exactly `stride` bytes.
+    fn add_skip(&mut self, source: StateIdx, target: StateIdx, stride: usize) {
+        let mut last = source;
+        for _ in 1..stride {
+            let next = self.add_state(Accept::Never);
+            self.add_transition(last, next, Range::full());
+            last = next;
+        }
+        self.add_transition(last, target, Range::full());
+    }
+
+    // `resume_looks` are the kinds of char that get initial states of their own, for starting in
+    // the middle of the input (see `anchor_resumable`).
+    fn anchor_with_stride(
+        mut self,
+        max_states: usize,
+        stride: usize,
+        resume_looks: &[Look],
+        words: &WordChars
+    ) -> ::Result<Nfa<u8, NoLooks>> {
         let loop_accept = self.init_accept(Look::Full);
         let loop_state = self.add_state(loop_accept);
         let init_accept = self.init_accept(Look::Boundary);
@@ -385,11 +778,10 @@ impl Nfa<u8, NoLooks> {
                     self.add_transition(loop_state, st_idx, Range::new(b'\n', b'\n'));
                 },
                 Look::WordChar | Look::NotWordChar => {
-                    let dfa: &Dfa<_> =
-                        if look == Look::WordChar { &WORD_CHAR_DFA } else { &NOT_WORD_CHAR_DFA };
+                    let dfa = char_dfa(look, words);
 
-                    try!(self.add_min_utf8_sequences(loop_state, dfa, st_idx, max_states));
-                    try!(self.add_min_utf8_sequences(init_state, dfa, st_idx, max_states));
+                    try!(self.add_min_utf8_sequences(loop_state, &dfa, st_idx, max_states));
+                    try!(self.add_min_utf8_sequences(init_state, &dfa, st_idx, max_states));
                 },
                 Look::Empty => {
                     panic!("Cannot start with an empty look");
@@ -412,15 +804,31 @@ impl Nfa<u8, NoLooks> {
         // if they are accepting then the accept should take priority over the transition (since
         // making the transition means that we are searching for a match that starts later).
         if init_accept != Accept::Always {
-            self.add_transition(init_state, loop_state, Range::full());
+            self.add_skip(init_state, loop_state, stride);
         }
         if loop_accept != Accept::Always {
-            self.add_transition(loop_state, loop_state, Range::full());
+            self.add_skip(loop_state, loop_state, stride);
         }
 
         // The new Nfa is only allowed to start at the beginning of the input, and only at the new
         // initial state.
         self.init.push((Look::Boundary, init_state));
+
+        // After a char that matches `look`, we can start in any of the old initial states that
+        // allow it (in order of priority, as above), or else keep searching.
+        for &look in resume_looks {
+            let mut accepted = false;
+            for &(_, st_idx) in init.iter().filter(|pair| look <= pair.0) {
+                self.init.push((look, st_idx));
+                if self.states[st_idx].accept == Accept::Always {
+                    accepted = true;
+                    break;
+                }
+            }
+            if !accepted {
+                self.init.push((look, loop_state));
+            }
+        }
         self.trim_unreachable();
         Ok(self)
     }
@@ -479,7 +887,7 @@ impl Nfa<u8, NoLooks> {
         &mut self,
         start_state: StateIdx,
         mut end_state: StateIdx,
-        seq: MergedUtf8Sequences
+        seq: &MergedUtf8Sequences
     ) {
         let mut last_state = start_state;
         for range in &seq.head {
@@ -499,16 +907,121 @@ impl Nfa<u8, NoLooks> {
         }
     }
 
+    // For every `(source, target)` pair, adds transitions from `source` to `target` that consume
+    // a maximal invalid subsequence of UTF-8 (see `InvalidUtf8::Replace`).
+    fn add_replacements(&mut self, pairs: &[(StateIdx, StateIdx)], max_states: usize)
+    -> ::Result<()> {
+        // A sequence that got truncated is only invalid if the byte after it doesn't continue it.
+        // So instead of going straight to the target state, truncated sequences go to a "guard"
+        // state that copies the target's transitions, except for the ones on bytes that would
+        // continue the sequence. The guards are indexed by the target state and the range of
+        // bytes that they forbid.
+        let mut guards = BTreeMap::new();
+
+        for &(src, tgt) in pairs {
+            if self.states[tgt].accept_tokens > 0 {
+                // The target is a look-ahead state, so we need to keep track of how many bytes we
+                // consume. For simplicity, we only bother with the single-byte sequences here.
+                let look = self.states[tgt].accept_look;
+                let acc_state = self.states[tgt].accept_state;
+                let end = self.add_look_ahead_state(look, 1, acc_state);
+                self.add_single_byte_replacements(src, end);
+                continue;
+            }
+
+            self.add_single_byte_replacements(src, tgt);
+            for &(lead, second, len) in &UTF8_LEADS {
+                // At step k, a sequence whose first k bytes are in `bytes` is truncated unless the
+                // next byte is in `cont`.
+                let mut prev = src;
+                let mut bytes = lead;
+                let mut cont = second;
+                for k in 0..(len - 1) {
+                    let guard = self.replacement_guard(&mut guards, tgt, cont);
+                    self.add_transition(prev, guard, Range::new(bytes.0, bytes.1));
+                    if k + 2 < len {
+                        let next = self.add_state(Accept::Never);
+                        self.add_transition(prev, next, Range::new(bytes.0, bytes.1));
+                        prev = next;
+                        bytes = cont;
+                        cont = (0x80, 0xBF);
+                    }
+                }
+            }
+            if self.states.len() > max_states {
+                return Err(Error::TooManyStates);
+            }
+        }
+
+        // Now that all the other transitions are in place, copy them into the guards.
+        for (&(tgt, excl_start, excl_end), &guard) in &guards {
+            let trans = self.states[tgt].consuming.ranges_values().cloned().collect::<Vec<_>>();
+            for (range, next) in trans {
+                if range.start < excl_start {
+                    let end = min(range.end, excl_start - 1);
+                    self.add_transition(guard, next, Range::new(range.start, end));
+                }
+                if range.end > excl_end {
+                    let start = max(range.start, excl_end + 1);
+                    self.add_transition(guard, next, Range::new(start, range.end));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Adds transitions from `source` to `target` on the bytes that can never be part of a valid
+    // UTF-8 sequence at this point: either they are never valid, or they are continuation bytes
+    // without a leading byte.
+    fn add_single_byte_replacements(&mut self, source: StateIdx, target: StateIdx) {
+        self.add_transition(source, target, Range::new(0x80, 0xBF));
+        self.add_transition(source, target, Range::new(0xC0, 0xC1));
+        self.add_transition(source, target, Range::new(0xF5, 0xFF));
+    }
+
+    // Returns the guard state for `target` that forbids the bytes in `excl` (see
+    // `add_replacements`), creating it if necessary. The guard state's transitions are added
+    // later.
+    fn replacement_guard(
+        &mut self,
+        guards: &mut BTreeMap<(StateIdx, u8, u8), StateIdx>,
+        target: StateIdx,
+        excl: (u8, u8),
+    ) -> StateIdx {
+        if let Some(&guard) = guards.get(&(target, excl.0, excl.1)) {
+            return guard;
+        }
+
+        let accept = self.states[target].accept;
+        let guard_accept = if accept == Accept::Never { Accept::Never } else { Accept::AtEoi };
+        let guard = self.add_state(guard_accept);
+        if accept == Accept::Always {
+            // We can accept before the end of the input, but only after looking ahead to check
+            // that the next byte doesn't continue the truncated sequence.
+            let ahead = self.add_state(Accept::Always);
+            self.states[ahead].accept_state = guard;
+            self.states[ahead].accept_tokens = 1;
+            self.add_transition(guard, ahead, Range::new(0, excl.0 - 1));
+            self.add_transition(guard, ahead, Range::new(excl.1 + 1, 0xFF));
+        }
+        guards.insert((target, excl.0, excl.1), guard);
+        guard
+    }
+
     // Adds a byte path from `start_state` to `end_state` for every char in `ranges`.
-    fn add_utf8_sequences<I>(
+    fn add_utf8_sequences(
         &mut self,
         start_state: StateIdx,
-        ranges: I,
+        ranges: Vec<Range<u32>>,
         end_state: StateIdx,
-        max_states: usize
-    ) -> ::Result<()>
-    where I: Iterator<Item=Range<u32>> {
-        for m in MergedUtf8Sequences::from_ranges(ranges) {
+        max_states: usize,
+        cache: &mut Utf8Cache
+    ) -> ::Result<()> {
+        if !cache.seqs.contains_key(&ranges) {
+            let seqs = MergedUtf8Sequences::from_ranges(ranges.iter().cloned()).collect();
+            cache.seqs.insert(ranges.clone(), seqs);
+        }
+        for m in &cache.seqs[&ranges] {
             self.add_utf8_sequence(start_state, end_state, m);
             if self.states.len() > max_states {
                 return Err(Error::TooManyStates);
@@ -518,7 +1031,11 @@ impl Nfa<u8, NoLooks> {
     }
 
     // Finds the transitions out of the given set of states, as a RangeMap.
-    fn transition_map(&self, states: &[StateIdx]) -> RangeMap<u8, Vec<usize>> {
+    //
+    // `scratch` must have length `self.num_states()` and be full of `false`, and it is left that
+    // way. It's passed in so that the determinizer can reuse one buffer for every DFA state.
+    fn transition_map(&self, states: &[StateIdx], scratch: &mut Vec<bool>)
+    -> RangeMap<u8, Vec<usize>> {
         let mut transitions = states.into_iter()
             .flat_map(|s| self.states[*s].consuming.ranges_values().cloned())
             .collect::<RangeMultiMap<u8, StateIdx>>()
@@ -538,9 +1055,8 @@ impl Nfa<u8, NoLooks> {
             }
         }
 
-        let mut scratch = vec![false; self.num_states()];
         for pair in transitions.as_mut_slice() {
-            uniquify(&mut pair.1, &mut scratch);
+            uniquify(&mut pair.1, scratch);
         }
 
         transitions
@@ -553,15 +1069,178 @@ enum MatchChoice {
     LongestMatch,
 }
 
+/// The order in which new DFA states are explored while turning an `Nfa` into a `Dfa`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExploreOrder {
+    /// Explore the most recently discovered state first.
+    DepthFirst,
+    /// Explore the least recently discovered state first.
+    BreadthFirst,
+}
+
+/// Options for turning an `Nfa` into a `Dfa`. See `RegexOptions::determinize`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeterminizeOptions {
+    /// The maximum number of DFA states to create before giving up.
+    pub max_states: usize,
+    /// The order in which to explore DFA states.
+    pub order: ExploreOrder,
+    /// If true, accepting DFA states are explored before all other states (and `order` only
+    /// decides the order within the accepting and within the non-accepting states).
+    pub accepting_first: bool,
+    /// If this time passes before we are done, give up with `Error::TimedOut`.
+    pub deadline: Option<Instant>,
+}
+
+impl Default for DeterminizeOptions {
+    fn default() -> DeterminizeOptions {
+        DeterminizeOptions {
+            max_states: usize::MAX,
+            order: ExploreOrder::DepthFirst,
+            accepting_first: false,
+            deadline: None,
+        }
+    }
+}
+
+/// How to treat input that isn't valid UTF-8. See `Nfa::byte_me_with`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InvalidUtf8 {
+    /// Invalid UTF-8 never matches anything, not even `.` or a negated character class.
+    NeverMatch,
+    /// Every maximal invalid subsequence (the same ones that `String::from_utf8_lossy` replaces)
+    /// matches as though it were the replacement character U+FFFD.
+    ///
+    /// Since matching doesn't look behind the start of the match, a match can begin with a
+    /// continuation byte even if it belongs to a valid character that started before the match.
+    /// Apart from that, valid UTF-8 is treated exactly the same as with `NeverMatch`.
+    Replace,
+}
+
+impl Default for InvalidUtf8 {
+    fn default() -> InvalidUtf8 {
+        InvalidUtf8::NeverMatch
+    }
+}
+
+// The bytes that can begin a multi-byte UTF-8 sequence. Each entry has a range of leading bytes,
+// the range of bytes that may follow them, and the length of the sequence.
+const UTF8_LEADS: [((u8, u8), (u8, u8), usize); 8] = [
+    ((0xC2, 0xDF), (0x80, 0xBF), 2),
+    ((0xE0, 0xE0), (0xA0, 0xBF), 3),
+    ((0xE1, 0xEC), (0x80, 0xBF), 3),
+    ((0xED, 0xED), (0x80, 0x9F), 3),
+    ((0xEE, 0xEF), (0x80, 0xBF), 3),
+    ((0xF0, 0xF0), (0x90, 0xBF), 4),
+    ((0xF1, 0xF3), (0x80, 0xBF), 4),
+    ((0xF4, 0xF4), (0x80, 0x8F), 4),
+];
+
+/// Some statistics about the process of turning an `Nfa` into a `Dfa`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DeterminizeStats {
+    /// The number of times that we looked up a set of NFA states.
+    pub lookups: usize,
+    /// The number of lookups that found an existing DFA state.
+    pub hits: usize,
+    /// The number of lookups that had to compare against a set with the same hash.
+    pub comparisons: usize,
+    /// The total number of NFA states stored, summed over all the DFA states.
+    pub stored_states: usize,
+}
+
+impl DeterminizeStats {
+    /// Adds `other` to these statistics (for example, to total them up over several
+    /// conversions).
+    pub fn add(&mut self, other: &DeterminizeStats) {
+        self.lookups += other.lookups;
+        self.hits += other.hits;
+        self.comparisons += other.comparisons;
+        self.stored_states += other.stored_states;
+    }
+}
+
+// Stores sets of NFA states (one for each DFA state) contiguously in a single buffer, so that
+// creating a DFA state costs at most one allocation (and usually none).
+//
+// The set belonging to DFA state `i` is `elts[offsets[i]..offsets[i+1]]`.
+struct StateSetArena {
+    elts: Vec<StateIdx>,
+    offsets: Vec<usize>,
+    // Maps the hash of a set to all of the stored sets with that hash.
+    buckets: HashMap<u64, Vec<StateIdx>>,
+}
+
+impl StateSetArena {
+    fn new() -> StateSetArena {
+        StateSetArena {
+            elts: Vec::new(),
+            offsets: vec![0],
+            buckets: HashMap::new(),
+        }
+    }
+
+    // FNV-1a, one word at a time. The sets are short and we hash a lot of them, so this is much
+    // cheaper than feeding them through a general-purpose `Hasher`.
+    fn hash(s: &[StateIdx]) -> u64 {
+        let mut h: u64 = 0xcbf29ce484222325;
+        for &x in s {
+            h ^= x as u64;
+            h = h.wrapping_mul(0x100000001b3);
+        }
+        h
+    }
+
+    fn get(&self, idx: StateIdx) -> &[StateIdx] {
+        &self.elts[self.offsets[idx]..self.offsets[idx + 1]]
+    }
+
+    // Returns the index of the set `s` (if it is stored), and the number of sets that we had to
+    // compare it to.
+    fn find(&self, hash: u64, s: &[StateIdx]) -> (Option<StateIdx>, usize) {
+        let mut comparisons = 0;
+        if let Some(bucket) = self.buckets.get(&hash) {
+            for &idx in bucket {
+                comparisons += 1;
+                if self.get(idx) == s {
+                    return (Some(idx), comparisons);
+                }
+            }
+        }
+        (None, comparisons)
+    }
+
+    fn insert(&mut self, hash: u64, s: &[StateIdx]) -> StateIdx {
+        let idx = self.offsets.len() - 1;
+        self.elts.extend_from_slice(s);
+        self.offsets.push(self.elts.len());
+        self.buckets.entry(hash).or_insert_with(Vec::new).push(idx);
+        idx
+    }
+}
+
 // This contains all the intermediate data structures that we need when turning an `Nfa` into a
 // `Dfa`.
 struct Determinizer<'a> {
     nfa: &'a Nfa<u8, NoLooks>,
     dfa: Dfa<(Look, u8)>,
-    state_map: HashMap<StateSet, StateIdx>,
-    active_states: Vec<StateSet>,
-    max_states: usize,
+    // The sets of NFA states corresponding to the DFA states; the index of a set in the arena is
+    // the index of the corresponding DFA state.
+    state_sets: StateSetArena,
+    // The DFA states whose transitions we haven't computed yet.
+    active_states: VecDeque<StateIdx>,
+    // If `options.accepting_first` is true, the accepting states whose transitions we haven't
+    // computed yet (and then `active_states` only has non-accepting states).
+    accepting_active_states: VecDeque<StateIdx>,
+    options: DeterminizeOptions,
     match_choice: MatchChoice,
+    // The size of the largest StateSet that we've seen so far.
+    largest_state_set: usize,
+    stats: DeterminizeStats,
+    // Buffers that are reused for every DFA state, instead of being allocated afresh each time:
+    // the scratch space for `Nfa::transition_map`, and the transitions of the current state.
+    scratch: Vec<bool>,
+    dfa_trans: Vec<(Range<u8>, StateIdx)>,
 }
 
 impl<'a> Determinizer<'a> {
@@ -571,24 +1250,31 @@ impl<'a> Determinizer<'a> {
     // `init` is a vector of length Look::num(). Each entry gives a set of initial states that
     // will be turned into the initial states of the dfa.
     fn determinize(nfa: &Nfa<u8, NoLooks>,
-                   max_states: usize,
+                   options: &DeterminizeOptions,
                    match_choice: MatchChoice,
-                   init: Vec<(Look, StateIdx)>) -> ::Result<Dfa<(Look, u8)>> {
-        let mut det = Determinizer::new(nfa, max_states, match_choice);
+                   init: Vec<(Look, StateIdx)>)
+    -> ::Result<(Dfa<(Look, u8)>, DeterminizeStats)> {
+        let mut det = Determinizer::new(nfa, options, match_choice);
         try!(det.run(init));
-        Ok(det.dfa)
+        det.stats.stored_states = det.state_sets.elts.len();
+        Ok((det.dfa, det.stats))
     }
 
     fn new(nfa: &'a Nfa<u8, NoLooks>,
-           max_states: usize,
+           options: &DeterminizeOptions,
            match_choice: MatchChoice) -> Determinizer<'a> {
         Determinizer {
             nfa: nfa,
             dfa: Dfa::new(),
-            state_map: HashMap::new(),
-            active_states: Vec::new(),
-            max_states: max_states,
+            state_sets: StateSetArena::new(),
+            active_states: VecDeque::new(),
+            accepting_active_states: VecDeque::new(),
+            options: *options,
             match_choice: match_choice,
+            largest_state_set: 0,
+            stats: DeterminizeStats::default(),
+            scratch: vec![false; nfa.num_states()],
+            dfa_trans: Vec::new(),
         }
     }
 
@@ -652,30 +1338,61 @@ impl<'a> Determinizer<'a> {
     //
     // If the state already exists, returns the index of the old one. If there are too many states,
     // returns an error.
-    fn add_state(&mut self, mut s: StateSet) -> ::Result<StateIdx> {
+    fn add_state(&mut self, mut s: &[StateIdx]) -> ::Result<StateIdx> {
         // When we choose our matches by transition order, discard any states that have lower
         // priority than the best match we've found.
         if self.match_choice == MatchChoice::TransitionOrder {
             if let Some(accept_idx) = s.iter().position(|&i| self.nfa.states[i].accept == Accept::Always) {
-                s.truncate(accept_idx + 1);
+                s = &s[..(accept_idx + 1)];
             }
         }
 
-        if self.state_map.contains_key(&s) {
-            Ok(*self.state_map.get(&s).unwrap())
-        } else if self.dfa.num_states() >= self.max_states {
-            Err(Error::TooManyStates)
+        let hash = StateSetArena::hash(s);
+        let (existing, comparisons) = self.state_sets.find(hash, s);
+        self.stats.lookups += 1;
+        self.stats.comparisons += comparisons;
+
+        if let Some(idx) = existing {
+            self.stats.hits += 1;
+            Ok(idx)
+        } else if self.dfa.num_states() >= self.options.max_states {
+            Err(Error::DfaBlowup(BlowupReport {
+                nfa_states: self.nfa.num_states(),
+                dfa_states: self.dfa.num_states(),
+                largest_state_set: max(self.largest_state_set, s.len()),
+            }))
         } else {
-            let (acc, look, bytes_ago) = self.accept(&s);
+            self.largest_state_set = max(self.largest_state_set, s.len());
+            let (acc, look, bytes_ago) = self.accept(s);
             let ret = if acc != Accept::Never { Some ((look, bytes_ago)) } else { None };
             let new_state = self.dfa.add_state(acc, ret);
+            let set_idx = self.state_sets.insert(hash, s);
+            debug_assert!(new_state == set_idx);
 
-            self.active_states.push(s.clone());
-            self.state_map.insert(s, new_state);
+            if self.options.accepting_first && acc != Accept::Never {
+                self.accepting_active_states.push_back(new_state);
+            } else {
+                self.active_states.push_back(new_state);
+            }
             Ok(new_state)
         }
     }
 
+    // Returns the next state whose transitions need to be computed.
+    fn next_active_state(&mut self) -> Option<StateIdx> {
+        fn pop(queue: &mut VecDeque<StateIdx>, order: ExploreOrder) -> Option<StateIdx> {
+            match order {
+                ExploreOrder::DepthFirst => queue.pop_back(),
+                ExploreOrder::BreadthFirst => queue.pop_front(),
+            }
+        }
+
+        match pop(&mut self.accepting_active_states, self.options.order) {
+            Some(s) => Some(s),
+            None => pop(&mut self.active_states, self.options.order),
+        }
+    }
+
     // Creates a deterministic automaton representing the same language as our `nfa`.
     // Puts the new Dfa in self.dfa.
     fn run(&mut self, init: Vec<(Look, StateIdx)>) -> ::Result<()> {
@@ -689,23 +1406,32 @@ impl<'a> Determinizer<'a> {
                 .map(|(_, y)| y)
                 .collect();
             if !init_states.is_empty() {
-                let new_state_idx = try!(self.add_state(init_states));
-                self.dfa.init[look.as_usize()] = Some(new_state_idx);
+                let new_state_idx = try!(self.add_state(&init_states));
+                self.dfa.set_init_state(look, Some(new_state_idx));
             }
         }
 
-        while !self.active_states.is_empty() {
-            let state = self.active_states.pop().unwrap();
-            // This unwrap is ok because anything in active_states must also be in state_map.
-            let state_idx = *self.state_map.get(&state).unwrap();
-            let trans = self.nfa.transition_map(&state);
+        let mut steps = 0;
+        while let Some(state_idx) = self.next_active_state() {
+            if let Some(deadline) = self.options.deadline {
+                if Instant::now() >= deadline {
+                    return Err(Error::TimedOut(TimeoutReport {
+                        stage: Stage::Determinize,
+                        dfa_states: self.dfa.num_states(),
+                        steps: steps,
+                    }));
+                }
+            }
+            steps += 1;
+
+            let trans = self.nfa.transition_map(self.state_sets.get(state_idx), &mut self.scratch);
 
-            let mut dfa_trans = Vec::new();
+            self.dfa_trans.clear();
             for &(range, ref target) in trans.ranges_values() {
-                let target_idx = try!(self.add_state(target.clone()));
-                dfa_trans.push((range, target_idx));
+                let target_idx = try!(self.add_state(target));
+                self.dfa_trans.push((range, target_idx));
             }
-            self.dfa.set_transitions(state_idx, dfa_trans.into_iter().collect());
+            self.dfa.set_transitions(state_idx, self.dfa_trans.iter().cloned().collect());
         }
         Ok(())
     }
@@ -713,11 +1439,12 @@ impl<'a> Determinizer<'a> {
 
 #[cfg(test)]
 mod tests {
+    use error::Error;
     use look::Look;
     use dfa::Dfa;
     use nfa::{Accept, Nfa, NoLooks};
     use nfa::tests::{re_nfa, trans_nfa, trans_range_nfa};
-    use range_map::Range;
+    use range_map::{Range, RangeSet};
     use std::usize;
 
     fn re_nfa_anchored(re: &str) -> Nfa<u8, NoLooks> {
@@ -768,6 +1495,161 @@ mod tests {
         assert_eq!(nfa, target);
     }
 
+    #[test]
+    fn byte_me_cached() {
+        use nfa::{InvalidUtf8, Utf8Cache};
+
+        let mut cache = Utf8Cache::new();
+        for re in &[r"\w+", r"[^a]\w", r"\w+@\w+"] {
+            let cached = re_nfa(re).byte_me_cached(usize::MAX, InvalidUtf8::NeverMatch, &mut cache);
+            assert_eq!(cached.unwrap(), re_nfa(re).byte_me(usize::MAX).unwrap());
+        }
+        // `\w` was only encoded once.
+        let sets = cache.seqs.len();
+        re_nfa(r"x\w").byte_me_cached(usize::MAX, InvalidUtf8::NeverMatch, &mut cache).unwrap();
+        assert_eq!(cache.seqs.len(), sets + 1);
+    }
+
+    #[test]
+    fn determinize_blowup_report() {
+        let nfa = re_nfa("(a|b)*a(a|b)(a|b)(a|b)").byte_me(usize::MAX).unwrap();
+        match nfa.determinize(5) {
+            Err(Error::DfaBlowup(report)) => {
+                assert_eq!(report.nfa_states, nfa.num_states());
+                assert_eq!(report.dfa_states, 5);
+                assert!(report.largest_state_set > 1);
+                assert!(report.largest_state_set <= nfa.num_states());
+            },
+            x => panic!("expected a blowup report, found {:?}", x),
+        }
+    }
+
+    #[test]
+    fn determinize_stats() {
+        let nfa = re_nfa("(a|b)*a(a|b)(a|b)").byte_me(usize::MAX).unwrap();
+        let (dfa, stats) = nfa.determinize_with_stats(usize::MAX).unwrap();
+        assert_eq!(stats.lookups, stats.hits + dfa.num_states());
+        assert!(stats.hits > 0);
+        assert!(stats.comparisons >= stats.hits);
+        assert!(stats.stored_states >= dfa.num_states());
+        assert_eq!(dfa, nfa.determinize(usize::MAX).unwrap());
+    }
+
+    #[test]
+    fn determinize_options() {
+        use nfa::{DeterminizeOptions, ExploreOrder};
+
+        for re in &["abc|abd", "a*b+c?", "(foo|bar)[0-9]+baz", r"\bx\b"] {
+            let nfa = re_nfa(re).byte_me(usize::MAX).unwrap();
+            let default = nfa.determinize(usize::MAX).unwrap();
+            for &order in &[ExploreOrder::DepthFirst, ExploreOrder::BreadthFirst] {
+                for &accepting_first in &[false, true] {
+                    let options = DeterminizeOptions {
+                        order: order,
+                        accepting_first: accepting_first,
+                        ..Default::default()
+                    };
+                    let dfa = nfa.determinize_with_options(&options).unwrap().0;
+                    assert_eq!(dfa.num_states(), default.num_states());
+                    assert_eq!(dfa.optimize(), default.clone().optimize());
+                }
+            }
+        }
+
+        // In breadth-first order, states are numbered in order of their distance from the start.
+        let nfa = re_nfa("(ab|cd)(ef|gh)").byte_me(usize::MAX).unwrap();
+        let options = DeterminizeOptions {
+            order: ExploreOrder::BreadthFirst,
+            ..Default::default()
+        };
+        let dfa = nfa.determinize_with_options(&options).unwrap().0;
+        let mut dist = vec![usize::MAX; dfa.num_states()];
+        dist[0] = 0;
+        for s in 0..dfa.num_states() {
+            for &(_, t) in dfa.transitions(s).ranges_values() {
+                if dist[t] == usize::MAX {
+                    dist[t] = dist[s] + 1;
+                }
+            }
+        }
+        assert!(dist.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn reduce() {
+        // The alternatives have a common suffix, which only needs to be matched once.
+        let nfa = re_nfa("abc|xbc|ybc").byte_me(usize::MAX).unwrap();
+        let mut reduced = nfa.clone();
+        reduced.reduce();
+        assert!(reduced.num_states() < nfa.num_states());
+
+        for re in &["foo|bar|baz", "a|ab", "(a|b)*a(a|b)(a|b)", r"x|y", "(?m)^a$|[ab]+$"] {
+            let nfa = re_nfa(re).byte_me(usize::MAX).unwrap();
+            let mut reduced = nfa.clone();
+            reduced.reduce();
+            assert!(reduced.num_states() <= nfa.num_states());
+            assert_eq!(reduced.determinize(usize::MAX).unwrap().optimize(),
+                       nfa.determinize(usize::MAX).unwrap().optimize());
+        }
+    }
+
+    #[test]
+    fn within_distance() {
+        let fuzzy = |re: &str, k: usize| {
+            re_nfa(re).within_distance(k).byte_me(usize::MAX).unwrap()
+                .determinize(usize::MAX).unwrap()
+        };
+
+        let dfa = fuzzy("^abc$", 1);
+        for s in &["abc", "ab", "bc", "ac", "xbc", "abx", "abcd", "xabc", "axbc"] {
+            assert!(dfa.accepts(s.chars()), "should accept {:?}", s);
+        }
+        for s in &["", "a", "cab", "xbx", "abcde"] {
+            assert!(!dfa.accepts(s.chars()), "shouldn't accept {:?}", s);
+        }
+
+        let dfa = fuzzy("^abc$", 2);
+        assert!(dfa.accepts("a".chars()));
+        assert!(dfa.accepts("xbx".chars()));
+        assert!(!dfa.accepts("".chars()));
+
+        // Edits are made on chars, not bytes.
+        assert!(fuzzy("^é$", 1).accepts("e".chars()));
+        assert!(fuzzy("^a+$", 1).accepts("aabaa".chars()));
+        assert!(!fuzzy("^a+$", 1).accepts("abab".chars()));
+
+        assert_eq!(fuzzy("^abc$", 0).optimize(), re_dfa("^abc$").optimize());
+    }
+
+    #[test]
+    fn with_mismatches() {
+        let positions = vec![RangeSet::single('A' as u32),
+                             vec![Range::new('G' as u32, 'T' as u32)].into_iter().collect(),
+                             RangeSet::single('C' as u32)];
+        let dfa = |k: usize| {
+            Nfa::with_mismatches(&positions, k).byte_me(usize::MAX).unwrap()
+                .anchor_after(Look::Boundary).determinize(usize::MAX).unwrap()
+        };
+
+        let exact = dfa(0);
+        assert!(exact.accepts("AGC".chars()));
+        assert!(exact.accepts("ATC".chars()));
+        assert!(!exact.accepts("AAC".chars()));
+
+        let one = dfa(1);
+        for s in &["AGC", "AAC", "xTC", "AGé"] {
+            assert!(one.accepts(s.chars()), "should accept {:?}", s);
+        }
+        for s in &["", "AG", "AGCC", "xxC", "GAT"] {
+            assert!(!one.accepts(s.chars()), "shouldn't accept {:?}", s);
+        }
+        assert!(dfa(3).accepts("xyz".chars()));
+
+        // There is a state for each position and number of mismatches so far, except that we
+        // can't have made more mismatches than we have seen positions.
+        assert_eq!(Nfa::with_mismatches(&positions, 2).num_states(), 4 * 3 - 3);
+    }
+
     #[test]
     fn determinize_pruning() {
         assert_eq!(re_dfa("a|aa"), re_dfa("a"));
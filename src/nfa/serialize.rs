@@ -0,0 +1,54 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Serialization for `Accept` (enabled by the `serde` feature). An `Accept` is serialized as one of
+// the strings `"never"`, `"at_eoi"` or `"always"`.
+
+use nfa::Accept;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+impl Accept {
+    fn name(&self) -> &'static str {
+        match *self {
+            Accept::Never => "never",
+            Accept::AtEoi => "at_eoi",
+            Accept::Always => "always",
+        }
+    }
+}
+
+impl Serialize for Accept {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> Result<(), S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl Deserialize for Accept {
+    fn deserialize<D: Deserializer>(deserializer: &mut D) -> Result<Accept, D::Error> {
+        let name: String = try!(Deserialize::deserialize(deserializer));
+        match &name[..] {
+            "never" => Ok(Accept::Never),
+            "at_eoi" => Ok(Accept::AtEoi),
+            "always" => Ok(Accept::Always),
+            _ => Err(de::Error::custom(format!("unknown accept \"{}\"", name))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nfa::Accept;
+    use serde_json;
+
+    #[test]
+    fn accept_names() {
+        assert_eq!(serde_json::to_string(&Accept::AtEoi).unwrap(), r#""at_eoi""#);
+        assert_eq!(serde_json::from_str::<Accept>(r#""always""#).unwrap(), Accept::Always);
+        assert!(serde_json::from_str::<Accept>(r#""sometimes""#).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright 2015-2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use error::Error;
+use nfa::{Accept, Nfa, NoLooks, StateIdx};
+use range_map::Range;
+use std::cmp::{max, min};
+
+// Splits the inclusive range `start..end` into rectangles. That is, if we write every number in
+// the range as `(row << bits) + col` then each element of the return value is an inclusive range
+// of rows and an inclusive range of columns, and `start..end` is the disjoint union of their
+// products.
+fn rectangles(start: u32, end: u32, bits: u32) -> Vec<((u32, u32), (u32, u32))> {
+    let mask = (1 << bits) - 1;
+    let (start_row, start_col) = (start >> bits, start & mask);
+    let (end_row, end_col) = (end >> bits, end & mask);
+
+    if start_row == end_row {
+        return vec![((start_row, start_row), (start_col, end_col))];
+    }
+
+    let mut ret = Vec::with_capacity(3);
+    let mut mid_start = start_row;
+    let mut mid_end = end_row;
+    if start_col > 0 {
+        ret.push(((start_row, start_row), (start_col, mask)));
+        mid_start += 1;
+    }
+    if end_col < mask {
+        mid_end -= 1;
+    }
+    if mid_start <= mid_end {
+        ret.push(((mid_start, mid_end), (0, mask)));
+    }
+    if end_col < mask {
+        ret.push(((end_row, end_row), (0, end_col)));
+    }
+    ret
+}
+
+// Splits an inclusive range of code points into sequences of inclusive ranges of UTF-16 code
+// units. Every sequence has length one (for the basic multilingual plane) or two (for a high
+// surrogate followed by a low surrogate). Surrogate code points are not chars, so they are left
+// out.
+fn utf16_sequences(start: u32, end: u32) -> Vec<Vec<(u32, u32)>> {
+    let mut ret = Vec::new();
+    for &(lo, hi) in &[(0, 0xD7FF), (0xE000, 0xFFFF)] {
+        let (s, e) = (max(start, lo), min(end, hi));
+        if s <= e {
+            ret.push(vec![(s, e)]);
+        }
+    }
+
+    // Each surrogate contributes ten bits to the code point.
+    let (s, e) = (max(start, 0x10000), min(end, 0x10FFFF));
+    if s <= e {
+        for (high, low) in rectangles(s - 0x10000, e - 0x10000, 10) {
+            ret.push(vec![(0xD800 + high.0, 0xD800 + high.1), (0xDC00 + low.0, 0xDC00 + low.1)]);
+        }
+    }
+    ret
+}
+
+impl Nfa<u32, NoLooks> {
+    /// Converts this `Nfa` into one that consumes UTF-16 input, byte-by-byte.
+    ///
+    /// Every code unit is consumed as two bytes in the platform's native byte order, so the
+    /// resulting `Nfa` can be run on the memory of a `&[u16]` without converting it first. This
+    /// `Nfa` must not have any look-ahead (see `has_look_ahead`), because the look-ahead machinery
+    /// counts UTF-8 bytes.
+    pub fn utf16_me(self, max_states: usize) -> ::Result<Nfa<u8, NoLooks>> {
+        debug_assert!(!self.has_look_ahead());
+
+        let mut ret = self.without_transitions();
+
+        for (i, state) in self.states.into_iter().enumerate() {
+            for &(range, tgt) in state.consuming.ranges_values() {
+                for seq in utf16_sequences(range.start, range.end) {
+                    ret.add_utf16_sequence(i, tgt, &seq);
+                }
+                if ret.states.len() > max_states {
+                    return Err(Error::TooManyStates);
+                }
+            }
+        }
+        Ok(ret)
+    }
+}
+
+impl Nfa<u8, NoLooks> {
+    // Adds a byte path from `start_state` to `end_state` for every sequence of code units in
+    // `seq`.
+    fn add_utf16_sequence(
+        &mut self,
+        start_state: StateIdx,
+        end_state: StateIdx,
+        seq: &[(u32, u32)]
+    ) {
+        let mut last_state = start_state;
+        for (i, &(start, end)) in seq.iter().enumerate() {
+            let next_state = if i + 1 == seq.len() {
+                end_state
+            } else {
+                self.add_state(Accept::Never)
+            };
+
+            for (high, low) in rectangles(start, end, 8) {
+                let (first, second) = if cfg!(target_endian = "little") {
+                    (low, high)
+                } else {
+                    (high, low)
+                };
+                let mid_state = self.add_state(Accept::Never);
+                let first = Range::new(first.0 as u8, first.1 as u8);
+                let second = Range::new(second.0 as u8, second.1 as u8);
+                self.add_transition(last_state, mid_state, first);
+                self.add_transition(mid_state, next_state, second);
+            }
+            last_state = next_state;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rectangles, utf16_sequences};
+
+    fn expand(rects: &[((u32, u32), (u32, u32))], bits: u32) -> Vec<u32> {
+        let mut ret = Vec::new();
+        for &((r0, r1), (c0, c1)) in rects {
+            for row in r0..(r1 + 1) {
+                for col in c0..(c1 + 1) {
+                    ret.push((row << bits) + col);
+                }
+            }
+        }
+        ret.sort();
+        ret
+    }
+
+    #[test]
+    fn test_rectangles() {
+        for &(start, end) in &[(0, 0), (3, 200), (0, 255), (0, 256), (5, 1000), (256, 767),
+                               (255, 256), (0, 0xFFFF), (0x1234, 0xABCD)] {
+            let rects = rectangles(start, end, 8);
+            assert!(rects.len() <= 3);
+            assert_eq!(expand(&rects, 8), (start..(end + 1)).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_utf16_sequences() {
+        assert_eq!(utf16_sequences(0x61, 0x7A), vec![vec![(0x61, 0x7A)]]);
+        assert_eq!(utf16_sequences(0xD000, 0xE100),
+                   vec![vec![(0xD000, 0xD7FF)], vec![(0xE000, 0xE100)]]);
+        assert_eq!(utf16_sequences(0xD800, 0xDFFF), Vec::<Vec<(u32, u32)>>::new());
+
+        // U+1F600 is encoded as D83D DE00.
+        assert_eq!(utf16_sequences(0x1F600, 0x1F600),
+                   vec![vec![(0xD83D, 0xD83D), (0xDE00, 0xDE00)]]);
+        assert_eq!(utf16_sequences(0x10000, 0x10FFFF),
+                   vec![vec![(0xD800, 0xDBFF), (0xDC00, 0xDFFF)]]);
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use dfa::Dfa;
+use error::Error;
+use expr;
+use look::Look;
+use nfa::Nfa;
+use regex_syntax::Expr;
+use runner::program::TableInsts;
+use std;
+use std::cmp::max;
+
+/// Decides which of several regular expressions matches the whole of a string.
+///
+/// This is what a URL router or a list of file globs needs: the patterns often share long
+/// prefixes (`/users/[0-9]+`, `/users/[0-9]+/posts`, `/users/me`, ...), and the answer is the
+/// first pattern that matches. All of the patterns are compiled into one automaton, which reads
+/// each shared prefix once and then branches into the parts that differ, so finding the pattern
+/// takes a single pass over the input no matter how many patterns there are. The automaton is
+/// usually much smaller than the separate automata of the patterns put together.
+///
+/// ```rust
+/// use regex_dfa::PatternSet;
+///
+/// let routes = PatternSet::new(&["/users/me", "/users/[0-9]+", "/users/[0-9]+/posts"]).unwrap();
+/// assert_eq!(routes.matching_pattern("/users/me"), Some(0));
+/// assert_eq!(routes.matching_pattern("/users/12/posts"), Some(2));
+/// assert_eq!(routes.matching_pattern("/users/12/"), None);
+/// ```
+#[derive(Clone, Debug)]
+pub struct PatternSet {
+    // Accepts at the end of the input, returning the index of the first pattern that matches.
+    prog: TableInsts<usize>,
+    init: Option<usize>,
+}
+
+impl PatternSet {
+    /// Compiles a list of regular expressions. The patterns are identified by their index in
+    /// `res`.
+    pub fn new<S: AsRef<str>>(res: &[S]) -> ::Result<PatternSet> {
+        PatternSet::new_bounded(res, std::usize::MAX)
+    }
+
+    /// Like `new`, but fails if the automaton would require too many states.
+    pub fn new_bounded<S: AsRef<str>>(res: &[S], max_states: usize) -> ::Result<PatternSet> {
+        let mut set: Option<Dfa<usize>> = None;
+        for (idx, re) in res.iter().enumerate() {
+            let dfa = try!(PatternSet::make_dfa(re.as_ref(), max_states)).map_ret(|_| idx);
+
+            // The states of the product keep track of every pattern at once, and minimizing it
+            // merges the states that can only lead to the same pattern.
+            set = Some(match set {
+                None => dfa,
+                Some(set) => set.product(&dfa, |a, b| max(*a, *b))
+                    .map_ret(|(a, b)| a.or(b).expect("BUG: accepting state with no pattern"))
+                    .optimize(),
+            });
+            if set.as_ref().map_or(0, |s| s.num_states()) > max_states {
+                return Err(Error::TooManyStates);
+            }
+        }
+
+        let set = set.unwrap_or_else(Dfa::new);
+        Ok(PatternSet {
+            prog: set.compile(),
+            init: set.init_at_start(),
+        })
+    }
+
+    // Makes a `Dfa` that matches the whole input against `re`.
+    fn make_dfa(re: &str, max_states: usize) -> ::Result<Dfa<(Look, u8)>> {
+        // Wrapping `re` as `^(?:re)$` would fail for the empty pattern, since regex_syntax
+        // rejects empty groups.
+        let expr = Expr::Concat(vec![Expr::StartText, try!(Expr::parse(re)), Expr::EndText]);
+        let expr = expr::simplify(&expr);
+        let mut nfa = try!(Nfa::from_expr(&expr).remove_looks().byte_me(max_states));
+        nfa.reduce();
+        Ok(try!(nfa.determinize(max_states)).optimize())
+    }
+
+    /// Returns the number of states in the compiled automaton.
+    pub fn num_states(&self) -> usize {
+        self.prog.num_states()
+    }
+
+    /// Returns the index of the first pattern that matches all of `s`, if there is one.
+    pub fn matching_pattern(&self, s: &str) -> Option<usize> {
+        let state = try_opt!(self.init);
+        match self.prog.find_from(s.as_bytes(), 0, state) {
+            Ok((end, idx)) if end == s.len() => Some(idx),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+    use super::PatternSet;
+
+    #[test]
+    fn routes() {
+        let routes = ["/", "/users", "/users/[0-9]+", "/users/[0-9]+/posts", "/users/me",
+                      "/static/.*", "/users/[a-z]+"];
+        let set = PatternSet::new(&routes).unwrap();
+        let inputs = ["/", "", "/users", "/users/", "/users/12", "/users/12/posts", "/users/me",
+                      "/users/bob", "/users/bob/posts", "/static/", "/static/a/b.css", "/nope",
+                      "/users/12/posts/"];
+        for input in &inputs {
+            let expected = routes.iter().position(|re| {
+                Regex::new(&format!("^(?:{})$", re)).unwrap().is_match(input)
+            });
+            assert_eq!(set.matching_pattern(input), expected, "{}", input);
+        }
+
+        // Earlier patterns win.
+        assert_eq!(set.matching_pattern("/users/me"), Some(4));
+        let set = PatternSet::new(&["/users/[a-z]+", "/users/me"]).unwrap();
+        assert_eq!(set.matching_pattern("/users/me"), Some(0));
+    }
+
+    #[test]
+    fn shared_prefixes() {
+        let routes: Vec<String> = (0..20).map(|i| format!("/api/v1/items/{}/[a-z]+", i)).collect();
+        let set = PatternSet::new(&routes).unwrap();
+        let separate: usize = routes.iter()
+            .map(|re| PatternSet::new(&[re]).unwrap().num_states())
+            .sum();
+        assert!(set.num_states() * 3 < separate);
+        assert_eq!(set.matching_pattern("/api/v1/items/13/abc"), Some(13));
+        assert_eq!(set.matching_pattern("/api/v1/items/1/abc"), Some(1));
+        assert_eq!(set.matching_pattern("/api/v1/items/20/abc"), None);
+
+        assert!(PatternSet::new_bounded(&routes, 10).is_err());
+    }
+
+    #[test]
+    fn empty() {
+        let set = PatternSet::new::<&str>(&[]).unwrap();
+        assert_eq!(set.matching_pattern(""), None);
+        let set = PatternSet::new(&["", "a*"]).unwrap();
+        assert_eq!(set.matching_pattern(""), Some(0));
+        assert_eq!(set.matching_pattern("aa"), Some(1));
+    }
+}
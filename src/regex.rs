@@ -6,36 +6,859 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use analyze::{self, PatternReport};
+use byte_search::{memchr, memrchr};
+use charset::CharSet;
+use dfa::{Dfa, PrefixPart};
+use encode::{Decoder, Encode};
 use error::Error;
-use nfa::{Nfa, NoLooks};
+use expr::{self, AdaptivePolicy, Degradation};
+use glob::{self, GlobOptions};
+use haystack::Haystack;
+use like::{self, LikeOptions};
+use look::{Look, WordChars};
+use nfa::{DeterminizeOptions, DeterminizeStats, InvalidUtf8, Nfa, NoLooks, Utf8Cache};
+use regex_syntax::Expr;
 use runner::anchored::AnchoredEngine;
-use runner::forward_backward::{ForwardBackwardEngine, Prefix};
-use runner::Engine;
+use runner::end_anchored::EndAnchoredEngine;
+use runner::forward_backward::{ForwardBackwardEngine, PrefilterOptions, Prefix};
+use runner::line_start::LineStartEngine;
+use runner::look_behind::LookBehindEngine;
+use runner::{self, Engine};
+use runner::program::{Disassembly, InstInfo, TableInsts, TraceEvent};
+use searcher::Searcher;
 use std;
-use std::fmt::Debug;
+use std::fmt::{self, Debug, Formatter};
+#[cfg(feature = "profile")]
+use std::fmt::Display;
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+#[cfg(feature = "memmap")]
+use memmap::{Mmap, Protection};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "serde")]
+use serde::bytes::ByteBuf;
+#[cfg(feature = "memmap")]
+use std::io;
+#[cfg(feature = "memmap")]
+use std::path::Path;
+
+/// An explanation of why a `Regex` failed to match some input. See `Regex::explain`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NoMatch {
+    /// The furthest position in the input that the matcher got to.
+    pub pos: usize,
+    /// The program (named as in `Disassembly`) that got to position `pos`, or `None` if no program
+    /// read any input (for example, because the prefilter ruled out every starting position).
+    pub program: Option<&'static str>,
+    /// The state that the matcher was in at position `pos`. This is only meaningful if `program`
+    /// is not `None`.
+    pub state: usize,
+    /// The bytes that would have allowed the matcher to make progress at position `pos`, as a
+    /// list of inclusive ranges. This is empty if `program` is `None`.
+    pub expected: Vec<(u8, u8)>,
+}
+
+/// The parts of a `Regex`'s programs that were exercised by some inputs. See `Regex::coverage`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Coverage {
+    programs: Vec<(&'static str, Vec<InstInfo>)>,
+    // For each program, and each of its states, whether the state was visited and whether each
+    // of its transitions was taken.
+    visited: Vec<Vec<(bool, Vec<bool>)>>,
+}
+
+impl Coverage {
+    /// Returns the states that were never visited, as pairs of a program name (as in
+    /// `Disassembly`) and a state.
+    pub fn unvisited_states(&self) -> Vec<(&'static str, usize)> {
+        let mut ret = Vec::new();
+        for (&(name, _), states) in self.programs.iter().zip(&self.visited) {
+            for (st, &(visited, _)) in states.iter().enumerate() {
+                if !visited {
+                    ret.push((name, st));
+                }
+            }
+        }
+        ret
+    }
+
+    /// Returns the transitions that were never taken, as a program name, a source state, and a
+    /// transition (in the same format as `InstInfo::transitions`).
+    pub fn unvisited_transitions(&self) -> Vec<(&'static str, usize, (u8, u8, usize))> {
+        let mut ret = Vec::new();
+        for (&(name, ref insts), states) in self.programs.iter().zip(&self.visited) {
+            for (inst, &(_, ref taken)) in insts.iter().zip(states) {
+                for (&trans, &t) in inst.transitions.iter().zip(taken) {
+                    if !t {
+                        ret.push((name, inst.state, trans));
+                    }
+                }
+            }
+        }
+        ret
+    }
+
+    /// Returns true if every state was visited and every transition was taken.
+    pub fn is_complete(&self) -> bool {
+        self.visited.iter().all(|states| states.iter().all(|&(v, ref taken)| {
+            v && taken.iter().all(|&t| t)
+        }))
+    }
+
+    fn record(&mut self, ev: &TraceEvent) {
+        let (program, state, byte) = match *ev {
+            TraceEvent::Step { program, state, byte, .. } => (program, state, Some(byte)),
+            TraceEvent::Accept { program, state, .. } => (program, state, None),
+            TraceEvent::Search { .. } => { return; },
+        };
+        let prog_idx = match self.programs.iter().position(|p| p.0 == program) {
+            Some(i) => i,
+            None => { return; },
+        };
+
+        let inst = &self.programs[prog_idx].1[state];
+        let visited = &mut self.visited[prog_idx];
+        visited[state].0 = true;
+        if let Some(b) = byte {
+            if let Some(i) = inst.transitions.iter().position(|t| t.0 <= b && b <= t.1) {
+                visited[state].1[i] = true;
+                visited[inst.transitions[i].2].0 = true;
+            }
+        }
+    }
+}
+
+/// What a match needed to see after its end in order to know that it matched. See
+/// `Regex::find_with_end`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MatchEnd {
+    /// The match didn't depend on what came after it.
+    Anywhere,
+    /// The match needed to be at the end of the input (for example, because the regex ends with
+    /// `$`).
+    Eoi,
+    /// The match needed to look at the char after it (for example, because the regex ends with
+    /// `\b`), and this was the char.
+    Char(char),
+}
+
+/// A match, together with some of the text surrounding it. See `Regex::find_with_context`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchContext<'t> {
+    /// The byte index where the match starts.
+    pub start: usize,
+    /// The byte index where the match ends.
+    pub end: usize,
+    /// The text immediately before the match.
+    pub before: &'t str,
+    /// The text of the match.
+    pub matched: &'t str,
+    /// The text immediately after the match.
+    pub after: &'t str,
+}
+
+/// A set of byte strings, one of which must appear at the beginning of every match. See
+/// `Regex::required_prefixes`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Literals {
+    /// The byte strings, in sorted order. If this is empty, we don't know anything about the
+    /// beginnings of matches.
+    pub literals: Vec<Vec<u8>>,
+}
+
+impl Literals {
+    fn from_parts(parts: Vec<PrefixPart>) -> Literals {
+        // If any of the prefixes is empty then a match could start with anything.
+        if parts.iter().any(|p| p.0.is_empty()) {
+            return Literals::empty();
+        }
+
+        let mut lits: Vec<Vec<u8>> = parts.into_iter().map(|p| p.0).collect();
+        lits.sort();
+        lits.dedup();
+        Literals { literals: lits }
+    }
+
+    fn empty() -> Literals {
+        Literals { literals: Vec::new() }
+    }
+
+    /// Returns true if there are no literals (and so every input is a candidate for matching).
+    pub fn is_empty(&self) -> bool {
+        self.literals.is_empty()
+    }
+
+    /// Returns true if `input` contains at least one of the literals. If this returns false then
+    /// the `Regex` that produced these literals is guaranteed not to match `input`.
+    pub fn is_candidate(&self, input: &[u8]) -> bool {
+        self.is_empty() || self.literals.iter().any(|lit| {
+            lit.len() <= input.len() && input.windows(lit.len()).any(|w| w == &lit[..])
+        })
+    }
+}
+
+/// A set of bytes. See `Regex::start_bytes`.
+#[derive(Clone, PartialEq)]
+pub struct ByteSet {
+    set: Vec<bool>,
+}
+
+impl ByteSet {
+    fn from_bytes(bytes: &[u8]) -> ByteSet {
+        let mut set = vec![false; 256];
+        for &b in bytes {
+            set[b as usize] = true;
+        }
+        ByteSet { set: set }
+    }
+
+    fn full() -> ByteSet {
+        ByteSet { set: vec![true; 256] }
+    }
+
+    /// Returns true if `b` belongs to this set.
+    pub fn contains(&self, b: u8) -> bool {
+        self.set[b as usize]
+    }
+
+    /// Returns the number of bytes in this set.
+    pub fn len(&self) -> usize {
+        self.set.iter().filter(|&&x| x).count()
+    }
+
+    /// Returns true if this set has no bytes in it.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns true if this set contains every byte.
+    pub fn is_full(&self) -> bool {
+        self.len() == 256
+    }
+
+    /// Returns the bytes in this set, in increasing order.
+    pub fn bytes(&self) -> Vec<u8> {
+        (0..256usize).filter(|&b| self.set[b]).map(|b| b as u8).collect()
+    }
+}
+
+impl Debug for ByteSet {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_set().entries(self.bytes()).finish()
+    }
+}
+
+/// Which empty matches an iterator over matches reports. See `Matches::empty_matches`.
+///
+/// Whatever the choice, the search always continues one char after an empty match, so that the
+/// iterator makes progress.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EmptyMatches {
+    /// Report every empty match, like Perl and Python do. For example, `a*` finds `""`, `"aa"`,
+    /// `""`, and `""` in "baab".
+    All,
+    /// Report empty matches, except for the ones that begin where the previous match ended, like
+    /// the `regex` crate does. For example, `a*` finds `""`, `"aa"`, and `""` in "baab".
+    NotAfterMatch,
+    /// Don't report any empty matches. For example, `a*` finds only `"aa"` in "baab".
+    Never,
+}
+
+impl Default for EmptyMatches {
+    fn default() -> EmptyMatches {
+        EmptyMatches::NotAfterMatch
+    }
+}
+
+// Where an iteration over matches has got to. This is kept apart from the regex and the text, so
+// that `Matches` can borrow them and `IntoMatches` can own them.
+#[derive(Clone, Debug)]
+struct MatchesState {
+    // The byte position to start the next search from.
+    pos: usize,
+    // The end of the previous match, if there was one.
+    last_end: Option<usize>,
+    empty: EmptyMatches,
+}
+
+impl MatchesState {
+    fn new() -> MatchesState {
+        MatchesState {
+            pos: 0,
+            last_end: None,
+            empty: EmptyMatches::default(),
+        }
+    }
+
+    fn next(&mut self, re: &Regex, text: &str) -> Option<(usize, usize)> {
+        self.next_bytes(re, text.as_bytes()).map(|(start, end)| check_boundaries(text, start, end))
+    }
+
+    // Like `next`, but the input doesn't need to be valid UTF-8.
+    fn next_bytes(&mut self, re: &Regex, input: &[u8]) -> Option<(usize, usize)> {
+        while self.pos <= input.len() {
+            let (start, end) = match re.engine.find_look_ahead_at(input, self.pos) {
+                Some((start, end, look_behind, _)) => (start + look_behind as usize, end),
+                None => { break; },
+            };
+
+            if start < end {
+                self.pos = end;
+            } else {
+                // Make sure that we make progress after an empty match, by skipping to the start
+                // of the next char.
+                let rest = input.get((end + 1)..).unwrap_or(&[]);
+                self.pos = end + 1 + rest.iter().take_while(|&&b| b & 0xC0 == 0x80).count();
+                let skip = match self.empty {
+                    EmptyMatches::All => false,
+                    EmptyMatches::NotAfterMatch => self.last_end == Some(end),
+                    EmptyMatches::Never => true,
+                };
+                if skip {
+                    continue;
+                }
+            }
+            self.last_end = Some(end);
+            return Some((start, end));
+        }
+        self.pos = input.len() + 1;
+        None
+    }
+}
+
+/// An iterator over the non-overlapping matches in a string. See `Regex::find_iter`.
+#[derive(Debug)]
+pub struct Matches<'r, 't> {
+    re: &'r Regex,
+    text: &'t str,
+    state: MatchesState,
+}
+
+impl<'r, 't> Matches<'r, 't> {
+    /// Chooses which empty matches to report (by default, `EmptyMatches::NotAfterMatch`).
+    pub fn empty_matches(mut self, empty: EmptyMatches) -> Matches<'r, 't> {
+        self.state.empty = empty;
+        self
+    }
+}
+
+impl<'r, 't> Iterator for Matches<'r, 't> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        self.state.next(self.re, self.text)
+    }
+}
+
+/// An iterator over the non-overlapping matches in a string, which owns the `Regex` and the
+/// string. See `Regex::into_find_iter`.
+///
+/// Since it doesn't borrow anything, it can be stored in a struct or returned from a function.
+#[derive(Clone, Debug)]
+pub struct IntoMatches {
+    re: Regex,
+    text: String,
+    state: MatchesState,
+}
+
+impl IntoMatches {
+    /// Chooses which empty matches to report (by default, `EmptyMatches::NotAfterMatch`).
+    pub fn empty_matches(mut self, empty: EmptyMatches) -> IntoMatches {
+        self.state.empty = empty;
+        self
+    }
+
+    /// The string that is being searched (for looking up the text of the matches).
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Gives back the string that was being searched.
+    pub fn into_text(self) -> String {
+        self.text
+    }
+}
+
+impl Iterator for IntoMatches {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        self.state.next(&self.re, &self.text)
+    }
+}
+
+/// An iterator over the non-overlapping matches in a string, as char indices. See
+/// `Regex::find_iter_chars`.
+#[derive(Debug)]
+pub struct CharMatches<'r, 't> {
+    matches: Matches<'r, 't>,
+    // A byte position that we have already converted, and the corresponding char index.
+    byte_pos: usize,
+    char_pos: usize,
+}
+
+// In debug builds, checks that a match found in `s` starts and ends on `char` boundaries.
+//
+// The programs work on bytes, and the prefilters and the loops that drive them move around in
+// byte offsets, so a bug in any of them could produce an offset in the middle of a char. That
+// would make slicing `s` panic far away from the bug, so we check every match that we hand out.
+fn check_boundaries(s: &str, start: usize, end: usize) -> (usize, usize) {
+    debug_assert!(start <= end && end <= s.len(), "BUG: bad match {:?} in {:?}", (start, end), s);
+    debug_assert!(s.is_char_boundary(start) && s.is_char_boundary(end),
+                  "BUG: match {:?} isn't on char boundaries of {:?}", (start, end), s);
+    (start, end)
+}
+
+// Returns the number of chars in some valid UTF-8, by counting the bytes that start a char.
+fn count_chars(input: &[u8]) -> usize {
+    input.iter().filter(|&&b| b & 0xC0 != 0x80).count()
+}
+
+impl<'r, 't> CharMatches<'r, 't> {
+    // Converts a byte position (which must not be before `self.byte_pos`) into a char index.
+    fn char_index(&mut self, pos: usize) -> usize {
+        self.char_pos += count_chars(&self.matches.text.as_bytes()[self.byte_pos..pos]);
+        self.byte_pos = pos;
+        self.char_pos
+    }
+}
+
+impl<'r, 't> Iterator for CharMatches<'r, 't> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        self.matches.next().map(|(start, end)| {
+            let start = self.char_index(start);
+            let end = self.char_index(end);
+            (start, end)
+        })
+    }
+}
+
+/// An iterator over the non-overlapping matches in a memory-mapped file. See
+/// `Regex::find_in_file`.
+#[cfg(feature = "memmap")]
+#[derive(Debug)]
+pub struct FileMatches<'r> {
+    re: &'r Regex,
+    map: Mmap,
+    state: MatchesState,
+}
+
+#[cfg(feature = "memmap")]
+impl<'r> FileMatches<'r> {
+    /// Chooses which empty matches to report (by default, `EmptyMatches::Never`).
+    pub fn empty_matches(mut self, empty: EmptyMatches) -> FileMatches<'r> {
+        self.state.empty = empty;
+        self
+    }
+}
+
+#[cfg(feature = "memmap")]
+impl<'r> Iterator for FileMatches<'r> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        // This is only unsafe if the file is modified while we're looking at it.
+        let input = unsafe { self.map.as_slice() };
+        self.state.next_bytes(self.re, input)
+    }
+}
+
+/// An iterator over the lines of a string that contain a match. See `Regex::grep_lines`.
+#[derive(Debug)]
+pub struct GrepLines<'r, 't> {
+    re: &'r Regex,
+    text: &'t str,
+    // The byte position of the start of the next line to search.
+    pos: usize,
+    // The (one-based) line number of the line starting at `pos`.
+    line_num: usize,
+}
+
+impl<'r, 't> Iterator for GrepLines<'r, 't> {
+    type Item = (usize, &'t str);
+
+    fn next(&mut self) -> Option<(usize, &'t str)> {
+        // As with `str::lines`, empty text has no lines, and a newline at the end of the text
+        // doesn't start another one.
+        if self.pos >= self.text.len() {
+            return None;
+        }
+
+        let bytes = self.text.as_bytes();
+        // Searching the rest of the text with `find_in` (instead of slicing it off) means that
+        // `^` and `\b` still see the text before `pos`.
+        let start = match self.re.find_in(self.text, self.pos..self.text.len()) {
+            // An empty match after the final newline isn't on any line.
+            Some((start, _)) if start < self.text.len() || !self.text.ends_with('\n') => start,
+            _ => {
+                self.pos = self.text.len();
+                return None;
+            },
+        };
+
+        // Rather than splitting the text into lines and searching each one, we search the whole
+        // remaining text and then find the line that the match belongs to.
+        let skipped = &bytes[self.pos..start];
+        let line_num = self.line_num + skipped.iter().filter(|&&b| b == b'\n').count();
+        let line_start = memrchr(b'\n', skipped).map_or(self.pos, |i| self.pos + i + 1);
+        let line_end = memchr(b'\n', &bytes[start..]).map_or(self.text.len(), |i| start + i);
+
+        self.pos = line_end + 1;
+        self.line_num = line_num + 1;
+        Some((line_num, &self.text[line_start..line_end]))
+    }
+}
+
+/// An iterator over the first match in each record of some text. See `Regex::find_records`.
 #[derive(Debug)]
+pub struct RecordMatches<'r, 't> {
+    re: &'r Regex,
+    text: &'t [u8],
+    separator: u8,
+    // The byte position of the start of the next record to search.
+    pos: usize,
+    // The (zero-based) index of the record starting at `pos`.
+    record_idx: usize,
+}
+
+impl<'r, 't> Iterator for RecordMatches<'r, 't> {
+    type Item = (usize, usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize, usize)> {
+        while self.pos <= self.text.len() {
+            let rest = &self.text[self.pos..];
+            let record_start = self.pos;
+            let record_idx = self.record_idx;
+            let record = &rest[..memchr(self.separator, rest).unwrap_or(rest.len())];
+            self.pos += record.len() + 1;
+            self.record_idx += 1;
+
+            if let Some((start, end)) = self.re.find_bytes(record) {
+                return Some((record_idx, record_start + start, record_start + end));
+            }
+        }
+        None
+    }
+}
+
+/// Scratch space for matching, which can be allocated once and then reused by the `*_with_cache`
+/// methods of `Regex`.
+///
+/// Running a `Regex` doesn't need any scratch space of its own, because the whole DFA is built
+/// ahead of time. So for now, the cache only holds the output buffer of
+/// `Regex::match_spans_with_cache`.
+#[derive(Clone, Debug, Default)]
+pub struct MatchCache {
+    spans: Vec<Range<usize>>,
+}
+
+impl MatchCache {
+    /// Creates a new, empty `MatchCache`.
+    pub fn new() -> MatchCache {
+        MatchCache::default()
+    }
+}
+
+/// Options for compiling a `Regex`. See `Regex::new_with_options`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegexOptions {
+    /// The maximum number of states that the compiled `Regex` may have (see
+    /// `Regex::new_bounded`).
+    pub max_states: usize,
+    /// Whether to simplify the regular expression before compiling it. Simplification never
+    /// changes what the `Regex` matches; it only makes compilation cheaper. It is on by default,
+    /// and the only reason to turn it off is for debugging.
+    pub simplify: bool,
+    /// How to choose the prefilter that skips over input that can't begin a match.
+    pub prefilter: PrefilterOptions,
+    /// How `find_bytes` treats input that isn't valid UTF-8. By default, invalid UTF-8 never
+    /// matches anything.
+    pub invalid_utf8: InvalidUtf8,
+    /// How long compiling may take before giving up with `Error::TimedOut`. Only determinization
+    /// and minimization check the time (they are the stages that can be slow, even when the
+    /// number of states stays below `max_states`). By default, there is no limit.
+    pub time_limit: Option<Duration>,
+    /// How to turn the NFAs into DFAs (for example, the order in which to explore the DFA
+    /// states). The `max_states` and `deadline` fields are ignored: they come from `max_states`
+    /// and `time_limit` above.
+    pub determinize: DeterminizeOptions,
+    /// The chars that `\b` and `\B` treat as word chars. By default (`None`), they are the chars
+    /// that match `\w`. Choosing a different set gives word boundaries at other kinds of edges:
+    /// for example, with every char except `,` as a word char, `\b` matches at the edges of the
+    /// fields in a line of CSV. `\n` is never a word char, even if it is in the set.
+    pub word_chars: Option<CharSet>,
+}
+
+impl Default for RegexOptions {
+    fn default() -> RegexOptions {
+        RegexOptions {
+            max_states: std::usize::MAX,
+            simplify: true,
+            prefilter: PrefilterOptions::default(),
+            invalid_utf8: InvalidUtf8::NeverMatch,
+            time_limit: None,
+            determinize: DeterminizeOptions::default(),
+            word_chars: None,
+        }
+    }
+}
+
+/// One of the stages that a regular expression goes through on its way to becoming a `Regex`.
+/// See `Compiler`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stage {
+    /// Parsing the regular expression.
+    Parse,
+    /// Simplifying the parsed expression (only if `RegexOptions::simplify` is set).
+    Simplify,
+    /// Building an NFA from the expression.
+    Nfa,
+    /// Removing word boundaries and line anchors from the NFA.
+    RemovePredicates,
+    /// Turning the NFA over chars into an NFA over bytes.
+    Bytes,
+    /// Turning an NFA into a DFA. This stage may run more than once for a single regex.
+    Determinize,
+    /// Minimizing a DFA. This stage may run more than once for a single regex.
+    Minimize,
+    /// Compiling a DFA into the program that gets run on the input. This stage may run more than
+    /// once for a single regex.
+    Program,
+}
+
+// Keeps track of the stages of compiling a single regex.
+struct Stages<'a> {
+    hook: Option<Box<FnMut(Stage, Duration) + 'a>>,
+    stop_after: Option<Stage>,
+    // If this passes while determinizing or minimizing, we give up.
+    deadline: Option<Instant>,
+    // The options for every determinization (apart from `max_states` and `deadline`).
+    determinize: DeterminizeOptions,
+    // The statistics of every determinization so far, added together.
+    determinize_stats: DeterminizeStats,
+    // The word chars for `\b` and `\B`.
+    word_chars: WordChars,
+}
+
+impl<'a> Stages<'a> {
+    fn none() -> Stages<'static> {
+        Stages {
+            hook: None,
+            stop_after: None,
+            deadline: None,
+            determinize: DeterminizeOptions::default(),
+            determinize_stats: DeterminizeStats::default(),
+            word_chars: WordChars::unicode(),
+        }
+    }
+
+    // Runs one stage, reporting how long it took. If we were asked to stop after this stage,
+    // returns `Error::Stopped`.
+    fn run<T, F: FnOnce() -> ::Result<T>>(&mut self, stage: Stage, f: F) -> ::Result<T> {
+        let start = Instant::now();
+        let ret = try!(f());
+        if let Some(ref mut hook) = self.hook {
+            hook(stage, start.elapsed());
+        }
+        if self.stop_after == Some(stage) {
+            Err(Error::Stopped(stage))
+        } else {
+            Ok(ret)
+        }
+    }
+}
+
+/// Compiles regular expressions, while reporting on the progress of the compilation.
+///
+/// `Regex::new_with_options` is all that most people need, but a `Compiler` can also tell you how
+/// much time went into each `Stage` of the compilation, or stop the compilation after any stage.
+/// The resulting `Regex` is the same as the one from `Regex::new_with_options`.
+///
+/// ```rust
+/// use regex_dfa::{Compiler, Error, RegexOptions, Stage};
+///
+/// let mut stages = Vec::new();
+/// {
+///     let mut compiler = Compiler::new(RegexOptions::default())
+///         .on_stage(|stage, _time| stages.push(stage));
+///     assert!(compiler.compile("a+b").is_ok());
+/// }
+/// assert_eq!(stages[0], Stage::Parse);
+///
+/// let mut compiler = Compiler::new(RegexOptions::default()).stop_after(Stage::Nfa);
+/// match compiler.compile("a+b") {
+///     Err(Error::Stopped(Stage::Nfa)) => {},
+///     _ => panic!("should have stopped"),
+/// }
+/// ```
+pub struct Compiler<'a> {
+    options: RegexOptions,
+    stages: Stages<'a>,
+}
+
+impl<'a> Compiler<'a> {
+    /// Creates a new `Compiler` with the given options.
+    pub fn new(options: RegexOptions) -> Compiler<'a> {
+        Compiler {
+            options: options,
+            stages: Stages {
+                hook: None,
+                stop_after: None,
+                deadline: None,
+                determinize: DeterminizeOptions::default(),
+                determinize_stats: DeterminizeStats::default(),
+                word_chars: WordChars::unicode(),
+            },
+        }
+    }
+
+    /// Calls `hook` after every stage, with the stage and the time that it took.
+    pub fn on_stage<F: FnMut(Stage, Duration) + 'a>(mut self, hook: F) -> Compiler<'a> {
+        self.stages.hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Stops compiling after the first time that `stage` finishes, returning
+    /// `Error::Stopped(stage)`.
+    pub fn stop_after(mut self, stage: Stage) -> Compiler<'a> {
+        self.stages.stop_after = Some(stage);
+        self
+    }
+
+    /// Compiles a regular expression.
+    pub fn compile(&mut self, re: &str) -> ::Result<Regex> {
+        Regex::compile(re, &self.options, &mut Utf8Cache::new(), &mut self.stages)
+    }
+
+    /// Returns some statistics about turning NFAs into DFAs, added up over all of the regular
+    /// expressions that this `Compiler` has compiled so far.
+    pub fn determinize_stats(&self) -> DeterminizeStats {
+        self.stages.determinize_stats
+    }
+}
+
+/// Compiles a batch of regular expressions with the same options, sharing work between them.
+///
+/// Sets of rules often use the same character classes (like `\w` or `\p{L}`) over and over, and
+/// translating big classes into UTF-8 is one of the slower parts of compiling a regular
+/// expression. A `MultiCompiler` only does this once for each class. It also only compiles each
+/// distinct regular expression once. The resulting `Regex`es are the same as the ones from
+/// `Regex::new_with_options`.
+///
+/// ```rust
+/// use regex_dfa::{MultiCompiler, RegexOptions};
+///
+/// let mut compiler = MultiCompiler::new(RegexOptions::default());
+/// let res = compiler.compile_all(&[r"\w+@\w+", r"user=\w+", r"\w+@\w+", "("]);
+/// assert_eq!(res[1].as_ref().unwrap().find("user=bob"), Some((0, 8)));
+/// assert!(res[3].is_err());
+/// ```
+pub struct MultiCompiler {
+    options: RegexOptions,
+    utf8: Utf8Cache,
+    compiled: HashMap<String, Regex>,
+}
+
+impl MultiCompiler {
+    /// Creates a new `MultiCompiler` with the given options.
+    pub fn new(options: RegexOptions) -> MultiCompiler {
+        MultiCompiler {
+            options: options,
+            utf8: Utf8Cache::new(),
+            compiled: HashMap::new(),
+        }
+    }
+
+    /// Compiles a regular expression, reusing whatever we can from the previous ones.
+    pub fn compile(&mut self, re: &str) -> ::Result<Regex> {
+        if let Some(regex) = self.compiled.get(re) {
+            return Ok(regex.clone());
+        }
+        let regex = try!(Regex::compile(re, &self.options, &mut self.utf8, &mut Stages::none()));
+        self.compiled.insert(re.to_owned(), regex.clone());
+        Ok(regex)
+    }
+
+    /// Compiles all of the regular expressions in `res`, returning the results in the same order.
+    pub fn compile_all<S: AsRef<str>>(&mut self, res: &[S]) -> Vec<::Result<Regex>> {
+        res.iter().map(|re| self.compile(re.as_ref())).collect()
+    }
+}
+
+// A `Regex` never changes after it is compiled, so clones can share everything.
+#[derive(Clone, Debug)]
 pub struct Regex {
-    engine: Box<Engine<u8>>,
+    engine: Arc<Engine<u8>>,
+    analysis: Arc<Analysis>,
+}
+
+// Some facts about a `Regex` that we learn while compiling it.
+#[derive(Clone, Debug)]
+struct Analysis {
+    prefixes: Literals,
+    start_bytes: ByteSet,
+}
+
+impl Analysis {
+    // The analysis for a regex that we know nothing about.
+    fn unknown() -> Analysis {
+        Analysis {
+            prefixes: Literals::empty(),
+            start_bytes: ByteSet::full(),
+        }
+    }
 }
 
 // An engine that doesn't match anything.
 #[derive(Clone, Debug)]
 struct EmptyEngine;
 
-impl<Ret: Debug> Engine<Ret> for EmptyEngine {
-    fn find(&self, _: &str) -> Option<(usize, usize, Ret)> { None }
+impl<Ret: Debug + Send + Sync> Engine<Ret> for EmptyEngine {
+    fn find_look_ahead_before(&self, _: &[u8], _: usize, _: usize)
+    -> Option<(usize, usize, Ret, (Look, u8))> {
+        None
+    }
+    fn is_match(&self, _: &[u8]) -> bool { false }
+    fn for_detection(&self) -> Box<Engine<Ret>> { Box::new(EmptyEngine) }
     fn clone_box(&self) -> Box<Engine<Ret>> { Box::new(EmptyEngine) }
+    fn disassemble(&self) -> Disassembly {
+        Disassembly { programs: Vec::new(), prefilter: None }
+    }
+    fn trace(&self, _: &str) -> Vec<TraceEvent> { Vec::new() }
+    fn encode(&self, out: &mut Vec<u8>) { out.push(runner::EMPTY_TAG); }
 }
 
-impl Clone for Regex {
-    fn clone(&self) -> Regex {
-        Regex {
-            engine: self.engine.clone_box(),
-        }
+// Decodes an engine that was written by `Engine::encode`.
+fn decode_engine(input: &mut Decoder) -> Option<Arc<Engine<u8>>> {
+    match try_opt!(u8::decode(input)) {
+        runner::EMPTY_TAG => Some(Arc::new(EmptyEngine)),
+        runner::ANCHORED_TAG =>
+            AnchoredEngine::decode(input).map(|e| Arc::new(e) as Arc<Engine<u8>>),
+        runner::LINE_START_TAG =>
+            LineStartEngine::decode(input).map(|e| Arc::new(e) as Arc<Engine<u8>>),
+        runner::LOOK_BEHIND_TAG =>
+            LookBehindEngine::decode(input).map(|e| Arc::new(e) as Arc<Engine<u8>>),
+        runner::FORWARD_BACKWARD_TAG =>
+            ForwardBackwardEngine::<u8>::decode(input).map(|e| Arc::new(e) as Arc<Engine<u8>>),
+        runner::END_ANCHORED_TAG =>
+            EndAnchoredEngine::decode(input).map(|e| Arc::new(e) as Arc<Engine<u8>>),
+        _ => None,
     }
 }
 
+// The encoding of a `Regex` begins with this, followed by the version of this crate.
+const MAGIC: &'static [u8] = b"regex_dfa";
+const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
 impl Regex {
     /// Creates a new `Regex` from a regular expression string.
     pub fn new(re: &str) -> ::Result<Regex> {
@@ -45,81 +868,2724 @@ impl Regex {
     /// Creates a new `Regex` from a regular expression string, but only if it doesn't require too
     /// many states.
     pub fn new_bounded(re: &str, max_states: usize) -> ::Result<Regex> {
-        let nfa = try!(Nfa::from_regex(re));
-        let nfa = nfa.remove_looks();
+        Regex::new_with_options(re, &RegexOptions { max_states: max_states, ..Default::default() })
+    }
 
-        let eng = if nfa.is_empty() {
-            Box::new(EmptyEngine) as Box<Engine<u8>>
-        } else if nfa.is_anchored() {
-            Box::new(try!(Regex::make_anchored(nfa, max_states))) as Box<Engine<u8>>
+    /// Creates a new `Regex` from a regular expression string, using the given options.
+    pub fn new_with_options(re: &str, options: &RegexOptions) -> ::Result<Regex> {
+        Regex::compile(re, options, &mut Utf8Cache::new(), &mut Stages::none())
+    }
+
+    /// Reports on the features that a regular expression uses, and on whether (and to what size)
+    /// it compiles with the given options. Only syntax errors are returned as errors; compilation
+    /// errors are part of the report.
+    ///
+    /// ```rust
+    /// use regex_dfa::{Regex, RegexOptions};
+    ///
+    /// let report = Regex::analyze(r"\bfoo{2,3}", &RegexOptions::default()).unwrap();
+    /// assert!(report.word_boundaries);
+    /// assert_eq!(report.bounded_repeats, 1);
+    /// assert!(report.is_compilable());
+    /// ```
+    pub fn analyze(re: &str, options: &RegexOptions) -> ::Result<PatternReport> {
+        let mut report = analyze::features(&try!(Expr::parse(re)));
+        match Regex::new_with_options(re, options) {
+            Ok(regex) => { report.compiled_size = Some(regex.to_bytes().len()); },
+            Err(e) => { report.compile_error = Some(e); },
+        }
+        Ok(report)
+    }
+
+    fn compile(re: &str, options: &RegexOptions, utf8: &mut Utf8Cache, stages: &mut Stages)
+    -> ::Result<Regex> {
+        stages.deadline = options.time_limit.map(|limit| Instant::now() + limit);
+        stages.determinize = options.determinize;
+        stages.word_chars = match options.word_chars {
+            Some(ref set) => {
+                let ranges = set.ranges().iter()
+                    .map(|&(lo, hi)| (lo as u32, hi as u32))
+                    .collect::<Vec<_>>();
+                WordChars::from_ranges(&ranges)
+            },
+            None => WordChars::unicode(),
+        };
+        let expr = try!(stages.run(Stage::Parse, || Expr::parse(re).map_err(Error::from)));
+        let expr = if options.simplify {
+            try!(stages.run(Stage::Simplify, || Ok(expr::simplify(&expr))))
         } else {
-            Box::new(try!(Regex::make_forward_backward(nfa, max_states))) as Box<Engine<u8>>
+            expr
         };
+        let invalid = options.invalid_utf8;
+        Regex::from_expr(&expr, options.max_states, &options.prefilter, invalid, utf8, stages)
+    }
 
-        Ok(Regex { engine: eng })
+    /// Creates a new `Regex` that matches anything within edit distance `k` of something that
+    /// `re` matches. The edit distance counts the chars that need to be inserted, deleted, or
+    /// substituted.
+    ///
+    /// Word boundaries and multi-line anchors are not supported; regular expressions that use them
+    /// are rejected with `Error::InvalidEngine`.
+    ///
+    /// ```rust
+    /// use regex_dfa::Regex;
+    ///
+    /// let re = Regex::new_approximate("^kitten$", 2).unwrap();
+    /// assert!(re.is_match("sitten"));
+    /// assert!(re.is_match("sittin"));
+    /// assert!(!re.is_match("sitting"));
+    /// ```
+    pub fn new_approximate(re: &str, k: usize) -> ::Result<Regex> {
+        Regex::new_approximate_bounded(re, k, std::usize::MAX)
     }
 
-    fn make_anchored(nfa: Nfa<u32, NoLooks>, max_states: usize)
-    -> ::Result<AnchoredEngine<u8>> {
-        let nfa = try!(nfa.byte_me(max_states));
-        let dfa = try!(nfa.determinize(max_states))
-            .optimize()
-            .map_ret(|(_, bytes)| bytes);
-        let prog = dfa.compile();
+    /// Like `new_approximate`, but fails if the `Regex` would require too many states.
+    pub fn new_approximate_bounded(re: &str, k: usize, max_states: usize) -> ::Result<Regex> {
+        let expr = expr::simplify(&try!(Expr::parse(re)));
+        let nfa = Nfa::from_expr(&expr).remove_looks();
+        if nfa.has_look_behind() || nfa.has_look_ahead() {
+            return Err(Error::InvalidEngine(
+                "approximate matching doesn't support word boundaries or multi-line anchors"));
+        }
 
-        Ok(AnchoredEngine::new(prog))
+        let nfa = nfa.within_distance(k);
+        let mut nfa = try!(nfa.byte_me_with(max_states, InvalidUtf8::NeverMatch));
+        nfa.reduce();
+        let prefilter = PrefilterOptions::default();
+        Regex::from_byte_nfa(nfa, max_states, &prefilter, false, Prefix::Empty, &mut Stages::none())
     }
 
-    fn make_forward_backward(nfa: Nfa<u32, NoLooks>, max_states: usize)
-    -> ::Result<ForwardBackwardEngine<u8>> {
-        if nfa.is_anchored() {
-            return Err(Error::InvalidEngine("anchors rule out the forward-backward engine"));
-        }
+    /// Creates a new `Regex` that matches anything with the same length as something that `re`
+    /// matches, but with up to `k` of the chars replaced by other chars.
+    ///
+    /// Only regular expressions that always match exactly the same number of chars are supported:
+    /// they may contain literals, character classes, `.`, and repetitions with a fixed count, but
+    /// no alternations, anchors, or variable repetitions. Other regular expressions are rejected
+    /// with `Error::InvalidEngine`.
+    ///
+    /// ```rust
+    /// use regex_dfa::Regex;
+    ///
+    /// let re = Regex::new_with_mismatches("TATA[AT]A[AT]", 1).unwrap();
+    /// assert_eq!(re.find("GGTATAAAAGG"), Some((2, 9)));
+    /// assert_eq!(re.find("GGTCTAAAAGG"), Some((2, 9)));
+    /// assert_eq!(re.find("GGTCTAAACGG"), None);
+    /// ```
+    pub fn new_with_mismatches(re: &str, k: usize) -> ::Result<Regex> {
+        Regex::new_with_mismatches_bounded(re, k, std::usize::MAX)
+    }
 
-        let f_nfa = try!(try!(nfa.clone().byte_me(max_states)).anchor(max_states));
-        let b_nfa = try!(try!(nfa.byte_me(max_states)).reverse(max_states));
+    /// Like `new_with_mismatches`, but fails if the `Regex` would require too many states.
+    pub fn new_with_mismatches_bounded(re: &str, k: usize, max_states: usize)
+    -> ::Result<Regex> {
+        let expr = try!(Expr::parse(re));
+        let positions = match expr::fixed_positions(&expr) {
+            Some(p) => p,
+            None => {
+                return Err(Error::InvalidEngine(
+                    "mismatch matching only supports fixed-length sequences of chars and classes"));
+            },
+        };
 
-        let f_dfa = try!(f_nfa.determinize(max_states)).optimize();
-        let b_dfa = try!(b_nfa.determinize_longest(max_states)).optimize();
-        let b_dfa = b_dfa.map_ret(|(_, bytes)| bytes);
+        let nfa = Nfa::with_mismatches(&positions, k);
+        let mut nfa = try!(nfa.byte_me_with(max_states, InvalidUtf8::NeverMatch));
+        nfa.reduce();
+        let prefilter = PrefilterOptions::default();
+        Regex::from_byte_nfa(nfa, max_states, &prefilter, false, Prefix::Empty, &mut Stages::none())
+    }
 
-        let b_prog = b_dfa.compile();
-        let f_dfa = f_dfa.map_ret(|(look, bytes)| {
-            let b_dfa_state = b_dfa.init[look.as_usize()].expect("BUG: back dfa must have this init");
-            (b_dfa_state, bytes)
-        });
+    /// Creates a new `Regex` that matches the strings (usually paths) matching a glob pattern.
+    /// The glob must match the whole string. See `GlobOptions` for the syntax.
+    ///
+    /// ```rust
+    /// use regex_dfa::{GlobOptions, Regex};
+    ///
+    /// let re = Regex::from_glob("src/**/*.rs", &GlobOptions::default()).unwrap();
+    /// assert!(re.is_match("src/lib.rs"));
+    /// assert!(re.is_match("src/runner/mod.rs"));
+    /// assert!(!re.is_match("src/lib.rs.orig"));
+    /// assert!(!re.is_match("tests/src/lib.rs"));
+    /// ```
+    pub fn from_glob(glob: &str, options: &GlobOptions) -> ::Result<Regex> {
+        let expr = expr::simplify(&try!(glob::to_expr(glob, options)));
+        let prefilter = PrefilterOptions::default();
+        let invalid = InvalidUtf8::NeverMatch;
+        let utf8 = &mut Utf8Cache::new();
+        Regex::from_expr(&expr, options.max_states, &prefilter, invalid, utf8, &mut Stages::none())
+    }
 
-        let mut f_prog = f_dfa.compile();
-        let prefix = Prefix::from_parts(f_dfa.prefix_strings());
-        match prefix {
-            Prefix::Empty => {},
-            _ => {
-                // If there is a non-trivial prefix, we can usually speed up matching by deleting
-                // transitions that return to the start state. That way, instead of returning to
-                // the start state, we will just fail to match. Then we get to search for the
-                // prefix before trying to match again.
-                let f_dfa = f_dfa.cut_loop_to_init().optimize();
-                f_prog = f_dfa.compile();
-            },
+    /// Creates a new `Regex` that matches the same strings as an SQL `LIKE` (or, with
+    /// `case_insensitive`, `ILIKE`) pattern. See `LikeOptions` for the syntax.
+    ///
+    /// ```rust
+    /// use regex_dfa::{LikeOptions, Regex};
+    ///
+    /// let re = Regex::from_like("ab_%\\%", &LikeOptions::default()).unwrap();
+    /// assert!(re.is_match("abc%"));
+    /// assert!(re.is_match("abcdef%"));
+    /// assert!(!re.is_match("ab%"));
+    /// assert!(!re.is_match("abcd"));
+    /// ```
+    pub fn from_like(pattern: &str, options: &LikeOptions) -> ::Result<Regex> {
+        let expr = expr::simplify(&try!(like::to_expr(pattern, options)));
+        let prefilter = PrefilterOptions::default();
+        let invalid = InvalidUtf8::NeverMatch;
+        let utf8 = &mut Utf8Cache::new();
+        Regex::from_expr(&expr, options.max_states, &prefilter, invalid, utf8, &mut Stages::none())
+    }
+
+    /// Creates a new `Regex` from a regular expression string, simplifying the regular expression
+    /// if it requires too many states.
+    ///
+    /// The simplifications (see `AdaptivePolicy`) only ever make the language of the regular
+    /// expression bigger, so the resulting `Regex` might find matches that the original one would
+    /// not. Returns the `Regex`, together with a list of the simplifications that were needed. If
+    /// the regex requires too many states even after all of the allowed simplifications, returns
+    /// an error.
+    pub fn new_adaptive(re: &str, policy: &AdaptivePolicy)
+    -> ::Result<(Regex, Vec<Degradation>)> {
+        fn too_big(res: &::Result<Regex>) -> bool {
+            match *res {
+                Err(Error::TooManyStates) | Err(Error::DfaBlowup(_)) => true,
+                _ => false,
+            }
         }
 
-        Ok(ForwardBackwardEngine::new(f_prog, prefix, b_prog))
-    }
+        let mut expr = expr::simplify(&try!(Expr::parse(re)));
+        let mut applied = Vec::new();
+        let prefilter = PrefilterOptions::default();
+        let invalid = InvalidUtf8::NeverMatch;
+        let utf8 = &mut Utf8Cache::new();
+        let stages = &mut Stages::none();
+        let mut res =
+            Regex::from_expr(&expr, policy.max_states, &prefilter, invalid, utf8, stages);
 
-    /// Returns the index range of the first match, if there is a match. The indices returned are
-    /// byte indices of the string. The first index is inclusive; the second is exclusive.
-    pub fn find(&self, s: &str) -> Option<(usize, usize)> {
-        if let Some((start, end, look_behind)) = self.engine.find(s) {
-            Some((start + look_behind as usize, end))
-        } else {
-            None
+        if too_big(&res) {
+            if let Some(max_ranges) = policy.max_class_ranges {
+                let (new_expr, count) = expr::coarsen_classes(&expr, max_ranges);
+                if count > 0 {
+                    expr = new_expr;
+                    applied.push(Degradation::CoarsenedClasses(count));
+                    res = Regex::from_expr(&expr, policy.max_states, &prefilter, invalid, utf8,
+                                           stages);
+                }
+            }
+        }
+        if too_big(&res) {
+            if let Some(cap) = policy.max_repeat {
+                let (new_expr, count) = expr::cap_repetitions(&expr, cap);
+                if count > 0 {
+                    expr = new_expr;
+                    applied.push(Degradation::CappedRepetitions(count));
+                    res = Regex::from_expr(&expr, policy.max_states, &prefilter, invalid, utf8,
+                                           stages);
+                }
+            }
         }
+
+        res.map(|re| (re, applied))
     }
 
-    pub fn is_match(&self, s: &str) -> bool {
-        // TODO: for the forward-backward engine, this could be faster because we don't need
-        // to run backward.
-        self.find(s).is_some()
+    fn from_expr(
+        expr: &Expr,
+        max_states: usize,
+        prefilter: &PrefilterOptions,
+        invalid_utf8: InvalidUtf8,
+        utf8: &mut Utf8Cache,
+        stages: &mut Stages
+    ) -> ::Result<Regex> {
+        let nfa = try!(stages.run(Stage::Nfa, || Ok(Nfa::from_expr(expr))));
+        let words = stages.word_chars.clone();
+        let nfa = try!(stages.run(Stage::RemovePredicates, || Ok(nfa.remove_looks_with(&words))));
+
+        // If the regex looks behind the start of the match then the prefixes that we find include
+        // the char before the match, so they don't tell us anything about the match itself.
+        let look_behind = nfa.has_look_behind();
+        let nfa = try!(stages.run(Stage::Bytes, || {
+            let mut nfa = try!(nfa.byte_me_cached(max_states, invalid_utf8, utf8));
+            nfa.reduce();
+            Ok(nfa)
+        }));
+        let leading_run = Prefix::from_leading_run(expr, prefilter);
+        Regex::from_byte_nfa(nfa, max_states, prefilter, look_behind, leading_run, stages)
+    }
+
+    // Builds a `Regex` from an `Nfa` that has already been converted to consume bytes.
+    //
+    // `leading_run` is a prefilter to fall back on if the forward-backward engine doesn't find a
+    // better one (see `Prefix::from_leading_run`).
+    fn from_byte_nfa(
+        nfa: Nfa<u8, NoLooks>,
+        max_states: usize,
+        prefilter: &PrefilterOptions,
+        look_behind: bool,
+        leading_run: Prefix,
+        stages: &mut Stages
+    ) -> ::Result<Regex> {
+        let (eng, analysis) = if nfa.is_empty() {
+            let analysis = Analysis {
+                prefixes: Literals::empty(),
+                start_bytes: ByteSet::from_bytes(&[]),
+            };
+            (Arc::new(EmptyEngine) as Arc<Engine<u8>>, analysis)
+        } else if nfa.is_anchored() {
+            let (eng, analysis) = try!(Regex::make_anchored(nfa, max_states, stages));
+            (Arc::new(eng) as Arc<Engine<u8>>, analysis)
+        } else if nfa.is_end_anchored() {
+            let analysis = try!(Regex::analyze_forward(&nfa, max_states, stages));
+            let eng = try!(Regex::make_end_anchored(nfa, max_states, stages));
+            (Arc::new(eng) as Arc<Engine<u8>>, analysis)
+        } else if nfa.is_line_anchored() {
+            let fallback =
+                try!(Regex::make_fallback(&nfa, max_states, prefilter, &leading_run, stages));
+            let eng = try!(Regex::make_line_start(nfa, max_states, fallback, stages));
+            (Arc::new(eng) as Arc<Engine<u8>>, Analysis::unknown())
+        } else if let Some(eng) =
+                try!(Regex::make_look_behind(&nfa, max_states, prefilter, &leading_run, stages)) {
+            (Arc::new(eng) as Arc<Engine<u8>>, Analysis::unknown())
+        } else {
+            let (eng, analysis) = try!(
+                Regex::make_forward_backward(nfa, max_states, prefilter, leading_run, stages));
+            (Arc::new(eng) as Arc<Engine<u8>>, analysis)
+        };
+
+        Ok(Regex {
+            engine: eng,
+            analysis: Arc::new(if look_behind { Analysis::unknown() } else { analysis }),
+        })
     }
-}
 
+    // Determinizes and minimizes `nfa`. If `longest` is true, the `Dfa` only needs to find the
+    // longest match.
+    fn make_dfa(nfa: &Nfa<u8, NoLooks>, max_states: usize, longest: bool, stages: &mut Stages)
+    -> ::Result<Dfa<(Look, u8)>> {
+        let deadline = stages.deadline;
+        let options = DeterminizeOptions {
+            max_states: max_states,
+            deadline: deadline,
+            ..stages.determinize
+        };
+        let mut stats = DeterminizeStats::default();
+        let dfa = stages.run(Stage::Determinize, || {
+            let (dfa, dfa_stats) = try!(if longest {
+                nfa.determinize_longest_with_options(&options)
+            } else {
+                nfa.determinize_with_options(&options)
+            });
+            stats = dfa_stats;
+            Ok(dfa)
+        });
+        // Count the determinization even if we were asked to stop after it.
+        stages.determinize_stats.add(&stats);
+        let dfa = try!(dfa);
+        stages.run(Stage::Minimize, || dfa.optimize_until(deadline))
+    }
+
+    fn make_anchored(nfa: Nfa<u8, NoLooks>, max_states: usize, stages: &mut Stages)
+    -> ::Result<(AnchoredEngine, Analysis)> {
+        let dfa = try!(Regex::make_dfa(&nfa, max_states, false, stages));
+        let prog = try!(stages.run(Stage::Program, || Ok(dfa.compile())));
+        let analysis = Analysis {
+            prefixes: Literals::from_parts(dfa.prefix_strings()),
+            start_bytes: ByteSet::from_bytes(&dfa.start_bytes()),
+        };
+
+        Ok((AnchoredEngine::new(prog), analysis))
+    }
+
+    // Makes a forward-backward engine for the engines that try anchored matches at many positions
+    // to fall back on, if their attempts take too long (see `runner::Budget`). Returns `None` if
+    // it would need too many states, since those engines are still correct without it.
+    fn make_fallback(
+        nfa: &Nfa<u8, NoLooks>,
+        max_states: usize,
+        opts: &PrefilterOptions,
+        leading_run: &Prefix,
+        stages: &mut Stages
+    ) -> ::Result<Option<ForwardBackwardEngine<u8>>> {
+        let leading_run = leading_run.clone();
+        match Regex::make_forward_backward(nfa.clone(), max_states, opts, leading_run, stages) {
+            Ok((eng, _)) => Ok(Some(eng)),
+            Err(Error::TooManyStates) | Err(Error::DfaBlowup(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Finds the prefixes and start bytes of `nfa` from its forward `Dfa`, for engines that don't
+    // otherwise need one.
+    fn analyze_forward(nfa: &Nfa<u8, NoLooks>, max_states: usize, stages: &mut Stages)
+    -> ::Result<Analysis> {
+        let f_nfa = try!(nfa.clone().anchor(max_states));
+        let f_dfa = try!(Regex::make_dfa(&f_nfa, max_states, false, stages));
+        let deadline = stages.deadline;
+        let cut_dfa = try!(stages.run(Stage::Minimize, || {
+            f_dfa.clone().cut_loop_to_init().optimize_until(deadline)
+        }));
+        Ok(Analysis {
+            prefixes: Literals::from_parts(f_dfa.prefix_strings()),
+            start_bytes: ByteSet::from_bytes(&cut_dfa.start_bytes()),
+        })
+    }
+
+    // Makes an engine for an `Nfa` whose matches all end at the end of the input.
+    fn make_end_anchored(nfa: Nfa<u8, NoLooks>, max_states: usize, stages: &mut Stages)
+    -> ::Result<EndAnchoredEngine> {
+        let b_nfa = try!(nfa.reverse_with(max_states, &stages.word_chars));
+        let b_dfa = try!(Regex::make_dfa(&b_nfa, max_states, true, stages));
+        let b_dfa = b_dfa.map_ret(|(_, bytes)| bytes);
+        let init = b_dfa.init_at_start();
+        let prog = try!(stages.run(Stage::Program, || Ok(b_dfa.compile())));
+        Ok(EndAnchoredEngine::new(prog, init))
+    }
+
+    // Makes an engine for an `Nfa` whose matches can only begin at the start of a line.
+    fn make_line_start(
+        nfa: Nfa<u8, NoLooks>,
+        max_states: usize,
+        fallback: Option<ForwardBackwardEngine<u8>>,
+        stages: &mut Stages
+    ) -> ::Result<LineStartEngine> {
+        let mut compile = |look: Look| -> ::Result<TableInsts<(Look, u8)>> {
+            let dfa = try!(Regex::make_dfa(&nfa.anchor_after(look), max_states, false, stages));
+            stages.run(Stage::Program, || Ok(dfa.compile()))
+        };
+        let at_start = try!(compile(Look::Boundary));
+        let after_newline = try!(compile(Look::NewLine));
+        Ok(LineStartEngine::new(at_start, after_newline, fallback))
+    }
+
+    // Makes an engine for an `Nfa` that looks at the char before the match. Returns `None` if the
+    // `Nfa` doesn't look behind, or if there's no prefilter (in which case the forward-backward
+    // engine is better).
+    fn make_look_behind(
+        nfa: &Nfa<u8, NoLooks>,
+        max_states: usize,
+        opts: &PrefilterOptions,
+        leading_run: &Prefix,
+        stages: &mut Stages
+    ) -> ::Result<Option<LookBehindEngine>> {
+        if !nfa.has_look_behind() {
+            return Ok(None);
+        }
+
+        let looks = [Look::Boundary, Look::NewLine, Look::WordChar, Look::NotWordChar];
+        let dfa = try!(Regex::make_dfa(&nfa.anchor_after_looks(&looks), max_states, false, stages));
+
+        // The prefilter needs to find every match, whatever the char before it.
+        let mut parts = Vec::new();
+        for &look in &looks {
+            let look_parts = dfa.prefix_strings_after(look);
+            if look_parts.iter().any(|p| p.0.is_empty()) {
+                return Ok(None);
+            }
+            parts.extend(look_parts);
+        }
+        let prefix = Prefix::from_parts(parts, opts);
+        if let Prefix::Empty = prefix {
+            return Ok(None);
+        }
+
+        let init = Look::all().iter().map(|&look| dfa.init_state(look)).collect();
+        let prog = try!(stages.run(Stage::Program, || Ok(dfa.compile())));
+        let fallback = try!(Regex::make_fallback(nfa, max_states, opts, leading_run, stages));
+        let words = stages.word_chars.clone();
+        Ok(Some(LookBehindEngine::new(prog, init, words, prefix, fallback)))
+    }
+
+    fn make_forward_backward(
+        nfa: Nfa<u8, NoLooks>,
+        max_states: usize,
+        opts: &PrefilterOptions,
+        leading_run: Prefix,
+        stages: &mut Stages
+    ) -> ::Result<(ForwardBackwardEngine<u8>, Analysis)> {
+        if nfa.is_anchored() {
+            return Err(Error::InvalidEngine("anchors rule out the forward-backward engine"));
+        }
+
+        let f_nfa = try!(nfa.clone().anchor_resumable(max_states, &stages.word_chars));
+        let b_nfa = try!(nfa.reverse_with(max_states, &stages.word_chars));
+
+        let f_dfa = try!(Regex::make_dfa(&f_nfa, max_states, false, stages));
+        let b_dfa = try!(Regex::make_dfa(&b_nfa, max_states, true, stages));
+        let b_dfa = b_dfa.map_ret(|(_, bytes)| bytes);
+
+        let b_prog = try!(stages.run(Stage::Program, || Ok(b_dfa.compile())));
+        let f_dfa = f_dfa.map_ret(|(look, bytes)| {
+            let b_dfa_state = b_dfa.init_state(look).expect("BUG: back dfa must have this init");
+            (b_dfa_state, look, bytes)
+        });
+
+        // A search can start after any kind of char, so the prefilter needs to find the matches
+        // that begin in every one of the initial states.
+        let looks = [Look::Boundary, Look::NewLine, Look::WordChar, Look::NotWordChar];
+        let mut parts = Vec::new();
+        for &look in &looks {
+            let look_parts = f_dfa.prefix_strings_after(look);
+            if look_parts.iter().any(|p| p.0.is_empty()) {
+                parts.clear();
+                break;
+            }
+            parts.extend(look_parts);
+        }
+        let literals = Literals::from_parts(f_dfa.prefix_strings());
+        let mut prefix = Prefix::from_parts(parts, opts);
+
+        // If there is a non-trivial prefix, we can usually speed up matching by deleting
+        // transitions that return to the start state. That way, instead of returning to the start
+        // state, we will just fail to match. Then we get to search for the prefix before trying to
+        // match again.
+        let deadline = stages.deadline;
+        let cut_dfa = try!(stages.run(Stage::Minimize, || {
+            f_dfa.clone().cut_loop_to_init().optimize_until(deadline)
+        }));
+
+        // In the cut `Dfa`, the only transitions out of the start state are the ones that begin a
+        // match. If there are only a few of them, we can search for them even when the prefixes
+        // were too varied to give us a useful `Prefix`.
+        let start_bytes = cut_dfa.start_bytes();
+        if let Prefix::Empty = prefix {
+            let mut bytes = looks.iter()
+                .flat_map(|&look| cut_dfa.start_bytes_after(look))
+                .collect::<Vec<_>>();
+            bytes.sort();
+            bytes.dedup();
+            prefix = Prefix::from_start_bytes(&bytes, opts);
+        }
+
+        // If the regex begins with something like `.*c`, we can at least skip to the line
+        // containing the next `c`. The cut `Dfa` would be no good here, because it would fail on
+        // every byte of the `.*`, so we run the original one from wherever the prefix takes us.
+        let cut = match prefix {
+            Prefix::Empty => false,
+            _ => true,
+        };
+        if !cut {
+            prefix = leading_run;
+        }
+
+        let init = Look::all().iter()
+            .map(|&look| if cut { cut_dfa.init_state(look) } else { f_dfa.init_state(look) })
+            .collect();
+        let f_prog = try!(stages.run(Stage::Program, || Ok(if cut {
+            cut_dfa.compile()
+        } else {
+            f_dfa.compile()
+        })));
+        let analysis = Analysis {
+            prefixes: literals,
+            start_bytes: ByteSet::from_bytes(&start_bytes),
+        };
+
+        let words = stages.word_chars.clone();
+        Ok((ForwardBackwardEngine::new(f_prog, init, words, prefix, b_prog), analysis))
+    }
+
+    /// Returns the literal byte strings that every match must start with.
+    ///
+    /// This is intended for pre-filtering: if some text doesn't contain any of the literals, then
+    /// there's no need to run the `Regex` on it. If the returned set is empty, nothing is known
+    /// about how matches start (and so nothing can be filtered out).
+    pub fn required_prefixes(&self) -> &Literals {
+        &self.analysis.prefixes
+    }
+
+    /// Returns the set of bytes that can appear at the beginning of a match.
+    ///
+    /// If some byte isn't in this set, then no match can start at a position holding that byte.
+    /// The set is full if this `Regex` can match the empty string, or if it has look-behind
+    /// assertions (like `\b`) at the start.
+    pub fn start_bytes(&self) -> &ByteSet {
+        &self.analysis.start_bytes
+    }
+
+    /// Returns the index range of the first match, if there is a match. The indices returned are
+    /// byte indices of the string. The first index is inclusive; the second is exclusive.
+    pub fn find(&self, s: &str) -> Option<(usize, usize)> {
+        self.find_bytes(s.as_bytes()).map(|(start, end)| check_boundaries(s, start, end))
+    }
+
+    /// Returns the index range of the first match in a string of bytes.
+    ///
+    /// The bytes don't need to be valid UTF-8. By default, invalid sequences are simply never
+    /// matched (not even by `.` or negated character classes), but they can also be treated as
+    /// U+FFFD (see `RegexOptions::invalid_utf8`).
+    pub fn find_bytes(&self, input: &[u8]) -> Option<(usize, usize)> {
+        if let Some((start, end, look_behind)) = self.engine.find(input) {
+            Some((start + look_behind as usize, end))
+        } else {
+            None
+        }
+    }
+
+    /// Returns an iterator over the byte ranges of all the non-overlapping matches in `s`.
+    ///
+    /// Unlike `match_spans`, this includes empty matches (except for the ones immediately
+    /// following another match), which is what the `regex` crate does; use
+    /// `Matches::empty_matches` to change that. Each search continues from the end of the
+    /// previous match, and assertions like `\b` look at the text before it.
+    ///
+    /// ```rust
+    /// use regex_dfa::Regex;
+    ///
+    /// let re = Regex::new("a*").unwrap();
+    /// assert_eq!(re.find_iter("baab").collect::<Vec<_>>(), vec![(0, 0), (1, 3), (4, 4)]);
+    /// ```
+    pub fn find_iter<'r, 't>(&'r self, s: &'t str) -> Matches<'r, 't> {
+        Matches {
+            re: self,
+            text: s,
+            state: MatchesState::new(),
+        }
+    }
+
+    /// Like `find_iter`, but the iterator owns the `Regex` and the string instead of borrowing
+    /// them. Cloning a `Regex` is cheap, so `re.clone().into_find_iter(s)` is fine too.
+    ///
+    /// ```rust
+    /// use regex_dfa::{IntoMatches, Regex};
+    ///
+    /// fn words(text: String) -> IntoMatches {
+    ///     Regex::new("[a-z]+").unwrap().into_find_iter(text)
+    /// }
+    ///
+    /// let mut iter = words("ab, cd".to_owned());
+    /// assert_eq!(iter.next(), Some((0, 2)));
+    /// assert_eq!(iter.next(), Some((4, 6)));
+    /// assert_eq!(iter.into_text(), "ab, cd");
+    /// ```
+    pub fn into_find_iter(self, s: String) -> IntoMatches {
+        IntoMatches {
+            re: self,
+            text: s,
+            state: MatchesState::new(),
+        }
+    }
+
+    /// Returns the end of the first match in `s`, if there is one.
+    ///
+    /// This is here for compatibility with the `regex` crate. It currently returns the end of the
+    /// match that `find` returns, so it isn't any faster than `find`; if you only need to know
+    /// the end of some match, `Detector::shortest_match` stops as soon as it sees one.
+    pub fn shortest_match(&self, s: &str) -> Option<usize> {
+        self.find(s).map(|(_, end)| end)
+    }
+
+    /// Like `find_iter`, but the matches are given as char indices instead of byte indices.
+    ///
+    /// The matches come out in order, so each char is only counted once: going through all the
+    /// matches takes time proportional to the length of `s`, not to the number of matches times
+    /// the length of `s`.
+    ///
+    /// ```rust
+    /// use regex_dfa::Regex;
+    ///
+    /// let re = Regex::new("[a-z]+").unwrap();
+    /// assert_eq!(re.find_iter_chars("αβ ab γ cd").collect::<Vec<_>>(), vec![(3, 5), (8, 10)]);
+    /// ```
+    pub fn find_iter_chars<'r, 't>(&'r self, s: &'t str) -> CharMatches<'r, 't> {
+        CharMatches {
+            matches: self.find_iter(s),
+            byte_pos: 0,
+            char_pos: 0,
+        }
+    }
+
+    /// Like `shortest_match`, but returns a char index instead of a byte index.
+    pub fn shortest_match_chars(&self, s: &str) -> Option<usize> {
+        self.shortest_match(s).map(|end| count_chars(&s.as_bytes()[..end]))
+    }
+
+    /// Finds the first match in `s`, and also says what the match needed to see after its end.
+    ///
+    /// This is useful for tokenizers: if the match looked at the char after it, then more input
+    /// could change the match, even if the char itself isn't part of the match.
+    pub fn find_with_end(&self, s: &str) -> Option<(usize, usize, MatchEnd)> {
+        self.engine.find_look_ahead(s.as_bytes()).map(|(start, end, look_behind, (look, bytes))| {
+            let (start, end) = check_boundaries(s, start + look_behind as usize, end);
+            let match_end = if bytes > 0 {
+                MatchEnd::Char(s[end..].chars().next().expect("BUG: missing look-ahead char"))
+            } else if look == Look::Boundary {
+                MatchEnd::Eoi
+            } else {
+                MatchEnd::Anywhere
+            };
+            (start, end, match_end)
+        })
+    }
+
+    /// Finds the first match that lies entirely within `range`, a range of byte indices in `s`.
+    ///
+    /// Unlike calling `find` on `&s[range]`, the assertions (`^`, `$`, `\b`, and so on) are
+    /// evaluated relative to all of `s`: for example, `^` doesn't match at `range.start` unless
+    /// it is the beginning of `s` (or of a line, with the `m` flag), and `\b` looks at the chars
+    /// on both sides of the range. This is useful for searching a part of a larger text, like the
+    /// visible part of a document in an editor.
+    ///
+    /// The match is the one that `find` would return if `s` ended at `range.end` (except for the
+    /// assertions): the leftmost one, and then the one that the regex prefers, so `a+` takes as
+    /// many `a`s as fit in `range`. It isn't necessarily the shortest match, because the
+    /// matching programs only record where the preferred match ends.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the ends of `range` aren't `char` boundaries of `s`, or if `range.start >
+    /// range.end`.
+    ///
+    /// ```rust
+    /// use regex_dfa::Regex;
+    ///
+    /// let re = Regex::new(r"\bcat\b").unwrap();
+    /// assert_eq!(re.find_in("concat cat", 3..10), Some((7, 10)));
+    /// assert_eq!(re.find_in("concat cat", 3..9), None);
+    /// ```
+    pub fn find_in(&self, s: &str, range: Range<usize>) -> Option<(usize, usize)> {
+        assert!(range.start <= range.end);
+        assert!(s.is_char_boundary(range.start) && s.is_char_boundary(range.end));
+        // Leave one char after the range, so that look-ahead assertions can see it.
+        let ctx_end = range.end + s[range.end..].chars().next().map_or(0, |c| c.len_utf8());
+        let input = &s.as_bytes()[..ctx_end];
+
+        self.engine.find_look_ahead_before(input, range.start, range.end)
+            .map(|(start, end, look_behind, _)| {
+                check_boundaries(s, start + look_behind as usize, end)
+            })
+    }
+
+    /// If there is a match starting at `pos`, returns its end.
+    ///
+    /// This is for checking candidate positions that were found some other way (for example,
+    /// from an index of the text). The assertions see all of `s`, so `\b` at `pos` looks at the
+    /// char before it. The match is the one that `find` would return if it started searching at
+    /// `pos`.
+    ///
+    /// Most regexes can check `pos` directly, but regexes that can't use a prefilter may need to
+    /// search past `pos` before they know that there is no match there.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pos` is not a `char` boundary of `s`.
+    ///
+    /// ```rust
+    /// use regex_dfa::Regex;
+    ///
+    /// let re = Regex::new(r"\bfoo\d+").unwrap();
+    /// assert_eq!(re.verify_at("a foo12 xfoo3", 2), Some(7));
+    /// assert_eq!(re.verify_at("a foo12 xfoo3", 9), None);
+    /// ```
+    pub fn verify_at(&self, s: &str, pos: usize) -> Option<usize> {
+        assert!(s.is_char_boundary(pos));
+        let found = self.engine.find_look_ahead_starting_at(s.as_bytes(), pos);
+        match found {
+            Some((start, end, look_behind, _)) if start + look_behind as usize == pos => Some(end),
+            _ => None,
+        }
+    }
+
+    /// Finds the first match in `s`, but only tries the positions that `searcher` suggests.
+    ///
+    /// This is for when something outside the `Regex` knows where matches can start; see
+    /// `Searcher` for what it needs to promise. Each candidate is checked with `verify_at`.
+    pub fn find_with_searcher<S: Searcher + ?Sized>(&self, s: &str, searcher: &S)
+    -> Option<(usize, usize)> {
+        let mut pos = 0;
+        while pos <= s.len() {
+            let cand = try_opt!(searcher.search(s.as_bytes(), pos));
+            if cand < pos || cand > s.len() {
+                break;
+            }
+            if s.is_char_boundary(cand) {
+                if let Some(end) = self.verify_at(s, cand) {
+                    return Some((cand, end));
+                }
+            }
+            pos = cand + 1;
+        }
+        None
+    }
+
+    /// Finds the first match in `s`, and returns it together with up to `before` chars of text
+    /// preceding it and up to `after` chars of text following it.
+    ///
+    /// The context windows are measured in `char`s rather than bytes, so they never split a
+    /// multi-byte character. They are shorter than requested if the match is too close to the
+    /// beginning or end of `s`.
+    pub fn find_with_context<'t>(&self, s: &'t str, before: usize, after: usize)
+    -> Option<MatchContext<'t>> {
+        self.find(s).map(|(start, end)| {
+            let ctx_start = s[..start].char_indices()
+                .rev()
+                .take(before)
+                .last()
+                .map_or(start, |(i, _)| i);
+            let ctx_end = s[end..].char_indices()
+                .nth(after)
+                .map_or(s.len(), |(i, _)| end + i);
+
+            MatchContext {
+                start: start,
+                end: end,
+                before: &s[ctx_start..start],
+                matched: &s[start..end],
+                after: &s[end..ctx_end],
+            }
+        })
+    }
+
+    /// Returns the byte ranges of all non-overlapping, non-empty matches in `s`.
+    ///
+    /// The ranges are sorted, and they always start and end on `char` boundaries, so they can be
+    /// used directly for slicing `s` (e.g. for highlighting matches).
+    pub fn match_spans(&self, s: &str) -> Vec<Range<usize>> {
+        let mut ret = Vec::new();
+        self.push_match_spans(s, &mut ret);
+        ret
+    }
+
+    /// Like `match_spans`, but stores the spans in `cache` instead of allocating a new `Vec`.
+    ///
+    /// Calling this repeatedly with the same `cache` only allocates when there are more matches
+    /// than ever before.
+    pub fn match_spans_with_cache<'c>(&self, s: &str, cache: &'c mut MatchCache)
+    -> &'c [Range<usize>] {
+        cache.spans.clear();
+        self.push_match_spans(s, &mut cache.spans);
+        &cache.spans
+    }
+
+    // Appends the spans of all non-overlapping, non-empty matches in `s` to `ret`.
+    fn push_match_spans(&self, s: &str, ret: &mut Vec<Range<usize>>) {
+        let matches = self.find_iter(s).empty_matches(EmptyMatches::Never);
+        ret.extend(matches.map(|(start, end)| start..end));
+    }
+
+    /// Like `match_spans`, but merges any spans that touch one another.
+    pub fn merged_match_spans(&self, s: &str) -> Vec<Range<usize>> {
+        let mut ret: Vec<Range<usize>> = Vec::new();
+        for span in self.match_spans(s) {
+            if let Some(last) = ret.last_mut() {
+                if last.end == span.start {
+                    last.end = span.end;
+                    continue;
+                }
+            }
+            ret.push(span);
+        }
+        ret
+    }
+
+    /// Returns an iterator over all lines of `text` that contain a match, together with their
+    /// (one-based) line numbers. The returned lines don't include the trailing `'\n'`. As with
+    /// `str::lines`, a `'\n'` at the end of `text` doesn't start another (empty) line.
+    ///
+    /// The regex is run over the whole text rather than line-by-line, so use `(?m)` if `^` and `$`
+    /// should match at the beginning and end of lines. A match that spans several lines is
+    /// reported on the line where it starts.
+    pub fn grep_lines<'r, 't>(&'r self, text: &'t str) -> GrepLines<'r, 't> {
+        GrepLines {
+            re: self,
+            text: text,
+            pos: 0,
+            line_num: 1,
+        }
+    }
+
+    /// Returns an iterator over the first match in each record of `text`, where the records are
+    /// separated by `separator` (for example, the ASCII record separator `0x1e`).
+    ///
+    /// Each record is searched as though it were the whole input, so `^` and `$` match at the
+    /// beginning and end of records, and matches never span more than one record. The iterator
+    /// yields `(record_index, start, end)`, where the record index is zero-based and `start` and
+    /// `end` are positions in `text`. Records without a match are skipped.
+    ///
+    /// ```rust
+    /// use regex_dfa::Regex;
+    ///
+    /// let re = Regex::new("^id=[0-9]+").unwrap();
+    /// let text = b"id=1 x\x1ex id=2\x1eid=3";
+    /// assert_eq!(re.find_records(text, 0x1e).collect::<Vec<_>>(),
+    ///            vec![(0, 0, 4), (2, 14, 18)]);
+    /// ```
+    pub fn find_records<'r, 't>(&'r self, text: &'t [u8], separator: u8)
+    -> RecordMatches<'r, 't> {
+        RecordMatches {
+            re: self,
+            text: text,
+            separator: separator,
+            pos: 0,
+            record_idx: 0,
+        }
+    }
+
+    /// Returns a listing of the compiled programs that this `Regex` runs.
+    ///
+    /// This is mainly useful for debugging: `regex.disassemble().to_string()` gives a readable
+    /// summary of the states, their transitions, and the prefilter (if any).
+    pub fn disassemble(&self) -> Disassembly {
+        self.engine.disassemble()
+    }
+
+    /// Returns a compact binary representation of this `Regex`, which can be turned back into a
+    /// `Regex` (without recompiling it) using `from_bytes`.
+    ///
+    /// The representation is specific to the version of this crate that produced it. See also
+    /// `ProgramCache`, which uses this to save compiled regexes on disk. With the `serde` feature,
+    /// `Regex` also implements `Serialize` and `Deserialize`, using the same representation.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        VERSION.as_bytes().to_vec().encode(&mut out);
+        self.engine.encode(&mut out);
+        self.analysis.prefixes.literals.encode(&mut out);
+        self.analysis.start_bytes.set.encode(&mut out);
+        out
+    }
+
+    /// Reads a `Regex` that was written by `to_bytes`.
+    ///
+    /// Returns `Error::InvalidProgram` if `bytes` is malformed, or if it was written by a
+    /// different version of this crate.
+    pub fn from_bytes(bytes: &[u8]) -> ::Result<Regex> {
+        Regex::decode(bytes).ok_or(Error::InvalidProgram)
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Regex> {
+        let mut input = Decoder::new(bytes);
+        if try_opt!(input.take(MAGIC.len())) != MAGIC {
+            return None;
+        }
+        let version: Vec<u8> = try_opt!(Vec::decode(&mut input));
+        if version != VERSION.as_bytes() {
+            return None;
+        }
+
+        let engine = try_opt!(decode_engine(&mut input));
+        let literals = try_opt!(Vec::decode(&mut input));
+        let start_bytes: Vec<bool> = try_opt!(Vec::decode(&mut input));
+        if start_bytes.len() != 256 || !input.is_empty() {
+            return None;
+        }
+
+        Some(Regex {
+            engine: engine,
+            analysis: Arc::new(Analysis {
+                prefixes: Literals { literals: literals },
+                start_bytes: ByteSet { set: start_bytes },
+            }),
+        })
+    }
+
+    /// Runs `find` on the given string, recording each step that is taken.
+    ///
+    /// This is intended for debugging why a regex does or doesn't match some input; it is much
+    /// slower than `find`.
+    pub fn trace(&self, s: &str) -> Vec<TraceEvent> {
+        self.engine.trace(s)
+    }
+
+    /// Checks that `find` does a linear amount of work on `s`.
+    ///
+    /// Every search is supposed to consume at most a constant number of bytes (currently 4) for
+    /// each byte of the input, however many places it has to try starting a match. This counts
+    /// the bytes that the programs consume while searching `s` (going by `trace`), and panics if
+    /// there are too many. It is intended for testing with adversarial inputs, such as the ones
+    /// from `worst_case_input`.
+    ///
+    /// ```rust
+    /// use regex_dfa::Regex;
+    /// use std::iter;
+    ///
+    /// // Every line starts a match that only fails at the end of the input.
+    /// let re = Regex::new("(?m)^a[^y]*yz").unwrap();
+    /// let lines: String = iter::repeat("a\n").take(1000).collect();
+    /// re.debug_assert_linear(&(lines + "yy"));
+    /// ```
+    pub fn debug_assert_linear(&self, s: &str) {
+        let steps = self.trace(s).iter()
+            .filter(|ev| match **ev { TraceEvent::Step { .. } => true, _ => false })
+            .count();
+        let limit = runner::LINEAR_FACTOR * (s.len() + 1);
+        assert!(steps <= limit,
+                "searching {} bytes took {} steps (the limit is {})", s.len(), steps, limit);
+    }
+
+    /// Runs `find` on every string in `corpus`, and records which states of the compiled
+    /// programs were visited and which transitions were taken.
+    ///
+    /// The states and transitions that are never used point to parts of the regular expression
+    /// that `corpus` doesn't exercise. Like `trace` (which it is built on), this is much slower
+    /// than `find`.
+    ///
+    /// ```rust
+    /// use regex_dfa::Regex;
+    ///
+    /// let re = Regex::new("^(yes|no)$").unwrap();
+    /// let cov = re.coverage(&["yes"]);
+    /// assert!(!cov.is_complete());
+    /// assert!(cov.unvisited_transitions().iter().any(|t| (t.2).0 == b'n'));
+    /// assert!(re.coverage(&["yes", "no"]).is_complete());
+    /// ```
+    pub fn coverage<S: AsRef<str>>(&self, corpus: &[S]) -> Coverage {
+        let programs = self.disassemble().programs;
+        let visited = programs.iter()
+            .map(|p| p.1.iter().map(|inst| (false, vec![false; inst.transitions.len()])).collect())
+            .collect();
+        let mut ret = Coverage { programs: programs, visited: visited };
+        for s in corpus {
+            for ev in self.trace(s.as_ref()) {
+                ret.record(&ev);
+            }
+        }
+        ret
+    }
+
+    /// If this `Regex` doesn't match `s`, explains why not.
+    ///
+    /// Returns `None` if there is a match. Otherwise, returns the furthest position that any
+    /// attempted match reached, the program and state it was in, and the bytes that would have
+    /// allowed that attempt to continue. Since the matcher works on bytes, the expected bytes might
+    /// only be part of a character. If no attempt read any input, there is no program and no
+    /// expected bytes.
+    ///
+    /// A regex that is anchored at the end (like `abc$`) is matched backward from the end of the
+    /// input, so the furthest position is the earliest one, and the expected bytes are the ones
+    /// that would have been allowed just before it.
+    pub fn explain(&self, s: &str) -> Option<NoMatch> {
+        if self.find(s).is_some() {
+            return None;
+        }
+
+        let programs = self.disassemble().programs;
+        let expected = |program: &str, state: usize| -> Vec<(u8, u8)> {
+            programs.iter()
+                .find(|p| p.0 == program)
+                .and_then(|p| p.1.get(state))
+                .map(|inst| inst.transitions.iter().map(|t| (t.0, t.1)).collect())
+                .unwrap_or_else(Vec::new)
+        };
+
+        // The end-anchored engine only has a backward program, which reads the byte before its
+        // position. (The forward-backward engine only runs its backward program after a match.)
+        let backward = programs.first().map_or(false, |p| p.0 == "backward");
+        let mut ret = NoMatch {
+            pos: if backward { s.len() } else { 0 },
+            program: None,
+            state: 0,
+            expected: Vec::new(),
+        };
+        for ev in self.trace(s) {
+            if let TraceEvent::Step { program, pos, state, next, .. } = ev {
+                let (reached, reached_state) = match (next, backward) {
+                    (Some(next), false) => (pos + 1, next),
+                    (Some(next), true) => (pos, next),
+                    (None, false) => (pos, state),
+                    (None, true) => (pos + 1, state),
+                };
+                if (!backward && reached >= ret.pos) || (backward && reached <= ret.pos) {
+                    ret = NoMatch {
+                        pos: reached,
+                        program: Some(program),
+                        state: reached_state,
+                        expected: expected(program, reached_state),
+                    };
+                }
+            }
+        }
+        Some(ret)
+    }
+
+    /// Returns an input of length `len` that is intended to make this `Regex` work as hard as
+    /// possible.
+    ///
+    /// The input is built by repeating a "near miss": the shortest input that gets within one
+    /// byte of a match, followed by a byte that makes the match fail. Every repetition gets past
+    /// any prefilter and then makes the matcher give up as late as possible. This is meant for
+    /// benchmarking; there is no guarantee that it is actually the slowest input of its length.
+    ///
+    /// A regex that is anchored at the end (like `abc$`) is matched backward from the end of the
+    /// input, so only the near miss at the end of the input matters.
+    pub fn worst_case_input(&self, len: usize) -> Vec<u8> {
+        let programs = self.disassemble().programs;
+        let (name, insts) = match programs.first() {
+            Some(p) => (p.0, &p.1),
+            None => { return vec![0; len]; },
+        };
+        if insts.is_empty() {
+            return vec![0; len];
+        }
+        let accepts = |st: usize| insts[st].accept || insts[st].accept_at_eoi;
+
+        // Find the shortest path from the initial state to an accepting state. For every state,
+        // remember the state and byte that we came from.
+        let mut prev: Vec<Option<(usize, u8)>> = vec![None; insts.len()];
+        let mut queue = VecDeque::new();
+        let mut visited = vec![false; insts.len()];
+        let mut accept_state = None;
+        visited[0] = true;
+        queue.push_back(0);
+        while let Some(st) = queue.pop_front() {
+            if accepts(st) {
+                accept_state = Some(st);
+                break;
+            }
+            for &(lo, _, tgt) in &insts[st].transitions {
+                if !visited[tgt] {
+                    visited[tgt] = true;
+                    prev[tgt] = Some((st, lo));
+                    queue.push_back(tgt);
+                }
+            }
+        }
+
+        let mut near_miss = Vec::new();
+        let mut last_state = 0;
+        if let Some(mut st) = accept_state {
+            while let Some((p, b)) = prev[st] {
+                near_miss.push(b);
+                st = p;
+            }
+            near_miss.reverse();
+            // Drop the last byte, and remember the state that we were in before reading it.
+            if near_miss.pop().is_some() {
+                let mut st = 0;
+                for &b in &near_miss {
+                    st = insts[st].transitions.iter().find(|t| t.0 == b).unwrap().2;
+                }
+                last_state = st;
+            }
+        }
+
+        // Finish the near miss with a byte that doesn't lead to a match: preferably one with no
+        // transition at all, or else one that leads to a non-accepting state.
+        let trans = &insts[last_state].transitions;
+        let no_transition = (0..256usize)
+            .map(|b| b as u8)
+            .find(|&b| !trans.iter().any(|t| t.0 <= b && b <= t.1));
+        let non_accepting = trans.iter().find(|t| !accepts(t.2)).map(|t| t.0);
+        if let Some(b) = no_transition.or(non_accepting) {
+            near_miss.push(b);
+        } else if near_miss.is_empty() {
+            near_miss.push(0);
+        }
+
+        let mut ret: Vec<u8> = near_miss.iter().cloned().cycle().take(len).collect();
+        // The end-anchored engine only has a backward program, which reads the input from the
+        // end, so the near misses need to be reversed (and one of them needs to be at the end).
+        if name == "backward" {
+            ret.reverse();
+        }
+        ret
+    }
+
+    pub fn is_match(&self, s: &str) -> bool {
+        self.engine.is_match(s.as_bytes())
+    }
+
+    /// Checks each of `inputs` for a match, returning one answer for each input.
+    ///
+    /// This is faster than calling `is_match` on every input, because it stops searching each
+    /// input as soon as it sees a match (as a `Detector` does) without building a new `Detector`
+    /// for every call.
+    pub fn is_match_batch<S: AsRef<str>>(&self, inputs: &[S]) -> Vec<bool> {
+        let mut ret = vec![false; inputs.len()];
+        self.is_match_batch_into(inputs, &mut ret);
+        ret
+    }
+
+    /// Like `is_match_batch`, but writes the answers into `out` instead of allocating.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `out` and `inputs` have different lengths.
+    pub fn is_match_batch_into<S: AsRef<str>>(&self, inputs: &[S], out: &mut [bool]) {
+        assert_eq!(inputs.len(), out.len());
+        let detector = self.engine.for_detection();
+        for (input, ans) in inputs.iter().zip(out.iter_mut()) {
+            *ans = detector.is_match(input.as_ref().as_bytes());
+        }
+    }
+
+    /// Returns a `Detector`, which can only check whether there is a match (but does it faster
+    /// than `is_match`, because it stops as soon as it sees any match).
+    pub fn for_detection(&self) -> Detector {
+        Detector { engine: self.engine.for_detection() }
+    }
+}
+
+/// A compiled regular expression that can only check whether it matches. See
+/// `Regex::for_detection`.
+#[derive(Debug)]
+pub struct Detector {
+    engine: Box<Engine<u8>>,
+}
+
+impl Clone for Detector {
+    fn clone(&self) -> Detector {
+        Detector { engine: self.engine.clone_box() }
+    }
+}
+
+impl Detector {
+    /// Returns true if there is a match in `s`.
+    pub fn is_match(&self, s: &str) -> bool {
+        self.engine.is_match(s.as_bytes())
+    }
+
+    /// Returns the position at which the first match ends, if there is one.
+    ///
+    /// This is the earliest position at which we know that there is a match, so it can be
+    /// before the end of the match that `Regex::find` returns (for example, `a+` matches all of
+    /// "aaa", but this returns 1).
+    pub fn shortest_match(&self, s: &str) -> Option<usize> {
+        self.engine.find(s.as_bytes()).map(|(_, end, _)| end)
+    }
+
+    /// Returns true if there is a match in `input`, which doesn't need to be valid UTF-8 (see
+    /// `Regex::find_bytes`).
+    pub fn is_match_bytes(&self, input: &[u8]) -> bool {
+        self.engine.is_match(input)
+    }
+}
+
+/// A compiled regular expression that checks whether there is a match in input that arrives in
+/// pieces (for example, from a socket).
+///
+/// Everything that a `Regex` supports also works here, including word boundaries and multi-line
+/// anchors that fall between two pieces of input: the program that runs on the input consumes it
+/// one byte at a time, and remembers everything it needs to know about the bytes that it has
+/// already seen (such as whether the last char was a word char).
+///
+/// ```rust
+/// use regex_dfa::StreamDetector;
+///
+/// let det = StreamDetector::new(r"\bfoo\b").unwrap();
+/// let mut stream = det.stream();
+/// assert!(!stream.feed(b"a fo"));
+/// assert!(stream.feed(b"o b"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct StreamDetector {
+    prog: TableInsts<(Look, u8)>,
+    init: Option<usize>,
+}
+
+impl StreamDetector {
+    /// Creates a new `StreamDetector` from a regular expression string.
+    pub fn new(re: &str) -> ::Result<StreamDetector> {
+        StreamDetector::new_bounded(re, std::usize::MAX)
+    }
+
+    /// Creates a new `StreamDetector` from a regular expression string, but only if it doesn't
+    /// require too many states.
+    pub fn new_bounded(re: &str, max_states: usize) -> ::Result<StreamDetector> {
+        let expr = expr::simplify(&try!(Expr::parse(re)));
+        let nfa = Nfa::from_expr(&expr).remove_looks();
+        let mut nfa = try!(nfa.byte_me_with(max_states, InvalidUtf8::NeverMatch));
+        nfa.reduce();
+        if nfa.is_empty() {
+            let dfa = Dfa::<(Look, u8)>::new();
+            return Ok(StreamDetector { prog: dfa.compile(), init: None });
+        }
+
+        // Unlike the other engines, we can't go back and look for the start of a match, so
+        // everything has to happen in one forward pass.
+        let nfa = try!(nfa.anchor(max_states));
+        let dfa = try!(nfa.determinize(max_states)).optimize();
+        Ok(StreamDetector {
+            prog: dfa.compile(),
+            init: dfa.init_at_start(),
+        })
+    }
+
+    /// Returns true if there is a match anywhere in `input`, which can be stored in several
+    /// pieces (see `Haystack`).
+    pub fn is_match_in<H: Haystack + ?Sized>(&self, input: &H) -> bool {
+        let mut stream = self.stream();
+        input.for_each_chunk(&mut |chunk: &[u8]| !stream.feed(chunk));
+        stream.finish()
+    }
+
+    /// Starts checking a new stream of input.
+    pub fn stream<'a>(&'a self) -> DetectorStream<'a> {
+        DetectorStream {
+            detector: self,
+            state: self.init,
+            matched: false,
+        }
+    }
+}
+
+/// A stream of input that is being checked by a `StreamDetector`.
+#[derive(Clone, Debug)]
+pub struct DetectorStream<'a> {
+    detector: &'a StreamDetector,
+    // The state of the program, or `None` if it has failed.
+    state: Option<usize>,
+    matched: bool,
+}
+
+impl<'a> DetectorStream<'a> {
+    /// Adds some more input to the stream. Returns true if there is a match in the input so far.
+    ///
+    /// A match that depends on what comes after it (for example, because the regex ends with
+    /// `\b` or `$`) will only be reported once that is known, which may be on a later call to
+    /// `feed` or `finish`.
+    pub fn feed(&mut self, input: &[u8]) -> bool {
+        if !self.matched {
+            if let Some(state) = self.state {
+                let prog = &self.detector.prog;
+                self.state = prog.run_until_accept(state, input);
+                self.matched = self.state.map_or(false, |s| prog.accept[s].is_some());
+            }
+        }
+        self.matched
+    }
+
+    /// Signals the end of the input. Returns true if there is a match in the input.
+    pub fn finish(&mut self) -> bool {
+        if let Some(state) = self.state {
+            let prog = &self.detector.prog;
+            self.matched |= prog.accept[state].is_some() || prog.accept_at_eoi[state].is_some();
+        }
+        self.state = None;
+        self.matched
+    }
+
+    /// Returns true if a match has been found so far.
+    pub fn is_match(&self) -> bool {
+        self.matched
+    }
+}
+
+/// A regular expression that searches in UTF-16 text.
+///
+/// The regular expression is compiled into an automaton that consumes UTF-16 code units, so
+/// searching doesn't require transcoding the input into UTF-8. All positions are measured in code
+/// units. Unpaired surrogates in the input are never matched (not even by `.` or negated character
+/// classes).
+///
+/// The code units are read in the platform's native byte order, which is the order they have in
+/// a `[u16]`. Input that was read as bytes in the other order (for example, UTF-16BE on a
+/// little-endian machine) needs to be byte-swapped first.
+///
+/// Word boundaries and multi-line anchors are not supported yet; regular expressions that use them
+/// are rejected with `Error::InvalidEngine`.
+#[derive(Debug)]
+pub struct Utf16Regex {
+    engine: Box<Engine<u8>>,
+}
+
+impl Clone for Utf16Regex {
+    fn clone(&self) -> Utf16Regex {
+        Utf16Regex {
+            engine: self.engine.clone_box(),
+        }
+    }
+}
+
+impl Utf16Regex {
+    /// Creates a new `Utf16Regex` from a regular expression string.
+    pub fn new(re: &str) -> ::Result<Utf16Regex> {
+        Utf16Regex::new_bounded(re, std::usize::MAX)
+    }
+
+    /// Creates a new `Utf16Regex` from a regular expression string, but only if it doesn't
+    /// require too many states.
+    pub fn new_bounded(re: &str, max_states: usize) -> ::Result<Utf16Regex> {
+        let expr = expr::simplify(&try!(Expr::parse(re)));
+        let nfa = Nfa::from_expr(&expr).remove_looks();
+        if nfa.has_look_behind() || nfa.has_look_ahead() {
+            return Err(Error::InvalidEngine(
+                "UTF-16 matching doesn't support word boundaries or multi-line anchors"));
+        }
+
+        let engine = if nfa.is_empty() {
+            Box::new(EmptyEngine) as Box<Engine<u8>>
+        } else if nfa.is_anchored() {
+            let nfa = try!(nfa.utf16_me(max_states));
+            let dfa = try!(nfa.determinize(max_states)).optimize();
+            Box::new(AnchoredEngine::new(dfa.compile())) as Box<Engine<u8>>
+        } else {
+            let f_nfa = try!(try!(nfa.clone().utf16_me(max_states)).anchor_utf16(max_states));
+            let b_nfa = try!(try!(nfa.utf16_me(max_states)).reverse(max_states));
+
+            let f_dfa = try!(f_nfa.determinize(max_states)).optimize();
+            let b_dfa = try!(b_nfa.determinize_longest(max_states)).optimize();
+            let b_dfa = b_dfa.map_ret(|(_, bytes)| bytes);
+            let f_dfa = f_dfa.map_ret(|(look, bytes)| {
+                let b_dfa_state = b_dfa.init_state(look)
+                    .expect("BUG: back dfa must have this init");
+                (b_dfa_state, look, bytes)
+            });
+
+            // Byte prefilters could find matches that don't start on a code unit boundary, so we
+            // don't use any. There's no looking behind, so every search starts in the same state.
+            let init = vec![f_dfa.init_at_start(); Look::num()];
+            let eng = ForwardBackwardEngine::new(f_dfa.compile(), init, WordChars::unicode(),
+                                                 Prefix::Empty,
+                                                 b_dfa.compile());
+            Box::new(eng) as Box<Engine<u8>>
+        };
+
+        Ok(Utf16Regex { engine: engine })
+    }
+
+    /// Returns the range of code unit indices of the first match, if there is a match. The first
+    /// index is inclusive; the second is exclusive.
+    pub fn find(&self, input: &[u16]) -> Option<(usize, usize)> {
+        // The automaton reads code units in the platform's byte order, so it can run directly on
+        // the memory of `input`.
+        let bytes = unsafe {
+            std::slice::from_raw_parts(input.as_ptr() as *const u8, input.len() * 2)
+        };
+        self.engine.find(bytes).map(|(start, end, _)| (start / 2, end / 2))
+    }
+
+    /// Returns true if there is a match anywhere in `input`.
+    pub fn is_match(&self, input: &[u16]) -> bool {
+        self.find(input).is_some()
+    }
+}
+
+/// A regular expression that searches in text with a single-byte encoding, such as Latin-1.
+///
+/// Every byte of the input is a single char, so (unlike `Regex::find_bytes`) there are no invalid
+/// sequences. This is useful for legacy data that isn't valid UTF-8. By default, the input is
+/// interpreted as Latin-1; other encodings can be described by giving the char that each byte
+/// stands for.
+///
+/// Word boundaries and multi-line anchors are not supported yet; regular expressions that use them
+/// are rejected with `Error::InvalidEngine`.
+#[derive(Clone, Debug)]
+pub struct SingleByteRegex {
+    regex: Regex,
+}
+
+impl SingleByteRegex {
+    /// Creates a new `SingleByteRegex` for searching in Latin-1 text.
+    pub fn new(re: &str) -> ::Result<SingleByteRegex> {
+        SingleByteRegex::with_mapping(re, &SingleByteRegex::latin1())
+    }
+
+    /// Creates a new `SingleByteRegex` for searching in binary data, such as file headers or
+    /// network packets.
+    ///
+    /// The regular expression describes bytes rather than chars: `\xNN` (or the char U+00NN)
+    /// stands for the byte `0xNN`, `.` matches any byte (including `\n`), and chars above U+00FF
+    /// never match. Classes such as `\w` and `\s` keep their usual meaning on chars, and so they
+    /// also match some bytes above `0x7F`; use explicit ranges for exact control.
+    ///
+    /// ```rust
+    /// use regex_dfa::SingleByteRegex;
+    ///
+    /// let png = SingleByteRegex::new_binary(r"^\x89PNG\r\n\x1a\n").unwrap();
+    /// assert!(png.is_match(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR"));
+    /// ```
+    pub fn new_binary(re: &str) -> ::Result<SingleByteRegex> {
+        SingleByteRegex::new_binary_bounded(re, std::usize::MAX)
+    }
+
+    /// Like `new_binary`, but fails if the regular expression would require too many states.
+    pub fn new_binary_bounded(re: &str, max_states: usize) -> ::Result<SingleByteRegex> {
+        // The `s` flag makes `.` match the byte `\n`. The user's flags come later, so they can
+        // still turn it off.
+        let re = format!("(?s){}", re);
+        SingleByteRegex::with_mapping_bounded(&re, &SingleByteRegex::latin1(), max_states)
+    }
+
+    // The mapping in which every byte stands for the char with the same value.
+    fn latin1() -> [char; 256] {
+        let mut ret = ['\0'; 256];
+        for (b, c) in ret.iter_mut().enumerate() {
+            *c = b as u8 as char;
+        }
+        ret
+    }
+
+    /// Creates a new `SingleByteRegex` for searching in text where the byte `b` stands for the
+    /// char `mapping[b]`.
+    pub fn with_mapping(re: &str, mapping: &[char; 256]) -> ::Result<SingleByteRegex> {
+        SingleByteRegex::with_mapping_bounded(re, mapping, std::usize::MAX)
+    }
+
+    /// Like `with_mapping`, but fails if the regular expression would require too many states.
+    pub fn with_mapping_bounded(re: &str, mapping: &[char; 256], max_states: usize)
+    -> ::Result<SingleByteRegex> {
+        let expr = expr::simplify(&try!(Expr::parse(re)));
+        let nfa = Nfa::from_expr(&expr).remove_looks();
+        if nfa.has_look_behind() || nfa.has_look_ahead() {
+            return Err(Error::InvalidEngine(
+                "single-byte matching doesn't support word boundaries or multi-line anchors"));
+        }
+
+        let mut nfa = nfa.single_byte_me(mapping);
+        nfa.reduce();
+        let prefilter = PrefilterOptions::default();
+        let stages = &mut Stages::none();
+        let regex =
+            try!(Regex::from_byte_nfa(nfa, max_states, &prefilter, false, Prefix::Empty, stages));
+        Ok(SingleByteRegex { regex: regex })
+    }
+
+    /// Returns the index range of the first match, if there is a match. The first index is
+    /// inclusive; the second is exclusive.
+    pub fn find(&self, input: &[u8]) -> Option<(usize, usize)> {
+        self.regex.find_bytes(input)
+    }
+
+    /// Returns true if there is a match anywhere in `input`.
+    pub fn is_match(&self, input: &[u8]) -> bool {
+        self.find(input).is_some()
+    }
+}
+
+#[cfg(feature = "memmap")]
+impl Regex {
+    /// Returns an iterator over the non-overlapping matches in the file at `path`.
+    ///
+    /// The file is memory-mapped rather than read, so this is suitable for scanning very large
+    /// files: the matches are found one at a time, as the iterator is advanced. The file doesn't
+    /// need to be valid UTF-8 (see `find_bytes`), so the returned offsets are byte offsets that
+    /// need not lie on `char` boundaries of any particular encoding. As with `find_iter`, each
+    /// search continues from the end of the previous match and assertions like `\b` look at the
+    /// bytes before it, but empty matches are skipped (see `FileMatches::empty_matches`).
+    pub fn find_in_file<'r, P: AsRef<Path>>(&'r self, path: P) -> io::Result<FileMatches<'r>> {
+        let map = try!(Mmap::open_path(path, Protection::Read));
+        let mut state = MatchesState::new();
+        state.empty = EmptyMatches::Never;
+        Ok(FileMatches {
+            re: self,
+            map: map,
+            state: state,
+        })
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl Regex {
+    /// Like `is_match_batch`, but checks the inputs in parallel.
+    ///
+    /// There is some overhead to splitting up the work, so this is only worth it for big
+    /// batches.
+    pub fn par_is_match_batch<S: AsRef<str> + Sync>(&self, inputs: &[S]) -> Vec<bool> {
+        let detector = self.engine.for_detection();
+        inputs.par_iter().map(|input| detector.is_match(input.as_ref().as_bytes())).collect()
+    }
+}
+
+// A `Regex` is serialized as the bytes from `to_bytes`, so (like those bytes) it can only be
+// deserialized by the same version of this crate.
+#[cfg(feature = "serde")]
+impl Serialize for Regex {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> Result<(), S::Error> {
+        serializer.serialize_bytes(&self.to_bytes())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Deserialize for Regex {
+    fn deserialize<D: Deserializer>(deserializer: &mut D) -> Result<Regex, D::Error> {
+        let bytes: ByteBuf = try!(Deserialize::deserialize(deserializer));
+        Regex::from_bytes(&bytes).map_err(|e| de::Error::custom(format!("{}", e)))
+    }
+}
+
+/// How many times each state of a `Regex`'s programs was executed. See `Regex::profile`.
+#[cfg(feature = "profile")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    /// For each program (named as in `Disassembly`), the number of bytes that were consumed in
+    /// each of its states.
+    pub programs: Vec<(&'static str, Vec<u64>)>,
+}
+
+#[cfg(feature = "profile")]
+impl Profile {
+    /// Returns the `n` states that consumed the most bytes, as triples of a program name, a state
+    /// and a count, with the busiest state first. States that were never executed are omitted.
+    pub fn hottest(&self, n: usize) -> Vec<(&'static str, usize, u64)> {
+        let mut ret: Vec<_> = self.programs.iter()
+            .flat_map(|&(name, ref counts)| {
+                counts.iter().enumerate().map(move |(st, &c)| (name, st, c))
+            })
+            .filter(|x| x.2 > 0)
+            .collect();
+        // The sort is stable, so ties stay in the order of the programs and states.
+        ret.sort_by(|a, b| b.2.cmp(&a.2));
+        ret.truncate(n);
+        ret
+    }
+}
+
+#[cfg(feature = "profile")]
+impl Display for Profile {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (name, st, count) in self.hottest(std::usize::MAX) {
+            try!(writeln!(f, "{} program, state {}: {}", name, st, count));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "profile")]
+impl Regex {
+    /// Runs `find` on `s`, counting how many bytes are consumed in each state of the compiled
+    /// programs.
+    ///
+    /// This is meant for finding the parts of a regular expression that the matcher spends its
+    /// time in (compare the states with `disassemble`). The counts are gathered separately from
+    /// the normal matching code, which stays exactly as fast as it was; in exchange, profiling is
+    /// much slower than `find`.
+    pub fn profile(&self, s: &str) -> Profile {
+        let mut ret = Profile {
+            programs: self.disassemble().programs.into_iter()
+                .map(|(name, insts)| (name, vec![0; insts.len()]))
+                .collect(),
+        };
+        for ev in self.trace(s) {
+            if let TraceEvent::Step { program, state, .. } = ev {
+                if let Some(p) = ret.programs.iter_mut().find(|p| p.0 == program) {
+                    p.1[state] += 1;
+                }
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nfa::ExploreOrder;
+    use runner::forward_backward::PrefilterOptions;
+    use std::collections::HashSet;
+    use super::*;
+
+    #[test]
+    fn utf16() {
+        fn utf16_pos(s: &str, pos: usize) -> usize {
+            s[..pos].encode_utf16().count()
+        }
+
+        let res = ["a", "[a-z]+", "b.d", "^ab", "c$", "\\d+", "x|\u{1F600}+", "[^a]", "(?i)é+"];
+        let inputs = ["abcd", "aaa", "xyz", "b\u{1F600}d", "\u{1F600}\u{1F600}y", "ÉéX", "",
+                      "12c"];
+        for re in &res {
+            let re8 = Regex::new(re).unwrap();
+            let re16 = Utf16Regex::new(re).unwrap();
+            for input in &inputs {
+                let units: Vec<u16> = input.encode_utf16().collect();
+                let expected = re8.find(input)
+                    .map(|(s, e)| (utf16_pos(input, s), utf16_pos(input, e)));
+                assert_eq!(re16.find(&units), expected);
+            }
+        }
+
+        // On a little-endian machine, the bytes of these code units contain "a\0", but not at a
+        // code unit boundary.
+        let re = Utf16Regex::new("a").unwrap();
+        assert_eq!(re.find(&[0x6100, 0x0061]), Some((1, 2)));
+        assert_eq!(re.find(&[0x6100, 0x0000]), None);
+
+        // Unpaired surrogates are never matched.
+        let re = Utf16Regex::new(".").unwrap();
+        assert_eq!(re.find(&[0xD800, 0x0061]), Some((1, 2)));
+        assert_eq!(re.find(&[0xDC00]), None);
+
+        assert!(Utf16Regex::new("\\bfoo").is_err());
+        assert!(Utf16Regex::new("(?m)foo$").is_err());
+    }
+
+    #[test]
+    fn single_byte() {
+        let re = SingleByteRegex::new("caf[ée]+").unwrap();
+        assert_eq!(re.find(b"un caf\xe9!"), Some((3, 7)));
+        assert_eq!(re.find(b"un cafe\xe9\xe9"), Some((3, 9)));
+        // The UTF-8 encoding of 'é' doesn't mean 'é' in Latin-1.
+        assert_eq!(re.find("un café".as_bytes()), None);
+
+        let re = SingleByteRegex::new("^.+$").unwrap();
+        assert_eq!(re.find(b"\xff\x80\xc3"), Some((0, 3)));
+
+        // An encoding in which every byte from 0x80 up is the Greek letter alpha.
+        let mut mapping = ['\0'; 256];
+        for (b, c) in mapping.iter_mut().enumerate() {
+            *c = if b < 0x80 { b as u8 as char } else { 'α' };
+        }
+        let re = SingleByteRegex::with_mapping("xα+y", &mapping).unwrap();
+        assert_eq!(re.find(b"ax\x80\xfe\xaay"), Some((1, 6)));
+        assert_eq!(re.find(b"axy"), None);
+
+        assert!(SingleByteRegex::new("\\bfoo").is_err());
+    }
+
+    #[test]
+    fn single_byte_binary() {
+        // A TLS handshake record: content type 0x16, then version 3.x.
+        let re = SingleByteRegex::new_binary(r"\x16\x03[\x00-\x04]..\x01").unwrap();
+        assert_eq!(re.find(b"\xff\x16\x03\x01\x02\x00\x01\x00"), Some((1, 7)));
+        assert_eq!(re.find(b"\x16\x03\x05\x02\x00\x01"), None);
+        // `.` matches every byte, including '\n'.
+        assert_eq!(re.find(b"\x16\x03\x03\n\n\x01"), Some((0, 6)));
+
+        let re = SingleByteRegex::new_binary(r"^\x7fELF[\x01\x02]").unwrap();
+        assert!(re.is_match(b"\x7fELF\x02\x01\x01"));
+        assert!(!re.is_match(b"\x00\x7fELF\x02"));
+
+        let re = SingleByteRegex::new_binary(r"(?-s)a.b").unwrap();
+        assert!(!re.is_match(b"a\nb"));
+        assert!(re.is_match(b"a\xffb"));
+    }
+
+    #[test]
+    fn match_spans_with_cache() {
+        let re = Regex::new("[0-9]+").unwrap();
+        let mut cache = MatchCache::new();
+        for s in &["1 22 333 4444", "", "a1b2", "55"] {
+            let expected = re.match_spans(s);
+            assert_eq!(re.match_spans_with_cache(s, &mut cache), &expected[..]);
+        }
+
+        re.match_spans_with_cache("1 2 3 4 5 6 7 8", &mut cache);
+        let cap = cache.spans.capacity();
+        assert_eq!(re.match_spans_with_cache("9 8 7", &mut cache), &[0..1, 2..3, 4..5]);
+        assert_eq!(cache.spans.capacity(), cap);
+    }
+
+    #[test]
+    fn prefilter_options() {
+        let re = Regex::new("[0-9]+").unwrap();
+        assert!(re.disassemble().prefilter.is_some());
+
+        let digits: Vec<u8> = (b'0'..(b'9' + 1)).collect();
+        let prefilter = PrefilterOptions { common_bytes: digits, ..Default::default() };
+        let opts = RegexOptions { prefilter: prefilter, ..Default::default() };
+        let re = Regex::new_with_options("[0-9]+", &opts).unwrap();
+        assert!(re.disassemble().prefilter.is_none());
+        assert_eq!(re.find("ab123x"), Some((2, 5)));
+        // Every match of "[0-9]+x" has a digit just before an 'x', so it can search for the 'x'.
+        let re = Regex::new_with_options("[0-9]+x", &opts).unwrap();
+        assert_eq!(re.disassemble().prefilter, Some("byte 'x' at offset 1".to_owned()));
+        assert_eq!(re.find("ab123x"), Some((2, 6)));
+
+        let prefilter = PrefilterOptions { max_byte_set_size: 4, ..Default::default() };
+        let opts = RegexOptions { prefilter: prefilter, ..Default::default() };
+        let re = Regex::new_with_options("[0-9]+", &opts).unwrap();
+        assert!(re.disassemble().prefilter.is_none());
+        let re = Regex::new_with_options("[0-2]+", &opts).unwrap();
+        assert!(re.disassemble().prefilter.is_some());
+    }
+
+    #[test]
+    fn byte_pair_prefilter() {
+        let re = Regex::new("[a-c]qu+x").unwrap();
+        assert_eq!(re.disassemble().prefilter, Some("bytes \"qu\" at offset 1".to_owned()));
+        assert_eq!(re.find("q qu aq aqx bquux"), Some((12, 17)));
+        assert_eq!(re.find("uq cqu"), None);
+
+        let prefilter = PrefilterOptions { byte_pairs: false, ..Default::default() };
+        let opts = RegexOptions { prefilter: prefilter, ..Default::default() };
+        let re = Regex::new_with_options("[a-c]qu+x", &opts).unwrap();
+        assert_eq!(re.disassemble().prefilter, Some("byte 'q' at offset 1".to_owned()));
+    }
+
+    #[test]
+    fn simplify_option() {
+        let no_simplify = RegexOptions { simplify: false, ..Default::default() };
+        for re in &["foo(bar|baz|bat)", "ab|a|ac", "(a|(b|c))|d", "x(?:y(?:z))+"] {
+            let simple = Regex::new(re).unwrap();
+            let not_simple = Regex::new_with_options(re, &no_simplify).unwrap();
+            for input in &["foobat", "fooba", "abc", "a", "ac", "d", "xyzyz", "xy"] {
+                assert_eq!(simple.find(input), not_simple.find(input));
+            }
+        }
+    }
+
+    #[test]
+    fn new_adaptive() {
+        let policy = AdaptivePolicy {
+            max_states: 50,
+            max_class_ranges: None,
+            max_repeat: Some(10),
+        };
+        assert!(Regex::new_bounded("[a-z]{1,200}x", 50).is_err());
+        let (re, applied) = Regex::new_adaptive("[a-z]{1,200}x", &policy).unwrap();
+        assert_eq!(applied, vec![Degradation::CappedRepetitions(1)]);
+        assert_eq!(re.find("--abcx--"), Some((2, 6)));
+
+        // Nothing is simplified if it isn't necessary.
+        let (re, applied) = Regex::new_adaptive("[a-z]{1,3}x", &policy).unwrap();
+        assert!(applied.is_empty());
+        assert_eq!(re.find("abcdx"), Some((1, 5)));
+
+        let policy = AdaptivePolicy { max_repeat: None, ..policy };
+        assert!(Regex::new_adaptive("[a-z]{1,200}x", &policy).is_err());
+    }
+
+    #[test]
+    fn find_bytes() {
+        let re = Regex::new("a.c").unwrap();
+        assert_eq!(re.find_bytes(b"xxabc"), Some((2, 5)));
+        // Invalid UTF-8 doesn't match anything, not even `.`.
+        assert_eq!(re.find_bytes(b"a\xffc"), None);
+        assert_eq!(re.find_bytes(b"a\xffcabc"), Some((3, 6)));
+    }
+
+    #[cfg(feature = "memmap")]
+    #[test]
+    fn find_in_file() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::io::Write;
+
+        let path = env::temp_dir().join("regex_dfa_find_in_file.txt");
+        File::create(&path).unwrap().write_all(b"ab cab\xff ab\nab").unwrap();
+        let re = Regex::new(r"\bab").unwrap();
+        // The search after each match still sees the byte before it, so "cab" isn't a match.
+        let matches: Vec<_> = re.find_in_file(&path).unwrap().collect();
+        assert_eq!(matches, vec![(0, 2), (8, 10), (11, 13)]);
+
+        let re = Regex::new("x*").unwrap();
+        assert_eq!(re.find_in_file(&path).unwrap().next(), None);
+        assert_eq!(re.find_in_file(&path).unwrap().empty_matches(EmptyMatches::All).count(), 14);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn find_bytes_lossy() {
+        let opts = RegexOptions { invalid_utf8: InvalidUtf8::Replace, ..Default::default() };
+        let re = Regex::new_with_options("a.c", &opts).unwrap();
+        assert_eq!(re.find_bytes(b"a\xffc"), Some((0, 3)));
+        assert_eq!(re.find_bytes(b"a\x80c"), Some((0, 3)));
+        // A truncated sequence is a single replacement char.
+        assert_eq!(re.find_bytes(b"a\xe2\x82c"), Some((0, 4)));
+        assert_eq!(re.find_bytes(b"a\xf0\x9f\x98c"), Some((0, 5)));
+        // ...but an invalid byte after a leading byte isn't part of it.
+        assert_eq!(re.find_bytes(b"a\xe0\x80c"), None);
+        // Valid UTF-8 is treated as usual.
+        assert_eq!(re.find_bytes("aéc".as_bytes()), Some((0, 4)));
+        assert_eq!(re.find_bytes("a\u{FFFD}c".as_bytes()), Some((0, 5)));
+
+        let re = Regex::new_with_options("x[^a]", &opts).unwrap();
+        assert_eq!(re.find_bytes(b"x\xc3"), Some((0, 2)));
+        assert_eq!(re.find_bytes(b"x\xc3y"), Some((0, 2)));
+        assert_eq!(re.find_bytes("xé".as_bytes()), Some((0, 3)));
+
+        let re = Regex::new_with_options("^x[^a]$", &opts).unwrap();
+        assert_eq!(re.find_bytes(b"x\xc3\xa9"), Some((0, 3)));
+        assert_eq!(re.find_bytes(b"x\xc3\x41"), None);
+
+        let re = Regex::new_with_options("\\w+", &opts).unwrap();
+        assert_eq!(re.find_bytes(b"\xffab\xff"), Some((1, 3)));
+    }
+
+    #[test]
+    fn match_spans() {
+        let re = Regex::new("[0-9]+").unwrap();
+        assert_eq!(re.match_spans("a12b3"), vec![1..3, 4..5]);
+        assert_eq!(re.match_spans("abc"), vec![]);
+
+        let re = Regex::new("é|ab").unwrap();
+        let s = "éabxéé";
+        assert_eq!(re.match_spans(s), vec![0..2, 2..4, 5..7, 7..9]);
+        assert_eq!(re.merged_match_spans(s), vec![0..4, 5..9]);
+        for span in re.match_spans(s) {
+            assert!(s.is_char_boundary(span.start) && s.is_char_boundary(span.end));
+        }
+
+        // Empty matches are skipped, even when they fall between multi-byte chars.
+        let re = Regex::new("a*").unwrap();
+        assert_eq!(re.match_spans("éaaéa"), vec![2..4, 6..7]);
+    }
+
+    #[test]
+    fn required_prefixes() {
+        let lits = |re: &str| Regex::new(re).unwrap().required_prefixes().literals.clone();
+        let abc = lits("abc[0-9]");
+        assert!(!abc.is_empty());
+        assert!(abc.iter().all(|lit| lit.starts_with(b"abc")));
+        assert_eq!(lits("^foo|^bar"), vec![b"bar".to_vec(), b"foo".to_vec()]);
+        assert!(lits("a*").is_empty());
+        assert_eq!(lits(r"foo"), vec![b"foo".to_vec()]);
+
+        let re = Regex::new("(foo|bar)[0-9]+").unwrap();
+        let prefs = re.required_prefixes();
+        assert!(!prefs.is_empty());
+        for lit in &prefs.literals {
+            assert!(lit.starts_with(b"foo") || lit.starts_with(b"bar"));
+        }
+        assert!(prefs.is_candidate(b"xxbar12"));
+        assert!(!prefs.is_candidate(b"baz12"));
+    }
+
+    #[test]
+    fn start_bytes() {
+        let bytes = |re: &str| Regex::new(re).unwrap().start_bytes().bytes();
+        assert_eq!(bytes("abc"), b"a".to_vec());
+        assert_eq!(bytes("^(foo|bar)"), b"bf".to_vec());
+        assert_eq!(bytes("[a-c]x|éy|zz"), vec![b'a', b'b', b'c', b'z', 0xc3]);
+        assert!(Regex::new("a*").unwrap().start_bytes().is_full());
+        assert!(Regex::new(r"\bfoo").unwrap().start_bytes().is_full());
+
+        // The start bytes are enough to make a prefilter, even when the prefixes aren't.
+        let re = Regex::new("[aé]x").unwrap();
+        assert_eq!(re.find("ééxax"), Some((2, 5)));
+        assert_eq!(re.find("ééaax"), Some((5, 7)));
+    }
+
+    #[test]
+    fn find_with_context() {
+        let re = Regex::new("[0-9]+").unwrap();
+        let ctx = re.find_with_context("abcdef123ghijk", 2, 3).unwrap();
+        assert_eq!(ctx.start, 6);
+        assert_eq!(ctx.end, 9);
+        assert_eq!((ctx.before, ctx.matched, ctx.after), ("ef", "123", "ghi"));
+
+        // The context is shorter near the ends of the input.
+        let ctx = re.find_with_context("a1", 5, 5).unwrap();
+        assert_eq!((ctx.before, ctx.matched, ctx.after), ("a", "1", ""));
+
+        // Multi-byte chars are never split.
+        let ctx = re.find_with_context("éèê42üû", 2, 1).unwrap();
+        assert_eq!((ctx.before, ctx.matched, ctx.after), ("èê", "42", "ü"));
+
+        assert_eq!(re.find_with_context("abc", 1, 1), None);
+    }
+
+    #[test]
+    fn grep_lines() {
+        let re = Regex::new("o+").unwrap();
+        let text = "foo\nbar\nbaz\nboo\n\nzoo";
+        assert_eq!(re.grep_lines(text).collect::<Vec<_>>(),
+                   vec![(1, "foo"), (4, "boo"), (6, "zoo")]);
+
+        let re = Regex::new("(?m)^b").unwrap();
+        assert_eq!(re.grep_lines(text).collect::<Vec<_>>(),
+                   vec![(2, "bar"), (3, "baz"), (4, "boo")]);
+
+        let re = Regex::new("x").unwrap();
+        assert_eq!(re.grep_lines(text).next(), None);
+
+        // Without the m flag, `^` only matches at the start of the text, not of each line.
+        let re = Regex::new("^b").unwrap();
+        assert_eq!(re.grep_lines("boo\nbar").collect::<Vec<_>>(), vec![(1, "boo")]);
+
+        // As with `str::lines`, a final newline doesn't start another line.
+        let re = Regex::new("x*").unwrap();
+        assert_eq!(re.grep_lines("a\nb\n").collect::<Vec<_>>(), vec![(1, "a"), (2, "b")]);
+        assert_eq!(re.grep_lines("a\n\nb").collect::<Vec<_>>(),
+                   vec![(1, "a"), (2, ""), (3, "b")]);
+        assert_eq!(re.grep_lines("").next(), None);
+        let re = Regex::new("$").unwrap();
+        assert_eq!(re.grep_lines("a\n").next(), None);
+        assert_eq!(re.grep_lines("a\nb").collect::<Vec<_>>(), vec![(2, "b")]);
+    }
+
+    #[test]
+    fn find_records() {
+        let re = Regex::new("^[a-z]+$").unwrap();
+        let text = b"abc\x1e12\x1ex y\x1e\x1ez";
+        assert_eq!(re.find_records(text, 0x1e).collect::<Vec<_>>(),
+                   vec![(0, 0, 3), (4, 12, 13)]);
+
+        // Matches can't cross a separator, even if the regex could match the separator itself.
+        let re = Regex::new("b.c").unwrap();
+        assert_eq!(re.find_records(b"ab\x1ec\x1ebxc", 0x1e).collect::<Vec<_>>(),
+                   vec![(2, 5, 8)]);
+
+        // Empty records at the beginning and the end.
+        let re = Regex::new("^$").unwrap();
+        assert_eq!(re.find_records(b"\x1ea\x1e", 0x1e).collect::<Vec<_>>(),
+                   vec![(0, 0, 0), (2, 3, 3)]);
+        assert_eq!(re.find_records(b"", 0x1e).collect::<Vec<_>>(), vec![(0, 0, 0)]);
+    }
+
+    #[test]
+    fn line_start() {
+        let re = Regex::new("(?m)^b[a-z]+").unwrap();
+        assert_eq!(re.disassemble().programs[1].0, "after newline");
+        assert_eq!(re.find("bar"), Some((0, 3)));
+        assert_eq!(re.find("foo\nabc\nbaz"), Some((8, 11)));
+        assert_eq!(re.find("foo bar\n"), None);
+
+        let re = Regex::new("(?m)^$").unwrap();
+        assert_eq!(re.find("a\n\nb"), Some((2, 2)));
+        let re = Regex::new("(?m)^x$").unwrap();
+        assert_eq!(re.find("xy\nx\n"), Some((3, 4)));
+        let re = Regex::new("(?m)^x\\b").unwrap();
+        assert_eq!(re.find("xy\nx y"), Some((3, 4)));
+        let re = Regex::new("(?m)^é|^a").unwrap();
+        assert_eq!(re.find("b\né"), Some((2, 4)));
+    }
+
+    #[test]
+    fn look_behind() {
+        let re = Regex::new("\\bfoo").unwrap();
+        assert_eq!(re.disassemble().programs[0].0, "anchored");
+        assert!(re.disassemble().prefilter.is_some());
+        assert_eq!(re.find("foo"), Some((0, 3)));
+        assert_eq!(re.find("xfoo foo"), Some((5, 8)));
+        assert_eq!(re.find("éfoo\nfoo"), Some((6, 9)));
+        assert_eq!(re.find("xfoo"), None);
+
+        let re = Regex::new("\\Bfoo\\b").unwrap();
+        assert_eq!(re.find("foo foox xfoox xfoo"), Some((16, 19)));
+
+        let re = Regex::new("(?m)(^|x)abc").unwrap();
+        assert_eq!(re.find("yabc\nabc"), Some((5, 8)));
+        assert_eq!(re.find("yabcxabc"), Some((4, 8)));
+    }
+
+    #[test]
+    fn for_detection() {
+        for re in &["a+b", "^a+", "(?m)^x", "\\bfoo", "[0-9]+x", "a|b|c", "$"] {
+            let regex = Regex::new(re).unwrap();
+            let detector = regex.for_detection();
+            for s in &["", "aaab", "bbb", "y\nx", "afoo foo", "123x", "aaa"] {
+                assert_eq!(regex.is_match(s), regex.find(s).is_some());
+                assert_eq!(detector.is_match(s), regex.is_match(s));
+            }
+        }
+        let detector = Regex::new("a").unwrap().for_detection();
+        assert!(detector.is_match_bytes(b"\xffa"));
+        assert!(!detector.is_match_bytes(b"\xff"));
+    }
+
+    #[test]
+    fn to_bytes_round_trip() {
+        for re in &["a+b", "^a+", "(?m)^x", "\\bfoo", "[0-9]+x", "a|b|c", "$"] {
+            let regex = Regex::new(re).unwrap();
+            let bytes = regex.to_bytes();
+            let back = Regex::from_bytes(&bytes).unwrap();
+            assert_eq!(back.disassemble(), regex.disassemble());
+            assert_eq!(back.required_prefixes(), regex.required_prefixes());
+            for s in &["", "aaab", "bbb", "y\nx", "afoo foo", "123x", "aaa"] {
+                assert_eq!(back.find(s), regex.find(s));
+            }
+
+            assert!(Regex::from_bytes(&bytes[..(bytes.len() - 1)]).is_err());
+        }
+        assert!(Regex::from_bytes(b"regex_dfa").is_err());
+    }
+
+    #[test]
+    fn leftmost_first() {
+        let find = |re: &str, s: &str| Regex::new(re).unwrap().find(s);
+        assert_eq!(find("a|ab", "xab"), Some((1, 2)));
+        assert_eq!(find("ab|a", "xab"), Some((1, 3)));
+        assert_eq!(find("sam|samwise", "samwise"), Some((0, 3)));
+        assert_eq!(find("samwise|sam", "samwise"), Some((0, 7)));
+        assert_eq!(find("(a|ab)(c|bcd)", "abcd"), Some((0, 4)));
+        assert_eq!(find("a*?b|a", "aab"), Some((0, 3)));
+        assert_eq!(find("x+?", "xxx"), Some((0, 1)));
+        assert_eq!(find("\\bfoo|foobar", "foobar"), Some((0, 3)));
+    }
+
+    #[test]
+    fn stream_detector_word_boundaries() {
+        let det = StreamDetector::new(r"\bfoo\b").unwrap();
+        let check = |chunks: &[&[u8]]| {
+            let mut stream = det.stream();
+            for chunk in chunks {
+                stream.feed(chunk);
+            }
+            stream.finish()
+        };
+        assert!(check(&[b"xx fo", b"o bar"]));
+        assert!(check(&[b"xx f", b"", b"o", b"o"]));
+        assert!(check(&[b"foo"]));
+        assert!(!check(&[b"xx fo", b"obar"]));
+        assert!(!check(&[b"x", b"foo "]));
+        assert!(!check(&[b"caf\xc3", b"\xa9foo"]));
+        assert!(!check(&[b"caf\xc3\xa9 ", b"foo\xc3", b"\xa9"]));
+        assert!(check(&[b"\xc3\xa9 f", b"oo\xc2\xa0"]));
+
+        // The match isn't reported until we know what comes after it.
+        let mut stream = det.stream();
+        assert!(!stream.feed(b"a foo"));
+        assert!(stream.feed(b"."));
+        let mut stream = det.stream();
+        assert!(!stream.feed(b"a foo"));
+        assert!(stream.finish());
+    }
+
+    #[test]
+    fn stream_detector() {
+        let check = |re: &str, chunks: &[&str]| {
+            let det = StreamDetector::new(re).unwrap();
+            let mut stream = det.stream();
+            for chunk in chunks {
+                stream.feed(chunk.as_bytes());
+            }
+            let ret = stream.finish();
+            assert_eq!(det.is_match_in(chunks), ret);
+            ret
+        };
+        assert!(check("^ab", &["a", "bc"]));
+        assert!(!check("^ab", &["c", "ab"]));
+        assert!(check("(?m)^ab$", &["x\na", "b\n"]));
+        assert!(!check("(?m)^ab$", &["xa", "b\n"]));
+        assert!(check("a+b", &["aa", "a", "b"]));
+        assert!(check("a$", &["ba"]));
+        assert!(!check("a$", &["a", "b"]));
+        assert!(check("", &[]));
+        assert!(!check("abc", &["ab", "d", "c"]));
+    }
+
+    #[test]
+    fn approximate() {
+        let re = Regex::new_approximate("^hello$", 1).unwrap();
+        for s in &["hello", "hallo", "helo", "helloo", "jello", "ello"] {
+            assert!(re.is_match(s), "should match {:?}", s);
+        }
+        for s in &["", "help!", "hel", "ehllo"] {
+            assert!(!re.is_match(s), "shouldn't match {:?}", s);
+        }
+
+        // Unanchored regexes find approximate matches inside longer strings.
+        let re = Regex::new_approximate("needle", 1).unwrap();
+        assert!(re.is_match("a haystack with a neddle in it"));
+        assert!(!re.is_match("a haystack with a nodle in it"));
+
+        let re = Regex::new_approximate("^[0-9]{3}$", 1).unwrap();
+        assert!(re.is_match("12"));
+        assert!(re.is_match("12a"));
+        assert!(!re.is_match("1a2b"));
+
+        assert!(Regex::new_approximate("\\bfoo", 1).is_err());
+        assert!(Regex::new_approximate_bounded("^abcdefgh$", 3, 10).is_err());
+    }
+
+    #[test]
+    fn mismatches() {
+        let re = Regex::new_with_mismatches("ACGT", 1).unwrap();
+        for s in &["ACGT", "TCGT", "ACTT", "AC-T", "xxACGAxx"] {
+            assert!(re.is_match(s), "should match {:?}", s);
+        }
+        for s in &["", "ACG", "AGGA", "TTTT"] {
+            assert!(!re.is_match(s), "shouldn't match {:?}", s);
+        }
+        assert_eq!(re.find("GGACCTGG"), Some((2, 6)));
+
+        // Wildcard positions never count as mismatches.
+        let re = Regex::new_with_mismatches("A.[CG]{2}", 0).unwrap();
+        assert!(re.is_match("AxCG"));
+        assert!(!re.is_match("AxCT"));
+        assert!(Regex::new_with_mismatches("A.[CG]{2}", 1).unwrap().is_match("AxCT"));
+
+        // Mismatches are counted in chars, not bytes.
+        assert!(Regex::new_with_mismatches("café", 1).unwrap().is_match("cafe"));
+
+        assert!(Regex::new_with_mismatches("a+", 1).is_err());
+        assert!(Regex::new_with_mismatches("a|bc", 1).is_err());
+        assert!(Regex::new_with_mismatches("^abc", 1).is_err());
+    }
+
+    #[test]
+    fn time_limit() {
+        let opts = RegexOptions { time_limit: Some(Duration::from_secs(0)), ..Default::default() };
+        match Regex::new_with_options("a+b", &opts) {
+            Err(Error::TimedOut(report)) => {
+                assert_eq!(report.stage, Stage::Determinize);
+                assert_eq!(report.steps, 0);
+            },
+            x => panic!("expected a timeout, got {:?}", x),
+        }
+
+        let hour = Some(Duration::from_secs(3600));
+        let opts = RegexOptions { time_limit: hour, ..Default::default() };
+        assert_eq!(Regex::new_with_options("a+b", &opts).unwrap().find("xaab"), Some((1, 4)));
+    }
+
+    #[test]
+    fn determinize_options() {
+        // The exploration order changes how the states are numbered, but not what matches.
+        let determinize = DeterminizeOptions {
+            order: ExploreOrder::BreadthFirst,
+            accepting_first: true,
+            ..Default::default()
+        };
+        let opts = RegexOptions { determinize: determinize, ..Default::default() };
+        for re in &["a+b", "(?:ab|cd)*e", "^x[0-9]+", "\\bfoo\\b"] {
+            let re0 = Regex::new(re).unwrap();
+            let re1 = Regex::new_with_options(re, &opts).unwrap();
+            for text in &["xaab", "abcde", "x123", "a foo!", "foobar"] {
+                assert_eq!(re0.find(text), re1.find(text), "{} on {}", re, text);
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize() {
+        use serde_json;
+
+        for re in &["a+b", "^abc$", "\\bfoo", "x*$"] {
+            let regex = Regex::new(re).unwrap();
+            let json = serde_json::to_string(&regex).unwrap();
+            let back: Regex = serde_json::from_str(&json).unwrap();
+            assert_eq!(back.to_bytes(), regex.to_bytes());
+            assert_eq!(back.find("xaab foo abc"), regex.find("xaab foo abc"));
+        }
+        assert!(serde_json::from_str::<Regex>("[1,2,3]").is_err());
+    }
+
+    #[test]
+    fn word_chars() {
+        // With everything but commas as word chars, `\b` is at the edges of the fields.
+        let words = CharSet::from_ranges(&[(',', ',')]).negated();
+        let opts = RegexOptions { word_chars: Some(words), ..Default::default() };
+        let cases: &[(&str, &str, &[(usize, usize)], &[(usize, usize)])] = &[
+            // (regex, text, default matches, matches with the custom word chars)
+            ("\\b,", "x ,y", &[], &[(2, 3)]),
+            ("\\by\\b", "x y,z", &[(2, 3)], &[]),
+            ("y\\b", "y z", &[(0, 1)], &[]),
+            ("^x\\b", "x y", &[(0, 1)], &[]),
+            ("\\bz$", "y z", &[(2, 3)], &[]),
+            ("\\b[a-z ]+\\b", "ab cd,ef", &[(0, 5), (6, 8)], &[(0, 5), (6, 8)]),
+            ("\\Bb", "a b,b", &[], &[(2, 3)]),
+            // `\n` is never a word char.
+            ("\\bx", "\nx", &[(1, 2)], &[(1, 2)]),
+        ];
+        for &(re, text, default, custom) in cases {
+            let re0 = Regex::new(re).unwrap();
+            let re1 = Regex::new_with_options(re, &opts).unwrap();
+            let re2 = Regex::from_bytes(&re1.to_bytes()).unwrap();
+            assert_eq!(re0.find_iter(text).collect::<Vec<_>>(), default, "{} on {:?}", re, text);
+            assert_eq!(re1.find_iter(text).collect::<Vec<_>>(), custom, "{} on {:?}", re, text);
+            assert_eq!(re2.find_iter(text).collect::<Vec<_>>(), custom, "{} on {:?}", re, text);
+        }
+    }
+
+    #[test]
+    fn compiler_stages() {
+        // Unanchored regexes need a forward and a backward DFA.
+        let mut stages = Vec::new();
+        let re = {
+            let mut compiler = Compiler::new(RegexOptions::default())
+                .on_stage(|stage, _| stages.push(stage));
+            compiler.compile("a+b").unwrap()
+        };
+        assert_eq!(re.find("xaab"), Some((1, 4)));
+        assert_eq!(&stages[..5],
+                   &[Stage::Parse, Stage::Simplify, Stage::Nfa, Stage::RemovePredicates,
+                     Stage::Bytes]);
+        assert_eq!(stages.iter().filter(|&&s| s == Stage::Determinize).count(), 2);
+        assert_eq!(stages.last(), Some(&Stage::Program));
+
+        let mut compiler = Compiler::new(RegexOptions::default()).stop_after(Stage::Determinize);
+        match compiler.compile("a+b") {
+            Err(Error::Stopped(Stage::Determinize)) => {},
+            x => panic!("expected to stop, got {:?}", x),
+        }
+        let stats = compiler.determinize_stats();
+        assert!(stats.lookups > 0);
+        assert!(stats.stored_states > 0);
+
+        // The statistics add up over all the regexes that a `Compiler` compiles.
+        let mut compiler = Compiler::new(RegexOptions::default());
+        compiler.compile("a+b").unwrap();
+        let first = compiler.determinize_stats();
+        compiler.compile("a+b").unwrap();
+        let second = compiler.determinize_stats();
+        assert_eq!(second.lookups, 2 * first.lookups);
+        assert_eq!(second.hits, 2 * first.hits);
+
+        // Errors from a stage take precedence over stopping.
+        let mut compiler = Compiler::new(RegexOptions::default()).stop_after(Stage::Parse);
+        assert!(match compiler.compile("a(") { Err(Error::RegexSyntax(_)) => true, _ => false });
+    }
+
+    #[test]
+    fn multi_compiler() {
+        let res = [r"\bfoo\w+", r"\p{Greek}+\w", "a+b", r"\bfoo\w+", "a(", r"(?i)\w+@\w+"];
+        let mut compiler = MultiCompiler::new(RegexOptions::default());
+        let compiled = compiler.compile_all(&res);
+        assert_eq!(compiled.len(), res.len());
+        for (re, regex) in res.iter().zip(compiled) {
+            match Regex::new(re) {
+                Ok(expected) => assert_eq!(regex.unwrap().to_bytes(), expected.to_bytes()),
+                Err(_) => assert!(regex.is_err()),
+            }
+        }
+        assert_eq!(compiler.compile("a+b").unwrap().find("xaab"), Some((1, 4)));
+    }
+
+    #[test]
+    fn char_boundaries() {
+        // Multi-byte chars around literals (which use the prefilters), in loops, and next to
+        // assertions. All of the search methods check that their matches are on char boundaries
+        // in debug builds, so these would panic if something went wrong.
+        let text = "ééaé€b€éab😀é😀ab";
+        let res = ["ab", "é", "😀é", "a€", "é[a-z]", "[^a]+", ".b", "é+", "(?:é|€)*b", "\\b\\w",
+                   "\\w\\b", "\\B", "ab$", "(?m)^é", "[a😀]{2}", "."];
+        for re_str in &res {
+            let re = Regex::new(re_str).unwrap();
+            let matches: Vec<_> = re.find_iter(text).collect();
+            for &(start, end) in &matches {
+                let _ = &text[start..end];
+            }
+            assert_eq!(re.find(text), matches.first().cloned(), "{}", re_str);
+            assert_eq!(re.find_iter_chars(text).count(), matches.len());
+            for i in (0..text.len()).filter(|&i| text.is_char_boundary(i)) {
+                let _ = re.find_in(text, i..text.len());
+                let _ = re.find_with_end(&text[i..]);
+            }
+            let _ = re.match_spans(text);
+        }
+
+        let re = Regex::new("é[a-z]").unwrap();
+        assert_eq!(re.find_iter(text).collect::<Vec<_>>(), vec![(2, 5), (14, 17)]);
+        let re = Regex::new("[^a]+").unwrap();
+        assert_eq!(re.find(text), Some((0, 4)));
+    }
+
+    // Which engine a `Regex` uses, going by the names of its programs.
+    fn engine_kind(re: &Regex) -> &'static str {
+        let dis = re.disassemble();
+        match dis.programs.first().map(|p| p.0) {
+            None => "empty",
+            Some("start") => "line start",
+            Some("forward") => "forward-backward",
+            Some("backward") => "end-anchored",
+            Some(_) => if dis.prefilter.is_some() { "look-behind" } else { "anchored" },
+        }
+    }
+
+    #[test]
+    fn engines_on_mixed_scripts() {
+        let text = "Ωmega абв αβγ 漢字x abc\nπρ déjà vu 😀😀x";
+        let cases: Vec<(&str, Vec<(usize, usize)>)> = vec![
+            ("^Ω", vec![(0, 2)]),
+            ("^a", vec![]),
+            ("(?m)^πρ", vec![(33, 37)]),
+            ("(?m)^d", vec![]),
+            ("\\bβ", vec![]),
+            ("\\bvu\\b", vec![(45, 47)]),
+            ("\\bαβ", vec![(14, 18)]),
+            ("é+jà", vec![(39, 44)]),
+            ("[αβγ]+", vec![(14, 20)]),
+            ("字x", vec![(24, 28)]),
+            ("😀+x", vec![(48, 57)]),
+            ("x", vec![(27, 28), (56, 57)]),
+            ("\\w+\\b", vec![(0, 6), (7, 13), (14, 20), (21, 28), (29, 32), (33, 37), (38, 44),
+                            (45, 47), (56, 57)]),
+            ("[^\\x00-\\x7f]{2}\\s", vec![(9, 14), (16, 21), (33, 38)]),
+            ("😀x$", vec![(52, 57)]),
+            ("\\bvu$", vec![]),
+            ("\\w+$", vec![(56, 57)]),
+        ];
+
+        let mut kinds = HashSet::new();
+        for &(re_str, ref expected) in &cases {
+            let re = Regex::new(re_str).unwrap();
+            kinds.insert(engine_kind(&re));
+            assert_eq!(re.find(text), expected.first().cloned(), "{}", re_str);
+            assert_eq!(&re.find_iter(text).collect::<Vec<_>>(), expected, "{}", re_str);
+            assert_eq!(re.is_match(text), !expected.is_empty(), "{}", re_str);
+            for &(start, end) in expected {
+                assert_eq!(re.find_in(text, start..end), Some((start, end)), "{}", re_str);
+            }
+        }
+        for kind in &["anchored", "line start", "look-behind", "forward-backward", "end-anchored"] {
+            assert!(kinds.contains(kind), "no test for the {} engine", kind);
+        }
+    }
+
+    #[test]
+    fn leading_run() {
+        // The searching forward program goes back to its initial state after each char of the
+        // leading run, so cutting its loops already leaves a program that starts at the literal.
+        // That beats rewinding, so the run is only a fallback.
+        let prefilter = |re: &str| Regex::new(re).unwrap().disassemble().prefilter;
+        assert_eq!(prefilter("(.*)c(.*)"), Some("byte 'c' at offset 0".to_owned()));
+        assert_eq!(prefilter("[^x]*xy"), Some("bytes \"xy\" at offset 0".to_owned()));
+
+        let re = Regex::new("(.*)c(.*)").unwrap();
+        assert_eq!(re.find("ab\nxyc z"), Some((3, 8)));
+        assert_eq!(re.find("abc\nc"), Some((0, 3)));
+        assert_eq!(re.find("ab\nxy"), None);
+        assert_eq!(re.find_iter("ac\nbc").collect::<Vec<_>>(), vec![(0, 2), (3, 5)]);
+        let copy = Regex::from_bytes(&re.to_bytes()).unwrap();
+        assert_eq!(copy.find("ab\nxyc z"), Some((3, 8)));
+
+        let re = Regex::new("[^\"]*\"").unwrap();
+        assert_eq!(re.find("ab\"cd\""), Some((0, 3)));
+        assert_eq!(re.find_iter("ab\"cd\"").collect::<Vec<_>>(), vec![(0, 3), (3, 6)]);
+
+        let re = Regex::new("(?s).*é").unwrap();
+        assert_eq!(re.find("a\nbé"), Some((0, 5)));
+        assert_eq!(re.find("a\nbe"), None);
+    }
+
+    #[test]
+    fn verify_at() {
+        let text = "ab\nab xab";
+        for re_str in &["ab", "(?m)^ab", "\\bab", "^ab", "b|ab"] {
+            let re = Regex::new(re_str).unwrap();
+            let starts: Vec<usize> = re.find_iter(text).map(|m| m.0).collect();
+            for pos in 0..(text.len() + 1) {
+                let expected = re.find_in(text, pos..text.len())
+                    .and_then(|m| if m.0 == pos { Some(m.1) } else { None });
+                assert_eq!(re.verify_at(text, pos), expected, "{} at {}", re_str, pos);
+            }
+            for &start in &starts {
+                assert!(re.verify_at(text, start).is_some());
+            }
+        }
+        assert_eq!(Regex::new("(?m)^ab").unwrap().verify_at(text, 3), Some(5));
+        assert_eq!(Regex::new("(?m)^ab").unwrap().verify_at(text, 7), None);
+        assert_eq!(Regex::new("\\bab").unwrap().verify_at(text, 7), None);
+        assert_eq!(Regex::new("b|ab").unwrap().verify_at(text, 0), Some(2));
+    }
+
+    #[test]
+    fn find_iter() {
+        let re = Regex::new("[0-9]+").unwrap();
+        assert_eq!(re.find_iter("a1b22c333").collect::<Vec<_>>(), vec![(1, 2), (3, 5), (6, 9)]);
+        assert_eq!(re.find_iter("abc").next(), None);
+
+        // Empty matches, including between the bytes of a multi-byte char.
+        let re = Regex::new("x*").unwrap();
+        assert_eq!(re.find_iter("é").collect::<Vec<_>>(), vec![(0, 0), (2, 2)]);
+        assert_eq!(re.find_iter("xxa").collect::<Vec<_>>(), vec![(0, 2), (3, 3)]);
+
+        // Assertions see the text before the start of each search.
+        let re = Regex::new("\\bab").unwrap();
+        assert_eq!(re.find_iter("ab abab ab").collect::<Vec<_>>(), vec![(0, 2), (3, 5), (8, 10)]);
+        let re = Regex::new("^a").unwrap();
+        assert_eq!(re.find_iter("aaa").collect::<Vec<_>>(), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn into_find_iter() {
+        // An iterator that can be kept around without borrowing anything.
+        struct Words {
+            iter: IntoMatches,
+        }
+
+        let re = Regex::new("\\bab|x*").unwrap();
+        for text in &["ab abab ab", "xxa", "é", ""] {
+            let expected: Vec<_> = re.find_iter(text).collect();
+            let words = Words { iter: re.clone().into_find_iter(text.to_string()) };
+            assert_eq!(words.iter.collect::<Vec<_>>(), expected);
+
+            let expected: Vec<_> = re.find_iter(text).empty_matches(EmptyMatches::Never).collect();
+            let iter = re.clone().into_find_iter(text.to_string())
+                .empty_matches(EmptyMatches::Never);
+            assert_eq!(iter.text(), *text);
+            assert_eq!(iter.collect::<Vec<_>>(), expected);
+        }
+    }
+
+    #[test]
+    fn empty_matches() {
+        let re = Regex::new("a*").unwrap();
+        let find = |empty| re.find_iter("baab").empty_matches(empty).collect::<Vec<_>>();
+        assert_eq!(find(EmptyMatches::All), vec![(0, 0), (1, 3), (3, 3), (4, 4)]);
+        assert_eq!(find(EmptyMatches::NotAfterMatch), vec![(0, 0), (1, 3), (4, 4)]);
+        assert_eq!(find(EmptyMatches::Never), vec![(1, 3)]);
+
+        // Empty matches never come between the bytes of a char.
+        let re = Regex::new("x*").unwrap();
+        assert_eq!(re.find_iter("€").empty_matches(EmptyMatches::All).collect::<Vec<_>>(),
+                   vec![(0, 0), (3, 3)]);
+    }
+
+    #[test]
+    fn is_match_batch() {
+        let re = Regex::new("\\bab+\\b").unwrap();
+        let inputs = ["ab", "xab", "x abb", "", "abbbbc", "é ab"];
+        let expected = vec![true, false, true, false, false, true];
+        assert_eq!(re.is_match_batch(&inputs), expected);
+
+        let mut out = vec![true; inputs.len()];
+        re.is_match_batch_into(&inputs, &mut out);
+        assert_eq!(out, expected);
+
+        let owned: Vec<String> = inputs.iter().map(|s| s.to_string()).collect();
+        assert_eq!(re.is_match_batch(&owned), expected);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_is_match_batch() {
+        let re = Regex::new("[0-9]{3}").unwrap();
+        let inputs: Vec<String> = (0..10000).map(|i| format!("x{}y", i)).collect();
+        let expected: Vec<bool> = (0..10000).map(|i| i >= 100).collect();
+        assert_eq!(re.par_is_match_batch(&inputs), expected);
+        assert_eq!(re.is_match_batch(&inputs), expected);
+    }
+
+    #[test]
+    fn clone_shares() {
+        fn is_send_sync<T: Send + Sync>(_: &T) {}
+
+        let re = Regex::new("a+b").unwrap();
+        let clone = re.clone();
+        assert_eq!(&*re.engine as *const Engine<u8>, &*clone.engine as *const Engine<u8>);
+        assert_eq!(&*re.analysis as *const Analysis, &*clone.analysis as *const Analysis);
+        drop(re);
+        assert_eq!(clone.find("xaab"), Some((1, 4)));
+        is_send_sync(&clone);
+    }
+
+    #[test]
+    fn char_indices() {
+        let re = Regex::new("b+").unwrap();
+        assert_eq!(re.find_iter_chars("äbb€b").collect::<Vec<_>>(), vec![(1, 3), (4, 5)]);
+        assert_eq!(re.shortest_match_chars("€€b"), Some(3));
+        assert_eq!(re.shortest_match_chars("€€"), None);
+
+        let re = Regex::new("x*").unwrap();
+        assert_eq!(re.find_iter_chars("a€").collect::<Vec<_>>(), vec![(0, 0), (1, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn shortest_match() {
+        let re = Regex::new("a+").unwrap();
+        assert_eq!(re.shortest_match("baaa"), Some(4));
+        assert_eq!(re.for_detection().shortest_match("baaa"), Some(2));
+        assert_eq!(re.shortest_match("b"), None);
+        assert_eq!(re.for_detection().shortest_match("b"), None);
+    }
+
+    #[test]
+    fn find_with_end() {
+        let re = Regex::new("ab").unwrap();
+        assert_eq!(re.find_with_end("xab"), Some((1, 3, MatchEnd::Anywhere)));
+        assert_eq!(re.find_with_end("xa"), None);
+
+        let re = Regex::new("ab$").unwrap();
+        assert_eq!(re.find_with_end("xab"), Some((1, 3, MatchEnd::Eoi)));
+
+        let re = Regex::new("[a-z]+\\b").unwrap();
+        assert_eq!(re.find_with_end("12 ab"), Some((3, 5, MatchEnd::Eoi)));
+        assert_eq!(re.find_with_end("12 ab, c"), Some((3, 5, MatchEnd::Char(','))));
+        assert_eq!(re.find_with_end("12 ab…"), Some((3, 5, MatchEnd::Char('…'))));
+
+        let re = Regex::new("(?m)^a$").unwrap();
+        assert_eq!(re.find_with_end("b\na\n"), Some((2, 3, MatchEnd::Char('\n'))));
+
+        // Anchored matches that look ahead.
+        let re = Regex::new("^a\\b").unwrap();
+        assert_eq!(re.find("a b"), Some((0, 1)));
+        assert_eq!(re.find_with_end("a b"), Some((0, 1, MatchEnd::Char(' '))));
+        assert_eq!(re.find_with_end("ab"), None);
+    }
+
+    #[test]
+    fn find_in() {
+        // Look-behind, with a prefilter.
+        let re = Regex::new("\\bfoo").unwrap();
+        assert_eq!(re.find_in("xfoo foo", 1..8), Some((5, 8)));
+        assert_eq!(re.find_in("xfoo foo", 4..8), Some((5, 8)));
+        assert_eq!(re.find_in("foo", 0..3), Some((0, 3)));
+
+        // Look-behind, without a prefilter.
+        let re = Regex::new("\\b\\w*").unwrap();
+        assert_eq!(re.find_in("ab cd", 1..5), Some((2, 2)));
+        assert_eq!(re.find_in("ab cd", 3..5), Some((3, 5)));
+        let re = Regex::new("\\b\\w+").unwrap();
+        assert_eq!(re.find_in("ab cd", 1..5), Some((3, 5)));
+        assert_eq!(re.find_in("ab cd", 4..5), None);
+
+        // Anchors are relative to the whole string.
+        let re = Regex::new("^a").unwrap();
+        assert_eq!(re.find_in("aa", 1..2), None);
+        assert_eq!(re.find_in("aa", 0..2), Some((0, 1)));
+        let re = Regex::new("^a|b").unwrap();
+        assert_eq!(re.find_in("ba", 1..2), None);
+        assert_eq!(re.find_iter("ba").collect::<Vec<_>>(), vec![(0, 1)]);
+        let re = Regex::new("(?m)^a").unwrap();
+        assert_eq!(re.find_in("ba\na", 1..4), Some((3, 4)));
+        assert_eq!(re.find_in("ba\na", 3..4), Some((3, 4)));
+        let re = Regex::new("a$").unwrap();
+        assert_eq!(re.find_in("aab", 0..2), None);
+        assert_eq!(re.find_in("aab", 0..3), None);
+        assert_eq!(re.find_in("aa", 1..2), Some((1, 2)));
+        let re = Regex::new("a\\b").unwrap();
+        assert_eq!(re.find_in("aa a", 0..1), None);
+        assert_eq!(re.find_in("aa a", 0..2), Some((1, 2)));
+
+        // Matches are cut off at the end of the range.
+        let re = Regex::new("a.*z|b").unwrap();
+        assert_eq!(re.find_in("abz", 0..2), Some((1, 2)));
+        assert_eq!(re.find_in("abz", 0..3), Some((0, 3)));
+        assert_eq!(re.find_in("xé", 1..3), None);
+        let re = Regex::new("a+").unwrap();
+        assert_eq!(re.find_in("aaaa", 0..2), Some((0, 2)));
+        assert_eq!(re.find_in("aaaa", 1..3), Some((1, 3)));
+        let re = Regex::new("ab|a").unwrap();
+        assert_eq!(re.find_in("ab", 0..1), Some((0, 1)));
+        let re = Regex::new("^a+").unwrap();
+        assert_eq!(re.find_in("aaa", 0..2), Some((0, 2)));
+        let re = Regex::new("(?m)^a+").unwrap();
+        assert_eq!(re.find_in("b\naaa", 1..4), Some((2, 4)));
+        let re = Regex::new("\\ba+").unwrap();
+        assert_eq!(re.find_in(" aaa", 0..3), Some((1, 3)));
+    }
+
+    #[test]
+    fn worst_case_input() {
+        let re = Regex::new("abcd").unwrap();
+        let input = re.worst_case_input(10);
+        assert_eq!(input.len(), 10);
+        assert_eq!(&input[..3], b"abc");
+        assert_eq!(&input[4..7], b"abc");
+        assert!(input[3] != b'd');
+        assert_eq!(re.find_bytes(&input), None);
+
+        let re = Regex::new("^x[0-9]+y").unwrap();
+        let input = re.worst_case_input(7);
+        assert_eq!(input.len(), 7);
+        assert_eq!(re.find_bytes(&input), None);
+
+        assert_eq!(Regex::new("a|b").unwrap().worst_case_input(0), Vec::<u8>::new());
+
+        // End-anchored regexes are matched backward from the end.
+        let re = Regex::new("abc$").unwrap();
+        let input = re.worst_case_input(11);
+        assert_eq!(input.len(), 11);
+        assert_eq!(&input[9..], b"bc");
+        assert_eq!(&input[5..8], &input[8..11]);
+        assert!(input[8] != b'a');
+        assert_eq!(re.find_bytes(&input), None);
+    }
+
+    #[test]
+    fn end_anchored() {
+        use std::iter;
+
+        let re = Regex::new("[a-z]+[0-9]$").unwrap();
+        assert_eq!(engine_kind(&re), "end-anchored");
+        assert_eq!(re.find("ab1 cd2"), Some((4, 7)));
+        assert_eq!(re.find("ab1 cd2 "), None);
+        assert_eq!(re.find_iter("ab1 cd2").collect::<Vec<_>>(), vec![(4, 7)]);
+
+        // Only the end of the input is read.
+        let text: String = iter::repeat("ab1 ").take(1000).collect();
+        assert_eq!(re.find(&(text.clone() + "xyz9")), Some((4000, 4004)));
+        assert!(re.trace(&(text.clone() + "xyz9")).len() < 20);
+        assert!(re.trace(&(text + "xyz")).len() < 20);
+
+        // The analysis comes from the forward automaton, even though the engine doesn't use it.
+        let lower: Vec<u8> = (b'a'..(b'z' + 1)).collect();
+        assert_eq!(re.start_bytes().bytes(), lower);
+        assert!(!re.required_prefixes().is_candidate(b"123"));
+        let re = Regex::new("abc$").unwrap();
+        assert_eq!(engine_kind(&re), "end-anchored");
+        assert_eq!(re.required_prefixes().literals, vec![b"abc".to_vec()]);
+        assert_eq!(re.start_bytes().bytes(), b"a".to_vec());
+
+        // Look-behind at the start of the match.
+        let re = Regex::new("\\b[0-9]+$").unwrap();
+        assert_eq!(engine_kind(&re), "end-anchored");
+        assert_eq!(re.find("a12 34"), Some((4, 6)));
+        assert_eq!(re.find("a1234"), None);
+        assert_eq!(re.find_iter("12").collect::<Vec<_>>(), vec![(0, 2)]);
+    }
+
+    #[test]
+    fn linear_search() {
+        use std::iter;
+
+        let repeat = |s: &str, n: usize| -> String { iter::repeat(s).take(n).collect() };
+        let cases = vec![
+            // Every anchored attempt runs to the end of the input before failing.
+            ("(?m)^(a[^y]*yz|c)", "line start", repeat("a\n", 1000) + "c\nyy", Some((2000, 2001))),
+            ("(?m)^(a[^y]*yz|c)", "line start", repeat("a\n", 1000) + "yy", None),
+            ("\\b(a[^y]*yz|c)", "look-behind", repeat("a ", 1000) + "c yy", Some((2000, 2001))),
+            ("\\b(a[^y]*yz|c)", "look-behind", repeat("a ", 1000) + "yy", None),
+            ("a[^y]*yz", "forward-backward", repeat("a", 2000) + "yy", None),
+            ("[a-z]+[0-9]", "forward-backward", repeat("a", 2000), None),
+        ];
+        for (re_str, kind, text, expected) in cases {
+            let re = Regex::new(re_str).unwrap();
+            assert_eq!(engine_kind(&re), kind, "{}", re_str);
+            assert_eq!(re.find(&text), expected, "{}", re_str);
+            assert_eq!(re.is_match(&text), expected.is_some(), "{}", re_str);
+            re.debug_assert_linear(&text);
+
+            // The fallback survives encoding.
+            let back = Regex::from_bytes(&re.to_bytes()).unwrap();
+            assert_eq!(back.find(&text), expected, "{}", re_str);
+        }
+
+        // Short inputs never need the fallback, and searches that don't start at the beginning
+        // don't use it.
+        let re = Regex::new("\\b(a[^y]*yz|c)").unwrap();
+        assert_eq!(re.find("a c"), Some((2, 3)));
+        assert_eq!(re.find_iter(&(repeat("a ", 100) + "c c")).count(), 2);
+    }
+
+    #[test]
+    fn explain() {
+        let re = Regex::new("^abc").unwrap();
+        assert_eq!(re.explain("abc"), None);
+        let expl = re.explain("abd").unwrap();
+        assert_eq!(expl.pos, 2);
+        assert!(expl.program.is_some());
+        assert_eq!(expl.expected, vec![(b'c', b'c')]);
+
+        let re = Regex::new("x[0-9]+y").unwrap();
+        let expl = re.explain("zzx12a").unwrap();
+        assert_eq!(expl.pos, 5);
+        assert!(expl.expected.contains(&(b'y', b'y')));
+        assert!(expl.expected.contains(&(b'0', b'9')));
+
+        // End-anchored regexes are matched backward from the end.
+        let re = Regex::new("abc$").unwrap();
+        let expl = re.explain("xab").unwrap();
+        assert_eq!(expl.pos, 3);
+        assert_eq!(expl.expected, vec![(b'c', b'c')]);
+        let expl = re.explain("xbc").unwrap();
+        assert_eq!(expl.pos, 1);
+        assert_eq!(expl.program, Some("backward"));
+        assert_eq!(expl.expected, vec![(b'a', b'a')]);
+
+        // No attempt gets as far as reading a byte, so there is nothing to expect.
+        let re = Regex::new("\\bfoo\\d+").unwrap();
+        let expl = re.explain("xfoo").unwrap();
+        assert_eq!(expl.pos, 0);
+        assert_eq!(expl.program, None);
+        assert_eq!(expl.expected, vec![]);
+    }
+
+    #[test]
+    fn coverage() {
+        let re = Regex::new("^(a|b[0-9]+|c)$").unwrap();
+        // The state after "a" is also the state after "c", but the states for "b[0-9]+" are never
+        // reached.
+        let cov = re.coverage(&["a", "x"]);
+        assert!(!cov.is_complete());
+        let missing = cov.unvisited_transitions();
+        assert!(missing.iter().any(|t| (t.2).0 <= b'c' && b'c' <= (t.2).1));
+        assert!(!missing.iter().any(|t| (t.2).0 <= b'a' && b'a' <= (t.2).1));
+        assert!(!cov.unvisited_states().is_empty());
+
+        let cov = re.coverage(&["a", "b12", "c"]);
+        assert!(cov.unvisited_states().is_empty());
+        assert!(cov.unvisited_transitions().is_empty());
+        assert!(cov.is_complete());
+
+        let empty: &[&str] = &[];
+        assert_eq!(re.coverage(empty).unvisited_states().len(),
+                   re.disassemble().programs.iter().map(|p| p.1.len()).sum::<usize>());
+    }
+
+    #[cfg(feature = "profile")]
+    #[test]
+    fn profile() {
+        let re = Regex::new("^a*b").unwrap();
+        let prof = re.profile("aaaab");
+        let total: u64 = prof.programs.iter().flat_map(|p| p.1.iter()).sum();
+        assert_eq!(total, 5);
+
+        // The loop on "a" is the hottest state.
+        let hottest = prof.hottest(1);
+        assert_eq!(hottest.len(), 1);
+        assert!(hottest[0].2 >= 4);
+        let line = format!("{} program, state {}: {}", hottest[0].0, hottest[0].1, hottest[0].2);
+        assert!(prof.to_string().starts_with(&line));
+        assert!(re.profile("").hottest(10).is_empty());
+    }
+}
@@ -6,36 +6,73 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::fmt::Debug;
-use runner::Engine;
-use runner::program::TableInsts;
+use encode::{Decoder, Encode};
+use look::Look;
+use runner::{ANCHORED_TAG, Engine};
+use runner::program::{Disassembly, TableInsts, TraceEvent};
 
+// The program returns the kind of char that it looked ahead at, and the number of bytes of
+// look-ahead.
 #[derive(Clone, Debug)]
-pub struct AnchoredEngine<Ret> {
-    prog: TableInsts<Ret>,
+pub struct AnchoredEngine {
+    prog: TableInsts<(Look, u8)>,
 }
 
-impl<Ret: Copy + Debug> AnchoredEngine<Ret> {
-    pub fn new(prog: TableInsts<Ret>) -> AnchoredEngine<Ret> {
+impl AnchoredEngine {
+    pub fn new(prog: TableInsts<(Look, u8)>) -> AnchoredEngine {
         AnchoredEngine {
             prog: prog,
         }
     }
+
+    // Decodes an engine that was written by `encode` (minus the tag).
+    pub fn decode(input: &mut Decoder) -> Option<AnchoredEngine> {
+        TableInsts::decode(input).map(AnchoredEngine::new)
+    }
 }
 
-impl<Ret: Copy + Debug + 'static> Engine<Ret> for AnchoredEngine<Ret> {
-    fn find(&self, s: &str) -> Option<(usize, usize, Ret)> {
-        let input = s.as_bytes();
-        if self.prog.is_empty() {
+impl Engine<u8> for AnchoredEngine {
+    fn find_look_ahead_before(&self, input: &[u8], pos: usize, limit: usize)
+    -> Option<(usize, usize, u8, (Look, u8))> {
+        // Every match starts at the beginning of the input.
+        if self.prog.is_empty() || pos > 0 {
             None
-        } else if let Ok(end) = self.prog.find_from(input, 0, 0) {
-            Some((0, end.0, end.1))
+        } else if let Ok((end, (look, bytes))) = self.prog.find_before(input, 0, limit, 0) {
+            Some((0, end - bytes as usize, 0, (look, bytes)))
         } else {
             None
         }
     }
 
-    fn clone_box(&self) -> Box<Engine<Ret>> {
+    fn is_match(&self, input: &[u8]) -> bool {
+        !self.prog.is_empty() && self.prog.find_from(input, 0, 0).is_ok()
+    }
+
+    fn for_detection(&self) -> Box<Engine<u8>> {
+        Box::new(AnchoredEngine::new(self.prog.for_detection()))
+    }
+
+    fn clone_box(&self) -> Box<Engine<u8>> {
         Box::new(self.clone())
     }
+
+    fn disassemble(&self) -> Disassembly {
+        Disassembly {
+            programs: vec![("anchored", self.prog.disassemble())],
+            prefilter: None,
+        }
+    }
+
+    fn trace(&self, s: &str) -> Vec<TraceEvent> {
+        let mut trace = Vec::new();
+        if !self.prog.is_empty() {
+            let _ = self.prog.trace_find_from("anchored", s.as_bytes(), 0, 0, &mut trace);
+        }
+        trace
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(ANCHORED_TAG);
+        self.prog.encode(out);
+    }
 }
@@ -0,0 +1,95 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use encode::{Decoder, Encode};
+use look::Look;
+use runner::{END_ANCHORED_TAG, Engine};
+use runner::program::{Disassembly, TableInsts, TraceEvent};
+
+/// An engine for regexes whose matches all end at the end of the input (for example, `foo$`).
+///
+/// Scanning forward would mean reading the whole input, even though only the end of it matters.
+/// Instead, we run the backward program (the same one that the forward-backward engine uses) from
+/// the end of the input, and it finds the start of the leftmost match; it usually gives up after
+/// only a few bytes. The program returns the number of bytes that it looked behind.
+#[derive(Clone, Debug)]
+pub struct EndAnchoredEngine {
+    backward: TableInsts<u8>,
+    // The state of `backward` to start in, or `None` if nothing matches.
+    init: Option<usize>,
+}
+
+impl EndAnchoredEngine {
+    pub fn new(backward: TableInsts<u8>, init: Option<usize>) -> EndAnchoredEngine {
+        EndAnchoredEngine {
+            backward: backward,
+            init: init,
+        }
+    }
+
+    // Decodes an engine that was written by `encode` (minus the tag).
+    pub fn decode(input: &mut Decoder) -> Option<EndAnchoredEngine> {
+        let backward: TableInsts<u8> = try_opt!(TableInsts::decode(input));
+        let init: Option<usize> = try_opt!(Option::decode(input));
+        if init.map_or(false, |s| s >= backward.num_states()) {
+            return None;
+        }
+        Some(EndAnchoredEngine::new(backward, init))
+    }
+}
+
+impl Engine<u8> for EndAnchoredEngine {
+    // As with the forward-backward engine, the backward program may look at the char before
+    // `pos`, but it can't extend the match to before the search started. Every match ends at the
+    // end of the input, so there is none if `limit` is before that.
+    fn find_look_ahead_before(&self, input: &[u8], pos: usize, limit: usize)
+    -> Option<(usize, usize, u8, (Look, u8))> {
+        let state = try_opt!(self.init);
+        if pos > input.len() || limit < input.len() {
+            return None;
+        }
+        self.backward.longest_backward_find_after(input, pos, input.len(), state)
+            .map(|(start, bytes)| (start, input.len(), bytes, (Look::Boundary, 0)))
+    }
+
+    fn is_match(&self, input: &[u8]) -> bool {
+        self.find(input).is_some()
+    }
+
+    fn for_detection(&self) -> Box<Engine<u8>> {
+        // Any match will do, so the backward program can stop at the first one.
+        Box::new(EndAnchoredEngine::new(self.backward.for_detection(), self.init))
+    }
+
+    fn clone_box(&self) -> Box<Engine<u8>> {
+        Box::new(self.clone())
+    }
+
+    fn disassemble(&self) -> Disassembly {
+        Disassembly {
+            programs: vec![("backward", self.backward.disassemble())],
+            prefilter: None,
+        }
+    }
+
+    fn trace(&self, s: &str) -> Vec<TraceEvent> {
+        let mut trace = Vec::new();
+        if let Some(state) = self.init {
+            let input = s.as_bytes();
+            self.backward.trace_longest_backward_find_from(
+                "backward", input, input.len(), state, &mut trace);
+        }
+        trace
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(END_ANCHORED_TAG);
+        self.backward.encode(out);
+        self.init.encode(out);
+    }
+}
@@ -6,90 +6,208 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::{ascii, u8};
 use std::fmt::Debug;
+use byte_search::{memchr, memrchr};
+use charset::CharSet;
 //use dfa::{Dfa, PrefixPart, RetTrait};
 use dfa::PrefixPart;
 use itertools::Itertools;
-use memchr::memchr;
-use runner::Engine;
-use runner::program::TableInsts;
+use encode::{Decoder, Encode};
+use look::{Look, WordChars};
+use regex_syntax::{Expr, Repeater};
+use runner::{Engine, FORWARD_BACKWARD_TAG};
+use runner::program::{Disassembly, TableInsts, TraceEvent};
 
+// The forward program returns the initial state for the backward program, the kind of char that
+// it looked ahead at, and the number of bytes of look-ahead.
 #[derive(Clone, Debug)]
 pub struct ForwardBackwardEngine<Ret> {
-    forward: TableInsts<(usize, u8)>,
+    forward: TableInsts<(usize, Look, u8)>,
+    // The initial state of `forward` for each kind of char before the place where the search
+    // starts, indexed by `Look::as_usize`.
+    init: Vec<Option<usize>>,
+    // The word chars that decide which of `init` to use.
+    words: WordChars,
     backward: TableInsts<Ret>,
     prefix: Prefix,
 }
 
 impl<Ret: Copy + Debug> ForwardBackwardEngine<Ret> {
-    pub fn new(forward: TableInsts<(usize, u8)>, prefix: Prefix, backward: TableInsts<Ret>) -> Self {
+    pub fn new(forward: TableInsts<(usize, Look, u8)>,
+               init: Vec<Option<usize>>,
+               words: WordChars,
+               prefix: Prefix,
+               backward: TableInsts<Ret>)
+    -> Self {
         ForwardBackwardEngine {
             forward: forward,
+            init: init,
+            words: words,
             backward: backward,
             prefix: prefix,
         }
     }
 
-    fn find_with_searcher<SearchFn>(&self, input: &[u8], search: SearchFn)
-    -> Option<(usize, usize, Ret)>
-    where SearchFn: Fn(&[u8], usize) -> Option<usize> {
-        let mut pos = 0;
-        while let Some(start) = search(input, pos) {
-            match self.forward.find_from(input, start, 0) {
-                Ok((end, (rev_state, look_ahead))) => {
-                    let rev_pos = end.saturating_sub(look_ahead as usize);
-                    let (start_pos, ret) = self.backward
-                        .longest_backward_find_from(input, rev_pos, rev_state)
-                        .expect("BUG: matched forward but failed to match backward");
-                    return Some((start_pos, rev_pos, ret));
+    // Decodes an engine that was written by `encode` (minus the tag).
+    pub fn decode(input: &mut Decoder) -> Option<Self> where Ret: Encode {
+        let forward: TableInsts<(usize, Look, u8)> = try_opt!(TableInsts::decode(input));
+        let init: Vec<Option<usize>> = try_opt!(Vec::decode(input));
+        let words = try_opt!(WordChars::decode(input));
+        let backward: TableInsts<Ret> = try_opt!(TableInsts::decode(input));
+        let prefix = try_opt!(Prefix::decode(input));
 
+        let num_states = forward.num_states();
+        if init.len() != Look::num() || init.iter().any(|&s| s.map_or(false, |s| s >= num_states)) {
+            return None;
+        }
+
+        // The forward program returns initial states for the backward program.
+        let num_states = backward.num_states();
+        if forward.accept.iter().chain(forward.accept_at_eoi.iter())
+                .any(|&ret| ret.map_or(false, |r| r.0 >= num_states)) {
+            return None;
+        }
+        Some(ForwardBackwardEngine::new(forward, init, words, prefix, backward))
+    }
+
+    // Writes an optional fallback engine (see `runner::Budget`).
+    pub fn encode_fallback(fallback: &Option<Self>, out: &mut Vec<u8>) where Ret: Encode {
+        fallback.is_some().encode(out);
+        if let Some(ref eng) = *fallback {
+            eng.forward.encode(out);
+            eng.init.encode(out);
+            eng.words.encode(out);
+            eng.backward.encode(out);
+            eng.prefix.encode(out);
+        }
+    }
+
+    // Decodes an optional fallback engine that was written by `encode_fallback`.
+    pub fn decode_fallback(input: &mut Decoder) -> Option<Option<Self>> where Ret: Encode {
+        if try_opt!(bool::decode(input)) {
+            ForwardBackwardEngine::decode(input).map(Some)
+        } else {
+            Some(None)
+        }
+    }
+
+    // Returns the initial state of the forward program for a search that starts at `pos`, if
+    // there is one.
+    fn init_state(&self, input: &[u8], pos: usize) -> Option<usize> {
+        self.init[self.words.before(input, pos).as_usize()]
+    }
+
+    // Runs the forward program from `pos`, returning the end of the match and the forward
+    // program's return value (if there is a match that ends at or before `limit`). Every step is
+    // reported to `on_event`.
+    fn find_with_searcher<SearchFn, F>(&self,
+                                       input: &[u8],
+                                       mut pos: usize,
+                                       limit: usize,
+                                       search: SearchFn,
+                                       on_event: &mut F)
+    -> Option<(usize, (usize, Look, u8))>
+    where SearchFn: Fn(&[u8], usize) -> Option<usize>, F: FnMut(TraceEvent) {
+        let ends_before_limit = |pos: usize, (_, _, bytes): (usize, Look, u8)| {
+            pos - bytes as usize <= limit
+        };
+        loop {
+            let found = search(input, pos);
+            on_event(TraceEvent::Search { pos: pos, found: found });
+            let start = try_opt!(found);
+            let state = match self.init_state(input, start) {
+                Some(state) => state,
+                None => {
+                    pos = start + 1;
+                    continue;
+                },
+            };
+            let forward = &self.forward;
+            match forward.find_before_with("forward", input, start, limit, state,
+                                           ends_before_limit, on_event) {
+                Ok(ret) => {
+                    return Some(ret);
                 },
                 Err(end) => {
                     pos = end + 1;
                 },
             }
         }
-
-        None
     }
 
-}
-
-impl<Ret: Copy + Debug + 'static> Engine<Ret> for ForwardBackwardEngine<Ret> {
-    fn find(&self, s: &str) -> Option<(usize, usize, Ret)> {
-        let input = s.as_bytes();
+    fn find_forward<F>(&self, input: &[u8], pos: usize, limit: usize, on_event: &mut F)
+    -> Option<(usize, (usize, Look, u8))>
+    where F: FnMut(TraceEvent) {
         if self.forward.is_empty() {
             return None;
         }
+        let search = |s: &[u8], pos: usize| self.prefix.search(s, pos);
+        self.find_with_searcher(input, pos, limit, search, on_event)
+    }
+}
 
-        match self.prefix {
-            Prefix::Empty => self.find_with_searcher(
-                input,
-                |s, pos| if pos <= s.len() { Some(pos) } else { None }
-            ),
-            Prefix::ByteSet { ref bytes, offset } => self.find_with_searcher(
-                input,
-                |s, pos| if pos + offset <= s.len() {
-                        s[(pos + offset)..].iter().position(|c| bytes[*c as usize]).map(|x| x + pos)
-                    } else {
-                        None
-                    }
-            ),
-            Prefix::Byte { byte, offset } => self.find_with_searcher(
-                input,
-                |s, pos| if pos + offset <= s.len() {
-                    memchr(byte, &input[(pos + offset)..]).map(|x| x + pos)
-                } else {
-                    None
-                }
-            ),
-            //Prefix::ByteBackwards { .. } => unimplemented!(),
-        }
+impl ForwardBackwardEngine<u8> {
+    // The forward program starts in the initial state for the char before `pos`, so it only finds
+    // matches that begin at or after `pos`. The backward program may look at the char before
+    // `pos` too, but it can't extend the match to before the search started.
+    fn find_with<F>(&self, input: &[u8], pos: usize, limit: usize, on_event: &mut F)
+    -> Option<(usize, usize, u8, (Look, u8))>
+    where F: FnMut(TraceEvent) {
+        self.find_forward(input, pos, limit, on_event).map(|(end, (rev_state, look, look_ahead))| {
+            let rev_pos = end.saturating_sub(look_ahead as usize);
+            let (start_pos, ret) = self.backward
+                .longest_backward_find_after_with("backward", input, pos, rev_pos, rev_state,
+                                                  on_event)
+                .expect("BUG: matched forward but failed to match backward");
+            (start_pos, rev_pos, ret, (look, look_ahead))
+        })
+    }
+}
+
+impl Engine<u8> for ForwardBackwardEngine<u8> {
+    fn find_look_ahead_before(&self, input: &[u8], pos: usize, limit: usize)
+    -> Option<(usize, usize, u8, (Look, u8))> {
+        self.find_with(input, pos, limit, &mut |_| {})
+    }
+
+    fn is_match(&self, input: &[u8]) -> bool {
+        // We don't need to run backward to know that there's a match.
+        self.find_forward(input, 0, input.len(), &mut |_| {}).is_some()
+    }
+
+    fn for_detection(&self) -> Box<Engine<u8>> {
+        let forward = self.forward.for_detection();
+        Box::new(ForwardBackwardEngine::new(forward, self.init.clone(), self.words.clone(),
+                                            self.prefix.clone(), self.backward.clone()))
     }
 
-    fn clone_box(&self) -> Box<Engine<Ret>> {
+    fn clone_box(&self) -> Box<Engine<u8>> {
         Box::new(self.clone())
     }
+
+    fn disassemble(&self) -> Disassembly {
+        Disassembly {
+            programs: vec![("forward", self.forward.disassemble()),
+                           ("backward", self.backward.disassemble())],
+            prefilter: self.prefix.describe(),
+        }
+    }
+
+    fn trace(&self, s: &str) -> Vec<TraceEvent> {
+        let mut trace = Vec::new();
+        self.find_with(s.as_bytes(), 0, s.len(), &mut |ev| trace.push(ev));
+        trace
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(FORWARD_BACKWARD_TAG);
+        self.forward.encode(out);
+        self.init.encode(out);
+        self.words.encode(out);
+        self.backward.encode(out);
+        self.prefix.encode(out);
+    }
 }
 
 /// A `Prefix` is the first part of a DFA. Anything matching the DFA should start with
@@ -105,62 +223,334 @@ pub enum Prefix {
     ByteSet { bytes: Vec<bool>, offset: usize },
     // Matches a specific byte and then rewinds some number of bytes.
     Byte { byte: u8, offset: usize },
+    // Matches two specific consecutive bytes and then rewinds some number of bytes (`offset` is
+    // the position of the first byte). We search for the rarer byte (the one at index `rare`) and
+    // then check the other one.
+    BytePair { bytes: [u8; 2], offset: usize, rare: usize },
+    // Finds a specific byte and then rewinds to just after the last `stop` byte before it (or to
+    // where the search started, if there is no `stop` byte or it is `None`). This is for regexes
+    // that begin with something like `.*c`: a match can start almost anywhere, but not before the
+    // line containing the next `c`.
+    ByteRun { byte: u8, stop: Option<u8> },
     // Matches a specific byte and then runs a DFA backwards.
     //ByteBackwards { byte: u8, rev: Dfa<()> },
 }
 
-// How big we allow the byte sets to be. In order for byte sets to be a performance win, finding a
-// byte in the set needs to be sufficiently rare; therefore, we only use small sets. There might be
-// room for a better heuristic, though: we could use large sets that only have rare bytes.
-const MAX_BYTE_SET_SIZE: usize = 16;
+// A `Prefix` is encoded as a tag (0 to 4, in the order of the variants above) followed by its
+// fields.
+impl Encode for Prefix {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            Prefix::Empty => { out.push(0); },
+            Prefix::ByteSet { ref bytes, offset } => {
+                out.push(1);
+                bytes.encode(out);
+                offset.encode(out);
+            },
+            Prefix::Byte { byte, offset } => {
+                out.push(2);
+                byte.encode(out);
+                offset.encode(out);
+            },
+            Prefix::BytePair { bytes, offset, rare } => {
+                out.push(3);
+                bytes[0].encode(out);
+                bytes[1].encode(out);
+                offset.encode(out);
+                rare.encode(out);
+            },
+            Prefix::ByteRun { byte, stop } => {
+                out.push(4);
+                byte.encode(out);
+                stop.encode(out);
+            },
+        }
+    }
 
-impl Prefix {
-    fn byte_prefix(parts: &[PrefixPart]) -> Option<Prefix> {
-        fn common_prefix<'a>(s1: &'a [u8], s2: &'a [u8]) -> &'a [u8] {
-            let prefix_len = s1.iter().zip(s2.iter())
-                .take_while(|pair| pair.0 == pair.1)
-                .count();
-            &s1[0..prefix_len]
-        }
-
-        let mut parts = parts.iter();
-        if let Some(first) = parts.next() {
-            let lit = parts.fold(&first.0[..], |acc, p| common_prefix(acc, &p.0));
-            if !lit.is_empty() {
-                // See if the common prefix contains a full codepoint. If it does, search for the last
-                // byte of that codepoint.
-                let cp_last_byte = ((!lit[0]).leading_zeros() as usize).saturating_sub(1);
-                if cp_last_byte < lit.len() {
-                    return Some(Prefix::Byte { byte: lit[cp_last_byte], offset: cp_last_byte });
+    fn decode(input: &mut Decoder) -> Option<Prefix> {
+        match try_opt!(u8::decode(input)) {
+            0 => Some(Prefix::Empty),
+            1 => {
+                let bytes: Vec<bool> = try_opt!(Vec::decode(input));
+                let offset = try_opt!(usize::decode(input));
+                if bytes.len() == 256 {
+                    Some(Prefix::ByteSet { bytes: bytes, offset: offset })
+                } else {
+                    None
                 }
-            }
+            },
+            2 => {
+                let byte = try_opt!(u8::decode(input));
+                let offset = try_opt!(usize::decode(input));
+                Some(Prefix::Byte { byte: byte, offset: offset })
+            },
+            3 => {
+                let first = try_opt!(u8::decode(input));
+                let second = try_opt!(u8::decode(input));
+                let offset = try_opt!(usize::decode(input));
+                let rare = try_opt!(usize::decode(input));
+                if rare < 2 {
+                    Some(Prefix::BytePair { bytes: [first, second], offset: offset, rare: rare })
+                } else {
+                    None
+                }
+            },
+            4 => {
+                let byte = try_opt!(u8::decode(input));
+                let stop = try_opt!(Option::decode(input));
+                Some(Prefix::ByteRun { byte: byte, stop: stop })
+            },
+            _ => None,
         }
+    }
+}
+
+/// Options that control which prefilter (if any) a `Regex` uses to skip over input that can't
+/// begin a match. See `RegexOptions::prefilter`.
+///
+/// A prefilter is only a win if the bytes it searches for are rare in the input, but the default
+/// heuristics don't know anything about the input. If most of the input consists of some
+/// particular bytes (say, digits), listing them in `common_bytes` prevents the `Regex` from
+/// searching for them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrefilterOptions {
+    /// Whether to use a prefilter at all.
+    pub enabled: bool,
+    /// The largest set of bytes that the prefilter will search for. In order for searching for a
+    /// set of bytes to be a performance win, finding a byte in the set needs to be sufficiently
+    /// rare; therefore, by default we only use small sets.
+    pub max_byte_set_size: usize,
+    /// Bytes that are too common in the input to be worth searching for. We never choose a
+    /// prefilter that searches for any of these.
+    pub common_bytes: Vec<u8>,
+    /// How common each byte is: `byte_frequencies[b]` is the rank of byte `b`, where higher ranks
+    /// are more common. When there is a choice, the prefilter searches for the rarest bytes. The
+    /// default ranking is tuned for UTF-8 text that is mostly in English; if your input looks
+    /// different, you can supply your own (with 256 entries).
+    pub byte_frequencies: Vec<u8>,
+    /// Whether to search for two consecutive bytes when every match contains the same pair of
+    /// bytes at the same offset. Checking the second byte weeds out most of the false positives
+    /// that come from searching for a single byte.
+    pub byte_pairs: bool,
+}
 
-        None
+// Strips off any groups around `expr`.
+fn ungroup(expr: &Expr) -> &Expr {
+    match *expr {
+        Expr::Group { ref e, .. } => ungroup(e),
+        _ => expr,
     }
+}
 
-    fn byte_set_prefix(parts: &[PrefixPart]) -> Option<Prefix> {
-        let crit_byte_pos = |p: &PrefixPart| ((!p.0[0]).leading_zeros() as usize).saturating_sub(1);
-        let crit_byte_posns: Vec<usize> = parts.iter().map(crit_byte_pos).dedup().collect();
+// If `expr` is a repetition like `.*` that can match anything except one ASCII byte, returns
+// that byte (or `Some(None)` if it can match anything at all, like `(?s).*`).
+fn run_stop(expr: &Expr) -> Option<Option<u8>> {
+    let e = match *ungroup(expr) {
+        Expr::Repeat { ref e, r: Repeater::ZeroOrMore, .. } => ungroup(e),
+        _ => { return None; },
+    };
+    match *e {
+        Expr::AnyChar => Some(None),
+        Expr::AnyCharNoNL => Some(Some(b'\n')),
+        Expr::Class(ref cc) => {
+            let ranges: Vec<(char, char)> = cc.iter().map(|r| (r.start, r.end)).collect();
+            let excluded = CharSet::from_ranges(&ranges).negated();
+            let ex = excluded.ranges();
+            if ex.len() == 1 && ex[0].0 == ex[0].1 && (ex[0].0 as u32) < 0x80 {
+                Some(Some(ex[0].0 as u8))
+            } else {
+                None
+            }
+        },
+        _ => None,
+    }
+}
 
-        if crit_byte_posns.len() == 1 {
-            let crit_byte = crit_byte_posns[0];
-            if parts.iter().all(|x| x.0.len() > crit_byte) {
-                let mut crit_bytes: Vec<u8> = parts.iter().map(|x| x.0[crit_byte]).collect();
-                crit_bytes.sort();
-                crit_bytes.dedup();
+// If every match of `expr` begins with a particular (case-sensitive) char, returns it.
+fn first_literal_char(expr: &Expr) -> Option<char> {
+    match *ungroup(expr) {
+        Expr::Literal { ref chars, casei: false } => chars.first().cloned(),
+        Expr::Concat(ref es) => es.first().and_then(first_literal_char),
+        _ => None,
+    }
+}
 
-                if crit_bytes.len() <= MAX_BYTE_SET_SIZE {
-                    let mut ret = vec![false; 256];
-                    for &b in &crit_bytes {
-                        ret[b as usize] = true;
-                    }
-                    return Some(Prefix::ByteSet { bytes: ret, offset: crit_byte });
+// Returns the first position at or after `pos` where `bytes` appear, rewound by `offset`. We use
+// `memchr` to find `bytes[rare]`, and then check the other byte by hand.
+fn find_byte_pair(input: &[u8], pos: usize, bytes: [u8; 2], offset: usize, rare: usize)
+-> Option<usize> {
+    let rare_offset = offset + rare;
+    let other_offset = offset + 1 - rare;
+    let mut pos = pos;
+    while pos + rare_offset <= input.len() {
+        match memchr(bytes[rare], &input[(pos + rare_offset)..]) {
+            Some(x) => {
+                let start = pos + x;
+                if input.get(start + other_offset) == Some(&bytes[1 - rare]) {
+                    return Some(start);
                 }
+                pos = start + 1;
+            },
+            None => { return None; },
+        }
+    }
+    None
+}
+
+// A rough ranking of how common each byte is in (mostly English) UTF-8 text.
+fn default_byte_frequencies() -> Vec<u8> {
+    // These bytes are listed from the rarest to the most common. Anything not listed (control
+    // characters, and bytes that never appear in UTF-8) is rarer still.
+    let mut listed: Vec<u8> = Vec::new();
+    // UTF-8 continuation bytes, followed by leading bytes.
+    listed.extend(0x80..0xC0);
+    listed.extend(0xF0..0xF5);
+    listed.extend(0xE0..0xF0);
+    listed.extend(0xC2..0xE0);
+    listed.extend_from_slice(b"QZXJKVYUOGBFWPHNLDRIMECSAT");
+    listed.extend_from_slice(b"`~^|\\{}<>@#$%&*+=[]_;!?");
+    listed.extend_from_slice(b"7869543210");
+    listed.extend_from_slice(b"\"':/()-\r\t");
+    listed.extend_from_slice(b"zqxjkvbpygfwmucldrhsnioate");
+    listed.extend_from_slice(b",.\n ");
+
+    let mut is_listed = vec![false; 256];
+    for &b in &listed {
+        is_listed[b as usize] = true;
+    }
+    let order = (0..256usize).filter(|&b| !is_listed[b]).map(|b| b as u8).chain(listed);
+
+    let mut ret = vec![0; 256];
+    for (rank, b) in order.enumerate() {
+        ret[b as usize] = rank as u8;
+    }
+    ret
+}
+
+impl Default for PrefilterOptions {
+    fn default() -> PrefilterOptions {
+        PrefilterOptions {
+            enabled: true,
+            max_byte_set_size: 16,
+            common_bytes: Vec::new(),
+            byte_frequencies: default_byte_frequencies(),
+            byte_pairs: true,
+        }
+    }
+}
+
+impl PrefilterOptions {
+    // Is it worth searching for all of these bytes?
+    fn allows(&self, bytes: &[u8]) -> bool {
+        self.enabled
+            && bytes.len() <= self.max_byte_set_size
+            && !bytes.iter().any(|b| self.common_bytes.contains(b))
+    }
+
+    // Returns the rank of `b` in `byte_frequencies`.
+    fn frequency(&self, b: u8) -> u8 {
+        self.byte_frequencies.get(b as usize).cloned().unwrap_or(u8::MAX)
+    }
+}
+
+impl Prefix {
+    // Makes a `Prefix` that searches for one of `bytes` and then rewinds by `offset`.
+    fn from_bytes_at(bytes: &[u8], offset: usize) -> Prefix {
+        if bytes.len() == 1 {
+            Prefix::Byte { byte: bytes[0], offset: offset }
+        } else {
+            let mut set = vec![false; 256];
+            for &b in bytes {
+                set[b as usize] = true;
+            }
+            Prefix::ByteSet { bytes: set, offset: offset }
+        }
+    }
+
+    // If all of the `parts` agree on two consecutive bytes, this finds the pair whose rarer byte is
+    // the rarest and searches for it.
+    fn byte_pair_prefix(parts: &[PrefixPart], opts: &PrefilterOptions) -> Option<Prefix> {
+        let min_len = parts.iter().map(|p| p.0.len()).min().unwrap_or(0);
+        if !opts.byte_pairs || min_len < 2 {
+            return None;
+        }
+
+        // The best pair so far, and the frequencies of its rarer and more common bytes.
+        let mut best: Option<(Prefix, (u8, u8))> = None;
+        for offset in 0..(min_len - 1) {
+            let bytes = [parts[0].0[offset], parts[0].0[offset + 1]];
+            if parts.iter().any(|p| p.0[offset] != bytes[0] || p.0[offset + 1] != bytes[1])
+                    || !opts.allows(&bytes) {
+                continue;
+            }
+
+            let freqs = [opts.frequency(bytes[0]), opts.frequency(bytes[1])];
+            let rare = if freqs[1] < freqs[0] { 1 } else { 0 };
+            let score = (freqs[rare], freqs[1 - rare]);
+            if best.as_ref().map_or(true, |b| score < b.1) {
+                let pref = Prefix::BytePair { bytes: bytes, offset: offset, rare: rare };
+                best = Some((pref, score));
+            }
+        }
+        best.map(|b| b.0)
+    }
+
+    // Every match starts with one of `parts`, so at every offset that is shorter than all the
+    // parts, a match must have one of the parts' bytes. This finds the offset where those bytes
+    // are the rarest (according to `opts.byte_frequencies`) and searches for them.
+    fn rarest_bytes_prefix(parts: &[PrefixPart], opts: &PrefilterOptions) -> Option<Prefix> {
+        let min_len = match parts.iter().map(|p| p.0.len()).min() {
+            Some(len) => len,
+            None => { return None; },
+        };
+
+        // The best offset so far, together with its bytes and their total frequency.
+        let mut best: Option<(usize, Vec<u8>, usize)> = None;
+        for offset in 0..min_len {
+            let mut bytes: Vec<u8> = parts.iter().map(|p| p.0[offset]).collect();
+            bytes.sort();
+            bytes.dedup();
+            if !opts.allows(&bytes) {
+                continue;
+            }
+
+            let score = bytes.iter().fold(0, |acc, &b| acc + opts.frequency(b) as usize + 1);
+            if best.as_ref().map_or(true, |b| score < b.2) {
+                best = Some((offset, bytes, score));
             }
         }
+        best.map(|(offset, bytes, _)| Prefix::from_bytes_at(&bytes, offset))
+    }
+
+    /// If `expr` begins with a repetition like `.*` or `[^x]*` followed by a literal char, makes
+    /// a `Prefix::ByteRun` that looks for the char and then rewinds over the repetition.
+    /// Otherwise (or if the options don't allow it), returns `Prefix::Empty`.
+    pub fn from_leading_run(expr: &Expr, opts: &PrefilterOptions) -> Prefix {
+        let es = match *ungroup(expr) {
+            Expr::Concat(ref es) if es.len() >= 2 => es,
+            _ => { return Prefix::Empty; },
+        };
+        match (run_stop(&es[0]), first_literal_char(&es[1])) {
+            (Some(stop), Some(c)) => {
+                let byte = c.to_string().as_bytes()[0];
+                if opts.allows(&[byte]) {
+                    Prefix::ByteRun { byte: byte, stop: stop }
+                } else {
+                    Prefix::Empty
+                }
+            },
+            _ => Prefix::Empty,
+        }
+    }
 
-        None
+    /// Makes a `Prefix` that searches for any of the given bytes, or `Prefix::Empty` if the
+    /// options don't allow searching for them.
+    pub fn from_start_bytes(bytes: &[u8], opts: &PrefilterOptions) -> Prefix {
+        if bytes.is_empty() || !opts.allows(bytes) {
+            Prefix::Empty
+        } else {
+            Prefix::from_bytes_at(bytes, 0)
+        }
     }
 
     /*
@@ -182,17 +572,60 @@ impl Prefix {
     }
     */
 
+    /// Returns the first position at or after `pos` where a match could start.
+    pub fn search(&self, input: &[u8], pos: usize) -> Option<usize> {
+        match *self {
+            Prefix::Empty => if pos <= input.len() { Some(pos) } else { None },
+            Prefix::ByteSet { ref bytes, offset } => if pos + offset <= input.len() {
+                input[(pos + offset)..].iter().position(|c| bytes[*c as usize]).map(|x| x + pos)
+            } else {
+                None
+            },
+            Prefix::Byte { byte, offset } => if pos + offset <= input.len() {
+                memchr(byte, &input[(pos + offset)..]).map(|x| x + pos)
+            } else {
+                None
+            },
+            Prefix::BytePair { bytes, offset, rare } =>
+                find_byte_pair(input, pos, bytes, offset, rare),
+            Prefix::ByteRun { byte, stop } => if pos <= input.len() {
+                memchr(byte, &input[pos..]).map(|x| {
+                    let run = &input[pos..(pos + x)];
+                    stop.and_then(|st| memrchr(st, run)).map_or(pos, |y| pos + y + 1)
+                })
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Returns a human-readable description of this `Prefix`, or `None` if it is empty.
+    pub fn describe(&self) -> Option<String> {
+        let esc = |b: u8| -> String { ascii::escape_default(b).map(|c| c as char).collect() };
+        match *self {
+            Prefix::Empty => None,
+            Prefix::Byte { byte, offset } =>
+                Some(format!("byte '{}' at offset {}", esc(byte), offset)),
+            Prefix::ByteSet { ref bytes, offset } => {
+                let set = (0..256usize).filter(|&b| bytes[b]).map(|b| esc(b as u8)).join("");
+                Some(format!("one of the bytes \"{}\" at offset {}", set, offset))
+            },
+            Prefix::BytePair { bytes, offset, .. } =>
+                Some(format!("bytes \"{}{}\" at offset {}", esc(bytes[0]), esc(bytes[1]), offset)),
+            Prefix::ByteRun { byte, stop: Some(stop) } => Some(
+                format!("byte '{}', rewinding to after the previous '{}'", esc(byte), esc(stop))),
+            Prefix::ByteRun { byte, stop: None } =>
+                Some(format!("byte '{}', rewinding to the start", esc(byte))),
+        }
+    }
+
     /// Converts a set of `PrefixParts` into a `Prefix` that matches any of the strings.
-    pub fn from_parts(mut parts: Vec<PrefixPart>) -> Prefix {
+    pub fn from_parts(mut parts: Vec<PrefixPart>, opts: &PrefilterOptions) -> Prefix {
         parts.retain(|x| !x.0.is_empty());
 
-        if let Some(pref) = Prefix::byte_prefix(&parts) {
-            pref
-        } else if let Some(pref) = Prefix::byte_set_prefix(&parts) {
-            pref
-        } else {
-            Prefix::Empty
-        }
+        Prefix::byte_pair_prefix(&parts, opts)
+            .or_else(|| Prefix::rarest_bytes_prefix(&parts, opts))
+            .unwrap_or(Prefix::Empty)
     }
 }
 
@@ -200,13 +633,19 @@ impl Prefix {
 mod tests {
     use dfa::PrefixPart;
     use super::*;
+    use super::default_byte_frequencies;
 
-    fn pref(strs: Vec<&str>) -> Prefix {
+    fn pref_with_options(strs: Vec<&str>, opts: &PrefilterOptions) -> Prefix {
         Prefix::from_parts(
             strs.into_iter()
                 .enumerate()
                 .map(|(i, s)| PrefixPart(s.as_bytes().to_vec(), i))
-                .collect())
+                .collect(),
+            opts)
+    }
+
+    fn pref(strs: Vec<&str>) -> Prefix {
+        pref_with_options(strs, &PrefilterOptions::default())
     }
 
     #[test]
@@ -217,14 +656,106 @@ mod tests {
         assert!(matches!(pref(vec![""]), Empty));
         assert!(matches!(pref(vec!["a"]), Byte {..}));
         assert!(matches!(pref(vec!["", "a", ""]), Byte {..}));
-        assert!(matches!(pref(vec!["abc"]), Byte {..}));
-        assert!(matches!(pref(vec!["abc", ""]), Byte {..}));
+        assert!(matches!(pref(vec!["abc"]), BytePair {..}));
+        assert!(matches!(pref(vec!["abc", ""]), BytePair {..}));
         assert!(matches!(pref(vec!["a", "b", "c"]), ByteSet {..}));
         assert!(matches!(pref(vec!["a", "b", "", "c"]), ByteSet {..}));
         assert!(matches!(pref(vec!["a", "baa", "", "c"]), ByteSet {..}));
         assert!(matches!(pref(vec!["ab", "baa", "", "cb"]), ByteSet {..}));
         assert!(matches!(pref(vec!["ab", "aaa", "", "acb"]), Byte {..}));
-        assert!(matches!(pref(vec!["ab", "abc", "abd"]), Byte {..}));
+        assert!(matches!(pref(vec!["ab", "abc", "abd"]), BytePair {..}));
+        assert!(matches!(pref(vec!["abc", "xbcd"]), BytePair { offset: 1, .. }));
+    }
+
+    #[test]
+    fn test_default_byte_frequencies() {
+        let freqs = default_byte_frequencies();
+        let mut sorted = freqs.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..256usize).map(|b| b as u8).collect::<Vec<_>>());
+        assert!(freqs[b'z' as usize] < freqs[b'e' as usize]);
+        assert!(freqs[b'e' as usize] < freqs[b' ' as usize]);
+        assert!(freqs[0xA9] < freqs[0xC3]);
+    }
+
+    #[test]
+    fn test_prefix_frequency() {
+        use super::Prefix::*;
+
+        let singles = PrefilterOptions { byte_pairs: false, ..Default::default() };
+        let pref = |strs| pref_with_options(strs, &singles);
+        assert!(matches!(pref(vec!["ez"]), Byte { byte: b'z', offset: 1 }));
+        assert!(matches!(pref(vec!["ez", "ezra"]), Byte { byte: b'z', offset: 1 }));
+        // The second byte is always the same, even though the first isn't.
+        assert!(matches!(pref(vec!["ax", "bx"]), Byte { byte: b'x', offset: 1 }));
+        // For 'é', search for the continuation byte instead of the (more common) leading byte.
+        assert!(matches!(pref(vec!["é"]), Byte { byte: 0xA9, offset: 1 }));
+
+        let mut freqs = default_byte_frequencies();
+        freqs[b'e' as usize] = 0;
+        let opts = PrefilterOptions { byte_frequencies: freqs, ..singles.clone() };
+        assert!(matches!(pref_with_options(vec!["ez"], &opts), Byte { byte: b'e', offset: 0 }));
+    }
+
+    #[test]
+    fn test_byte_pair() {
+        use super::Prefix::*;
+
+        assert!(matches!(pref(vec!["ez"]), BytePair { bytes: [b'e', b'z'], offset: 0, rare: 1 }));
+        assert!(matches!(pref(vec!["ezra", "ez"]), BytePair { offset: 0, .. }));
+        // The rarest pair wins, even if it isn't at the start.
+        assert!(matches!(pref(vec!["the zebra"]),
+                         BytePair { bytes: [b'z', b'e'], offset: 4, .. }));
+        // The parts don't agree on any pair.
+        assert!(matches!(pref(vec!["ax", "bx"]), Byte {..}));
+
+        let pair = pref(vec!["ez"]);
+        assert_eq!(pair.search(b"zzz eeez ez", 0), Some(6));
+        assert_eq!(pair.search(b"zzz eeez ez", 7), Some(9));
+        assert_eq!(pair.search(b"zzz eeez ez", 10), None);
+        assert_eq!(pair.search(b"ze", 0), None);
+        assert_eq!(pair.describe(), Some("bytes \"ez\" at offset 0".to_owned()));
+
+        let pair = pref(vec!["xez"]);
+        assert_eq!(pair.search(b"ez", 0), None);
+        assert_eq!(pair.search(b"aaez", 0), Some(1));
+    }
+
+    #[test]
+    fn test_prefix_options() {
+        use super::Prefix::*;
+
+        let disabled = PrefilterOptions { enabled: false, ..Default::default() };
+        assert!(matches!(pref_with_options(vec!["a"], &disabled), Empty));
+
+        let small = PrefilterOptions { max_byte_set_size: 2, ..Default::default() };
+        assert!(matches!(pref_with_options(vec!["a", "b"], &small), ByteSet {..}));
+        assert!(matches!(pref_with_options(vec!["a", "b", "c"], &small), Empty));
+
+        let common = PrefilterOptions { common_bytes: b"ab".to_vec(), ..Default::default() };
+        assert!(matches!(pref_with_options(vec!["ab"], &common), Empty));
+        assert!(matches!(pref_with_options(vec!["a", "c"], &common), Empty));
+        assert!(matches!(pref_with_options(vec!["c", "d"], &common), ByteSet {..}));
+        assert!(matches!(Prefix::from_start_bytes(b"xa", &common), Empty));
+        assert!(matches!(Prefix::from_start_bytes(b"x", &common), Byte {..}));
+    }
+
+    #[test]
+    fn test_leading_run() {
+        let run = |re: &str| {
+            Prefix::from_leading_run(&Expr::parse(re).unwrap(), &PrefilterOptions::default())
+        };
+        assert_eq!(run("(.*)c(.*)").describe(),
+                   Some("byte 'c', rewinding to after the previous '\\n'".to_owned()));
+        assert_eq!(run("[^x]*xy").describe(),
+                   Some("byte 'x', rewinding to after the previous 'x'".to_owned()));
+        assert_eq!(run("(?s).*ab").describe(), Some("byte 'a', rewinding to the start".to_owned()));
+        assert_eq!(run("(?i).*c").describe(), None);
+
+        let prefix = run("(.*)c(.*)");
+        assert_eq!(prefix.search(b"ab\nxyc z", 0), Some(3));
+        assert_eq!(prefix.search(b"abc\nc", 0), Some(0));
+        assert_eq!(prefix.search(b"ab\nxy", 0), None);
     }
 }
 
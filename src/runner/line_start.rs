@@ -0,0 +1,187 @@
+// Copyright 2015-2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byte_search::memchr;
+use encode::{Decoder, Encode};
+use look::Look;
+use runner::{Budget, Engine, LINE_START_TAG};
+use runner::forward_backward::ForwardBackwardEngine;
+use runner::program::{Disassembly, TableInsts, TraceEvent};
+
+/// An engine for regexes whose matches all begin either at the start of the input or just after a
+/// `'\n'` (for example, `(?m)^foo`).
+///
+/// Instead of running a DFA over the whole input, we use `memchr` to find the newlines and then
+/// try an anchored match just after each one. The programs return the kind of char that they
+/// looked ahead at, and the number of bytes of look-ahead.
+///
+/// If the anchored matches fail after running over too much of the input, we switch to `fallback`
+/// (see `runner::Budget`). It isn't part of the disassembly, since it only runs on unusual inputs.
+#[derive(Clone, Debug)]
+pub struct LineStartEngine {
+    // The program to run at the start of the input.
+    at_start: TableInsts<(Look, u8)>,
+    // The program to run just after a newline.
+    after_newline: TableInsts<(Look, u8)>,
+    fallback: Option<ForwardBackwardEngine<u8>>,
+}
+
+impl LineStartEngine {
+    pub fn new(at_start: TableInsts<(Look, u8)>,
+               after_newline: TableInsts<(Look, u8)>,
+               fallback: Option<ForwardBackwardEngine<u8>>)
+    -> LineStartEngine {
+        LineStartEngine {
+            at_start: at_start,
+            after_newline: after_newline,
+            fallback: fallback,
+        }
+    }
+
+    // Decodes an engine that was written by `encode` (minus the tag).
+    pub fn decode(input: &mut Decoder) -> Option<LineStartEngine> {
+        let at_start = try_opt!(TableInsts::decode(input));
+        let after_newline = try_opt!(TableInsts::decode(input));
+        let fallback = try_opt!(ForwardBackwardEngine::decode_fallback(input));
+        Some(LineStartEngine::new(at_start, after_newline, fallback))
+    }
+
+    // Tries an anchored match at `pos` that ends at or before `limit`, charging `budget` if it
+    // fails.
+    fn find_at(prog: &TableInsts<(Look, u8)>,
+               input: &[u8],
+               pos: usize,
+               limit: usize,
+               budget: &mut Budget)
+    -> Option<(usize, usize, u8, (Look, u8))> {
+        if prog.is_empty() {
+            return None;
+        }
+        match prog.find_before(input, pos, limit, 0) {
+            Ok((end, (look, bytes))) => Some((pos, end - bytes as usize, 0, (look, bytes))),
+            Err(end) => {
+                budget.fail(pos, end);
+                None
+            },
+        }
+    }
+}
+
+impl Engine<u8> for LineStartEngine {
+    fn find_look_ahead_before(&self, input: &[u8], pos: usize, limit: usize)
+    -> Option<(usize, usize, u8, (Look, u8))> {
+        let mut budget = Budget::new(input);
+        let from_start = pos == 0;
+        if from_start {
+            let prog = &self.at_start;
+            if let Some(ret) = LineStartEngine::find_at(prog, input, 0, limit, &mut budget) {
+                return Some(ret);
+            }
+        }
+        if self.after_newline.is_empty() {
+            return None;
+        }
+
+        // Start looking for newlines just before `pos`, so that `pos` itself is a candidate.
+        let mut pos = pos.saturating_sub(1);
+        loop {
+            if from_start && budget.is_exhausted() {
+                if let Some(ref fallback) = self.fallback {
+                    return fallback.find_look_ahead_before(input, 0, limit);
+                }
+            }
+            pos += match memchr(b'\n', &input[pos..]) {
+                Some(nl) => nl + 1,
+                None => { return None; },
+            };
+            let prog = &self.after_newline;
+            if let Some(ret) = LineStartEngine::find_at(prog, input, pos, limit, &mut budget) {
+                return Some(ret);
+            }
+        }
+    }
+
+    fn find_look_ahead_starting_at(&self, input: &[u8], pos: usize)
+    -> Option<(usize, usize, u8, (Look, u8))> {
+        let mut budget = Budget::new(input);
+        if pos == 0 {
+            LineStartEngine::find_at(&self.at_start, input, 0, input.len(), &mut budget)
+        } else if input[pos - 1] == b'\n' {
+            LineStartEngine::find_at(&self.after_newline, input, pos, input.len(), &mut budget)
+        } else {
+            None
+        }
+    }
+
+    fn is_match(&self, input: &[u8]) -> bool {
+        self.find(input).is_some()
+    }
+
+    fn for_detection(&self) -> Box<Engine<u8>> {
+        Box::new(LineStartEngine::new(self.at_start.for_detection(),
+                                      self.after_newline.for_detection(),
+                                      self.fallback.clone()))
+    }
+
+    fn clone_box(&self) -> Box<Engine<u8>> {
+        Box::new(self.clone())
+    }
+
+    fn disassemble(&self) -> Disassembly {
+        Disassembly {
+            programs: vec![("start", self.at_start.disassemble()),
+                           ("after newline", self.after_newline.disassemble())],
+            prefilter: Some("byte '\\n', then an anchored match".to_owned()),
+        }
+    }
+
+    fn trace(&self, s: &str) -> Vec<TraceEvent> {
+        let input = s.as_bytes();
+        let mut trace = Vec::new();
+        let mut budget = Budget::new(input);
+        if !self.at_start.is_empty() {
+            match self.at_start.trace_find_from("start", input, 0, 0, &mut trace) {
+                Ok(_) => { return trace; },
+                Err(end) => { budget.fail(0, end); },
+            }
+        }
+        if self.after_newline.is_empty() {
+            return trace;
+        }
+
+        let mut pos = 0;
+        loop {
+            if budget.is_exhausted() {
+                if let Some(ref fallback) = self.fallback {
+                    trace.extend(fallback.trace(s));
+                    break;
+                }
+            }
+            let found = memchr(b'\n', &input[pos..]).map(|nl| pos + nl + 1);
+            trace.push(TraceEvent::Search { pos: pos, found: found });
+            pos = match found {
+                Some(start) => start,
+                None => { break; },
+            };
+
+            let prog = &self.after_newline;
+            match prog.trace_find_from("after newline", input, pos, 0, &mut trace) {
+                Ok(_) => { break; },
+                Err(end) => { budget.fail(pos, end); },
+            }
+        }
+        trace
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(LINE_START_TAG);
+        self.at_start.encode(out);
+        self.after_newline.encode(out);
+        ForwardBackwardEngine::encode_fallback(&self.fallback, out);
+    }
+}
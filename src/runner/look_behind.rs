@@ -0,0 +1,175 @@
+// Copyright 2015-2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use encode::{Decoder, Encode};
+use look::{Look, WordChars};
+use runner::{Budget, Engine, LOOK_BEHIND_TAG};
+use runner::forward_backward::{ForwardBackwardEngine, Prefix};
+use runner::program::{Disassembly, TableInsts, TraceEvent};
+
+/// An engine for regexes that look at the char before the match (for example, `\bfoo`).
+///
+/// The forward-backward engine has to consume the char before the match, which usually makes
+/// prefilters useless. Instead, we search for the prefilter (which only needs to describe the
+/// match itself), look at the char before the candidate position, and then start an anchored
+/// match in the corresponding initial state. The program returns the kind of char that it looked
+/// ahead at, and the number of bytes of look-ahead.
+///
+/// As with `LineStartEngine`, if the anchored matches fail after running over too much of the
+/// input, we switch to `fallback` (see `runner::Budget`).
+#[derive(Clone, Debug)]
+pub struct LookBehindEngine {
+    prog: TableInsts<(Look, u8)>,
+    // The initial state of `prog` for each kind of preceding char, indexed by `Look::as_usize`.
+    init: Vec<Option<usize>>,
+    // The word chars that decide which of `init` to use.
+    words: WordChars,
+    prefix: Prefix,
+    fallback: Option<ForwardBackwardEngine<u8>>,
+}
+
+impl LookBehindEngine {
+    pub fn new(prog: TableInsts<(Look, u8)>,
+               init: Vec<Option<usize>>,
+               words: WordChars,
+               prefix: Prefix,
+               fallback: Option<ForwardBackwardEngine<u8>>)
+    -> LookBehindEngine {
+        LookBehindEngine {
+            prog: prog,
+            init: init,
+            words: words,
+            prefix: prefix,
+            fallback: fallback,
+        }
+    }
+
+    // Decodes an engine that was written by `encode` (minus the tag).
+    pub fn decode(input: &mut Decoder) -> Option<LookBehindEngine> {
+        let prog: TableInsts<(Look, u8)> = try_opt!(TableInsts::decode(input));
+        let init: Vec<Option<usize>> = try_opt!(Vec::decode(input));
+        let words = try_opt!(WordChars::decode(input));
+        let prefix = try_opt!(Prefix::decode(input));
+        let fallback = try_opt!(ForwardBackwardEngine::decode_fallback(input));
+
+        let num_states = prog.num_states();
+        if init.len() != Look::num() || init.iter().any(|&s| s.map_or(false, |s| s >= num_states)) {
+            return None;
+        }
+        Some(LookBehindEngine::new(prog, init, words, prefix, fallback))
+    }
+
+    // Returns the initial state for a match beginning at `pos`, if there is one.
+    fn init_state(&self, input: &[u8], pos: usize) -> Option<usize> {
+        self.init[self.words.before(input, pos).as_usize()]
+    }
+}
+
+impl Engine<u8> for LookBehindEngine {
+    fn find_look_ahead_before(&self, input: &[u8], mut pos: usize, limit: usize)
+    -> Option<(usize, usize, u8, (Look, u8))> {
+        if self.prog.is_empty() {
+            return None;
+        }
+
+        let mut budget = Budget::new(input);
+        let from_start = pos == 0;
+        while let Some(start) = self.prefix.search(input, pos) {
+            if from_start && budget.is_exhausted() {
+                if let Some(ref fallback) = self.fallback {
+                    return fallback.find_look_ahead_before(input, 0, limit);
+                }
+            }
+            if let Some(state) = self.init_state(input, start) {
+                match self.prog.find_before(input, start, limit, state) {
+                    Ok((end, (look, bytes))) => {
+                        return Some((start, end - bytes as usize, 0, (look, bytes)));
+                    },
+                    Err(end) => { budget.fail(start, end); },
+                }
+            }
+            pos = start + 1;
+        }
+        None
+    }
+
+    fn find_look_ahead_starting_at(&self, input: &[u8], pos: usize)
+    -> Option<(usize, usize, u8, (Look, u8))> {
+        if self.prog.is_empty() {
+            return None;
+        }
+        let state = try_opt!(self.init_state(input, pos));
+        self.prog.find_from(input, pos, state)
+            .ok()
+            .map(|(end, (look, bytes))| (pos, end - bytes as usize, 0, (look, bytes)))
+    }
+
+    fn is_match(&self, input: &[u8]) -> bool {
+        self.find(input).is_some()
+    }
+
+    fn for_detection(&self) -> Box<Engine<u8>> {
+        let prog = self.prog.for_detection();
+        Box::new(LookBehindEngine::new(prog, self.init.clone(), self.words.clone(),
+                                       self.prefix.clone(), self.fallback.clone()))
+    }
+
+    fn clone_box(&self) -> Box<Engine<u8>> {
+        Box::new(self.clone())
+    }
+
+    fn disassemble(&self) -> Disassembly {
+        Disassembly {
+            programs: vec![("anchored", self.prog.disassemble())],
+            prefilter: self.prefix.describe(),
+        }
+    }
+
+    fn trace(&self, s: &str) -> Vec<TraceEvent> {
+        let input = s.as_bytes();
+        let mut trace = Vec::new();
+        if self.prog.is_empty() {
+            return trace;
+        }
+
+        let mut budget = Budget::new(input);
+        let mut pos = 0;
+        loop {
+            let found = self.prefix.search(input, pos);
+            trace.push(TraceEvent::Search { pos: pos, found: found });
+            let start = match found {
+                Some(start) => start,
+                None => { break; },
+            };
+            if budget.is_exhausted() {
+                if let Some(ref fallback) = self.fallback {
+                    trace.extend(fallback.trace(s));
+                    break;
+                }
+            }
+
+            if let Some(state) = self.init_state(input, start) {
+                match self.prog.trace_find_from("anchored", input, start, state, &mut trace) {
+                    Ok(_) => { break; },
+                    Err(end) => { budget.fail(start, end); },
+                }
+            }
+            pos = start + 1;
+        }
+        trace
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(LOOK_BEHIND_TAG);
+        self.prog.encode(out);
+        self.init.encode(out);
+        self.words.encode(out);
+        self.prefix.encode(out);
+        ForwardBackwardEngine::encode_fallback(&self.fallback, out);
+    }
+}
@@ -1,10 +1,98 @@
+use look::Look;
+use runner::program::{Disassembly, TraceEvent};
 use std::fmt::Debug;
 
-pub trait Engine<Ret: Debug>: Debug {
-    fn find(&self, s: &str) -> Option<(usize, usize, Ret)>;
+// Engines are immutable once they are built, so they can be shared between threads.
+pub trait Engine<Ret: Debug>: Debug + Send + Sync {
+    fn find(&self, input: &[u8]) -> Option<(usize, usize, Ret)> {
+        self.find_look_ahead(input).map(|(start, end, ret, _)| (start, end, ret))
+    }
+    // Like `find`, but also says what the match needed to see after its end: the kind of char
+    // (`Look::Full` if it didn't look ahead at all, or `Look::Boundary` if it needed to be at the
+    // end of the input) and the number of bytes of look-ahead.
+    fn find_look_ahead(&self, input: &[u8]) -> Option<(usize, usize, Ret, (Look, u8))> {
+        self.find_look_ahead_at(input, 0)
+    }
+    // Like `find_look_ahead`, but only for matches that start at or after `pos`. Unlike searching
+    // in `&input[pos..]`, look-behind assertions at `pos` can see the char before it.
+    //
+    // The prefilters only suggest places to start, and the programs decide where the match
+    // really begins and ends. Since the programs only ever consume whole chars, if `input` is
+    // valid UTF-8 and `pos` is a char boundary then so are the start and end of the match (after
+    // adding the look-behind return value).
+    fn find_look_ahead_at(&self, input: &[u8], pos: usize)
+    -> Option<(usize, usize, Ret, (Look, u8))> {
+        self.find_look_ahead_before(input, pos, input.len())
+    }
+    // Like `find_look_ahead_at`, but only for matches that end at or before `limit`. Unlike
+    // searching in `&input[..limit]`, look-ahead assertions at `limit` can see the char after it.
+    // The search doesn't stop at `limit` (since it doesn't know where that char ends), so callers
+    // should leave at most one char after it.
+    fn find_look_ahead_before(&self, input: &[u8], pos: usize, limit: usize)
+    -> Option<(usize, usize, Ret, (Look, u8))>;
+    // Like `find_look_ahead_at`, but for callers that are only interested in a match starting at
+    // `pos`: engines that can check that position directly return `None` if there is no match
+    // there, but the others may still return a match that starts later.
+    fn find_look_ahead_starting_at(&self, input: &[u8], pos: usize)
+    -> Option<(usize, usize, Ret, (Look, u8))> {
+        self.find_look_ahead_at(input, pos)
+    }
+    fn is_match(&self, input: &[u8]) -> bool;
+    // Returns an engine whose `is_match` is faster, but whose `find` might not return the
+    // preferred match.
+    fn for_detection(&self) -> Box<Engine<Ret>>;
     fn clone_box(&self) -> Box<Engine<Ret>>;
+    fn disassemble(&self) -> Disassembly;
+    fn trace(&self, s: &str) -> Vec<TraceEvent>;
+    // Writes this engine in the format of the `encode` module, starting with its tag (see below).
+    fn encode(&self, out: &mut Vec<u8>);
 }
 
+// Searching is supposed to take time linear in the length of the input, but engines that try an
+// anchored match at every candidate position can be quadratic: for example, `(?m)^a[^y]*yz` on
+// many lines that start with `a` makes every attempt run to the end of the input. Such engines
+// count the bytes consumed by their failed attempts, and once a search that started at the
+// beginning of the input has spent more than the length of the input on them, they give up on
+// anchored matches and hand the search over to a forward-backward engine. Overall, no search of
+// the whole input consumes more than `LINEAR_FACTOR` bytes per input byte (see
+// `Regex::debug_assert_linear`).
+pub const LINEAR_FACTOR: usize = 4;
+
+pub struct Budget {
+    spent: usize,
+    limit: usize,
+}
+
+impl Budget {
+    pub fn new(input: &[u8]) -> Budget {
+        Budget {
+            spent: 0,
+            limit: input.len(),
+        }
+    }
+
+    // Records an anchored attempt that began at `start` and failed at `end` (as returned by
+    // `TableInsts::find_from`).
+    pub fn fail(&mut self, start: usize, end: usize) {
+        self.spent += end + 1 - start;
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.spent > self.limit
+    }
+}
+
+// The tags that begin the encodings of the different engines.
+pub const EMPTY_TAG: u8 = 0;
+pub const ANCHORED_TAG: u8 = 1;
+pub const LINE_START_TAG: u8 = 2;
+pub const LOOK_BEHIND_TAG: u8 = 3;
+pub const FORWARD_BACKWARD_TAG: u8 = 4;
+pub const END_ANCHORED_TAG: u8 = 5;
+
 pub mod anchored;
+pub mod end_anchored;
 pub mod forward_backward;
+pub mod line_start;
+pub mod look_behind;
 pub mod program;
@@ -6,12 +6,137 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::fmt::{Debug, Formatter, Error as FmtError};
-use std::u32;
+use encode::{Decoder, Encode};
+use haystack::Haystack;
+use look::Look;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::ascii;
+use std::cmp::min;
+use std::fmt::{self, Debug, Display, Formatter, Error as FmtError};
+use std::{u8, u32};
 
-pub type TableStateIdx = u32;
+/// The transitions of a `TableInsts`, stored in the smallest type that can hold every state.
+///
+/// Most regexes (and nearly all validation patterns) compile to programs with only a few states,
+/// and storing their transitions as `u8` makes the table four times smaller, so more of it stays
+/// in the cache.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Table {
+    /// For programs with at most 255 states; `u8::MAX` means "no transition".
+    Small(Vec<u8>),
+    /// For all other programs; `u32::MAX` means "no transition".
+    Large(Vec<u32>),
+}
+
+// A type that transition targets can be stored as.
+trait Target: Copy {
+    // Returns the target as an index. "No transition" turns into an index that is at least as
+    // big as the number of states.
+    fn index(self) -> usize;
+}
+
+impl Target for u8 {
+    #[inline(always)]
+    fn index(self) -> usize { self as usize }
+}
+
+impl Target for u32 {
+    #[inline(always)]
+    fn index(self) -> usize { self as usize }
+}
+
+impl Table {
+    /// Makes a table for a program with `num_states` states from a list of targets, where
+    /// `u32::MAX` means "no transition". The `Small` representation is chosen if possible.
+    ///
+    /// Targets that don't exist can't be represented faithfully in a `Small` table, so if there
+    /// are any then we keep the `Large` one (and let `TableInsts::check` complain about it).
+    pub fn from_targets(targets: Vec<u32>, num_states: usize) -> Table {
+        let valid = |&t: &u32| t == u32::MAX || (t as usize) < num_states;
+        if num_states <= u8::MAX as usize && targets.iter().all(valid) {
+            Table::Small(targets.into_iter()
+                .map(|t| if t == u32::MAX { u8::MAX } else { t as u8 })
+                .collect())
+        } else {
+            Table::Large(targets)
+        }
+    }
+
+    /// Returns the list of targets, where `u32::MAX` means "no transition".
+    pub fn to_targets(&self) -> Vec<u32> {
+        (0..self.len()).map(|i| self.get(i)).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        match *self {
+            Table::Small(ref t) => t.len(),
+            Table::Large(ref t) => t.len(),
+        }
+    }
+
+    /// Returns the target at index `i`, where `u32::MAX` means "no transition".
+    pub fn get(&self, i: usize) -> u32 {
+        match *self {
+            Table::Small(ref t) => if t[i] == u8::MAX { u32::MAX } else { t[i] as u32 },
+            Table::Large(ref t) => t[i],
+        }
+    }
+
+    /// Sets the target at index `i`, where `u32::MAX` means "no transition".
+    pub fn set(&mut self, i: usize, target: u32) {
+        match *self {
+            Table::Small(ref mut t) => {
+                debug_assert!(target == u32::MAX || target < u8::MAX as u32);
+                t[i] = if target == u32::MAX { u8::MAX } else { target as u8 };
+            },
+            Table::Large(ref mut t) => { t[i] = target; },
+        }
+    }
+}
+
+/// The set of bytes on which a state loops back to itself, when they all fit in a window of 64
+/// consecutive bytes (as they do for `[0-9]*`, `[A-Za-z_]*` or `[ \t]*`).
+///
+/// Testing a byte against the mask is a subtraction, a comparison and a shift, which is cheaper
+/// than going through the byte classes and the transition table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoopMask {
+    base: u8,
+    mask: u64,
+}
+
+impl LoopMask {
+    /// Returns the mask containing exactly `bytes`, or `None` if they don't fit in the window (or
+    /// if there aren't any).
+    fn from_bytes<I: Iterator<Item=u8>>(bytes: I) -> Option<LoopMask> {
+        let bytes: Vec<u8> = bytes.collect();
+        let base = try_opt!(bytes.iter().cloned().min());
+        let mut mask = 0u64;
+        for &b in &bytes {
+            let offset = b - base;
+            if offset >= 64 {
+                return None;
+            }
+            mask |= 1 << offset;
+        }
+        Some(LoopMask { base: base, mask: mask })
+    }
+
+    #[inline(always)]
+    fn contains(&self, b: u8) -> bool {
+        let offset = b.wrapping_sub(self.base) as u32;
+        offset < 64 && (self.mask >> offset) & 1 == 1
+    }
+}
 
 /// A DFA program implemented as a lookup table.
+///
+/// The whole program is a handful of flat arrays: there is no per-state instruction to decode,
+/// and each step of the main loop is two array lookups (one to find the byte's class, and one to
+/// find the next state). States are indices into the table, stored as `u8` if there are at most
+/// 255 of them and as `u32` otherwise (see `Table`), so a program can have up to `u32::MAX - 1`
+/// states.
 #[derive(Clone)]
 pub struct TableInsts<Ret> {
     /// The log (rounded up) of the number of different equivalence classes of bytes.
@@ -29,12 +154,91 @@ pub struct TableInsts<Ret> {
     ///
     /// For a given input byte `b` in state `state`, we look up the next state using
     /// `table[state << log_num_classes + b]`.
-    pub table: Vec<TableStateIdx>,
+    pub table: Table,
     /// If `accept[st]` is not `None` then `st` is accepting, and `accept[st]` is the data
     /// to return.
     pub accept: Vec<Option<Ret>>,
     /// Same as `accept`, but applies only at the end of the input.
     pub accept_at_eoi: Vec<Option<Ret>>,
+    /// For each state, the bytes on which it loops back to itself, if they fit in a `LoopMask`.
+    ///
+    /// This is derived from `table`, and must be recomputed (with `find_loops`) after changing it.
+    pub loops: Vec<Option<LoopMask>>,
+}
+
+/// A machine-readable description of one state of a compiled program.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InstInfo {
+    /// The index of this state.
+    pub state: usize,
+    /// The transitions out of this state. Each element is `(first_byte, last_byte, target)`, and
+    /// means that any byte between `first_byte` and `last_byte` (inclusive) leads to `target`.
+    /// Adjacent ranges with the same target are merged.
+    pub transitions: Vec<(u8, u8, usize)>,
+    /// Does this state accept, regardless of what comes next?
+    pub accept: bool,
+    /// Does this state accept if it is reached at the end of the input?
+    pub accept_at_eoi: bool,
+}
+
+/// A single step in the execution of a `Regex`, as recorded by `Regex::trace`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TraceEvent {
+    /// We searched for a place to start matching, beginning at `pos`. `found` is the position
+    /// that the search found (if any).
+    Search { pos: usize, found: Option<usize> },
+    /// In state `state`, the given program consumed `byte` at position `pos`, and moved to state
+    /// `next` (or failed, if `next` is `None`).
+    Step { program: &'static str, pos: usize, byte: u8, state: usize, next: Option<usize> },
+    /// The given program found a match ending (or, for backward programs, starting) at `pos`.
+    Accept { program: &'static str, pos: usize, state: usize },
+}
+
+/// A readable listing of the programs making up a `Regex`.
+///
+/// The `Display` implementation produces a human-readable listing; the fields can be used by tools
+/// that want to inspect the programs directly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Disassembly {
+    /// The programs that the `Regex` runs, together with a name describing their role.
+    pub programs: Vec<(&'static str, Vec<InstInfo>)>,
+    /// A description of the prefilter that is used to skip uninteresting input, if there is one.
+    pub prefilter: Option<String>,
+}
+
+// Escapes a byte so that it is printable.
+fn escape_byte(b: u8) -> String {
+    ascii::escape_default(b).map(|c| c as char).collect()
+}
+
+impl Display for Disassembly {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for &(name, ref insts) in &self.programs {
+            try!(writeln!(f, "{} program ({} states):", name, insts.len()));
+            for inst in insts {
+                try!(write!(f, "  state {}:", inst.state));
+                if inst.accept {
+                    try!(write!(f, " accept"));
+                } else if inst.accept_at_eoi {
+                    try!(write!(f, " accept at end of input"));
+                }
+                try!(writeln!(f, ""));
+
+                for &(start, end, tgt) in &inst.transitions {
+                    if start == end {
+                        try!(writeln!(f, "    '{}' => {}", escape_byte(start), tgt));
+                    } else {
+                        try!(writeln!(f, "    '{}'..'{}' => {}",
+                                      escape_byte(start), escape_byte(end), tgt));
+                    }
+                }
+            }
+        }
+        if let Some(ref pre) = self.prefilter {
+            try!(writeln!(f, "prefilter: {}", pre));
+        }
+        Ok(())
+    }
 }
 
 impl<Ret: Debug> Debug for TableInsts<Ret> {
@@ -52,7 +256,7 @@ impl<Ret: Debug> Debug for TableInsts<Ret> {
             try!(f.write_fmt(format_args!("State {}:\n", idx)));
             try!(f.debug_map()
                 .entries((0usize..num_classes)
-                    .map(|c| (c, self.table[(idx << self.log_num_classes) + c]))
+                    .map(|c| (c, self.table.get((idx << self.log_num_classes) + c)))
                     .filter(|x| x.1 != u32::MAX))
                 .finish());
             try!(f.write_str("\n"));
@@ -76,9 +280,9 @@ impl<Ret: Debug> Debug for TableInsts<Ret> {
 }
 
 impl<Ret: Copy + Debug> TableInsts<Ret> {
-    fn next_state(&self, state: usize, input: u8) -> Option<usize> {
+    pub fn next_state(&self, state: usize, input: u8) -> Option<usize> {
         let class = self.byte_class[input as usize];
-        let next_state = self.table[(state << self.log_num_classes) + class as usize];
+        let next_state = self.table.get((state << self.log_num_classes) + class as usize);
         if next_state != u32::MAX {
             Some(next_state as usize)
         } else {
@@ -92,30 +296,139 @@ impl<Ret: Copy + Debug> TableInsts<Ret> {
 
     pub fn find_from(&self, input: &[u8], pos: usize, state: usize)
     -> Result<(usize, Ret), usize> {
-        let mut state = state as u32;
+        self.find_from_with("", input, pos, state, &mut |_| {})
+    }
+
+    /// Does the same thing as `find_from`, but also reports every step to `on_event` (using `name`
+    /// for the program). When `on_event` does nothing, this is exactly as fast as `find_from`.
+    pub fn find_from_with<F>(&self,
+                             name: &'static str,
+                             input: &[u8],
+                             pos: usize,
+                             state: usize,
+                             on_event: &mut F)
+    -> Result<(usize, Ret), usize>
+    where F: FnMut(TraceEvent) {
+        self.find_before_with(name, input, pos, input.len(), state, |_, _| true, on_event)
+    }
+
+    /// Does the same thing as `find_from_with`, but only for matches that end at or before
+    /// `limit`. Every accepting position up to `limit` counts, but one after it only counts if
+    /// `keep` (which is given the position and the return value) says that the match really ends
+    /// at or before `limit`, because the rest was look-ahead.
+    pub fn find_before_with<K, F>(&self,
+                                  name: &'static str,
+                                  input: &[u8],
+                                  pos: usize,
+                                  limit: usize,
+                                  state: usize,
+                                  keep: K,
+                                  on_event: &mut F)
+    -> Result<(usize, Ret), usize>
+    where K: Fn(usize, Ret) -> bool, F: FnMut(TraceEvent) {
+        // We choose the representation once, outside the loop, so that each version of the loop
+        // only deals with one type of table.
+        match self.table {
+            Table::Small(ref table) =>
+                self.find_from_table(&table[..], name, input, pos, limit, state, keep, on_event),
+            Table::Large(ref table) =>
+                self.find_from_table(&table[..], name, input, pos, limit, state, keep, on_event),
+        }
+    }
+
+    fn find_from_table<T, K, F>(&self,
+                                table: &[T],
+                                name: &'static str,
+                                input: &[u8],
+                                pos: usize,
+                                limit: usize,
+                                state: usize,
+                                keep: K,
+                                on_event: &mut F)
+    -> Result<(usize, Ret), usize>
+    where T: Target, K: Fn(usize, Ret) -> bool, F: FnMut(TraceEvent) {
+        let mut state = state;
         let mut ret = Err(input.len());
+        // The self-loops below don't skip past `limit`, so that the main loop records the last
+        // accepting position that counts.
+        let skip_end = min(input.len().saturating_sub(1), limit);
 
-        if state as usize >= self.accept.len() {
+        if state >= self.accept.len() {
             panic!("BUG");
         }
-        for pos in pos..input.len() {
-            if let Some(accept_ret) = self.accept[state as usize] {
-                ret = Ok((pos, accept_ret));
+        // Reports the events for consuming the byte at `pos` in a state that loops back to itself.
+        let self_loop = |on_event: &mut F, pos: usize, state: usize, accepting: bool| {
+            if accepting {
+                on_event(TraceEvent::Accept { program: name, pos: pos, state: state });
+            }
+            on_event(TraceEvent::Step {
+                program: name,
+                pos: pos,
+                byte: input[pos],
+                state: state,
+                next: Some(state),
+            });
+        };
+
+        let mut pos = pos;
+        while pos < input.len() {
+            let accept_here = self.accept[state];
+            let accepting = accept_here.is_some();
+            if let Some(accept_ret) = accept_here {
+                on_event(TraceEvent::Accept { program: name, pos: pos, state: state });
+                if pos <= limit || keep(pos, accept_ret) {
+                    ret = Ok((pos, accept_ret));
+                }
             }
 
             // We've manually inlined next_state here, for better performance (measurably better
             // than using #[inline(always)]).
             // For some reason, these bounds checks (even though LLVM leaves them in) don't seem to
             // hurt performance.
-            let class = self.byte_class[input[pos] as usize];
-            state = self.table[((state as usize) << self.log_num_classes) + class as usize];
+            let base = state << self.log_num_classes;
+            let next = table[base + self.byte_class[input[pos] as usize] as usize].index();
+            on_event(TraceEvent::Step {
+                program: name,
+                pos: pos,
+                byte: input[pos],
+                state: state,
+                next: if next < self.accept.len() { Some(next) } else { None },
+            });
+            pos += 1;
 
-            // Since everything in `self.table` is either a valid state or u32::MAX, this is the
-            // same as checking if state == u32::MAX. We write it this way in the hope that
-            // rustc/LLVM will be able to elide the bounds check at the top of the loop.
-            if state as usize >= self.accept.len() {
+            if next == state {
+                // This state loops back to itself (as in `[^"]*`, anywhere in the regex). While it
+                // keeps doing that, each lookup only depends on the input and not on the previous
+                // lookup, so a tight loop that only looks for the way out runs faster than the main
+                // loop. We leave the last byte to the main loop, so that it records the accepting
+                // position exactly as it would have without skipping.
+                if let Some(mask) = self.loops[state] {
+                    // The common case of identifiers and numbers: skip the table entirely.
+                    while pos < skip_end && mask.contains(input[pos]) {
+                        self_loop(on_event, pos, state, accepting);
+                        pos += 1;
+                    }
+                    continue;
+                }
+                while pos < skip_end {
+                    let class = self.byte_class[input[pos] as usize];
+                    if table[base + class as usize].index() != state {
+                        break;
+                    }
+                    self_loop(on_event, pos, state, accepting);
+                    pos += 1;
+                }
+                continue;
+            }
+            state = next;
+
+            // Since everything in `table` is either a valid state or "no transition" (which is at
+            // least the number of states), this is the same as checking for "no transition". We
+            // write it this way in the hope that rustc/LLVM will be able to elide the bounds check
+            // at the top of the loop.
+            if state >= self.accept.len() {
                 if ret.is_err() {
-                    return Err(pos);
+                    return Err(pos - 1);
                 }
                 break;
             }
@@ -123,37 +436,627 @@ impl<Ret: Copy + Debug> TableInsts<Ret> {
 
         // If we made it to the end of the input, prefer a return value that is specific to EOI
         // over one that can occur anywhere.
-        if (state as usize) < self.accept.len() {
-            if let Some(accept_ret) = self.accept_at_eoi[state as usize] {
-                return Ok((input.len(), accept_ret))
+        if state < self.accept.len() {
+            if let Some(accept_ret) = self.accept_at_eoi[state] {
+                on_event(TraceEvent::Accept { program: name, pos: input.len(), state: state });
+                if input.len() <= limit || keep(input.len(), accept_ret) {
+                    return Ok((input.len(), accept_ret))
+                }
             }
         }
         ret
     }
 
-    pub fn longest_backward_find_from(&self, input: &[u8], pos: usize, mut state: usize)
+    /// Does the same thing as `find_from` (starting at the beginning of `input`), but reads the
+    /// input one chunk at a time. Returns `None` instead of the failure position.
+    pub fn find_in_chunks<H: Haystack + ?Sized>(&self, input: &H, state: usize)
     -> Option<(usize, Ret)> {
+        let mut state = Some(state);
+        let mut ret = None;
+        let mut pos = 0;
+        input.for_each_chunk(&mut |chunk: &[u8]| {
+            for &b in chunk {
+                let st = state.unwrap();
+                if let Some(accept_ret) = self.accept[st] {
+                    ret = Some((pos, accept_ret));
+                }
+                state = self.next_state(st, b);
+                if state.is_none() {
+                    return false;
+                }
+                pos += 1;
+            }
+            true
+        });
+
+        match state {
+            Some(st) => self.accept_at_eoi[st].map(|r| (pos, r)).or(ret),
+            None => ret,
+        }
+    }
+
+    // Runs the program backward from `pos` to `stop`, starting in `state`, and returns the
+    // earliest position where it accepted with a return value that `keep` allows. Every step is
+    // reported to `on_event`. If `stop` is zero, it's the beginning of the input.
+    fn longest_backward_find_with<K, F>(&self,
+                                        name: &'static str,
+                                        input: &[u8],
+                                        stop: usize,
+                                        pos: usize,
+                                        mut state: usize,
+                                        keep: K,
+                                        on_event: &mut F)
+    -> Option<(usize, Ret)>
+    where K: Fn(usize, Ret) -> bool, F: FnMut(TraceEvent) {
         let mut ret = None;
-        for pos in (0..pos).rev() {
+        for pos in (stop..pos).rev() {
             if let Some(next_ret) = self.accept[state] {
-                ret = Some((pos + 1, next_ret));
+                if keep(pos + 1, next_ret) {
+                    on_event(TraceEvent::Accept { program: name, pos: pos + 1, state: state });
+                    ret = Some((pos + 1, next_ret));
+                }
             }
-            if let Some(next_state) = self.next_state(state, input[pos]) {
-                state = next_state;
-            } else {
-                return ret;
+
+            let next = self.next_state(state, input[pos]);
+            on_event(TraceEvent::Step {
+                program: name,
+                pos: pos,
+                byte: input[pos],
+                state: state,
+                next: next,
+            });
+            match next {
+                Some(next) => { state = next; },
+                None => { return ret; },
             }
         }
 
-        if let Some(end_ret) = self.accept_at_eoi[state] {
-            Some((0, end_ret))
-        } else {
-            ret
+        let last = if stop == 0 { self.accept_at_eoi[state] } else { self.accept[state] };
+        match last {
+            Some(last_ret) if keep(stop, last_ret) => {
+                on_event(TraceEvent::Accept { program: name, pos: stop, state: state });
+                Some((stop, last_ret))
+            },
+            _ => ret,
         }
     }
 
+    /// Runs the program forward from `pos`, starting in `state`, and returns both the first and
+    /// the last places where it accepted, from the same pass over the input.
+    ///
+    /// Unlike `find_from`, this doesn't skip through states that loop back to themselves, since
+    /// it needs to see the first accepting position.
+    pub fn first_and_last_accept_from(&self, input: &[u8], pos: usize, mut state: usize)
+    -> Option<((usize, Ret), (usize, Ret))> {
+        let mut first = None;
+        let mut last = None;
+        for pos in pos..input.len() {
+            if let Some(ret) = self.accept[state] {
+                first = first.or(Some((pos, ret)));
+                last = Some((pos, ret));
+            }
+            match self.next_state(state, input[pos]) {
+                Some(next_state) => { state = next_state; },
+                None => { return first.and_then(|f| last.map(|l| (f, l))); },
+            }
+        }
+
+        if let Some(ret) = self.accept_at_eoi[state] {
+            last = Some((input.len(), ret));
+            first = first.or(last);
+        }
+        first.and_then(|f| last.map(|l| (f, l)))
+    }
+
+    /// Does the same thing as `find_from`, but records every step in `trace`.
+    ///
+    /// This is much slower than `find_from`, and is only intended for debugging.
+    pub fn trace_find_from(&self,
+                           name: &'static str,
+                           input: &[u8],
+                           pos: usize,
+                           state: usize,
+                           trace: &mut Vec<TraceEvent>)
+    -> Result<(usize, Ret), usize> {
+        self.find_from_with(name, input, pos, state, &mut |ev| trace.push(ev))
+    }
+
+    /// Runs the program backward from `pos` to the beginning of the input, starting in `state`,
+    /// and returns the earliest position where it accepted. Every step is recorded in `trace`.
+    pub fn trace_longest_backward_find_from(&self,
+                                            name: &'static str,
+                                            input: &[u8],
+                                            pos: usize,
+                                            state: usize,
+                                            trace: &mut Vec<TraceEvent>)
+    -> Option<(usize, Ret)> {
+        self.longest_backward_find_with(name, input, 0, pos, state, |_, _| true,
+                                        &mut |ev| trace.push(ev))
+    }
+
     pub fn is_empty(&self) -> bool {
         self.num_states() == 0
     }
+
+    /// Runs the program on `input`, starting in `state`, and stops early if it reaches an
+    /// accepting state. Returns the state that it stopped in, or `None` if it failed.
+    ///
+    /// Unlike the `find` methods, this doesn't need to see the whole input at once: the input can
+    /// be fed in one chunk at a time, starting each chunk in the state that the last one ended in.
+    pub fn run_until_accept(&self, mut state: usize, input: &[u8]) -> Option<usize> {
+        for &b in input {
+            if self.accept[state].is_some() {
+                break;
+            }
+            state = try_opt!(self.next_state(state, b));
+        }
+        Some(state)
+    }
+
+    /// Returns a version of this program that is only good for detecting whether there is a match.
+    ///
+    /// In the new program, accepting states have no transitions out of them, so `find_from` stops
+    /// as soon as it reaches one instead of continuing to look for a better match. The match that
+    /// it returns is therefore the shortest one, rather than the preferred one.
+    pub fn for_detection(&self) -> TableInsts<Ret> {
+        let mut ret = self.clone();
+        for st in 0..self.num_states() {
+            if self.accept[st].is_some() {
+                let start = st << self.log_num_classes;
+                let end = (st + 1) << self.log_num_classes;
+                for i in start..end {
+                    ret.table.set(i, u32::MAX);
+                }
+            }
+        }
+        ret.find_loops();
+        ret
+    }
+
+    /// Returns a description of every state in this program.
+    pub fn disassemble(&self) -> Vec<InstInfo> {
+        (0..self.num_states()).map(|st| {
+            let mut transitions: Vec<(u8, u8, usize)> = Vec::new();
+            for b in 0..256usize {
+                if let Some(tgt) = self.next_state(st, b as u8) {
+                    let extend = match transitions.last() {
+                        Some(&(_, end, last_tgt)) => end as usize + 1 == b && last_tgt == tgt,
+                        None => false,
+                    };
+                    if extend {
+                        transitions.last_mut().unwrap().1 = b as u8;
+                    } else {
+                        transitions.push((b as u8, b as u8, tgt));
+                    }
+                }
+            }
+
+            InstInfo {
+                state: st,
+                transitions: transitions,
+                accept: self.accept[st].is_some(),
+                accept_at_eoi: self.accept_at_eoi[st].is_some(),
+            }
+        }).collect()
+    }
+}
+
+impl TableInsts<u8> {
+    /// Runs a backward program that returns the number of bytes of look-behind that it consumed
+    /// from `pos`, starting in `state`, and returns the earliest position where it accepted, but
+    /// only for matches that start at or after `limit`.
+    ///
+    /// Unlike running on `&input[limit..]`, the program can read the char before `limit` to check
+    /// a look-behind assertion (instead of assuming that `limit` is the beginning of the input),
+    /// but it doesn't go any further back than that. The match starts at the returned position
+    /// plus the returned number of bytes.
+    pub fn longest_backward_find_after(&self,
+                                       input: &[u8],
+                                       limit: usize,
+                                       pos: usize,
+                                       state: usize)
+    -> Option<(usize, u8)> {
+        self.longest_backward_find_after_with("", input, limit, pos, state, &mut |_| {})
+    }
+
+    /// Does the same thing as `longest_backward_find_after`, but also reports every step to
+    /// `on_event` (using `name` for the program).
+    pub fn longest_backward_find_after_with<F>(&self,
+                                               name: &'static str,
+                                               input: &[u8],
+                                               limit: usize,
+                                               pos: usize,
+                                               state: usize,
+                                               on_event: &mut F)
+    -> Option<(usize, u8)>
+    where F: FnMut(TraceEvent) {
+        // The beginning of the char before `limit` (which is at most 4 bytes long).
+        let stop = if limit == 0 {
+            0
+        } else {
+            (limit.saturating_sub(4)..limit).rev()
+                .find(|&i| input[i] & 0xC0 != 0x80)
+                .unwrap_or(limit - 1)
+        };
+        let starts_after_limit = |pos: usize, bytes: u8| pos + bytes as usize >= limit;
+        self.longest_backward_find_with(name, input, stop, pos, state, starts_after_limit, on_event)
+    }
+}
+
+impl TableInsts<(Look, u8)> {
+    /// Like `find_from`, for a program that returns the kind of char that it looked ahead at and
+    /// the number of bytes of look-ahead, but only for matches that end at or before `limit`.
+    ///
+    /// Unlike running on `&input[..limit]`, the program can read the char after `limit` to check
+    /// a look-ahead assertion (instead of assuming that `limit` is the end of the input). As with
+    /// `find_from`, the match ends at the returned position minus the returned number of bytes.
+    pub fn find_before(&self, input: &[u8], pos: usize, limit: usize, state: usize)
+    -> Result<(usize, (Look, u8)), usize> {
+        let ends_before_limit = |pos: usize, (_, bytes): (Look, u8)| pos - bytes as usize <= limit;
+        self.find_before_with("", input, pos, limit, state, ends_before_limit, &mut |_| {})
+    }
+}
+
+impl<Ret> TableInsts<Ret> {
+    /// Recomputes `loops` from the transition table.
+    pub fn find_loops(&mut self) {
+        let loops = (0..self.accept.len()).map(|st| {
+            let base = st << self.log_num_classes;
+            let byte_class = &self.byte_class;
+            let table = &self.table;
+            LoopMask::from_bytes((0..256usize)
+                .filter(|&b| table.get(base + byte_class[b] as usize) == st as u32)
+                .map(|b| b as u8))
+        }).collect();
+        self.loops = loops;
+    }
+
+    // Checks that this program is well-formed, so that running it can't go out of bounds.
+    fn check(&self) -> Result<(), &'static str> {
+        let num_states = self.accept.len();
+
+        if self.log_num_classes > 8 || self.byte_class.len() != 256 {
+            return Err("bad byte classes");
+        }
+        if self.byte_class.iter().any(|&c| (c as usize) >> self.log_num_classes != 0) {
+            return Err("byte class out of range");
+        }
+        if self.table.len() != num_states << self.log_num_classes
+                || self.accept_at_eoi.len() != num_states {
+            return Err("wrong number of states");
+        }
+        if self.table.to_targets().iter().any(|&st| st != u32::MAX && st as usize >= num_states) {
+            return Err("transition to a non-existent state");
+        }
+        Ok(())
+    }
+}
+
+// A `TableInsts` is encoded as its fields, in order (with the table always written as `u32`s).
+// Decoding checks that the result is well-formed.
+impl<Ret: Copy + Encode> Encode for TableInsts<Ret> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.log_num_classes.encode(out);
+        self.byte_class.encode(out);
+        self.table.to_targets().encode(out);
+        self.accept.encode(out);
+        self.accept_at_eoi.encode(out);
+    }
+
+    fn decode(input: &mut Decoder) -> Option<TableInsts<Ret>> {
+        let log_num_classes = try_opt!(u32::decode(input));
+        let byte_class = try_opt!(Vec::decode(input));
+        let targets = try_opt!(Vec::decode(input));
+        let accept: Vec<Option<Ret>> = try_opt!(Vec::decode(input));
+        let mut insts = TableInsts {
+            log_num_classes: log_num_classes,
+            byte_class: byte_class,
+            table: Table::from_targets(targets, accept.len()),
+            accept: accept,
+            accept_at_eoi: try_opt!(Vec::decode(input)),
+            loops: Vec::new(),
+        };
+        if insts.check().is_ok() {
+            insts.find_loops();
+            Some(insts)
+        } else {
+            None
+        }
+    }
+}
+
+// Serialization (enabled by the `serde` feature).
+//
+// An `InstInfo` is serialized as the list `[state, transitions, accept, accept_at_eoi]`, and a
+// `Disassembly` as the pair `[programs, prefilter]`.
+#[cfg(feature = "serde")]
+impl Serialize for InstInfo {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> Result<(), S::Error> {
+        (self.state, &self.transitions, self.accept, self.accept_at_eoi).serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Deserialize for InstInfo {
+    fn deserialize<D: Deserializer>(deserializer: &mut D) -> Result<InstInfo, D::Error> {
+        let (state, transitions, accept, accept_at_eoi) =
+            try!(Deserialize::deserialize(deserializer));
+        Ok(InstInfo {
+            state: state,
+            transitions: transitions,
+            accept: accept,
+            accept_at_eoi: accept_at_eoi,
+        })
+    }
 }
 
+#[cfg(feature = "serde")]
+impl Serialize for Disassembly {
+    fn serialize<S: Serializer>(&self, serializer: &mut S) -> Result<(), S::Error> {
+        (&self.programs, &self.prefilter).serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dfa::Dfa;
+    use nfa::Accept;
+    use range_map::Range;
+    use super::*;
+
+    // Compiles a Dfa matching "a[b-d]" at the end of the input.
+    fn simple_prog() -> TableInsts<u8> {
+        let mut dfa: Dfa<u8> = Dfa::new();
+        dfa.add_state(Accept::Never, None);
+        dfa.add_state(Accept::Never, None);
+        dfa.add_state(Accept::AtEoi, Some(0));
+        dfa.set_transitions(0, Some((Range::new(b'a', b'a'), 1)).into_iter().collect());
+        dfa.set_transitions(1, Some((Range::new(b'b', b'd'), 2)).into_iter().collect());
+        dfa.compile()
+    }
+
+    #[test]
+    fn disassemble() {
+        let insts = simple_prog().disassemble();
+        assert_eq!(insts, vec![
+            InstInfo { state: 0, transitions: vec![(b'a', b'a', 1)], accept: false, accept_at_eoi: false },
+            InstInfo { state: 1, transitions: vec![(b'b', b'd', 2)], accept: false, accept_at_eoi: false },
+            InstInfo { state: 2, transitions: vec![], accept: false, accept_at_eoi: true },
+        ]);
+
+        let listing = Disassembly { programs: vec![("test", insts)], prefilter: None }.to_string();
+        assert!(listing.contains("'b'..'d' => 2"));
+        assert!(listing.contains("state 2: accept at end of input"));
+    }
+
+    #[test]
+    fn self_loops() {
+        // "x[^y]*y", where the middle state loops back to itself.
+        let mut dfa: Dfa<u8> = Dfa::new();
+        dfa.add_state(Accept::Never, None);
+        dfa.add_state(Accept::Never, None);
+        dfa.add_state(Accept::Always, Some(0));
+        dfa.set_transitions(0, Some((Range::new(b'x', b'x'), 1)).into_iter().collect());
+        dfa.set_transitions(1, vec![(Range::new(0, b'x'), 1), (Range::new(b'y', b'y'), 2),
+                                    (Range::new(b'z', 255), 1)].into_iter().collect());
+        let prog = dfa.compile();
+        assert_eq!(prog.find_from(b"xabcyz", 0, 0), Ok((5, 0)));
+        assert_eq!(prog.find_from(b"xy", 0, 0), Ok((2, 0)));
+        assert_eq!(prog.find_from(b"xabc", 0, 0), Err(4));
+        assert_eq!(prog.find_from(b"ab", 0, 0), Err(0));
+
+        // An accepting state that loops ("x[^y]*"): the match ends at the last position where the
+        // state was seen, as it would without skipping.
+        let mut dfa: Dfa<u8> = Dfa::new();
+        dfa.add_state(Accept::Never, None);
+        dfa.add_state(Accept::Always, Some(1));
+        dfa.set_transitions(0, Some((Range::new(b'x', b'x'), 1)).into_iter().collect());
+        dfa.set_transitions(1, vec![(Range::new(0, b'x'), 1), (Range::new(b'z', 255), 1)]
+                                   .into_iter().collect());
+        let prog = dfa.compile();
+        assert_eq!(prog.find_from(b"xabcyz", 0, 0), Ok((4, 1)));
+        assert_eq!(prog.find_from(b"xabc", 0, 0), Ok((4, 1)));
+        assert_eq!(prog.find_from(b"xa", 0, 0), Ok((2, 1)));
+        assert_eq!(prog.find_from(b"xaay", 0, 0), Ok((3, 1)));
+    }
+
+    #[test]
+    fn loop_masks() {
+        // "x[a-z_]*", whose loop fits in a mask, and "x[^y]*", whose loop doesn't.
+        let mut dfa: Dfa<u8> = Dfa::new();
+        dfa.add_state(Accept::Never, None);
+        dfa.add_state(Accept::Always, Some(1));
+        dfa.set_transitions(0, Some((Range::new(b'x', b'x'), 1)).into_iter().collect());
+        dfa.set_transitions(1, vec![(Range::new(b'_', b'_'), 1), (Range::new(b'a', b'z'), 1)]
+                                   .into_iter().collect());
+        let prog = dfa.compile();
+        assert_eq!(prog.loops[0], None);
+        let mask = prog.loops[1].unwrap();
+        assert!(mask.contains(b'_') && mask.contains(b'a') && mask.contains(b'z'));
+        assert!(!mask.contains(b'`') && !mask.contains(b'{') && !mask.contains(b'A'));
+        assert!(!mask.contains(b'_' + 64) && !mask.contains(b'_'.wrapping_sub(64)));
+        assert_eq!(prog.find_from(b"xab_c-", 0, 0), Ok((5, 1)));
+        assert_eq!(prog.find_from(b"xab_c", 0, 0), Ok((5, 1)));
+        assert_eq!(prog.find_from(b"x", 0, 0), Ok((1, 1)));
+
+        // The detection program has no loops left.
+        assert_eq!(prog.for_detection().loops, vec![None, None]);
+
+        dfa.set_transitions(1, vec![(Range::new(0, b'x'), 1), (Range::new(b'z', 255), 1)]
+                                   .into_iter().collect());
+        assert_eq!(dfa.compile().loops, vec![None, None]);
+    }
+
+    #[test]
+    fn find_in_chunks() {
+        let prog = simple_prog();
+        let chunks: &[&[u8]] = &[b"", b"a", b"", b"b"];
+        assert_eq!(prog.find_in_chunks(chunks, 0), Some((2, 0)));
+        assert_eq!(prog.find_in_chunks(&b"ab"[..], 0), prog.find_from(b"ab", 0, 0).ok());
+        let chunks: &[&[u8]] = &[b"a", b"bc"];
+        assert_eq!(prog.find_in_chunks(chunks, 0), None);
+        let chunks: &[&[u8]] = &[b"a"];
+        assert_eq!(prog.find_in_chunks(chunks, 0), None);
+
+        // "ab*", which accepts after every 'b' and keeps going.
+        let mut dfa: Dfa<u8> = Dfa::new();
+        dfa.add_state(Accept::Never, None);
+        dfa.add_state(Accept::Always, Some(1));
+        dfa.set_transitions(0, Some((Range::new(b'a', b'a'), 1)).into_iter().collect());
+        dfa.set_transitions(1, Some((Range::new(b'b', b'b'), 1)).into_iter().collect());
+        let prog = dfa.compile();
+        let chunks: &[&[u8]] = &[b"ab", b"b", b"bc", b"b"];
+        assert_eq!(prog.find_in_chunks(chunks, 0), Some((4, 1)));
+        assert_eq!(prog.find_in_chunks(&b"abbbcb"[..], 0), prog.find_from(b"abbbcb", 0, 0).ok());
+    }
+
+    #[test]
+    fn first_and_last_accept() {
+        // "ab*", which accepts after every 'b'.
+        let mut dfa: Dfa<u8> = Dfa::new();
+        dfa.add_state(Accept::Never, None);
+        dfa.add_state(Accept::Always, Some(1));
+        dfa.set_transitions(0, Some((Range::new(b'a', b'a'), 1)).into_iter().collect());
+        dfa.set_transitions(1, Some((Range::new(b'b', b'b'), 1)).into_iter().collect());
+        let prog = dfa.compile();
+        assert_eq!(prog.first_and_last_accept_from(b"xabbc", 1, 0), Some(((2, 1), (4, 1))));
+        assert_eq!(prog.first_and_last_accept_from(b"abb", 0, 0), Some(((1, 1), (3, 1))));
+        assert_eq!(prog.first_and_last_accept_from(b"a", 0, 0), Some(((1, 1), (1, 1))));
+        assert_eq!(prog.first_and_last_accept_from(b"ba", 0, 0), None);
+
+        let prog = simple_prog();
+        assert_eq!(prog.first_and_last_accept_from(b"ab", 0, 0), Some(((2, 0), (2, 0))));
+        assert_eq!(prog.first_and_last_accept_from(b"abc", 0, 0), None);
+    }
+
+    #[test]
+    fn compact_table() {
+        use encode::{Decoder, Encode};
+        use std::u32;
+
+        let prog = simple_prog();
+        match prog.table {
+            Table::Small(ref t) => assert_eq!(t.len(), prog.num_states() << prog.log_num_classes),
+            Table::Large(_) => panic!("expected a small table"),
+        }
+
+        // "a{300}" needs 301 states, which is too many for `u8`s.
+        let mut dfa: Dfa<u8> = Dfa::new();
+        for i in 0..301 {
+            dfa.add_state(if i == 300 { Accept::Always } else { Accept::Never }, Some(0));
+            if i > 0 {
+                dfa.set_transitions(i - 1, Some((Range::new(b'a', b'a'), i)).into_iter().collect());
+            }
+        }
+        let prog = dfa.compile();
+        match prog.table {
+            Table::Large(_) => {},
+            Table::Small(_) => panic!("expected a large table"),
+        }
+        let input = vec![b'a'; 310];
+        assert_eq!(prog.find_from(&input, 0, 0), Ok((300, 0)));
+        assert_eq!(prog.find_from(&input[..299], 0, 0), Err(299));
+        assert_eq!(prog.next_state(299, b'a'), Some(300));
+        assert_eq!(prog.next_state(300, b'a'), None);
+
+        let mut buf = Vec::new();
+        prog.encode(&mut buf);
+        let back = TableInsts::<u8>::decode(&mut Decoder::new(&buf)).unwrap();
+        assert_eq!(back.table, prog.table);
+
+        // Targets that are out of range can't sneak into a small table.
+        let bad = vec![0, 256, u32::MAX];
+        assert_eq!(Table::from_targets(bad.clone(), 3), Table::Large(bad));
+        assert_eq!(Table::from_targets(vec![0, 2, u32::MAX], 3), Table::Small(vec![0, 2, 255]));
+    }
+
+    #[test]
+    fn for_detection() {
+        let mut dfa: Dfa<u8> = Dfa::new();
+        dfa.add_state(Accept::Never, None);
+        dfa.add_state(Accept::Always, Some(1));
+        dfa.set_transitions(0, Some((Range::new(b'a', b'a'), 1)).into_iter().collect());
+        dfa.set_transitions(1, Some((Range::new(b'a', b'a'), 1)).into_iter().collect());
+        let prog = dfa.compile();
+        assert_eq!(prog.find_from(b"aaab", 0, 0), Ok((3, 1)));
+
+        let detect = prog.for_detection();
+        assert_eq!(detect.find_from(b"aaab", 0, 0), Ok((1, 1)));
+        assert_eq!(detect.find_from(b"b", 0, 0), Err(0));
+        assert!(detect.disassemble()[1].transitions.is_empty());
+
+        // States that only accept at the end of the input keep their transitions.
+        let prog = simple_prog();
+        assert_eq!(prog.for_detection().disassemble(), prog.disassemble());
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        use encode::{Decoder, Encode};
+
+        let prog = simple_prog();
+        let mut buf = Vec::new();
+        prog.encode(&mut buf);
+        let back = TableInsts::<u8>::decode(&mut Decoder::new(&buf)).unwrap();
+        assert_eq!(back.disassemble(), prog.disassemble());
+
+        // Point a transition at a state that doesn't exist.
+        let mut bad = prog.clone();
+        bad.table = Table::Large(bad.table.to_targets());
+        bad.table.set(0, prog.num_states() as u32);
+        let mut buf = Vec::new();
+        bad.encode(&mut buf);
+        assert!(TableInsts::<u8>::decode(&mut Decoder::new(&buf)).is_none());
+    }
+
+    #[test]
+    fn trace() {
+        use dfa::tests::make_dfa;
+        use look::Look;
+        use super::TraceEvent::*;
+
+        let prog = simple_prog();
+        let mut trace = Vec::new();
+        assert_eq!(prog.trace_find_from("p", b"ac", 0, 0, &mut trace), Ok((2, 0)));
+        assert_eq!(trace, vec![
+            Step { program: "p", pos: 0, byte: b'a', state: 0, next: Some(1) },
+            Step { program: "p", pos: 1, byte: b'c', state: 1, next: Some(2) },
+            Accept { program: "p", pos: 2, state: 2 },
+        ]);
+
+        trace.clear();
+        assert_eq!(prog.trace_find_from("p", b"ax", 0, 0, &mut trace), Err(1));
+        assert_eq!(trace, vec![
+            Step { program: "p", pos: 0, byte: b'a', state: 0, next: Some(1) },
+            Step { program: "p", pos: 1, byte: b'x', state: 1, next: None },
+        ]);
+
+        // The traced version should agree with the fast version.
+        let inputs: &[&[u8]] = &[b"", b"a", b"ab", b"abc", b"bd", b"ad"];
+        for input in inputs {
+            let mut trace = Vec::new();
+            assert_eq!(prog.trace_find_from("p", input, 0, 0, &mut trace),
+                       prog.find_from(input, 0, 0));
+        }
+
+        // The fast version skips through states that loop back to themselves, but the traced
+        // version still sees every byte.
+        let prog = make_dfa("a[b-y]*z").unwrap().compile();
+        let input = b"abbbbbbbz";
+        let mut trace = Vec::new();
+        assert_eq!(prog.trace_find_from("p", input, 0, 0, &mut trace), Ok((9, (Look::Full, 0))));
+        let steps = trace.iter().filter(|ev| match **ev { Step { .. } => true, _ => false });
+        assert_eq!(steps.count(), input.len());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_round_trip() {
+        use dfa::tests::make_dfa;
+        use serde_json;
+
+        let insts = make_dfa("a+b|[c-f]").unwrap().compile().disassemble();
+        let json = serde_json::to_string(&insts).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<InstInfo>>(&json).unwrap(), insts);
+    }
+}
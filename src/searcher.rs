@@ -0,0 +1,75 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/// A way of finding the places where a match might start, for use with
+/// `Regex::find_with_searcher`.
+///
+/// A `Regex` chooses its own prefilter, but it only knows about the regular expression. A
+/// `Searcher` can use anything else that is known about the input, such as an index of where
+/// certain words occur.
+///
+/// Any function or closure taking `(input, pos)` and returning `Option<usize>` is a `Searcher`.
+pub trait Searcher {
+    /// Returns the first candidate position at or after `pos`, or `None` if there are no more.
+    ///
+    /// Every position where a match starts must be a candidate: if this returns `Some(c)`, then
+    /// no match may start anywhere in `pos..c`, and if it returns `None` then no match may start
+    /// at or after `pos`. On the other hand, candidates don't need to be matches (they will be
+    /// checked), and candidates that are not `char` boundaries are skipped.
+    fn search(&self, input: &[u8], pos: usize) -> Option<usize>;
+}
+
+impl<F: Fn(&[u8], usize) -> Option<usize>> Searcher for F {
+    fn search(&self, input: &[u8], pos: usize) -> Option<usize> {
+        self(input, pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use byte_search::memchr;
+    use regex::Regex;
+    use super::Searcher;
+
+    // A searcher that only knows about the lines starting with a given byte.
+    #[derive(Debug)]
+    struct LineIndex {
+        starts: Vec<usize>,
+    }
+
+    impl Searcher for LineIndex {
+        fn search(&self, _: &[u8], pos: usize) -> Option<usize> {
+            self.starts.iter().cloned().find(|&s| s >= pos)
+        }
+    }
+
+    #[test]
+    fn find_with_searcher() {
+        let re = Regex::new("(?m)^#[a-z]+").unwrap();
+        let text = "x\n#ab\ny #cd\n#ef";
+        let index = LineIndex { starts: vec![2, 12] };
+        assert_eq!(re.find_with_searcher(text, &index), Some((2, 5)));
+
+        let re = Regex::new("[a-z]+").unwrap();
+        assert_eq!(re.find_with_searcher(text, &index), None);
+        let index = LineIndex { starts: vec![3, 13] };
+        assert_eq!(re.find_with_searcher(text, &index), Some((3, 5)));
+        let later = LineIndex { starts: vec![13] };
+        assert_eq!(re.find_with_searcher(text, &later), Some((13, 15)));
+        assert_eq!(re.find_with_searcher(text, &LineIndex { starts: vec![] }), None);
+
+        // Closures work too, and candidates that aren't char boundaries are skipped.
+        let re = Regex::new("é").unwrap();
+        let every = |input: &[u8], pos: usize| if pos <= input.len() { Some(pos) } else { None };
+        assert_eq!(re.find_with_searcher("aaé", &every), Some((2, 4)));
+        let bytes = |input: &[u8], pos: usize| {
+            if pos <= input.len() { memchr(0xA9, &input[pos..]).map(|i| i + pos) } else { None }
+        };
+        assert_eq!(re.find_with_searcher("aaé", &bytes), None);
+    }
+}
@@ -0,0 +1,308 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use dfa::{Dfa, transitions_from_targets};
+use error::Error;
+use look::Look;
+use nfa::{Accept, StateIdx};
+use runner::program::TableInsts;
+use std;
+use std::collections::HashMap;
+
+/// Scans binary data for many byte signatures at once, in the style of an antivirus or intrusion
+/// detection engine.
+///
+/// Signatures are written in hex, with `?` standing for an unknown nibble: `"4D 5A ?? ?? 50 45"`
+/// matches the bytes `MZ`, then any two bytes, then `PE`. Whitespace is ignored. All of the
+/// signatures are compiled into a single automaton, which finds every occurrence of every
+/// signature (including overlapping ones) in one pass over the input.
+///
+/// ```rust
+/// use regex_dfa::SignatureScanner;
+///
+/// let scanner = SignatureScanner::new(&["4D 5A", "5A ?0"]).unwrap();
+/// let hits: Vec<_> = scanner.scan(b"xMZP\x00").collect();
+/// assert_eq!(hits, vec![(1, 0), (2, 1)]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct SignatureScanner {
+    prog: TableInsts<usize>,
+    init: usize,
+    // The signatures that end when we enter an accepting state, indexed by the state's return
+    // value.
+    hits: Vec<Vec<usize>>,
+    // The length (in bytes) of each signature.
+    lens: Vec<usize>,
+}
+
+// One byte of a signature. It matches `b` if `b & mask == value`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct SigByte {
+    value: u8,
+    mask: u8,
+}
+
+impl SigByte {
+    fn matches(&self, b: u8) -> bool {
+        b & self.mask == self.value
+    }
+}
+
+impl SignatureScanner {
+    /// Compiles a list of signatures. The signatures are identified by their index in `sigs`.
+    pub fn new<S: AsRef<str>>(sigs: &[S]) -> ::Result<SignatureScanner> {
+        SignatureScanner::new_bounded(sigs, std::usize::MAX)
+    }
+
+    /// Like `new`, but fails if the automaton would require too many states.
+    pub fn new_bounded<S: AsRef<str>>(sigs: &[S], max_states: usize)
+    -> ::Result<SignatureScanner> {
+        let mut parsed = Vec::with_capacity(sigs.len());
+        for (i, sig) in sigs.iter().enumerate() {
+            match parse(sig.as_ref()) {
+                Ok(sig) => parsed.push(sig),
+                Err(msg) => { return Err(Error::SignatureSyntax(i, msg)); },
+            }
+        }
+
+        let (dfa, hits) = try!(build_dfa(&parsed, max_states));
+        let dfa = dfa.optimize();
+        Ok(SignatureScanner {
+            prog: dfa.compile(),
+            init: dfa.init_at_start().expect("BUG: no initial state"),
+            hits: hits,
+            lens: parsed.iter().map(|sig| sig.len()).collect(),
+        })
+    }
+
+    /// Returns the number of states in the compiled automaton.
+    pub fn num_states(&self) -> usize {
+        self.prog.num_states()
+    }
+
+    /// Returns an iterator over all occurrences of the signatures in `input`.
+    ///
+    /// The iterator yields pairs `(offset, id)`, where `offset` is the position in `input` where
+    /// the occurrence starts, and `id` is the index of the signature. The occurrences are sorted
+    /// by where they end (and then by `id`), so that the input only needs to be read once.
+    pub fn scan<'s, 't>(&'s self, input: &'t [u8]) -> SignatureHits<'s, 't> {
+        SignatureHits {
+            scanner: self,
+            input: input,
+            pos: 0,
+            state: self.init,
+            pending: &[],
+        }
+    }
+}
+
+/// An iterator over the occurrences of signatures in some input. See `SignatureScanner::scan`.
+#[derive(Debug)]
+pub struct SignatureHits<'s, 't> {
+    scanner: &'s SignatureScanner,
+    input: &'t [u8],
+    // The number of bytes of input that we have consumed.
+    pos: usize,
+    state: usize,
+    // The signatures that end at `pos` and haven't been returned yet.
+    pending: &'s [usize],
+}
+
+impl<'s, 't> Iterator for SignatureHits<'s, 't> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        loop {
+            if let Some((&sig, rest)) = self.pending.split_first() {
+                self.pending = rest;
+                return Some((self.pos - self.scanner.lens[sig], sig));
+            }
+            if self.pos >= self.input.len() {
+                return None;
+            }
+
+            let prog = &self.scanner.prog;
+            let byte = self.input[self.pos];
+            self.state = prog.next_state(self.state, byte).unwrap_or(self.scanner.init);
+            self.pos += 1;
+            if let Some(hits) = prog.accept[self.state] {
+                self.pending = &self.scanner.hits[hits];
+            }
+        }
+    }
+}
+
+// Parses a signature in hex-with-wildcards syntax.
+fn parse(sig: &str) -> Result<Vec<SigByte>, String> {
+    let digits: Vec<char> = sig.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() {
+        return Err("empty signature".to_owned());
+    }
+    if digits.len() % 2 != 0 {
+        return Err("odd number of hex digits".to_owned());
+    }
+
+    let mut ret = Vec::with_capacity(digits.len() / 2);
+    for pair in digits.chunks(2) {
+        let mut byte = SigByte { value: 0, mask: 0 };
+        for &c in pair {
+            byte.value <<= 4;
+            byte.mask <<= 4;
+            if c != '?' {
+                match c.to_digit(16) {
+                    Some(d) => { byte.value |= d as u8; },
+                    None => { return Err(format!("invalid hex digit '{}'", c)); },
+                }
+                byte.mask |= 0xF;
+            }
+        }
+        ret.push(byte);
+    }
+    Ok(ret)
+}
+
+// Returns the partial matches after reading `byte`, given the partial matches before reading it.
+//
+// A partial match `(sig, len)` means that the last `len` bytes of input match the first `len`
+// bytes of signature number `sig`.
+fn step(sigs: &[Vec<SigByte>], partial: &[(usize, usize)], byte: u8) -> Vec<(usize, usize)> {
+    // Every signature can start at any position.
+    let starts = (0..sigs.len()).map(|sig| (sig, 0));
+    partial.iter().cloned().chain(starts)
+        .filter(|&(sig, len)| len < sigs[sig].len() && sigs[sig][len].matches(byte))
+        .map(|(sig, len)| (sig, len + 1))
+        .collect()
+}
+
+// Builds a `Dfa` for finding the signatures, using the subset construction. Each state of the
+// `Dfa` corresponds to a set of partial matches, and it accepts if any of them are complete. The
+// return value of an accepting state is an index into the second return value, which lists the
+// signatures that are complete.
+fn build_dfa(sigs: &[Vec<SigByte>], max_states: usize)
+-> ::Result<(Dfa<usize>, Vec<Vec<usize>>)> {
+    let mut dfa = Dfa::new();
+    let mut hits = Vec::new();
+    let mut hit_ids = HashMap::<Vec<usize>, usize>::new();
+
+    // The sets of partial matches for the states that we have created so far. They are kept
+    // sorted, so that equal sets have equal representations.
+    let mut sets: Vec<Vec<(usize, usize)>> = vec![Vec::new()];
+    let mut set_ids = HashMap::<Vec<(usize, usize)>, StateIdx>::new();
+    set_ids.insert(Vec::new(), dfa.add_state(Accept::Never, None));
+
+    let mut cur = 0;
+    while cur < sets.len() {
+        let mut targets = vec![None; 256];
+        for b in 0..256 {
+            let mut next = step(sigs, &sets[cur], b as u8);
+            next.sort();
+            if let Some(&idx) = set_ids.get(&next) {
+                targets[b] = Some(idx);
+                continue;
+            }
+            if dfa.num_states() >= max_states {
+                return Err(Error::TooManyStates);
+            }
+
+            let complete: Vec<usize> = next.iter()
+                .filter(|&&(sig, len)| len == sigs[sig].len())
+                .map(|&(sig, _)| sig)
+                .collect();
+            let idx = if complete.is_empty() {
+                dfa.add_state(Accept::Never, None)
+            } else {
+                let hit_idx = *hit_ids.entry(complete.clone()).or_insert(hits.len());
+                if hit_idx == hits.len() {
+                    hits.push(complete);
+                }
+                dfa.add_state(Accept::Always, Some(hit_idx))
+            };
+            set_ids.insert(next.clone(), idx);
+            sets.push(next);
+            targets[b] = Some(idx);
+        }
+        dfa.set_transitions(cur, transitions_from_targets(&targets));
+        cur += 1;
+    }
+    dfa.set_init_state(Look::Boundary, Some(0));
+    Ok((dfa, hits))
+}
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+    use super::{SigByte, SignatureScanner};
+
+    // Finds all the hits by checking every signature at every position.
+    fn naive_scan(sigs: &[&str], input: &[u8]) -> Vec<(usize, usize)> {
+        let parsed: Vec<_> = sigs.iter().map(|s| super::parse(s).unwrap()).collect();
+        let mut ret = Vec::new();
+        for end in 1..(input.len() + 1) {
+            for (id, sig) in parsed.iter().enumerate() {
+                if sig.len() <= end {
+                    let start = end - sig.len();
+                    if sig.iter().zip(&input[start..end]).all(|(s, &b)| s.matches(b)) {
+                        ret.push((start, id));
+                    }
+                }
+            }
+        }
+        ret
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(super::parse("4D ?a").unwrap(),
+                   vec![SigByte { value: 0x4D, mask: 0xFF }, SigByte { value: 0x0A, mask: 0x0F }]);
+        assert_eq!(super::parse("??").unwrap(), vec![SigByte { value: 0, mask: 0 }]);
+        assert!(super::parse("").is_err());
+        assert!(super::parse("4D 5").is_err());
+        assert!(super::parse("4G").is_err());
+    }
+
+    #[test]
+    fn syntax_error() {
+        match SignatureScanner::new(&["4D", "xx"]) {
+            Err(Error::SignatureSyntax(1, _)) => {},
+            x => panic!("expected a syntax error, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn overlapping() {
+        let scanner = SignatureScanner::new(&["61 61", "61", "62 ?? 62"]).unwrap();
+        let hits: Vec<_> = scanner.scan(b"aaabab").collect();
+        assert_eq!(hits, vec![(0, 1), (0, 0), (1, 1), (1, 0), (2, 1), (4, 1), (3, 2)]);
+        assert_eq!(scanner.scan(b"").next(), None);
+    }
+
+    #[test]
+    fn matches_naive() {
+        let sigs = ["4D 5A ?? ?? 50 45", "5A 9?", "?0 ?0", "00 00 00", "50 45 00 00", "FF"];
+        let inputs: [&[u8]; 4] = [
+            b"MZ\x90\x00PE\x00\x00\x00\xff",
+            b"\x00\x00\x00\x00\x10\x20",
+            b"",
+            b"xyzMZMZ\x91\x01PE",
+        ];
+        let scanner = SignatureScanner::new(&sigs).unwrap();
+        for input in &inputs {
+            assert_eq!(scanner.scan(input).collect::<Vec<_>>(), naive_scan(&sigs, input));
+        }
+    }
+
+    #[test]
+    fn too_many_states() {
+        let sigs = ["?? ?? ?? ?? 00", "01 ?? ?? ?? ??"];
+        assert!(SignatureScanner::new(&sigs).is_ok());
+        match SignatureScanner::new_bounded(&sigs, 3) {
+            Err(Error::TooManyStates) => {},
+            x => panic!("expected too many states, got {:?}", x),
+        }
+    }
+}
@@ -0,0 +1,323 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use dfa::{Dfa, RetTrait, transitions_from_targets};
+use error::Error;
+use expr;
+use look::Look;
+use nfa::{Accept, Nfa, StateIdx};
+use regex_syntax::Expr;
+use std;
+use std::fmt::Write;
+use std::u32;
+
+/// The tables of a minimal automaton that checks whether an entire input matches a regular
+/// expression, in a form that can be written out as Rust source code.
+///
+/// This is for validating inputs in places where there is no room for this crate (such as
+/// embedded firmware): `to_rust` writes the tables as constant arrays, together with a short
+/// function that runs them. The automaton is minimal, bytes that always behave the same way share
+/// a column of the table, and the table uses the smallest integer type that can hold its states,
+/// so small validation patterns usually need well under a kilobyte.
+///
+/// Only whole inputs are checked, so the automaton doesn't need to report where matches are.
+/// Word boundaries and multi-line anchors are not supported; regular expressions that use them
+/// (except at the start and end, as `^` and `$`) are rejected with `Error::InvalidEngine`. Tables
+/// can also be built from an automaton that didn't come from a regular expression, with
+/// `Dfa::to_static_tables`.
+///
+/// ```rust
+/// use regex_dfa::StaticTables;
+///
+/// let tables = StaticTables::new("[0-9]{1,3}(\\.[0-9]{1,3}){3}").unwrap();
+/// assert!(tables.validate(b"192.168.0.1"));
+/// assert!(!tables.validate(b"192.168.0.1 "));
+/// assert!(tables.to_rust("is_ipv4").contains("pub fn is_ipv4(input: &[u8]) -> bool {"));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct StaticTables {
+    /// The column of the table for each byte.
+    pub byte_class: Vec<u8>,
+    /// The number of columns in the table.
+    pub num_classes: usize,
+    /// The transitions: the state after reading a byte of class `c` in state `s` is
+    /// `table[s * num_classes + c]`. The state `num_states()` means that the input is invalid.
+    pub table: Vec<u32>,
+    /// For each state, whether the input is valid if it ends in that state.
+    pub accept: Vec<bool>,
+    /// The state to start in. If it is `num_states()`, nothing is valid.
+    pub start: u32,
+}
+
+impl StaticTables {
+    /// Builds the tables for validating inputs against the regular expression `re`.
+    pub fn new(re: &str) -> ::Result<StaticTables> {
+        StaticTables::new_bounded(re, std::usize::MAX)
+    }
+
+    /// Like `new`, but fails if the automaton would require too many states.
+    pub fn new_bounded(re: &str, max_states: usize) -> ::Result<StaticTables> {
+        let expr = expr::simplify(&try!(Expr::parse(re)));
+        let nfa = Nfa::from_expr(&expr).remove_looks();
+        if nfa.has_look_behind() || nfa.has_look_ahead() {
+            return Err(Error::InvalidEngine(
+                "static tables don't support word boundaries or multi-line anchors"));
+        }
+        let mut nfa = try!(nfa.byte_me(max_states));
+        nfa.reduce();
+
+        // The match has to start at the beginning of the input, and since the whole input has to
+        // match, we need every match and not just the preferred one.
+        let nfa = nfa.anchor_after(Look::Boundary);
+        Ok(try!(nfa.determinize_longest(max_states)).to_static_tables())
+    }
+
+    // Turns the tables back into a `Dfa`, whose states accept wherever the input is valid if it
+    // ends there.
+    fn to_dfa(&self) -> Dfa<()> {
+        let dead = self.num_states();
+        let mut dfa = Dfa::new();
+        for &accept in &self.accept {
+            if accept {
+                dfa.add_state(Accept::Always, Some(()));
+            } else {
+                dfa.add_state(Accept::Never, None);
+            }
+        }
+        for st in 0..dead {
+            let targets: Vec<Option<StateIdx>> = self.byte_class.iter()
+                .map(|&class| self.table[st * self.num_classes + class as usize] as usize)
+                .map(|tgt| if tgt == dead { None } else { Some(tgt) })
+                .collect();
+            dfa.set_transitions(st, transitions_from_targets(&targets));
+        }
+        let start = if self.start as usize == dead { None } else { Some(self.start as usize) };
+        for &look in Look::all() {
+            dfa.set_init_state(look, start);
+        }
+        dfa
+    }
+
+    /// Builds the tables for validating inputs that consist of an input that `self` accepts,
+    /// followed by an input that `other` accepts.
+    ///
+    /// This lets independently maintained patterns be put together without going back to their
+    /// regular expressions: a header format followed by a body format, for example. Any `^` or `$`
+    /// in the original patterns refers to the beginning or end of that pattern's part.
+    ///
+    /// ```rust
+    /// use regex_dfa::StaticTables;
+    ///
+    /// let key = StaticTables::new("[a-z]+=").unwrap();
+    /// let value = StaticTables::new("[0-9]+|\"[^\"]*\"").unwrap();
+    /// let pair = key.then(&value).unwrap();
+    /// assert!(pair.validate(b"x=12"));
+    /// assert!(pair.validate(b"name=\"joe\""));
+    /// assert!(!pair.validate(b"x="));
+    /// assert!(!pair.validate(b"=12"));
+    /// ```
+    pub fn then(&self, other: &StaticTables) -> ::Result<StaticTables> {
+        self.then_bounded(other, std::usize::MAX)
+    }
+
+    /// Like `then`, but fails if the automaton would require too many states.
+    pub fn then_bounded(&self, other: &StaticTables, max_states: usize)
+    -> ::Result<StaticTables> {
+        Ok(try!(self.to_dfa().concat(&other.to_dfa(), max_states)).to_static_tables())
+    }
+
+    /// The number of states (not counting the one that means the input is invalid).
+    pub fn num_states(&self) -> usize {
+        self.accept.len()
+    }
+
+    /// Returns true if all of `input` matches, by running the tables in the same way that the
+    /// code from `to_rust` does.
+    pub fn validate(&self, input: &[u8]) -> bool {
+        let dead = self.num_states();
+        let mut state = self.start as usize;
+        for &b in input {
+            if state == dead {
+                return false;
+            }
+            state = self.table[state * self.num_classes + self.byte_class[b as usize] as usize]
+                as usize;
+        }
+        state < dead && self.accept[state]
+    }
+
+    // The name (and size) of the smallest unsigned integer type that can hold every state
+    // (including the one that means the input is invalid).
+    fn state_type(&self) -> (&'static str, usize) {
+        if self.num_states() <= std::u8::MAX as usize {
+            ("u8", 1)
+        } else if self.num_states() <= std::u16::MAX as usize {
+            ("u16", 2)
+        } else {
+            ("u32", 4)
+        }
+    }
+
+    /// The number of bytes that the tables written by `to_rust` take up.
+    pub fn size_in_bytes(&self) -> usize {
+        self.byte_class.len() + self.table.len() * self.state_type().1 + self.accept.len()
+    }
+
+    /// Writes the tables as Rust source code: three constant arrays, and a function called `name`
+    /// that takes a `&[u8]` and returns true if it is valid. The code has no dependencies, and it
+    /// works without the standard library.
+    pub fn to_rust(&self, name: &str) -> String {
+        fn write_array<T: ToString>(out: &mut String, name: &str, ty: &str, elts: &[T]) {
+            write!(out, "const {}: [{}; {}] = [", name, ty, elts.len()).unwrap();
+            for (i, elt) in elts.iter().enumerate() {
+                out.push_str(if i % 16 == 0 { "\n    " } else { " " });
+                out.push_str(&elt.to_string());
+                out.push(',');
+            }
+            out.push_str("\n];\n");
+        }
+
+        let prefix = name.to_uppercase();
+        let classes = format!("{}_CLASSES", prefix);
+        let table = format!("{}_TABLE", prefix);
+        let accept = format!("{}_ACCEPT", prefix);
+        let (ty, _) = self.state_type();
+
+        let mut out = String::new();
+        write_array(&mut out, &classes, "u8", &self.byte_class);
+        write_array(&mut out, &table, ty, &self.table);
+        write_array(&mut out, &accept, "bool", &self.accept);
+        write!(out, "\npub fn {}(input: &[u8]) -> bool {{\n", name).unwrap();
+        write!(out, "    let mut state = {}usize;\n", self.start).unwrap();
+        out.push_str("    for &b in input {\n");
+        write!(out, "        if state == {} {{\n", self.num_states()).unwrap();
+        out.push_str("            return false;\n");
+        out.push_str("        }\n");
+        write!(out, "        state = {}[state * {} + {}[b as usize] as usize] as usize;\n",
+               table, self.num_classes, classes).unwrap();
+        out.push_str("    }\n");
+        write!(out, "    state < {} && {}[state]\n", self.num_states(), accept).unwrap();
+        out.push_str("}\n");
+        out
+    }
+}
+
+impl<Ret: RetTrait> Dfa<Ret> {
+    /// Builds the tables (see `StaticTables`) for validating inputs that this `Dfa` accepts.
+    ///
+    /// The `Dfa` is treated as a plain automaton, like in `accepts`: we start from the initial
+    /// state for the beginning of the input, and an input is valid if it ends in a state that can
+    /// accept at the end of the input. Return values are ignored, and the automaton is minimized
+    /// before the tables are built.
+    ///
+    /// ```rust
+    /// use regex_dfa::Dfa;
+    ///
+    /// let tables = Dfa::from_words(vec!["GET", "POST", "PUT"]).to_static_tables();
+    /// assert!(tables.validate(b"PUT"));
+    /// assert!(!tables.validate(b"PUTS"));
+    /// ```
+    pub fn to_static_tables(&self) -> StaticTables {
+        let dfa = self.clone().map_ret(|_| ()).optimize();
+        let prog = dfa.compile();
+        let num_states = prog.num_states();
+        let num_classes = prog.byte_class.iter().cloned().max().map_or(1, |c| c as usize + 1);
+        let mut table = Vec::with_capacity(num_states * num_classes);
+        for st in 0..num_states {
+            for class in 0..num_classes {
+                let tgt = prog.table.get((st << prog.log_num_classes) + class);
+                table.push(if tgt == u32::MAX { num_states as u32 } else { tgt });
+            }
+        }
+
+        StaticTables {
+            byte_class: prog.byte_class.clone(),
+            num_classes: num_classes,
+            table: table,
+            accept: prog.accept_at_eoi.iter().map(|a| a.is_some()).collect(),
+            start: dfa.init_at_start().unwrap_or(num_states) as u32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+    use super::StaticTables;
+
+    #[test]
+    fn validate() {
+        let cases: &[(&str, &[&str])] = &[
+            ("a|ab", &["", "a", "ab", "abb", "b"]),
+            ("[0-9]+(\\.[0-9]+)?", &["1", "1.", "1.5", "12.34", ".5", "x"]),
+            ("^é*$", &["", "é", "éé", "e", "é\n"]),
+            ("(?i)yes|no", &["YES", "No", "nope", ""]),
+            ("a[^b]*", &["a", "acd", "acdb", "a\n"]),
+        ];
+        for &(re, inputs) in cases {
+            let tables = StaticTables::new(re).unwrap();
+            let anchored = Regex::new(&format!("^(?:{})$", re)).unwrap();
+            for input in inputs {
+                assert_eq!(tables.validate(input.as_bytes()), anchored.is_match(input),
+                           "{:?} on {:?}", re, input);
+            }
+        }
+        assert!(StaticTables::new("\\bfoo").is_err());
+    }
+
+    #[test]
+    fn then() {
+        let cases: &[(&str, &str, &[&str])] = &[
+            ("ab?", "b+c", &["abc", "abbc", "ac", "abb", "a", ""]),
+            ("a*", "a", &["", "a", "aa", "aaa", "b"]),
+            ("^x$", "y*", &["x", "xy", "xyy", "y", "xx"]),
+            ("[0-9]{1,2}", "\\.[0-9]{1,2}", &["1.2", "12.34", "123.4", "1.", ".1"]),
+            ("λ?", "ό", &["λό", "ό", "λ", "λλό"]),
+        ];
+        for &(first, second, inputs) in cases {
+            let tables = StaticTables::new(first).unwrap()
+                .then(&StaticTables::new(second).unwrap()).unwrap();
+            // The anchors of the first part refer to the ends of that part.
+            let first_part = first.trim_matches(&['^', '$'][..]);
+            let joined = Regex::new(&format!("^(?:{})(?:{})$", first_part, second)).unwrap();
+            for input in inputs {
+                assert_eq!(tables.validate(input.as_bytes()), joined.is_match(input),
+                           "{:?} then {:?} on {:?}", first, second, input);
+            }
+        }
+
+        let a = StaticTables::new("[ab]*a[ab]{6}").unwrap();
+        assert!(a.then_bounded(&a, 10).is_err());
+    }
+
+    #[test]
+    fn from_dfa() {
+        use dfa::Dfa;
+
+        let words = Dfa::from_words(vec!["car", "cart", "cat"]);
+        let tables = words.to_static_tables();
+        for input in &["car", "cart", "cat", "ca", "carts", ""] {
+            assert_eq!(tables.validate(input.as_bytes()), words.accepts(input.chars()), "{}", input);
+        }
+        // The tables are minimal: "r" and "t" share a final state.
+        assert_eq!(tables.num_states(), 5);
+    }
+
+    #[test]
+    fn to_rust() {
+        let tables = StaticTables::new("ab+").unwrap();
+        assert_eq!(tables.num_classes, 3);
+        assert_eq!(tables.num_states(), 3);
+        let code = tables.to_rust("abs");
+        assert!(code.contains("const ABS_CLASSES: [u8; 256] = ["));
+        assert!(code.contains("const ABS_TABLE: [u8; 9] = ["));
+        assert!(code.contains("const ABS_ACCEPT: [bool; 3] = ["));
+        assert!(code.contains("pub fn abs(input: &[u8]) -> bool {"));
+        assert_eq!(tables.size_in_bytes(), 256 + 9 + 3);
+    }
+}
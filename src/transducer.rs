@@ -0,0 +1,436 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nfa::StateIdx;
+use range_map::Range;
+use std;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// A finite-state transducer: an automaton that writes some output on every transition that it
+/// takes, and so translates its input into (possibly many) output strings.
+///
+/// Transducers can be built by hand (see `add_state`, `add_transition`, `add_copy` and `add_eps`)
+/// or put together from smaller ones, and then chained with `compose`. This is enough for simple
+/// rewriting tasks, like transliteration:
+///
+/// ```rust
+/// use regex_dfa::Transducer;
+///
+/// let greek = Transducer::from_pairs(&[("a", "α"), ("b", "β"), ("th", "θ")]);
+/// let others = Transducer::copy_chars('c', 'z');
+/// let t = greek.union(&others).star();
+/// assert_eq!(t.apply("bath"), vec!["βαth", "βαθ"]);
+/// ```
+///
+/// Unlike regular expressions, transducers can't in general be made deterministic (for example,
+/// one that reads a string and writes its last char first would need to remember the whole
+/// string). So `Transducer`s are always simulated nondeterministically, and `apply` returns every
+/// possible output. For ambiguous transducers, there can be a lot of them.
+#[derive(Clone, Debug)]
+pub struct Transducer {
+    // The initial state is always the first one.
+    states: Vec<State>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Output {
+    Str(String),
+    // Writes the char that was consumed.
+    Copy,
+}
+
+impl Output {
+    fn write(&self, ch: char, out: &mut String) {
+        match *self {
+            Output::Str(ref s) => out.push_str(s),
+            Output::Copy => out.push(ch),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct State {
+    accept: bool,
+    consuming: Vec<(Range<u32>, Output, StateIdx)>,
+    eps: Vec<(String, StateIdx)>,
+}
+
+impl Transducer {
+    /// Creates a transducer with a single, non-accepting, state.
+    pub fn new() -> Transducer {
+        Transducer { states: vec![State { accept: false, consuming: Vec::new(), eps: Vec::new() }] }
+    }
+
+    /// Creates a transducer that translates each of the first strings in `pairs` into the
+    /// corresponding second string.
+    pub fn from_pairs<S: AsRef<str>, T: AsRef<str>>(pairs: &[(S, T)]) -> Transducer {
+        let mut ret = Transducer::new();
+        for &(ref input, ref output) in pairs {
+            let chars: Vec<char> = input.as_ref().chars().collect();
+            let mut last = 0;
+            if chars.is_empty() {
+                last = ret.add_state(false);
+                ret.add_eps(0, last, output.as_ref());
+            }
+
+            // The whole output is written when the first char is read.
+            for (i, &ch) in chars.iter().enumerate() {
+                let next = ret.add_state(false);
+                ret.add_transition(last, next, ch, ch, if i == 0 { output.as_ref() } else { "" });
+                last = next;
+            }
+            ret.states[last].accept = true;
+        }
+        ret
+    }
+
+    /// Creates a transducer that reads a single char between `lo` and `hi` (inclusive), and
+    /// writes the same char.
+    pub fn copy_chars(lo: char, hi: char) -> Transducer {
+        let mut ret = Transducer::new();
+        let acc = ret.add_state(true);
+        ret.add_copy(0, acc, lo, hi);
+        ret
+    }
+
+    /// Returns the number of states.
+    pub fn num_states(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Adds a new state and returns its index.
+    pub fn add_state(&mut self, accept: bool) -> StateIdx {
+        self.states.push(State { accept: accept, consuming: Vec::new(), eps: Vec::new() });
+        self.states.len() - 1
+    }
+
+    /// Adds a transition from `source` to `target` that reads any char between `lo` and `hi`
+    /// (inclusive) and writes `output`.
+    pub fn add_transition(&mut self, source: StateIdx, target: StateIdx, lo: char, hi: char,
+                          output: &str) {
+        let range = Range::new(lo as u32, hi as u32);
+        self.add_output_transition(source, target, range, Output::Str(output.to_owned()));
+    }
+
+    /// Adds a transition from `source` to `target` that reads any char between `lo` and `hi`
+    /// (inclusive) and writes the char that it read.
+    pub fn add_copy(&mut self, source: StateIdx, target: StateIdx, lo: char, hi: char) {
+        self.add_output_transition(source, target, Range::new(lo as u32, hi as u32), Output::Copy);
+    }
+
+    /// Adds a transition from `source` to `target` that doesn't read anything, and writes
+    /// `output`.
+    pub fn add_eps(&mut self, source: StateIdx, target: StateIdx, output: &str) {
+        self.states[source].eps.push((output.to_owned(), target));
+    }
+
+    fn add_output_transition(&mut self, source: StateIdx, target: StateIdx, range: Range<u32>,
+                             output: Output) {
+        self.states[source].consuming.push((range, output, target));
+    }
+
+    // Adds all the states of `other` to this transducer, returning the index of its initial state.
+    fn append(&mut self, other: &Transducer) -> StateIdx {
+        let offset = self.states.len();
+        for st in &other.states {
+            self.states.push(State {
+                accept: st.accept,
+                consuming: st.consuming.iter()
+                    .map(|&(range, ref output, tgt)| (range, output.clone(), tgt + offset))
+                    .collect(),
+                eps: st.eps.iter()
+                    .map(|&(ref output, tgt)| (output.clone(), tgt + offset))
+                    .collect(),
+            });
+        }
+        offset
+    }
+
+    /// Returns a transducer that does whatever either this one or `other` does.
+    pub fn union(&self, other: &Transducer) -> Transducer {
+        let mut ret = Transducer::new();
+        let first = ret.append(self);
+        let second = ret.append(other);
+        ret.add_eps(0, first, "");
+        ret.add_eps(0, second, "");
+        ret
+    }
+
+    /// Returns a transducer that splits its input into any number of pieces and translates each
+    /// of them using this transducer.
+    pub fn star(&self) -> Transducer {
+        let mut ret = Transducer::new();
+        ret.states[0].accept = true;
+        let init = ret.append(self);
+        ret.add_eps(0, init, "");
+        for i in init..ret.states.len() {
+            if ret.states[i].accept {
+                ret.add_eps(i, 0, "");
+            }
+        }
+        ret
+    }
+
+    /// Returns the transducer that translates its input using this transducer, and then
+    /// translates the result using `other`.
+    pub fn compose(&self, other: &Transducer) -> Transducer {
+        let first = self.split_outputs();
+        let mut ret = Transducer::new();
+        let mut state_map = HashMap::new();
+        let mut active = VecDeque::new();
+        state_map.insert((0, 0), 0);
+        active.push_back((0, 0));
+
+        while let Some((p, q)) = active.pop_front() {
+            let src = *state_map.get(&(p, q)).unwrap();
+            ret.states[src].accept = first.states[p].accept && other.states[q].accept;
+
+            // Each of these is a transition of the composed transducer: its input (or `None` for
+            // an epsilon transition), its output, and its target.
+            let mut trans = Vec::new();
+            for &(ref output, q_tgt) in &other.states[q].eps {
+                trans.push((None, Output::Str(output.clone()), (p, q_tgt)));
+            }
+            for &(ref mid, p_tgt) in &first.states[p].eps {
+                match mid.chars().next() {
+                    None => trans.push((None, Output::Str(String::new()), (p_tgt, q))),
+                    Some(ch) => {
+                        for &(range, ref output, q_tgt) in &other.states[q].consuming {
+                            if range.start <= ch as u32 && ch as u32 <= range.end {
+                                let mut out = String::new();
+                                output.write(ch, &mut out);
+                                trans.push((None, Output::Str(out), (p_tgt, q_tgt)));
+                            }
+                        }
+                    },
+                }
+            }
+            for &(range, ref mid, p_tgt) in &first.states[p].consuming {
+                match *mid {
+                    Output::Copy => {
+                        for &(other_range, ref output, q_tgt) in &other.states[q].consuming {
+                            let start = std::cmp::max(range.start, other_range.start);
+                            let end = std::cmp::min(range.end, other_range.end);
+                            if start <= end {
+                                let r = Some(Range::new(start, end));
+                                trans.push((r, output.clone(), (p_tgt, q_tgt)));
+                            }
+                        }
+                    },
+                    Output::Str(ref s) => match s.chars().next() {
+                        None => trans.push((Some(range), Output::Str(String::new()), (p_tgt, q))),
+                        Some(ch) => {
+                            for &(other_range, ref output, q_tgt) in &other.states[q].consuming {
+                                if other_range.start <= ch as u32 && ch as u32 <= other_range.end {
+                                    let mut out = String::new();
+                                    output.write(ch, &mut out);
+                                    trans.push((Some(range), Output::Str(out), (p_tgt, q_tgt)));
+                                }
+                            }
+                        },
+                    },
+                }
+            }
+
+            for (range, output, tgt) in trans {
+                let tgt_idx = match state_map.get(&tgt) {
+                    Some(&idx) => idx,
+                    None => {
+                        let idx = ret.add_state(false);
+                        state_map.insert(tgt, idx);
+                        active.push_back(tgt);
+                        idx
+                    },
+                };
+                match (range, output) {
+                    (Some(range), output) => ret.add_output_transition(src, tgt_idx, range, output),
+                    (None, Output::Str(s)) => ret.add_eps(src, tgt_idx, &s),
+                    (None, Output::Copy) => unreachable!(),
+                }
+            }
+        }
+        ret
+    }
+
+    // Returns an equivalent transducer in which every transition writes at most one char (not
+    // counting `Output::Copy`, which always writes exactly one char).
+    fn split_outputs(&self) -> Transducer {
+        let mut ret = self.clone();
+        for st in &mut ret.states {
+            st.consuming.clear();
+            st.eps.clear();
+        }
+
+        // Adds a chain of epsilon transitions from `source` to `target`, writing one char of
+        // `output` each.
+        fn add_chain(ret: &mut Transducer, mut source: StateIdx, target: StateIdx, output: &str) {
+            let chars: Vec<char> = output.chars().collect();
+            for (i, ch) in chars.iter().enumerate() {
+                let next = if i + 1 == chars.len() { target } else { ret.add_state(false) };
+                ret.add_eps(source, next, &ch.to_string());
+                source = next;
+            }
+        }
+
+        for (src, st) in self.states.iter().enumerate() {
+            for &(range, ref output, tgt) in &st.consuming {
+                match *output {
+                    Output::Str(ref s) if s.chars().count() > 1 => {
+                        let mut chars = s.chars();
+                        let first = chars.next().unwrap().to_string();
+                        let mid = ret.add_state(false);
+                        ret.add_output_transition(src, mid, range, Output::Str(first));
+                        add_chain(&mut ret, mid, tgt, chars.as_str());
+                    },
+                    _ => ret.add_output_transition(src, tgt, range, output.clone()),
+                }
+            }
+            for &(ref output, tgt) in &st.eps {
+                if output.is_empty() {
+                    ret.add_eps(src, tgt, "");
+                } else {
+                    add_chain(&mut ret, src, tgt, output);
+                }
+            }
+        }
+        ret
+    }
+
+    /// Returns all the strings that this transducer can translate `input` into, in sorted order.
+    ///
+    /// Between reading two chars, we never follow a sequence of epsilon transitions that comes
+    /// back to a state it has already visited. Otherwise, a loop of epsilon transitions that
+    /// writes something could produce infinitely many outputs.
+    pub fn apply(&self, input: &str) -> Vec<String> {
+        let mut current = self.eps_closure(vec![(0, String::new())]);
+        for ch in input.chars() {
+            let mut next = Vec::new();
+            for &(st, ref out) in &current {
+                for &(range, ref output, tgt) in &self.states[st].consuming {
+                    if range.start <= ch as u32 && ch as u32 <= range.end {
+                        let mut new_out = out.clone();
+                        output.write(ch, &mut new_out);
+                        next.push((tgt, new_out));
+                    }
+                }
+            }
+            current = self.eps_closure(next);
+        }
+
+        let mut ret: Vec<String> = current.into_iter()
+            .filter(|&(st, _)| self.states[st].accept)
+            .map(|(_, out)| out)
+            .collect();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+
+    // Returns all the (state, output) pairs that can be reached from `configs` by following
+    // epsilon transitions.
+    fn eps_closure(&self, configs: Vec<(StateIdx, String)>) -> Vec<(StateIdx, String)> {
+        let mut seen = HashSet::new();
+        let mut on_path = vec![false; self.states.len()];
+        let mut ret = Vec::new();
+        for (st, out) in configs {
+            self.eps_visit(st, out, &mut on_path, &mut seen, &mut ret);
+        }
+        ret
+    }
+
+    fn eps_visit(&self, st: StateIdx, out: String, on_path: &mut Vec<bool>,
+                 seen: &mut HashSet<(StateIdx, String)>, ret: &mut Vec<(StateIdx, String)>) {
+        if on_path[st] || !seen.insert((st, out.clone())) {
+            return;
+        }
+
+        on_path[st] = true;
+        for &(ref output, tgt) in &self.states[st].eps {
+            let mut new_out = out.clone();
+            new_out.push_str(output);
+            self.eps_visit(tgt, new_out, on_path, seen, ret);
+        }
+        on_path[st] = false;
+        ret.push((st, out));
+    }
+}
+
+impl Default for Transducer {
+    fn default() -> Transducer {
+        Transducer::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Transducer;
+
+    #[test]
+    fn from_pairs() {
+        let t = Transducer::from_pairs(&[("one", "1"), ("two", "2"), ("one", "I")]);
+        assert_eq!(t.apply("one"), vec!["1", "I"]);
+        assert_eq!(t.apply("two"), vec!["2"]);
+        assert!(t.apply("").is_empty());
+        assert!(t.apply("three").is_empty());
+        assert!(t.apply("onetwo").is_empty());
+
+        let t = t.star();
+        assert_eq!(t.apply("twoone"), vec!["21", "2I"]);
+        assert_eq!(t.apply(""), vec![""]);
+    }
+
+    #[test]
+    fn eps_cycles() {
+        let t = Transducer::from_pairs(&[("", "0")]);
+        assert_eq!(t.apply(""), vec!["0"]);
+        // Going around the loop more than once would give infinitely many outputs.
+        assert_eq!(t.star().apply(""), vec!["", "0"]);
+        assert_eq!(t.star().star().apply(""), vec!["", "0"]);
+    }
+
+    #[test]
+    fn by_hand() {
+        // Copies digits, deletes lowercase letters, and writes "!" at the end.
+        let mut t = Transducer::new();
+        let end = t.add_state(true);
+        t.add_copy(0, 0, '0', '9');
+        t.add_transition(0, 0, 'a', 'z', "");
+        t.add_eps(0, end, "!");
+        assert_eq!(t.num_states(), 2);
+        assert_eq!(t.apply("a1b23"), vec!["123!"]);
+        assert_eq!(t.apply(""), vec!["!"]);
+        assert!(t.apply("A").is_empty());
+    }
+
+    #[test]
+    fn compose() {
+        let numbers = Transducer::from_pairs(&[("1", "one"), ("2", "two"), ("0", "zero")]).star();
+        let zeroes = Transducer::from_pairs(&[("o", "0")])
+            .union(&Transducer::copy_chars('a', 'n'))
+            .union(&Transducer::copy_chars('p', 'z'))
+            .star();
+        let t = numbers.compose(&zeroes);
+        assert_eq!(t.apply("12"), vec!["0netw0"]);
+        assert_eq!(t.apply("0"), vec!["zer0"]);
+        assert_eq!(t.apply(""), vec![""]);
+        assert!(t.apply("3").is_empty());
+
+        for input in &["", "1", "120", "2021"] {
+            let mut expected: Vec<String> = numbers.apply(input).iter()
+                .flat_map(|mid| zeroes.apply(mid))
+                .collect();
+            expected.sort();
+            assert_eq!(t.apply(input), expected);
+        }
+
+        // Composition with epsilon transitions on both sides.
+        let a = Transducer::from_pairs(&[("", "xy")]);
+        let b = Transducer::from_pairs(&[("xy", "z"), ("x", "w")]).star();
+        assert_eq!(a.compose(&b).apply(""), vec!["z"]);
+    }
+}
@@ -0,0 +1,67 @@
+// Copyright 2016 Joe Neeman.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Bindings for running a `Regex` from JavaScript, enabled by the `wasm` feature.
+//
+// For a small binary, build with `--no-default-features --features wasm`. That drops the
+// `memchr` dependency (which links to libc) in favor of plain loops.
+
+use regex::Regex;
+use wasm_bindgen::prelude::*;
+
+/// A `Regex` that can be used from JavaScript.
+///
+/// JavaScript strings are UTF-16, so all positions are measured in UTF-16 code units (which is
+/// what JavaScript's own string methods expect).
+#[wasm_bindgen]
+pub struct WasmRegex {
+    regex: Regex,
+}
+
+#[wasm_bindgen]
+impl WasmRegex {
+    /// Compiles a regular expression, throwing an error if it is invalid or if it would need
+    /// more than `max_states` states.
+    #[wasm_bindgen(constructor)]
+    pub fn new(re: &str, max_states: u32) -> Result<WasmRegex, JsValue> {
+        match Regex::new_bounded(re, max_states as usize) {
+            Ok(regex) => Ok(WasmRegex { regex: regex }),
+            Err(e) => Err(JsValue::from_str(&e.to_string())),
+        }
+    }
+
+    pub fn is_match(&self, s: &str) -> bool {
+        self.regex.is_match(s)
+    }
+
+    /// Returns the start and end of the first match, or an empty array if there is no match.
+    pub fn find(&self, s: &str) -> Vec<u32> {
+        match self.regex.find(s) {
+            Some((start, end)) => vec![utf16_len(&s[..start]), utf16_len(&s[..end])],
+            None => Vec::new(),
+        }
+    }
+}
+
+fn utf16_len(s: &str) -> u32 {
+    s.encode_utf16().count() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+    use super::*;
+
+    #[test]
+    fn find_utf16_positions() {
+        let re = WasmRegex { regex: Regex::new("b+").unwrap() };
+        assert_eq!(re.find("é\u{1F600}bbc"), vec![3, 5]);
+        assert_eq!(re.find("ccc"), Vec::<u32>::new());
+        assert!(re.is_match("abc"));
+    }
+}